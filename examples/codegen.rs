@@ -0,0 +1,22 @@
+use upnp_client::{codegen::generate_service_client, parser::fetch_service_description};
+
+// Prints a typed client for a service, e.g.
+// cargo run --example codegen -- http://192.168.1.10:1400/xml/AVTransport1.xml AVTransport
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let scpd_url = args
+        .next()
+        .ok_or("usage: codegen <scpd url> <service id>")?;
+    let service_id = args
+        .next()
+        .ok_or("usage: codegen <scpd url> <service id>")?;
+
+    let description = fetch_service_description(&scpd_url).await?;
+    let struct_name = format!("{}Client", service_id.rsplit(':').next().unwrap());
+    println!(
+        "{}",
+        generate_service_client(&struct_name, &service_id, &description)?
+    );
+    Ok(())
+}
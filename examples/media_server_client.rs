@@ -1,7 +1,9 @@
 use futures_util::StreamExt;
 use upnp_client::{
-    device_client::DeviceClient, discovery::discover_pnp_locations,
-    media_server::MediaServerClient, types::Device,
+    device_client::DeviceClient,
+    discovery::discover_pnp_locations,
+    media_server::MediaServerClient,
+    types::{BrowseFlag, Device, Filter},
 };
 
 const KODI_MEDIA_SERVER: &str = "Kodi - Media Server";
@@ -24,7 +26,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let device_client = DeviceClient::new(&kodi_device.location)?.connect().await?;
     let media_server_client = MediaServerClient::new(device_client);
     let results = media_server_client
-        .browse("0", "BrowseDirectChildren")
+        .browse("0", BrowseFlag::DirectChildren, &Filter::all())
         .await?;
     println!("{:#?}", results);
     Ok(())
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    device_client::DeviceClient,
+    types::{Action, UpnpTime},
+};
+
+// A value formatted according to the UPnP data type rules (UDA 2.0 section
+// 2.5). Implement it for enums mapping to allowedValueList entries.
+pub trait ActionValue {
+    fn to_action_value(&self) -> String;
+}
+
+macro_rules! display_action_value {
+    ($($ty:ty),*) => {
+        $(impl ActionValue for $ty {
+            fn to_action_value(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+display_action_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, f32, f64, str, String);
+
+impl ActionValue for bool {
+    fn to_action_value(&self) -> String {
+        (*self as u8).to_string()
+    }
+}
+
+impl ActionValue for Duration {
+    fn to_action_value(&self) -> String {
+        UpnpTime(*self).to_string()
+    }
+}
+
+impl<T: ActionValue + ?Sized> ActionValue for &T {
+    fn to_action_value(&self) -> String {
+        (**self).to_action_value()
+    }
+}
+
+// Input arguments of an action, kept in insertion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActionArgs {
+    args: Vec<(String, String)>,
+}
+
+impl ActionArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn arg(mut self, name: &str, value: impl ActionValue) -> Self {
+        self.insert(name, value);
+        self
+    }
+
+    // Replaces any previous value of the argument.
+    pub fn insert(&mut self, name: &str, value: impl ActionValue) {
+        let value = value.to_action_value();
+        match self.args.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.args.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.args
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    // Some devices reject arguments that are not in SCPD order.
+    pub(crate) fn sort_for(&mut self, action: &Action) {
+        self.args.sort_by_key(|(name, _)| {
+            action
+                .arguments
+                .iter()
+                .position(|argument| argument.name == *name)
+                .unwrap_or(usize::MAX)
+        });
+    }
+}
+
+impl From<HashMap<String, String>> for ActionArgs {
+    fn from(params: HashMap<String, String>) -> Self {
+        Self {
+            args: params.into_iter().collect(),
+        }
+    }
+}
+
+impl<K: AsRef<str>, V: ActionValue> FromIterator<(K, V)> for ActionArgs {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut args = ActionArgs::new();
+        for (name, value) in iter {
+            args.insert(name.as_ref(), value);
+        }
+        args
+    }
+}
+
+// Return type of generated methods, so callers of the macro don't need their
+// own anyhow dependency.
+pub type ActionResult<T> = Result<T>;
+
+// Anything that can issue actions through a DeviceClient, i.e. the receiver of
+// methods generated by `upnp_action!`.
+pub trait ActionTarget {
+    fn device_client(&self) -> &DeviceClient;
+}
+
+impl ActionTarget for DeviceClient {
+    fn device_client(&self) -> &DeviceClient {
+        self
+    }
+}
+
+// UPnP boolean, which devices send as "0"/"1" as often as "false"/"true".
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Boolean(pub bool);
+
+impl FromStr for Boolean {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => Ok(Boolean(true)),
+            "0" | "false" | "no" => Ok(Boolean(false)),
+            _ => Err(anyhow!("Invalid boolean {:?}", s)),
+        }
+    }
+}
+
+impl ActionValue for Boolean {
+    fn to_action_value(&self) -> String {
+        self.0.to_action_value()
+    }
+}
+
+impl Display for Boolean {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0 as u8)
+    }
+}
+
+impl From<bool> for Boolean {
+    fn from(value: bool) -> Self {
+        Boolean(value)
+    }
+}
+
+pub fn output<T>(values: &HashMap<String, String>, name: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = values
+        .get(name)
+        .ok_or_else(|| anyhow!("Response missing {} argument", name))?;
+    value
+        .parse()
+        .map_err(|e| anyhow!("Invalid {} argument {:?}: {}", name, value, e))
+}
+
+// Declares an action once and generates a typed async method for it:
+//
+//     upnp_action! {
+//         pub async fn get_volume(&self, channel: &str => "Channel") -> ("CurrentVolume": u8)
+//             = "RenderingControl"."GetVolume" { "InstanceID": 0 };
+//     }
+//
+// Arguments map to the named input arguments, fixed arguments go in the
+// trailing braces and output arguments are returned as a tuple (or a bare
+// value when there is only one) parsed with `FromStr`.
+#[macro_export]
+macro_rules! upnp_action {
+    (
+        $(#[$meta:meta])*
+        $vis:vis async fn $name:ident(&self $(, $arg:ident : $arg_ty:ty => $arg_name:literal)* $(,)?)
+            -> ($($out_name:literal : $out_ty:ty),* $(,)?)
+            = $service:literal . $action:literal
+            $({ $($fixed_name:literal : $fixed_value:expr),* $(,)? })?;
+    ) => {
+        $(#[$meta])*
+        #[allow(unused_parens, clippy::needless_question_mark)]
+        $vis async fn $name(&self $(, $arg: $arg_ty)*) -> $crate::action::ActionResult<($($out_ty),*)> {
+            #[allow(unused_mut)]
+            let mut params = $crate::action::ActionArgs::new();
+            $($(params.insert($fixed_name, $fixed_value);)*)?
+            $(params.insert($arg_name, $arg);)*
+            let response = $crate::action::ActionTarget::device_client(self)
+                .call_action($service, $action, params)
+                .await?;
+            #[allow(unused_variables)]
+            let values = $crate::parser::parse_action_response(&response)?;
+            Ok(($($crate::action::output::<$out_ty>(&values, $out_name)?),*))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{output, ActionArgs, Boolean};
+    use crate::types::{Action, Argument};
+
+    #[test]
+    fn test_formatting_action_args() {
+        let args = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("DesiredMute", true)
+            .arg("Target", Duration::from_secs(3725))
+            .arg("Offset", Duration::from_millis(1500))
+            .arg("Channel", "Master")
+            .arg("InstanceID", 1u32);
+        assert_eq!(
+            args.iter().collect::<Vec<_>>(),
+            [
+                ("InstanceID", "1"),
+                ("DesiredMute", "1"),
+                ("Target", "01:02:05"),
+                ("Offset", "00:00:01.500"),
+                ("Channel", "Master"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sorting_args_in_scpd_order() {
+        let argument = |name: &str| Argument {
+            name: name.to_string(),
+            direction: "in".to_string(),
+            ..Default::default()
+        };
+        let action = Action {
+            name: "Seek".to_string(),
+            arguments: vec![argument("InstanceID"), argument("Unit"), argument("Target")],
+        };
+        let mut args: ActionArgs = [
+            ("Target", "00:01:00"),
+            ("InstanceID", "0"),
+            ("Unit", "REL_TIME"),
+        ]
+        .into_iter()
+        .collect();
+        args.sort_for(&action);
+        let names: Vec<_> = args.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["InstanceID", "Unit", "Target"]);
+    }
+
+    #[test]
+    fn test_parsing_outputs() {
+        let values = [("CurrentMute".to_string(), "0".to_string())].into();
+        assert_eq!(
+            output::<Boolean>(&values, "CurrentMute").unwrap(),
+            Boolean(false)
+        );
+        assert!(output::<u32>(&values, "CurrentMute").is_ok());
+        assert!(output::<u32>(&values, "Missing").is_err());
+    }
+}
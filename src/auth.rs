@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use surf::{
+    http::Method,
+    middleware::{Middleware, Next},
+    Client, Request, Response, StatusCode,
+};
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    // Builds the Authorization header answering a WWW-Authenticate challenge,
+    // `uri` being the request target (path and query) of the retried request.
+    pub fn authorization(&self, challenge: &str, method: &str, uri: &str) -> Result<String> {
+        self.answer(challenge, method, uri, 1)
+    }
+
+    // `nc` counts the requests answering the challenge's nonce so far,
+    // this one included.
+    fn answer(&self, challenge: &str, method: &str, uri: &str, nc: u32) -> Result<String> {
+        let (scheme, params) = challenge
+            .trim()
+            .split_once(' ')
+            .unwrap_or((challenge.trim(), ""));
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => Ok(self.basic()),
+            "digest" => {
+                let cnonce = format!(
+                    "{:016x}",
+                    SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+                );
+                self.digest(&parse_challenge_params(params), method, uri, &cnonce, nc)
+            }
+            _ => Err(anyhow!("Unsupported authentication scheme {}", scheme)),
+        }
+    }
+
+    fn basic(&self) -> String {
+        let token = STANDARD.encode(format!("{}:{}", self.username, self.password));
+        format!("Basic {}", token)
+    }
+
+    fn digest(
+        &self,
+        params: &HashMap<String, String>,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+        nc: u32,
+    ) -> Result<String> {
+        let realm = params
+            .get("realm")
+            .ok_or_else(|| anyhow!("Digest challenge missing realm"))?;
+        let nonce = params
+            .get("nonce")
+            .ok_or_else(|| anyhow!("Digest challenge missing nonce"))?;
+        let algorithm = params.get("algorithm").map(|a| a.to_ascii_uppercase());
+        let qop = params
+            .get("qop")
+            .filter(|qop| qop.split(',').any(|q| q.trim() == "auth"));
+        let nc = format!("{:08x}", nc);
+
+        let mut ha1 = md5_hex(&format!("{}:{}:{}", self.username, realm, self.password));
+        if algorithm.as_deref() == Some("MD5-SESS") {
+            ha1 = md5_hex(&format!("{}:{}:{}", ha1, nonce, cnonce));
+        }
+        let ha2 = md5_hex(&format!("{}:{}", method, uri));
+        let response = match qop {
+            Some(_) => md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2)),
+            None => md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2)),
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+            self.username, realm, nonce, uri, response
+        );
+        if let Some(algorithm) = params.get("algorithm") {
+            header.push_str(&format!(", algorithm={}", algorithm));
+        }
+        if let Some(opaque) = params.get("opaque") {
+            header.push_str(&format!(", opaque=\"{}\"", opaque));
+        }
+        if qop.is_some() {
+            header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        Ok(header)
+    }
+}
+
+fn md5_hex(value: &str) -> String {
+    Md5::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn parse_challenge_params(params: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match value.find(',') {
+                Some(end) => (&value[..end], &value[end..]),
+                None => (value, ""),
+            },
+        };
+        result.insert(key, value.trim().to_string());
+        rest = remaining.trim_start().trim_start_matches(',');
+    }
+    result
+}
+
+pub(crate) fn request_target(url: &surf::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+// Answers the device's Basic or Digest challenge. Bodies are never buffered:
+// once a challenge is known it is answered up front, and before that, requests
+// with a body get their challenge from a HEAD request to the same URL. Only
+// bodiless requests are retried after a 401.
+pub(crate) struct Authentication {
+    credentials: Credentials,
+    challenge: Mutex<Challenge>,
+}
+
+#[derive(Clone)]
+enum Challenge {
+    Unknown,
+    // The last HEAD probe went through without one.
+    None,
+    // With the number of times it was answered, as Digest nonces must not be
+    // answered twice with the same count.
+    Some(String, u32),
+}
+
+impl Authentication {
+    pub fn new(credentials: Credentials) -> Self {
+        Self {
+            credentials,
+            challenge: Mutex::new(Challenge::Unknown),
+        }
+    }
+
+    // Answers the known challenge, if any.
+    fn authorize(&self, req: &mut Request) -> Result<()> {
+        let (challenge, nc) = match &mut *self.challenge.lock().unwrap() {
+            Challenge::Some(challenge, nc) => {
+                *nc += 1;
+                (challenge.clone(), *nc)
+            }
+            _ => return Ok(()),
+        };
+        let authorization = self.credentials.answer(
+            &challenge,
+            req.method().as_ref(),
+            &request_target(req.url()),
+            nc,
+        )?;
+        req.insert_header("Authorization", authorization);
+        Ok(())
+    }
+
+    // Whether the response is a 401 with a challenge, which is then
+    // remembered for the next requests.
+    fn challenged(&self, res: &Response) -> bool {
+        if res.status() != StatusCode::Unauthorized {
+            return false;
+        }
+        let Some(challenge) = res.header("WWW-Authenticate") else {
+            return false;
+        };
+        *self.challenge.lock().unwrap() = Challenge::Some(challenge.as_str().to_string(), 0);
+        true
+    }
+}
+
+#[surf::utils::async_trait]
+impl Middleware for Authentication {
+    async fn handle(
+        &self,
+        mut req: Request,
+        client: Client,
+        next: Next<'_>,
+    ) -> surf::Result<Response> {
+        let bodiless = req.len() == Some(0);
+        let known = self.challenge.lock().unwrap().clone();
+        match known {
+            Challenge::Some(..) => self.authorize(&mut req)?,
+            Challenge::Unknown if !bodiless => {
+                let mut probe = Request::new(Method::Head, req.url().clone());
+                for (name, values) in req.iter() {
+                    probe.insert_header(name, values);
+                }
+                probe.remove_header("Content-Length");
+                let res = next.run(probe, client.clone()).await?;
+                match self.challenged(&res) {
+                    true => self.authorize(&mut req)?,
+                    false => *self.challenge.lock().unwrap() = Challenge::None,
+                }
+            }
+            _ => {}
+        }
+
+        // Without a body the request can be sent again, e.g. with a fresh
+        // Digest nonce; `clone` keeps everything but the body.
+        let retry = bodiless.then(|| req.clone());
+        let res = next.run(req, client.clone()).await?;
+        match (self.challenged(&res), retry) {
+            (true, Some(mut retry)) => {
+                self.authorize(&mut retry)?;
+                next.run(retry, client).await
+            }
+            _ => Ok(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_challenge_params, Authentication, Credentials};
+    use crate::testing::{serve, TestResponse};
+    use futures_util::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use surf::{Body, Client, Config};
+
+    #[test]
+    fn test_digest_authorization() {
+        // RFC 2617 section 3.5 example
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let params = parse_challenge_params(challenge.trim_start_matches("Digest "));
+        assert_eq!(params["realm"], "testrealm@host.com");
+        assert_eq!(params["qop"], "auth,auth-int");
+
+        let header = credentials
+            .authorization(challenge, "GET", "/dir/index.html")
+            .unwrap();
+        assert!(header.starts_with("Digest username=\"Mufasa\""));
+        assert!(header.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+    }
+
+    #[test]
+    fn test_digest_response() {
+        // RFC 2617 section 3.5 example
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        let challenge = r#"realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let header = credentials
+            .digest(
+                &parse_challenge_params(challenge),
+                "GET",
+                "/dir/index.html",
+                "0a4f113b",
+                1,
+            )
+            .unwrap();
+        assert!(header.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(header.contains(r#"qop=auth, nc=00000001, cnonce="0a4f113b""#));
+    }
+
+    #[tokio::test]
+    async fn test_bodies_sent_once() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        let url = serve(move |request| {
+            let authorized = request.header("Authorization").is_some();
+            log.lock().unwrap().push(format!(
+                "{} {} {}",
+                request.method,
+                authorized,
+                request.body.len()
+            ));
+            match authorized {
+                true => TestResponse::status(200),
+                false => {
+                    TestResponse::status(401).header("WWW-Authenticate", "Basic realm=\"nas\"")
+                }
+            }
+        });
+        let client: Client = Config::new().try_into().unwrap();
+        let client = client.with(Authentication::new(Credentials::new("user", "secret")));
+
+        for _ in 0..2 {
+            let body = Body::from_reader(Cursor::new(vec![7u8; 4096]), Some(4096));
+            let res = client.post(&url).body(body).await.unwrap();
+            assert_eq!(res.status(), 200);
+        }
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["HEAD false 0", "POST true 4096", "POST true 4096"]
+        );
+    }
+
+    #[test]
+    fn test_digest_nonce_counts() {
+        let counts = Arc::new(Mutex::new(vec![]));
+        let log = counts.clone();
+        let url = serve(move |request| match request.header("Authorization") {
+            Some(authorization) => {
+                let params = parse_challenge_params(&authorization["Digest ".len()..]);
+                log.lock().unwrap().push(params["nc"].clone());
+                TestResponse::status(200)
+            }
+            None => TestResponse::status(401).header(
+                "WWW-Authenticate",
+                r#"Digest realm="nas", qop="auth", nonce="abc""#,
+            ),
+        });
+        let client: Client = Config::new().try_into().unwrap();
+        let client = client.with(Authentication::new(Credentials::new("user", "secret")));
+
+        async_io::block_on(async {
+            for _ in 0..3 {
+                assert_eq!(client.get(&url).await.unwrap().status(), 200);
+            }
+        });
+        assert_eq!(
+            *counts.lock().unwrap(),
+            vec!["00000001", "00000002", "00000003"]
+        );
+    }
+
+    #[test]
+    fn test_basic_authorization() {
+        let credentials = Credentials::new("Aladdin", "open sesame");
+        assert_eq!(
+            credentials
+                .authorization("Basic realm=\"device\"", "POST", "/control")
+                .unwrap(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+}
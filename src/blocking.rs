@@ -0,0 +1,187 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Error, Result};
+use futures_util::StreamExt;
+use tokio::runtime::Runtime;
+
+use crate::{
+    device_client::DeviceClient,
+    discovery::discover_pnp_locations,
+    media_renderer,
+    types::{Device, LoadOptions, PlayMode, PositionInfo, ProtocolInfo, TransportInfo},
+};
+
+fn runtime() -> Result<Arc<Runtime>> {
+    Ok(Arc::new(
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?,
+    ))
+}
+
+// Collects the devices answering the discovery request within `timeout`.
+pub fn discover(timeout: Duration) -> Result<Vec<Device>> {
+    runtime()?.block_on(async {
+        let devices = discover_pnp_locations().await?;
+        tokio::pin!(devices);
+
+        let mut found = vec![];
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                Some(device) = devices.next() => found.push(device),
+                _ = &mut deadline => break,
+            }
+        }
+        Ok(found)
+    })
+}
+
+// Synchronous MediaRendererClient driving its own tokio runtime, for CLI tools
+// and GUI applications that are not async.
+#[derive(Clone)]
+pub struct MediaRendererClient {
+    runtime: Arc<Runtime>,
+    inner: media_renderer::MediaRendererClient,
+}
+
+impl MediaRendererClient {
+    pub fn connect(url: &str) -> Result<Self> {
+        let runtime = runtime()?;
+        let device_client = runtime.block_on(DeviceClient::new(url)?.connect())?;
+        Ok(Self::with_runtime(runtime, device_client))
+    }
+
+    pub fn new(device_client: DeviceClient) -> Result<Self> {
+        Ok(Self::with_runtime(runtime()?, device_client))
+    }
+
+    fn with_runtime(runtime: Arc<Runtime>, device_client: DeviceClient) -> Self {
+        Self {
+            runtime,
+            inner: media_renderer::MediaRendererClient::new(device_client),
+        }
+    }
+
+    pub fn load(&self, url: &str, options: LoadOptions) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.load(url, options))
+    }
+
+    pub fn play(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.play())
+    }
+
+    pub fn pause(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.pause())
+    }
+
+    #[deprecated(note = "use `seek_to`, which takes a Duration")]
+    pub fn seek(&self, seconds: u64) -> Result<(), Error> {
+        self.seek_to(Duration::from_secs(seconds))
+    }
+
+    pub fn seek_to(&self, position: Duration) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.seek_to(position))
+    }
+
+    pub fn stop(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.stop())
+    }
+
+    pub fn next(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.next())
+    }
+
+    pub fn previous(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.previous())
+    }
+
+    pub fn set_next(&self, url: &str, options: LoadOptions) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.set_next(url, options))
+    }
+
+    pub fn get_play_mode(&self) -> Result<PlayMode, Error> {
+        self.runtime.block_on(self.inner.get_play_mode())
+    }
+
+    pub fn set_play_mode(&self, play_mode: PlayMode) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.set_play_mode(play_mode))
+    }
+
+    pub fn get_volume(&self) -> Result<u8, Error> {
+        self.runtime.block_on(self.inner.get_volume())
+    }
+
+    pub fn set_volume(&self, volume: u32) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.set_volume(volume))
+    }
+
+    pub fn get_supported_protocols(&self) -> Result<Vec<String>, Error> {
+        self.runtime.block_on(self.inner.get_supported_protocols())
+    }
+
+    pub fn get_sink_protocol_info(&self) -> Result<Vec<ProtocolInfo>, Error> {
+        self.runtime.block_on(self.inner.get_sink_protocol_info())
+    }
+
+    #[deprecated(note = "use `position`, which returns a Duration")]
+    pub fn get_position(&self) -> Result<u32, Error> {
+        Ok(self.position()?.as_secs() as u32)
+    }
+
+    pub fn position(&self) -> Result<Duration, Error> {
+        self.runtime.block_on(self.inner.position())
+    }
+
+    pub fn get_position_info(&self) -> Result<PositionInfo, Error> {
+        self.runtime.block_on(self.inner.get_position_info())
+    }
+
+    #[deprecated(note = "use `duration`, which returns a Duration")]
+    pub fn get_duration(&self) -> Result<u32, Error> {
+        Ok(self.duration()?.as_secs() as u32)
+    }
+
+    pub fn duration(&self) -> Result<Duration, Error> {
+        self.runtime.block_on(self.inner.duration())
+    }
+
+    pub fn get_transport_info(&self) -> Result<TransportInfo, Error> {
+        self.runtime.block_on(self.inner.get_transport_info())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MediaRendererClient;
+    use crate::testing::{serve_device, soap_response, TestResponse};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_blocking_renderer_client() {
+        let volume = Arc::new(Mutex::new("10".to_string()));
+        let state = volume.clone();
+        let url = serve_device(
+            &["RenderingControl"],
+            &["GetVolume", "SetVolume"],
+            move |request| match request.soap_action() {
+                Some("GetVolume") => {
+                    let volume = state.lock().unwrap().clone();
+                    TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", &volume)]))
+                }
+                Some("SetVolume") => {
+                    *state.lock().unwrap() = request.argument("DesiredVolume").unwrap();
+                    TestResponse::ok(soap_response("SetVolume", &[]))
+                }
+                _ => TestResponse::status(500),
+            },
+        );
+
+        let renderer = MediaRendererClient::connect(&url).unwrap();
+        assert_eq!(renderer.get_volume().unwrap(), 10);
+        renderer.set_volume(42).unwrap();
+        assert_eq!(*volume.lock().unwrap(), "42");
+        assert_eq!(renderer.get_volume().unwrap(), 42);
+    }
+}
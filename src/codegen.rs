@@ -0,0 +1,228 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::types::{Action, ServiceDescription};
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+// Generates the source of a typed client for a service, one `upnp_action!`
+// method per action with argument types taken from the related state
+// variables. The output can be written to OUT_DIR from a build script (with
+// an SCPD saved in the repository) or printed once and checked in.
+pub fn generate_service_client(
+    struct_name: &str,
+    service_id: &str,
+    description: &ServiceDescription,
+) -> Result<String> {
+    let mut source = String::new();
+    writeln!(source, "#[derive(Clone)]")?;
+    writeln!(source, "pub struct {} {{", struct_name)?;
+    writeln!(
+        source,
+        "    device_client: upnp_client::device_client::DeviceClient,"
+    )?;
+    writeln!(source, "}}\n")?;
+    writeln!(source, "impl {} {{", struct_name)?;
+    writeln!(
+        source,
+        "    pub fn new(device_client: upnp_client::device_client::DeviceClient) -> Self {{"
+    )?;
+    writeln!(source, "        Self {{ device_client }}")?;
+    writeln!(source, "    }}")?;
+    for action in &description.actions {
+        writeln!(source)?;
+        source.push_str(&generate_action(service_id, action, description)?);
+    }
+    writeln!(source, "}}\n")?;
+    writeln!(
+        source,
+        "impl upnp_client::action::ActionTarget for {} {{",
+        struct_name
+    )?;
+    writeln!(
+        source,
+        "    fn device_client(&self) -> &upnp_client::device_client::DeviceClient {{"
+    )?;
+    writeln!(source, "        &self.device_client")?;
+    writeln!(source, "    }}")?;
+    writeln!(source, "}}")?;
+    Ok(source)
+}
+
+fn generate_action(
+    service_id: &str,
+    action: &Action,
+    description: &ServiceDescription,
+) -> Result<String> {
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for argument in &action.arguments {
+        let data_type = description
+            .state_variable(&argument.related_state_variable)
+            .map(|v| v.data_type.as_str())
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} argument {} refers to unknown state variable {}",
+                    action.name,
+                    argument.name,
+                    argument.related_state_variable
+                )
+            })?;
+        match argument.direction.as_str() {
+            "in" => inputs.push(format!(
+                "{}: {} => {:?}",
+                identifier(&argument.name),
+                rust_type(data_type, true),
+                argument.name
+            )),
+            _ => outputs.push(format!(
+                "{:?}: {}",
+                argument.name,
+                rust_type(data_type, false)
+            )),
+        }
+    }
+
+    let mut signature = String::from("&self");
+    for input in &inputs {
+        signature.push_str(", ");
+        signature.push_str(input);
+    }
+    Ok(format!(
+        "    upnp_client::upnp_action! {{\n        pub async fn {}({}) -> ({})\n            = {:?}.{:?};\n    }}\n",
+        identifier(&action.name),
+        signature,
+        outputs.join(", "),
+        service_id,
+        action.name
+    ))
+}
+
+// UPnP data types (UDA 2.0 section 2.5) to Rust types. Strings are borrowed
+// when passed as arguments and booleans are only lenient when parsed.
+fn rust_type(data_type: &str, input: bool) -> &'static str {
+    match data_type {
+        "ui1" => "u8",
+        "ui2" => "u16",
+        "ui4" => "u32",
+        "ui8" => "u64",
+        "i1" => "i8",
+        "i2" => "i16",
+        "i4" | "int" => "i32",
+        "i8" => "i64",
+        "r4" => "f32",
+        "r8" | "number" | "float" | "fixed.14.4" => "f64",
+        "boolean" if input => "bool",
+        "boolean" => "upnp_client::action::Boolean",
+        _ if input => "&str",
+        _ => "String",
+    }
+}
+
+// "GetCurrentTransportActions" -> "get_current_transport_actions",
+// "X_GetURI" -> "x_get_uri".
+fn identifier(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase());
+            if previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        match c.is_ascii_alphanumeric() {
+            true => snake.push(c.to_ascii_lowercase()),
+            false if !snake.ends_with('_') => snake.push('_'),
+            false => {}
+        }
+    }
+    let snake = snake.trim_matches('_').to_string();
+    match RUST_KEYWORDS.contains(&snake.as_str()) || snake.starts_with(|c: char| c.is_ascii_digit())
+    {
+        true => format!("{}_", snake),
+        false => snake,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_service_client, identifier};
+    use crate::parser::parse_scpd;
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("GetVolume"), "get_volume");
+        assert_eq!(identifier("SetAVTransportURI"), "set_av_transport_uri");
+        assert_eq!(identifier("X_GetURI"), "x_get_uri");
+        assert_eq!(identifier("Type"), "type_");
+    }
+
+    #[test]
+    fn test_generating_service_client() {
+        const SCPD: &str = r#"<?xml version="1.0"?>
+        <scpd xmlns="urn:schemas-upnp-org:service-1-0">
+            <actionList>
+                <action>
+                    <name>GetMute</name>
+                    <argumentList>
+                        <argument>
+                            <name>InstanceID</name>
+                            <direction>in</direction>
+                            <relatedStateVariable>A_ARG_TYPE_InstanceID</relatedStateVariable>
+                        </argument>
+                        <argument>
+                            <name>Channel</name>
+                            <direction>in</direction>
+                            <relatedStateVariable>A_ARG_TYPE_Channel</relatedStateVariable>
+                        </argument>
+                        <argument>
+                            <name>CurrentMute</name>
+                            <direction>out</direction>
+                            <relatedStateVariable>Mute</relatedStateVariable>
+                        </argument>
+                    </argumentList>
+                </action>
+            </actionList>
+            <serviceStateTable>
+                <stateVariable sendEvents="no">
+                    <name>A_ARG_TYPE_InstanceID</name>
+                    <dataType>ui4</dataType>
+                </stateVariable>
+                <stateVariable sendEvents="no">
+                    <name>A_ARG_TYPE_Channel</name>
+                    <dataType>string</dataType>
+                    <allowedValueList>
+                        <allowedValue>Master</allowedValue>
+                    </allowedValueList>
+                </stateVariable>
+                <stateVariable sendEvents="yes">
+                    <name>Mute</name>
+                    <dataType>boolean</dataType>
+                </stateVariable>
+            </serviceStateTable>
+        </scpd>"#;
+        let description = parse_scpd(SCPD).unwrap();
+        assert_eq!(description.state_variables.len(), 3);
+        assert_eq!(description.state_variables[1].allowed_values, ["Master"]);
+        assert!(description.state_variables[2].send_events);
+
+        let source =
+            generate_service_client("RenderingControl", "RenderingControl", &description).unwrap();
+        assert!(source.contains("pub struct RenderingControl {"));
+        assert!(source.contains(
+            "pub async fn get_mute(&self, instance_id: u32 => \"InstanceID\", channel: &str => \"Channel\") -> (\"CurrentMute\": upnp_client::action::Boolean)"
+        ));
+        assert!(source.contains("= \"RenderingControl\".\"GetMute\";"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use surf::{
+    middleware::{Middleware, Next},
+    Client, Request, Response,
+};
+
+use crate::{
+    limits::{read_body, too_large, BodyLimit},
+    types::Limits,
+};
+
+// Advertises gzip/deflate support and transparently inflates compressed
+// descriptions, SCPDs and SOAP responses.
+pub(crate) struct Decompression;
+
+// Marks requests whose body is passed on as it is sent, like resource
+// downloads: they can be of any size and are resumed at byte offsets.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Identity;
+
+#[surf::utils::async_trait]
+impl Middleware for Decompression {
+    async fn handle(
+        &self,
+        mut req: Request,
+        client: Client,
+        next: Next<'_>,
+    ) -> surf::Result<Response> {
+        if req.ext::<Identity>().is_some() {
+            req.insert_header("Accept-Encoding", "identity");
+            return next.run(req, client).await;
+        }
+        let max_size = req
+            .ext::<BodyLimit>()
+            .map(|limit| limit.0)
+            .unwrap_or(Limits::default().max_response_size);
+        if req.header("Accept-Encoding").is_none() {
+            req.insert_header("Accept-Encoding", "gzip, deflate");
+        }
+        let mut res = next.run(req, client).await?;
+        let encoding = match res.header("Content-Encoding") {
+            Some(encoding) => encoding.as_str().trim().to_ascii_lowercase(),
+            None => return Ok(res),
+        };
+        if encoding == "identity" {
+            return Ok(res);
+        }
+
+        let body = read_body(&mut res, max_size).await?;
+        res.set_body(decompress(&encoding, &body, max_size)?);
+        res.remove_header("Content-Encoding");
+        res.remove_header("Content-Length");
+        Ok(res)
+    }
+}
+
+// Inflation stops past `max_size`, a compressed body is no way around the
+// response size limit.
+pub(crate) fn decompress(encoding: &str, body: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    let limit = max_size as u64 + 1;
+    let mut decompressed = Vec::new();
+    match encoding {
+        "gzip" | "x-gzip" => {
+            GzDecoder::new(body)
+                .take(limit)
+                .read_to_end(&mut decompressed)?;
+        }
+        // Servers disagree on whether deflate means zlib-wrapped or raw data.
+        "deflate" => {
+            if ZlibDecoder::new(body)
+                .take(limit)
+                .read_to_end(&mut decompressed)
+                .is_err()
+            {
+                decompressed.clear();
+                DeflateDecoder::new(body)
+                    .take(limit)
+                    .read_to_end(&mut decompressed)?;
+            }
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unsupported content encoding {}", encoding),
+            ))
+        }
+    }
+    match decompressed.len() > max_size {
+        true => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            too_large(max_size).to_string(),
+        )),
+        false => Ok(decompressed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress;
+    use flate2::{
+        write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+        Compression,
+    };
+    use std::io::Write;
+
+    #[test]
+    fn test_decompressing_response_bodies() {
+        let xml = b"<scpd><actionList/></scpd>";
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(xml).unwrap();
+        assert_eq!(
+            decompress("gzip", &gzip.finish().unwrap(), 1024).unwrap(),
+            xml
+        );
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(xml).unwrap();
+        assert_eq!(
+            decompress("deflate", &zlib.finish().unwrap(), 1024).unwrap(),
+            xml
+        );
+
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(xml).unwrap();
+        assert_eq!(
+            decompress("deflate", &raw.finish().unwrap(), 1024).unwrap(),
+            xml
+        );
+
+        assert!(decompress("br", xml, 1024).is_err());
+
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::best());
+        bomb.write_all(&vec![0; 1024 * 1024]).unwrap();
+        assert!(decompress("gzip", &bomb.finish().unwrap(), 1024).is_err());
+    }
+}
@@ -1,21 +1,50 @@
-use std::{collections::HashMap, env, net::TcpListener, sync::Arc, time::Duration};
+use std::{
+    env,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, UdpSocket},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::{
+    action::ActionArgs,
+    auth::{request_target, Authentication, Credentials},
+    compression::{decompress, Decompression, Identity},
+    hooks::{SoapHook, SoapRequest, SoapResponse},
+    limits::{read_body, read_hyper_body, reject_doctype, BodyLimit},
+    openhome::openhome_event,
     parser::{
-        deserialize_metadata, parse_av_transport_uri_metadata, parse_current_play_mode,
-        parse_current_track_metadata, parse_last_change, parse_location, parse_transport_state,
+        decode_xml, deserialize_metadata, parse_av_transport_uri_metadata, parse_current_play_mode,
+        parse_current_track_metadata, parse_last_change, parse_location_with_limits,
+        parse_property_set, parse_query_state_variable, parse_response_element,
+        parse_transport_state, scope_id, split_zone_id,
+    },
+    runtime,
+    sonos::sonos_event,
+    tls::{self, HttpsClient, ScopedConnector},
+    types::{
+        AVTransportEvent, Device, DeviceBusy, DownloadOptions, Event, Limits, Quirks, RetryPolicy,
+        Service,
     },
-    types::{AVTransportEvent, Device, Event, Service},
     BROADCAST_EVENT,
 };
 use anyhow::{anyhow, Result};
+use elementtree::Element;
+use futures_util::{io::BufReader, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use hyper::{
     server::conn::AddrStream,
     service::{make_service_fn, service_fn},
 };
 use hyper::{Body, Request, Response, Server};
-use surf::{Client, Config, Url};
+use hyper_rustls::HttpsConnector;
+use rustls::ClientConfig;
+use surf::{
+    http::other::RetryAfter,
+    middleware::{Middleware, Next},
+    Client, Config, StatusCode, Url,
+};
 use tokio::sync::Mutex;
+use url::Host;
 use xml_builder::{XMLBuilder, XMLElement, XMLVersion};
 
 #[derive(Clone)]
@@ -24,39 +53,282 @@ pub struct DeviceClient {
     http_client: Client,
     device: Option<Device>,
     stop: Arc<Mutex<bool>>,
+    keep_alive: bool,
+    quirks: Arc<RwLock<Quirks>>,
+    embedded_device: Option<String>,
+    credentials: Option<Credentials>,
+    tls_config: Arc<ClientConfig>,
+    // M-POST and GENA requests, which surf can't send.
+    hyper_client: hyper::Client<HttpsConnector<ScopedConnector>>,
+    // Shared with the RequestHeaders middleware of `http_client`.
+    headers: Arc<RwLock<Vec<(String, String)>>>,
+    hooks: Vec<Arc<dyn SoapHook>>,
+    action_queue: Option<Arc<Mutex<Option<Instant>>>>,
+    min_action_interval: Duration,
+    retry_policy: RetryPolicy,
+    local_address: Option<IpAddr>,
+    limits: Limits,
+    zone_id: Option<String>,
+    timeout: Option<Duration>,
+    // The configuration `new` built the transport from. async-h1 pools
+    // connections and sends "Connection: keep-alive" whatever the request
+    // says, so turning keep-alive off means rebuilding it without a pool.
+    transport: Option<Config>,
 }
 
 impl DeviceClient {
+    pub fn builder(url: &str) -> DeviceClientBuilder {
+        DeviceClientBuilder::new(url)
+    }
+
     pub fn new(url: &str) -> Result<Self> {
-        Ok(Self {
-            base_url: Url::parse(url)?,
-            http_client: Config::new()
-                .set_timeout(Some(Duration::from_secs(5)))
+        let config = Config::new()
+            .set_timeout(Some(Duration::from_secs(5)))
+            .set_http_keep_alive(true);
+        // Only the hyper transport can scope connections to an interface.
+        let http_client = match split_zone_id(url).1 {
+            Some(zone) => config
+                .set_http_client(HttpsClient::with_scope_id(
+                    tls::default_config(),
+                    None,
+                    scope_id(&zone),
+                ))
                 .try_into()?,
+            None => {
+                let mut device_client = Self::with_http_client(url, config.clone().try_into()?)?;
+                device_client.transport = Some(config);
+                return Ok(device_client);
+            }
+        };
+        Self::with_http_client(url, http_client)
+    }
+
+    // Use a preconfigured client (proxy, local address binding, TLS settings or
+    // a custom transport via `Client::with_http_client`) for descriptions, SOAP
+    // POSTs and downloads. surf can't send M-POST or the GENA methods, so the
+    // M-POST fallback and event subscriptions still go through hyper.
+    pub fn with_http_client(url: &str, http_client: Client) -> Result<Self> {
+        let user_agent = format!(
+            "upnp-client/{} ({})",
+            env!("CARGO_PKG_VERSION"),
+            env::consts::OS
+        );
+        let headers = Arc::new(RwLock::new(vec![("USER-AGENT".to_string(), user_agent)]));
+        let (url, zone_id) = split_zone_id(url);
+        let scope = zone_id.as_deref().map_or(0, scope_id);
+        Ok(Self {
+            base_url: Url::parse(&url)?,
+            zone_id,
+            http_client: http_client
+                .clone()
+                .with(Decompression)
+                .with(RequestHeaders(headers.clone())),
             device: None,
             stop: Arc::new(Mutex::new(false)),
+            keep_alive: true,
+            quirks: Arc::new(RwLock::new(Quirks::default())),
+            embedded_device: None,
+            credentials: None,
+            tls_config: Arc::new(tls::default_config()),
+            hyper_client: tls::hyper_client(
+                tls::https_connector(tls::default_config(), None, scope),
+                true,
+            ),
+            headers,
+            hooks: vec![],
+            action_queue: None,
+            min_action_interval: Duration::ZERO,
+            retry_policy: RetryPolicy::default(),
+            local_address: None,
+            limits: Limits::default(),
+            timeout: Some(Duration::from_secs(5)),
+            transport: None,
         })
     }
 
+    // Control https:// devices, trusting certificates according to `tls_config`
+    // (see `tls::pinned_certificate_config` for self-signed device certificates).
+    pub fn with_tls_config(url: &str, tls_config: ClientConfig) -> Result<Self> {
+        let http_client = Config::new()
+            .set_timeout(Some(Duration::from_secs(5)))
+            .set_http_client(HttpsClient::with_scope_id(
+                tls_config.clone(),
+                None,
+                split_zone_id(url).1.as_deref().map_or(0, scope_id),
+            ))
+            .try_into()?;
+        let mut device_client = Self::with_http_client(url, http_client)?;
+        device_client.tls_config = Arc::new(tls_config);
+        device_client.update_hyper_client();
+        Ok(device_client)
+    }
+
+    // Persistent connections are reused across SOAP calls by default; disable
+    // for devices that misbehave unless every request closes its connection.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self.update_hyper_client();
+        if let Some(config) = &self.transport {
+            let Ok(http_client) = Client::try_from(config.clone().set_http_keep_alive(keep_alive));
+            self.http_client = http_client
+                .with(Decompression)
+                .with(RequestHeaders(self.headers.clone()));
+            if let Some(credentials) = &self.credentials {
+                let authentication = Authentication::new(credentials.clone());
+                self.http_client = self.http_client.clone().with(authentication);
+            }
+        }
+        self
+    }
+
+    // Rebuilt whenever one of the settings it depends on changes, dropping
+    // the connections of the previous one.
+    fn update_hyper_client(&mut self) {
+        let connector = tls::https_connector(
+            (*self.tls_config).clone(),
+            self.local_address,
+            self.scope_id(),
+        );
+        self.hyper_client = tls::hyper_client(connector, self.keep_alive);
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Arc::new(RwLock::new(quirks));
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn user_agent(self, user_agent: &str) -> Self {
+        self.header("USER-AGENT", user_agent)
+    }
+
+    // Sent with every request to the device, e.g. `transferMode.dlna.org` or
+    // vendor headers required by some Samsung/LG firmwares. Clones of the
+    // client share their headers.
+    pub fn header(self, name: &str, value: &str) -> Self {
+        let name = name.to_ascii_uppercase();
+        {
+            let mut headers = self.headers.write().unwrap();
+            headers.retain(|(existing, _)| *existing != name);
+            headers.push((name, value.to_string()));
+        }
+        self
+    }
+
+    // Sends actions one at a time, at least `min_interval` apart, for renderers
+    // that fail or reorder commands arriving concurrently. Clones of the client
+    // share the same queue.
+    pub fn serialize_actions(mut self, min_interval: Duration) -> Self {
+        self.action_queue = Some(Arc::new(Mutex::new(None)));
+        self.min_action_interval = min_interval;
+        self
+    }
+
+    // Hooks run in installation order on every SOAP request and response.
+    pub fn hook(mut self, hook: impl SoapHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    // Credentials answering Basic or Digest challenges on description fetches,
+    // SOAP calls and event subscriptions.
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        let credentials = Credentials::new(username, password);
+        self.http_client = self
+            .http_client
+            .clone()
+            .with(Authentication::new(credentials.clone()));
+        self.credentials = Some(credentials);
+        self
+    }
+
+    // Target an embedded device of the root description, by UDN or device type
+    // (e.g. "urn:schemas-upnp-org:device:MediaRenderer").
+    pub fn embedded_device(mut self, udn_or_type: &str) -> Self {
+        self.embedded_device = Some(udn_or_type.to_string());
+        self
+    }
+
     pub async fn connect(&mut self) -> Result<Self> {
-        self.device = Some(parse_location(self.base_url.as_str()).await?);
-        Ok(Self {
-            base_url: self.base_url.clone(),
-            http_client: self.http_client.clone(),
-            device: self.device.clone(),
-            stop: self.stop.clone(),
-        })
+        let root =
+            parse_location_with_limits(&self.http_client, self.base_url.as_str(), &self.limits)
+                .await?;
+        self.device = match &self.embedded_device {
+            Some(udn_or_type) => Some(
+                root.find_device(udn_or_type)
+                    .ok_or_else(|| anyhow!("Embedded device {} not found", udn_or_type))?
+                    .clone(),
+            ),
+            None => Some(root),
+        };
+        Ok(self.clone())
     }
 
+    // The description loaded by `connect`.
+    pub fn device(&self) -> Option<&Device> {
+        self.device.as_ref()
+    }
+
+    // The device host, without the brackets of IPv6 literals.
     pub fn ip(&self) -> String {
-        self.base_url.host_str().unwrap().to_string()
+        match self.base_url.host() {
+            Some(Host::Ipv6(address)) => address.to_string(),
+            _ => self.base_url.host_str().unwrap().to_string(),
+        }
+    }
+
+    // Zone ID of a link-local IPv6 device URL, e.g. "eth0" for
+    // "http://[fe80::1%eth0]:8080/".
+    pub fn zone_id(&self) -> Option<&str> {
+        self.zone_id.as_deref()
+    }
+
+    fn scope_id(&self) -> u32 {
+        self.zone_id.as_deref().map_or(0, scope_id)
+    }
+
+    // The version of the service as loaded by `connect`, e.g. 2 for a
+    // renderer advertising AVTransport:2.
+    pub fn service_version(&self, service_id: &str) -> Option<u32> {
+        self.device
+            .as_ref()?
+            .find_service(&resolve_service(service_id))?
+            .version()
+    }
+
+    // Whether the service's SCPD, as loaded by `connect`, lists the action.
+    pub fn has_action(&self, service_id: &str, action_name: &str) -> bool {
+        self.device
+            .as_ref()
+            .and_then(|device| device.find_service(&resolve_service(service_id)))
+            .is_some_and(|service| service.actions.iter().any(|a| a.name == action_name))
     }
 
     pub async fn call_action(
         &self,
         service_id: &str,
         action_name: &str,
-        params: HashMap<String, String>,
+        params: impl Into<ActionArgs>,
+    ) -> Result<String> {
+        self.call_action_until(service_id, action_name, params.into(), None)
+            .await
+    }
+
+    async fn call_action_until(
+        &self,
+        service_id: &str,
+        action_name: &str,
+        mut params: ActionArgs,
+        deadline: Option<Instant>,
     ) -> Result<String> {
         if self.device.is_none() {
             return Err(anyhow!("Device not connected"));
@@ -67,22 +339,49 @@ impl DeviceClient {
         // check if action is available
         let action = service.actions.iter().find(|a| a.name == action_name);
         match action {
-            Some(_) => {
-                self.call_action_internal(&service, action_name, params)
+            Some(action) => {
+                params.sort_for(action);
+                self.call_action_internal(&service, action_name, params, deadline)
                     .await
             }
             None => Err(anyhow!("Action not found")),
         }
     }
 
+    // Same as `call_action`, returning the parsed `*Response` element instead of
+    // the raw envelope.
+    pub async fn call_action_element(
+        &self,
+        service_id: &str,
+        action_name: &str,
+        params: impl Into<ActionArgs>,
+    ) -> Result<Element> {
+        let response = self.call_action(service_id, action_name, params).await?;
+        parse_response_element(&response)
+    }
+
+    // Aborts the call once `deadline` passes. The in-flight request is dropped
+    // with its connection, so no half-read response is left in the pool.
+    pub async fn call_action_with_deadline(
+        &self,
+        service_id: &str,
+        action_name: &str,
+        params: impl Into<ActionArgs>,
+        deadline: Instant,
+    ) -> Result<String> {
+        let call = self.call_action_until(service_id, action_name, params.into(), Some(deadline));
+        runtime::timeout_at(deadline, call)
+            .await
+            .ok_or_else(|| anyhow!("Action {} exceeded its deadline", action_name))?
+    }
+
     async fn call_action_internal(
         &self,
         service: &Service,
         action_name: &str,
-        params: HashMap<String, String>,
+        params: ActionArgs,
+        deadline: Option<Instant>,
     ) -> Result<String> {
-        let control_url = Url::parse(&service.control_url)?;
-
         let mut xml = XMLBuilder::new()
             .version(XMLVersion::XML1_1)
             .encoding("UTF-8".into())
@@ -100,9 +399,12 @@ impl DeviceClient {
         let mut action = XMLElement::new(action.as_str());
         action.add_attribute("xmlns:u", service.service_type.as_str());
 
-        for (name, value) in params {
-            let mut param = XMLElement::new(name.as_str());
-            param.add_text(value).map_err(|e| anyhow!("{:?}", e))?;
+        // xml_builder writes text as it is.
+        for (name, value) in params.iter() {
+            let mut param = XMLElement::new(name);
+            param
+                .add_text(xml::escape::escape_str_pcdata(value).into_owned())
+                .map_err(|e| anyhow!("{:?}", e))?;
             action.add_child(param).map_err(|e| anyhow!("{:?}", e))?;
         }
 
@@ -115,34 +417,435 @@ impl DeviceClient {
         xml.generate(&mut writer).map_err(|e| anyhow!("{:?}", e))?;
         let xml = String::from_utf8(writer)?;
 
-        let soap_action = format!("\"{}#{}\"", service.service_type, action_name);
+        self.send_envelope(service, action_name, &xml, deadline)
+            .await
+    }
 
-        let mut res = self
+    // Sends `arguments_xml` verbatim as the content of the action element, for
+    // vendor actions taking nested XML arguments. The action doesn't have to be
+    // listed in the service description and the raw response envelope is returned.
+    pub async fn call_action_raw(
+        &self,
+        service_id: &str,
+        action_name: &str,
+        arguments_xml: &str,
+    ) -> Result<String> {
+        if self.device.is_none() {
+            return Err(anyhow!("Device not connected"));
+        }
+        let service_id = resolve_service(service_id);
+        let service = self.get_service_description(&service_id).await?;
+
+        let xml = build_raw_envelope(&service.service_type, action_name, arguments_xml);
+        self.send_envelope(&service, action_name, &xml, None).await
+    }
+
+    // Sends a complete SOAP envelope read from `envelope`, for actions whose
+    // arguments are too large to build in memory. The body can only be read
+    // once, so hooks, retries and quirk fallbacks don't apply.
+    pub async fn call_action_streaming<R>(
+        &self,
+        service_id: &str,
+        action_name: &str,
+        envelope: R,
+        length: Option<usize>,
+    ) -> Result<String>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        if self.device.is_none() {
+            return Err(anyhow!("Device not connected"));
+        }
+        let service_id = resolve_service(service_id);
+        let service = self.get_service_description(&service_id).await?;
+        let quirks = *self.quirks.read().unwrap();
+        let soap_action = match quirks.unquoted_soap_action {
+            true => format!("{}#{}", service.service_type, action_name),
+            false => format!("\"{}#{}\"", service.service_type, action_name),
+        };
+        let content_type = match quirks.omit_content_type_charset {
+            true => "text/xml",
+            false => "text/xml; charset=\"utf-8\"",
+        };
+
+        let mut req = self
             .http_client
-            .post(control_url)
-            .header("Content-Type", "text/xml; charset=\"utf-8\"")
-            .header("Content-Length", xml.len().to_string())
+            .post(Url::parse(&service.control_url)?)
+            .header("Content-Type", content_type)
             .header("SOAPACTION", soap_action)
-            .header("Connection", "close")
-            .body_string(xml.clone())
+            .body(surf::Body::from_reader(BufReader::new(envelope), length))
+            .build();
+        req.set_ext(BodyLimit(self.limits.max_response_size));
+        let mut res = self
+            .http_client
+            .send(req)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        let content_type = res.header("Content-Type").map(|v| v.as_str().to_string());
+        let body = read_body(&mut res, self.limits.max_response_size).await?;
+        let xml = decode_xml(&body, content_type.as_deref());
+        reject_doctype(&xml)?;
+        Ok(xml)
+    }
+
+    // Uploads `body` to `url`, e.g. the importURI of a ContentDirectory
+    // ImportResource transfer, without buffering it in memory.
+    pub async fn upload_resource<R>(
+        &self,
+        url: &str,
+        body: R,
+        length: Option<usize>,
+        content_type: &str,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let res = self
+            .http_client
+            .post(Url::parse(url)?)
+            .header("Content-Type", content_type)
+            .body(surf::Body::from_reader(BufReader::new(body), length))
             .send()
             .await
             .map_err(|e| anyhow!(e.to_string()))?;
-        res.body_string().await.map_err(|e| anyhow!(e.to_string()))
+        match res.status().is_success() {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "Upload to {} failed with status {}",
+                url,
+                res.status()
+            )),
+        }
+    }
+
+    // Downloads `url` into `writer`, e.g. a ContentDirectory resource. With
+    // a non-zero `options.offset` the download resumes there, using a Range
+    // request, or skipping the start of the body when the server ignores it.
+    // Returns the number of bytes written.
+    pub async fn download_resource<W>(
+        &self,
+        url: &str,
+        writer: &mut W,
+        options: &DownloadOptions,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut req = self.http_client.get(Url::parse(url)?);
+        if options.offset > 0 {
+            req = req.header("Range", format!("bytes={}-", options.offset));
+        }
+        let mut req = req.build();
+        req.set_ext(Identity);
+        let mut res = self
+            .http_client
+            .send(req)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        if !res.status().is_success() {
+            return Err(anyhow!(
+                "Download of {} failed with status {}",
+                url,
+                res.status()
+            ));
+        }
+        let mut skip = match res.status() {
+            StatusCode::PartialContent => 0,
+            _ => options.offset,
+        };
+        let total = res.len().map(|len| len as u64 + options.offset - skip);
+
+        let started = Instant::now();
+        let mut written = 0u64;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = res.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            let skipped = (skip.min(read as u64)) as usize;
+            skip -= skipped as u64;
+            writer.write_all(&buf[skipped..read]).await?;
+            written += (read - skipped) as u64;
+
+            if let Some(progress) = &options.progress {
+                progress(options.offset + written, total);
+            }
+            if let Some(rate) = options.max_bytes_per_second.filter(|rate| *rate > 0) {
+                let due = started + Duration::from_secs_f64(written as f64 / rate as f64);
+                runtime::sleep_until(due).await;
+            }
+        }
+        writer.flush().await?;
+        Ok(written)
+    }
+
+    // The UPnP 1.0 QueryStateVariable verb, deprecated by UPnP 1.1 but still
+    // answered by many devices, including for variables no action exposes.
+    pub async fn query_state_variable(&self, service_id: &str, variable: &str) -> Result<String> {
+        if self.device.is_none() {
+            return Err(anyhow!("Device not connected"));
+        }
+        let service_id = resolve_service(service_id);
+        let service = Service {
+            service_type: "urn:schemas-upnp-org:control-1-0".to_string(),
+            ..self.get_service_description(&service_id).await?
+        };
+
+        let arguments = format!(
+            "<u:varName>{}</u:varName>",
+            xml::escape::escape_str_pcdata(variable)
+        );
+        let xml = build_raw_envelope(&service.service_type, "QueryStateVariable", &arguments);
+        let response = self
+            .send_envelope(&service, "QueryStateVariable", &xml, None)
+            .await?;
+        parse_query_state_variable(&response)
+    }
+
+    async fn send_envelope(
+        &self,
+        service: &Service,
+        action_name: &str,
+        xml: &str,
+        deadline: Option<Instant>,
+    ) -> Result<String> {
+        let queue = match &self.action_queue {
+            Some(queue) => queue,
+            None => {
+                return self
+                    .send_envelope_internal(service, action_name, xml, deadline)
+                    .await
+            }
+        };
+
+        // tokio's Mutex is fair, so queued actions are sent in call order.
+        let mut last_action = queue.lock().await;
+        if let Some(last_action) = *last_action {
+            runtime::sleep_until(last_action + self.min_action_interval).await;
+        }
+        let result = self
+            .send_envelope_internal(service, action_name, xml, deadline)
+            .await;
+        *last_action = Some(Instant::now());
+        result
+    }
+
+    async fn send_envelope_internal(
+        &self,
+        service: &Service,
+        action_name: &str,
+        xml: &str,
+        deadline: Option<Instant>,
+    ) -> Result<String> {
+        let control_url = Url::parse(&service.control_url)?;
+        let soap_action = format!("{}#{}", service.service_type, action_name);
+
+        let mut quirks = *self.quirks.read().unwrap();
+        let (mut status, mut response) = self
+            .send_soap_request_with_retries(&control_url, &soap_action, xml, quirks, deadline)
+            .await?;
+
+        // UPnP 1.0 devices may only accept the M-POST extension method.
+        if status == StatusCode::MethodNotAllowed && !quirks.use_mpost {
+            quirks.use_mpost = true;
+            (status, response) = self
+                .send_soap_request(&control_url, &soap_action, xml, quirks)
+                .await?;
+            if status.is_success() {
+                *self.quirks.write().unwrap() = quirks;
+                return Ok(response);
+            }
+        }
+
+        if !quirks.auto_retry || !needs_quirks_retry(status, &response) {
+            return Ok(response);
+        }
+
+        // The device rejected the request before reaching the action, try the
+        // other header variants and remember the first one it accepts.
+        for candidate in quirks.alternatives() {
+            let (status, retry_response) = self
+                .send_soap_request(&control_url, &soap_action, xml, candidate)
+                .await?;
+            if status.is_success() {
+                *self.quirks.write().unwrap() = candidate;
+                return Ok(retry_response);
+            }
+        }
+
+        Ok(response)
+    }
+
+    async fn send_soap_request_with_retries(
+        &self,
+        control_url: &Url,
+        soap_action: &str,
+        xml: &str,
+        quirks: Quirks,
+        deadline: Option<Instant>,
+    ) -> Result<(StatusCode, String)> {
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .send_soap_request(control_url, soap_action, xml, quirks)
+                .await;
+            let backoff = self.retry_policy.backoff * (attempt + 1);
+            let wait = match &result {
+                Err(_) if attempt >= self.retry_policy.max_retries => None,
+                Err(e) => match e.downcast_ref::<DeviceBusy>() {
+                    Some(busy) if self.retry_policy.honor_retry_after => {
+                        Some(busy.retry_after.unwrap_or(backoff))
+                    }
+                    Some(_) => None,
+                    None => Some(backoff),
+                },
+                Ok(_) => None,
+            };
+            match wait {
+                // Fail right away rather than sleep past the caller's deadline.
+                Some(wait) if deadline.is_none_or(|deadline| Instant::now() + wait < deadline) => {
+                    attempt += 1;
+                    runtime::sleep(wait).await;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    async fn send_soap_request(
+        &self,
+        control_url: &Url,
+        soap_action: &str,
+        xml: &str,
+        quirks: Quirks,
+    ) -> Result<(StatusCode, String)> {
+        let soap_action = match quirks.unquoted_soap_action {
+            true => soap_action.to_string(),
+            false => format!("\"{}\"", soap_action),
+        };
+        let content_type = match quirks.omit_content_type_charset {
+            true => "text/xml",
+            false => "text/xml; charset=\"utf-8\"",
+        };
+        let connection = match self.keep_alive {
+            true => "keep-alive",
+            false => "close",
+        };
+
+        let mut request = SoapRequest {
+            control_url: control_url.clone(),
+            soap_action: soap_action.clone(),
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("SOAPACTION".to_string(), soap_action),
+                ("Connection".to_string(), connection.to_string()),
+            ],
+            body: xml.to_string(),
+        };
+        for hook in &self.hooks {
+            hook.on_request(&mut request)?;
+        }
+
+        let (status, content_type, retry_after, body) = match quirks.use_mpost {
+            true => {
+                let mut headers: Vec<(&str, String)> = vec![
+                    (
+                        "MAN",
+                        "\"http://schemas.xmlsoap.org/soap/envelope/\"; ns=01".to_string(),
+                    ),
+                    ("Accept-Encoding", "gzip, deflate".to_string()),
+                ];
+                for (name, value) in &request.headers {
+                    match name.eq_ignore_ascii_case("SOAPACTION") {
+                        true => headers.push(("01-SOAPACTION", value.clone())),
+                        false => headers.push((name.as_str(), value.clone())),
+                    }
+                }
+                let res = self
+                    .send_hyper_request(
+                        "M-POST",
+                        request.control_url.as_str(),
+                        &headers,
+                        &request.body,
+                    )
+                    .await?;
+                let status = res.status().as_u16();
+                let content_type = res
+                    .headers()
+                    .get("Content-Type")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                let retry_after = res
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                let encoding = res
+                    .headers()
+                    .get("Content-Encoding")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.trim().to_ascii_lowercase())
+                    .filter(|encoding| encoding != "identity");
+                let max_size = self.limits.max_response_size;
+                let mut body = self
+                    .with_timeout(read_hyper_body(res.into_body(), max_size))
+                    .await??;
+                // Not going through surf, so not through Decompression either.
+                if let Some(encoding) = encoding {
+                    body = decompress(&encoding, &body, max_size)?;
+                }
+                (status, content_type, retry_after, body)
+            }
+            false => {
+                let mut req = self
+                    .http_client
+                    .post(request.control_url.clone())
+                    .header("Content-Length", request.body.len().to_string());
+                for (name, value) in &request.headers {
+                    req = req.header(name.as_str(), value.as_str());
+                }
+                let mut req = req.body_string(request.body.clone()).build();
+                req.set_ext(BodyLimit(self.limits.max_response_size));
+                let mut res = self
+                    .http_client
+                    .send(req)
+                    .await
+                    .map_err(|e| anyhow!(e.to_string()))?;
+                let content_type = res.header("Content-Type").map(|v| v.as_str().to_string());
+                let retry_after = res.header("Retry-After").map(|v| v.as_str().to_string());
+                let body = read_body(&mut res, self.limits.max_response_size).await?;
+                (res.status().into(), content_type, retry_after, body)
+            }
+        };
+
+        let mut response = SoapResponse {
+            soap_action: request.soap_action,
+            status,
+            body: decode_xml(&body, content_type.as_deref()),
+        };
+        reject_doctype(&response.body)?;
+        for hook in &self.hooks {
+            hook.on_response(&mut response)?;
+        }
+        if response.status == 503 {
+            return Err(DeviceBusy {
+                retry_after: retry_after.as_deref().and_then(parse_retry_after),
+            }
+            .into());
+        }
+        let status = StatusCode::try_from(response.status).map_err(|e| anyhow!(e.to_string()))?;
+        Ok((status, response.body))
     }
 
     async fn get_service_description(&self, service_id: &str) -> Result<Service> {
         if let Some(device) = &self.device {
-            let service = device
-                .services
-                .iter()
-                .find(|s| s.service_id == service_id)
-                .ok_or_else(|| {
-                    anyhow!(
-                        "Service with requested service_id {} does not exist",
-                        service_id
-                    )
-                })?;
+            let service = device.find_service(service_id).ok_or_else(|| {
+                anyhow!(
+                    "Service with requested service_id {} does not exist",
+                    service_id
+                )
+            })?;
             return Ok(service.clone());
         }
         Err(anyhow!("Device not connected"))
@@ -155,25 +858,24 @@ impl DeviceClient {
         let service_id = resolve_service(service_id);
         let service = self.get_service_description(&service_id).await?;
 
-        let user_agent = format!(
-            "upnp-client/{} ({})",
-            env!("CARGO_PKG_VERSION"),
-            env::consts::OS
-        );
+        let address = self.callback_address()?.ip();
+        let port = self.ensure_eventing_server(address).await?;
+        let callback = match address {
+            IpAddr::V4(address) => format!("<http://{}:{}/>", address, port),
+            IpAddr::V6(address) => format!("<http://[{}]:{}/>", address, port),
+        };
 
-        let (address, port) = self.ensure_eventing_server().await?;
-        let callback = format!("<http://{}:{}>", address, port);
-
-        let client = hyper::Client::new();
-        let req = hyper::Request::builder()
-            .method("SUBSCRIBE")
-            .uri(service.event_sub_url.clone())
-            .header("CALLBACK", callback)
-            .header("NT", "upnp:event")
-            .header("TIMEOUT", "Second-1800")
-            .header("USER-AGENT", user_agent)
-            .body(hyper::Body::empty())?;
-        client.request(req).await?;
+        self.send_hyper_request(
+            "SUBSCRIBE",
+            &service.event_sub_url,
+            &[
+                ("CALLBACK", callback),
+                ("NT", "upnp:event".to_string()),
+                ("TIMEOUT", "Second-1800".to_string()),
+            ],
+            "",
+        )
+        .await?;
         Ok(())
     }
 
@@ -183,104 +885,140 @@ impl DeviceClient {
         }
         let service_id = resolve_service(service_id);
         let service = self.get_service_description(&service_id).await?;
-        let client = hyper::Client::new();
-        let req = hyper::Request::builder()
-            .method("UNSUBSCRIBE")
-            .uri(service.event_sub_url.clone())
-            .header("SID", sid)
-            .body(hyper::Body::empty())?;
-
-        client.request(req).await?;
+        self.send_hyper_request(
+            "UNSUBSCRIBE",
+            &service.event_sub_url,
+            &[("SID", sid.to_string())],
+            "",
+        )
+        .await?;
 
         self.release_eventing_server().await?;
         Ok(())
     }
 
-    async fn ensure_eventing_server(&mut self) -> Result<(String, u16)> {
-        let addr: &str = "0.0.0.0:0";
-        let listener = TcpListener::bind(addr)?;
+    async fn send_hyper_request(
+        &self,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, String)],
+        body: &str,
+    ) -> Result<Response<Body>> {
+        // hyper's connector registers sockets with tokio's reactor and would
+        // panic outside of it.
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(anyhow!("{} requests need a tokio runtime", method));
+        }
+        let client = &self.hyper_client;
+        let build_request = |authorization: Option<String>| {
+            let mut req = hyper::Request::builder().method(method).uri(uri);
+            for (name, value) in self.headers.read().unwrap().iter() {
+                req = req.header(name.as_str(), value.as_str());
+            }
+            for (name, value) in headers {
+                req = req.header(*name, value.as_str());
+            }
+            if let Some(authorization) = authorization {
+                req = req.header("AUTHORIZATION", authorization);
+            }
+            req.body(hyper::Body::from(body.to_string()))
+        };
 
-        let service = make_service_fn(|_: &AddrStream| async {
-            Ok::<_, hyper::Error>(service_fn(|req: Request<Body>| async move {
-                let sid = req
-                    .headers()
-                    .get("sid")
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-                let body = hyper::body::to_bytes(req.into_body()).await?;
-                let xml = String::from_utf8(body.to_vec()).unwrap();
-
-                let last_change = parse_last_change(xml.as_str()).unwrap();
-                let last_change = last_change.unwrap_or_default();
-
-                let transport_state = parse_transport_state(last_change.as_str()).unwrap();
-                let play_mode = parse_current_play_mode(last_change.as_str()).unwrap();
-                let av_transport_uri_metadata =
-                    parse_av_transport_uri_metadata(last_change.as_str()).unwrap();
-                let current_track_metadata =
-                    parse_current_track_metadata(last_change.as_str()).unwrap();
-
-                if let Some(state) = transport_state {
-                    let tx = BROADCAST_EVENT.lock().unwrap();
-                    let tx = tx.as_ref();
-                    let ev = AVTransportEvent::TransportState {
-                        sid: sid.clone(),
-                        transport_state: state,
-                    };
-                    tx.unwrap().send(Event::AVTransport(ev)).unwrap();
-                }
+        let res = self
+            .with_timeout(client.request(build_request(None)?))
+            .await??;
+        let challenge = res
+            .headers()
+            .get("WWW-Authenticate")
+            .and_then(|challenge| challenge.to_str().ok());
+        match (&self.credentials, challenge) {
+            (Some(credentials), Some(challenge)) if res.status() == 401 => {
+                let authorization = credentials.authorization(
+                    challenge,
+                    method,
+                    &request_target(&Url::parse(uri)?),
+                )?;
+                let request = build_request(Some(authorization))?;
+                Ok(self.with_timeout(client.request(request)).await??)
+            }
+            _ => Ok(res),
+        }
+    }
 
-                if let Some(mode) = play_mode {
-                    let tx = BROADCAST_EVENT.lock().unwrap();
-                    let tx = tx.as_ref();
-                    let ev = AVTransportEvent::CurrentPlayMode {
-                        sid: sid.clone(),
-                        play_mode: mode,
-                    };
-                    tx.unwrap().send(Event::AVTransport(ev)).unwrap();
-                }
+    // hyper requests get the same timeout as the surf client.
+    async fn with_timeout<F: Future>(&self, future: F) -> Result<F::Output> {
+        let deadline = self
+            .timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        match deadline {
+            Some(deadline) => runtime::timeout_at(deadline, future)
+                .await
+                .ok_or_else(|| anyhow!("Request to the device timed out")),
+            None => Ok(future.await),
+        }
+    }
 
-                if let Some(metadata) = av_transport_uri_metadata {
-                    let tx = BROADCAST_EVENT.lock().unwrap();
-                    let tx = tx.as_ref();
-                    let m = deserialize_metadata(metadata.as_str()).unwrap();
-                    let ev = AVTransportEvent::AVTransportURIMetaData {
-                        sid: sid.clone(),
-                        url: m.url,
-                        title: m.title,
-                        artist: m.artist,
-                        album: m.album,
-                        album_art_uri: m.album_art_uri,
-                        genre: m.genre,
-                    };
-                    tx.unwrap().send(Event::AVTransport(ev)).unwrap();
-                }
+    // The local address the device can reach us on: the bound interface, or
+    // whichever one the OS routes to the device through. Link-local addresses
+    // keep the scope of the device's zone.
+    fn callback_address(&self) -> Result<SocketAddr> {
+        if let Some(local_address) = self.local_address {
+            return Ok(match local_address {
+                IpAddr::V6(address) => SocketAddrV6::new(address, 0, 0, self.scope_id()).into(),
+                address => (address, 0).into(),
+            });
+        }
+        let port = self.base_url.port_or_known_default().unwrap_or(80);
+        let device: SocketAddr = match self.base_url.host() {
+            Some(Host::Ipv4(address)) => (address, port).into(),
+            Some(Host::Ipv6(address)) => {
+                SocketAddrV6::new(address, port, 0, self.scope_id()).into()
+            }
+            _ => self
+                .base_url
+                .socket_addrs(|| Some(port))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Could not resolve {}", self.base_url))?,
+        };
+        let unspecified: SocketAddr = match device {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(unspecified)?;
+        socket.connect(device)?;
+        Ok(socket.local_addr()?)
+    }
 
-                if let Some(metadata) = current_track_metadata {
-                    let m = deserialize_metadata(metadata.as_str()).unwrap();
-                    let tx = BROADCAST_EVENT.lock().unwrap();
-                    let tx = tx.as_ref();
-                    let ev = AVTransportEvent::CurrentTrackMetadata {
-                        sid: sid.clone(),
-                        url: m.url,
-                        title: m.title,
-                        artist: m.artist,
-                        album: m.album,
-                        album_art_uri: m.album_art_uri,
-                        genre: m.genre,
-                    };
-                    tx.unwrap().send(Event::AVTransport(ev)).unwrap();
-                }
+    async fn ensure_eventing_server(&mut self, address: IpAddr) -> Result<u16> {
+        // Like hyper's client, its server runs on tokio's reactor.
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(anyhow!("Event subscriptions need a tokio runtime"));
+        }
+        let unspecified: SocketAddr = match address {
+            IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let listener = TcpListener::bind(unspecified)?;
 
-                Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        let max_size = self.limits.max_response_size;
+        let service = make_service_fn(move |_: &AddrStream| async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| async move {
+                let status = match read_notify(req, max_size).await {
+                    Ok(events) => {
+                        events.into_iter().for_each(broadcast);
+                        hyper::StatusCode::OK
+                    }
+                    Err(status) => status,
+                };
+                let mut res = Response::new(Body::empty());
+                *res.status_mut() = status;
+                Ok::<_, hyper::Error>(res)
             }))
         });
 
-        let server = Server::from_tcp(listener).unwrap().serve(service);
+        let server = Server::from_tcp(listener)?.serve(service);
 
-        let address = server.local_addr().ip().to_string();
         let port = server.local_addr().port();
 
         let stop = self.stop.clone();
@@ -295,7 +1033,7 @@ impl DeviceClient {
             }
         });
 
-        Ok((address, port))
+        Ok(port)
     }
 
     async fn release_eventing_server(&mut self) -> Result<()> {
@@ -305,9 +1043,1092 @@ impl DeviceClient {
     }
 }
 
+// Events nobody is subscribed to, or whose stream was dropped, are lost.
+fn broadcast(event: Event) {
+    if let Some(tx) = BROADCAST_EVENT.lock().unwrap().as_ref() {
+        let _ = tx.send(event);
+    }
+}
+
+fn build_raw_envelope(service_type: &str, action_name: &str, arguments_xml: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+            r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:{action} xmlns:u="{service_type}">{arguments}</u:{action}>"#,
+            r#"</s:Body></s:Envelope>"#
+        ),
+        action = action_name,
+        service_type = service_type,
+        arguments = arguments_xml
+    )
+}
+
+// The events of a GENA NOTIFY, or the status to answer it with. Any host on
+// the LAN can send one, so nothing in it is trusted.
+async fn read_notify(req: Request<Body>, max_size: usize) -> Result<Vec<Event>, hyper::StatusCode> {
+    let sid = req
+        .headers()
+        .get("sid")
+        .and_then(|sid| sid.to_str().ok())
+        .map(str::to_string)
+        .ok_or(hyper::StatusCode::PRECONDITION_FAILED)?;
+    let body = read_hyper_body(req.into_body(), max_size)
+        .await
+        .map_err(|_| hyper::StatusCode::PAYLOAD_TOO_LARGE)?;
+    notify_events(&sid, body).map_err(|_| hyper::StatusCode::BAD_REQUEST)
+}
+
+fn notify_events(sid: &str, body: Vec<u8>) -> Result<Vec<Event>> {
+    let xml = String::from_utf8(body)?;
+    reject_doctype(&xml)?;
+    let mut events = vec![];
+
+    let properties = parse_property_set(&xml)?;
+    let last_change = parse_last_change(&xml)?;
+    // OpenHome and Sonos services event their variables as they are.
+    if last_change.is_none() {
+        for (name, value) in properties {
+            if let Some(ev) = openhome_event(sid, &name, &value) {
+                events.push(Event::OpenHome(ev));
+            }
+            if let Some(ev) = sonos_event(sid, &name, &value) {
+                events.push(Event::Sonos(ev));
+            }
+        }
+    }
+    let last_change = last_change.unwrap_or_default();
+    let sid = sid.to_string();
+
+    if let Some(state) = parse_transport_state(&last_change)? {
+        let ev = AVTransportEvent::TransportState {
+            sid: sid.clone(),
+            transport_state: state,
+        };
+        events.push(Event::AVTransport(ev));
+    }
+
+    if let Some(mode) = parse_current_play_mode(&last_change)? {
+        let ev = AVTransportEvent::CurrentPlayMode {
+            sid: sid.clone(),
+            play_mode: mode,
+        };
+        events.push(Event::AVTransport(ev));
+    }
+
+    if let Some(metadata) = parse_av_transport_uri_metadata(&last_change)? {
+        let m = deserialize_metadata(&metadata)?;
+        let ev = AVTransportEvent::AVTransportURIMetaData {
+            sid: sid.clone(),
+            url: m.url,
+            title: m.title,
+            artist: m.artist,
+            album: m.album,
+            album_art_uri: m.album_art_uri,
+            genre: m.genre,
+        };
+        events.push(Event::AVTransport(ev));
+    }
+
+    if let Some(metadata) = parse_current_track_metadata(&last_change)? {
+        let m = deserialize_metadata(&metadata)?;
+        let ev = AVTransportEvent::CurrentTrackMetadata {
+            sid,
+            url: m.url,
+            title: m.title,
+            artist: m.artist,
+            album: m.album,
+            album_art_uri: m.album_art_uri,
+            genre: m.genre,
+        };
+        events.push(Event::AVTransport(ev));
+    }
+
+    Ok(events)
+}
+
+pub struct DeviceClientBuilder {
+    url: String,
+    http_client: Option<Client>,
+    timeout: Option<Duration>,
+    tls_config: Option<ClientConfig>,
+    local_address: Option<IpAddr>,
+    keep_alive: bool,
+    quirks: Quirks,
+    retry_policy: RetryPolicy,
+    headers: Vec<(String, String)>,
+    credentials: Option<Credentials>,
+    embedded_device: Option<String>,
+    hooks: Vec<Arc<dyn SoapHook>>,
+    min_action_interval: Option<Duration>,
+    limits: Limits,
+}
+
+impl DeviceClientBuilder {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            http_client: None,
+            timeout: Some(Duration::from_secs(5)),
+            tls_config: None,
+            local_address: None,
+            keep_alive: true,
+            quirks: Quirks::default(),
+            retry_policy: RetryPolicy::default(),
+            headers: vec![],
+            credentials: None,
+            embedded_device: None,
+            hooks: vec![],
+            min_action_interval: None,
+            limits: Limits::default(),
+        }
+    }
+
+    // Takes precedence over timeout, TLS and interface binding options.
+    pub fn http_client(mut self, http_client: Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn tls_config(mut self, tls_config: ClientConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    pub fn local_address(mut self, local_address: IpAddr) -> Self {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn user_agent(self, user_agent: &str) -> Self {
+        self.header("USER-AGENT", user_agent)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some(Credentials::new(username, password));
+        self
+    }
+
+    pub fn embedded_device(mut self, udn_or_type: &str) -> Self {
+        self.embedded_device = Some(udn_or_type.to_string());
+        self
+    }
+
+    pub fn hook(mut self, hook: impl SoapHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub fn serialize_actions(mut self, min_interval: Duration) -> Self {
+        self.min_action_interval = Some(min_interval);
+        self
+    }
+
+    pub fn build(self) -> Result<DeviceClient> {
+        let (_, zone_id) = split_zone_id(&self.url);
+        let http_client = match self.http_client {
+            Some(http_client) => http_client,
+            None if self.tls_config.is_some()
+                || self.local_address.is_some()
+                || zone_id.is_some() =>
+            {
+                let tls_config = self.tls_config.clone().unwrap_or_else(tls::default_config);
+                let scope_id = zone_id.as_deref().map_or(0, scope_id);
+                let transport =
+                    HttpsClient::with_scope_id(tls_config, self.local_address, scope_id)
+                        .keep_alive(self.keep_alive)
+                        .timeout(self.timeout.unwrap_or(Duration::MAX));
+                Config::new()
+                    .set_timeout(self.timeout)
+                    .set_http_client(transport)
+                    .try_into()?
+            }
+            None => Config::new()
+                .set_timeout(self.timeout)
+                .set_http_keep_alive(self.keep_alive)
+                .try_into()?,
+        };
+
+        let mut device_client = DeviceClient::with_http_client(&self.url, http_client)?
+            .keep_alive(self.keep_alive)
+            .quirks(self.quirks)
+            .retry_policy(self.retry_policy)
+            .limits(self.limits);
+        if let Some(tls_config) = self.tls_config {
+            device_client.tls_config = Arc::new(tls_config);
+        }
+        device_client.local_address = self.local_address;
+        device_client.update_hyper_client();
+        device_client.timeout = self.timeout;
+        for (name, value) in &self.headers {
+            device_client = device_client.header(name, value);
+        }
+        if let Some(credentials) = &self.credentials {
+            device_client = device_client.credentials(&credentials.username, &credentials.password);
+        }
+        if let Some(udn_or_type) = &self.embedded_device {
+            device_client = device_client.embedded_device(udn_or_type);
+        }
+        device_client.hooks = self.hooks;
+        if let Some(min_interval) = self.min_action_interval {
+            device_client = device_client.serialize_actions(min_interval);
+        }
+        Ok(device_client)
+    }
+}
+
+struct RequestHeaders(Arc<RwLock<Vec<(String, String)>>>);
+
+#[surf::utils::async_trait]
+impl Middleware for RequestHeaders {
+    async fn handle(
+        &self,
+        mut req: surf::Request,
+        client: Client,
+        next: Next<'_>,
+    ) -> surf::Result<surf::Response> {
+        for (name, value) in self.0.read().unwrap().iter() {
+            req.insert_header(name.as_str(), value.as_str());
+        }
+        next.run(req, client).await
+    }
+}
+
+// 400s, and 500s that don't carry a UPnP fault, mean the device couldn't make
+// sense of the request headers rather than refusing the action itself.
+fn needs_quirks_retry(status: StatusCode, response: &str) -> bool {
+    match status {
+        StatusCode::BadRequest => true,
+        StatusCode::InternalServerError => !response.contains("UPnPError"),
+        _ => false,
+    }
+}
+
+// Retry-After holds either delay-seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let mut headers = surf::http::Response::new(StatusCode::ServiceUnavailable);
+    headers.insert_header("Retry-After", value);
+    RetryAfter::from_headers(&headers)
+        .ok()??
+        .duration_since(SystemTime::now())
+        .ok()
+}
+
 fn resolve_service(service_id: &str) -> String {
     match service_id.contains(':') {
         true => service_id.to_string(),
         false => format!("urn:upnp-org:serviceId:{}", service_id),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DeviceClient;
+    use crate::{
+        action::ActionArgs,
+        hooks::{SoapHook, SoapRequest, SoapResponse},
+        parser::{parse_action_response, scope_id},
+        testing::{serve, serve_device, soap_response, TestResponse},
+        tls::{self, HttpsClient},
+        types::{DeviceBusy, DownloadOptions, Limits, Quirks, RetryPolicy},
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use hyper::{
+        server::conn::AddrStream,
+        service::{make_service_fn, service_fn},
+        Body, Request, Server,
+    };
+    use std::{
+        collections::HashMap,
+        io::Write,
+        net::{Ipv4Addr, SocketAddr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+    use surf::{Client, Url};
+
+    #[tokio::test]
+    async fn test_decompressing_mpost_responses() {
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], |request| {
+            if request.method != "M-POST" {
+                return TestResponse::status(405);
+            }
+            assert!(request.header("Accept-Encoding").unwrap().contains("gzip"));
+            let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+            gzip.write_all(soap_response("GetVolume", &[("CurrentVolume", "12")]).as_bytes())
+                .unwrap();
+            let mut response = TestResponse::status(200)
+                .header("Content-Type", "text/xml")
+                .header("Content-Encoding", "gzip");
+            response.body = gzip.finish().unwrap();
+            response
+        });
+        let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let params = HashMap::from([("InstanceID".to_string(), "0".to_string())]);
+        let response = device_client
+            .call_action("RenderingControl", "GetVolume", params)
+            .await
+            .unwrap();
+        assert!(response.contains("<CurrentVolume>12</CurrentVolume>"));
+    }
+
+    #[tokio::test]
+    async fn test_reusing_connections_for_actions() {
+        for keep_alive in [true, false] {
+            let requests = Arc::new(Mutex::new(vec![]));
+            let log = requests.clone();
+            let url = serve_device(&["RenderingControl"], &["GetVolume"], move |request| {
+                log.lock().unwrap().push(request.connection);
+                TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")]))
+            });
+
+            let device_client = DeviceClient::new(&url)
+                .unwrap()
+                .keep_alive(keep_alive)
+                .connect()
+                .await
+                .unwrap();
+            for _ in 0..3 {
+                let params = HashMap::from([("InstanceID".to_string(), "0".to_string())]);
+                device_client
+                    .call_action("RenderingControl", "GetVolume", params)
+                    .await
+                    .unwrap();
+            }
+
+            let mut connections = requests.lock().unwrap().clone();
+            connections.dedup();
+            match keep_alive {
+                true => assert_eq!(connections.len(), 1),
+                false => assert_eq!(connections.len(), 3),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_soap_header_quirks_retry() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], move |request| {
+            let soap_action = request.header("SOAPACTION").unwrap().to_string();
+            let quoted = soap_action.starts_with('"');
+            log.lock().unwrap().push(soap_action);
+            match quoted {
+                true => TestResponse::status(400),
+                false => TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")])),
+            }
+        });
+
+        let device_client = DeviceClient::new(&url)
+            .unwrap()
+            .quirks(Quirks {
+                auto_retry: true,
+                ..Default::default()
+            })
+            .connect()
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            let params = HashMap::from([("InstanceID".to_string(), "0".to_string())]);
+            let response = device_client
+                .call_action("RenderingControl", "GetVolume", params)
+                .await
+                .unwrap();
+            assert!(response.contains("<CurrentVolume>12</CurrentVolume>"));
+        }
+
+        // The accepted variant is remembered for the next calls.
+        let action = "urn:schemas-upnp-org:service:RenderingControl:1#GetVolume";
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                format!("\"{}\"", action),
+                action.to_string(),
+                action.to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_request_headers() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], move |request| {
+            log.lock().unwrap().push((
+                request.header("User-Agent").map(str::to_string),
+                request.header("transferMode.dlna.org").map(str::to_string),
+            ));
+            TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")]))
+        });
+
+        let default = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let custom = DeviceClient::new(&url)
+            .unwrap()
+            .user_agent("Renderer-Compatible/1.0")
+            .header("transferMode.dlna.org", "Streaming")
+            .connect()
+            .await
+            .unwrap();
+        for device_client in [default, custom] {
+            let params = HashMap::from([("InstanceID".to_string(), "0".to_string())]);
+            device_client
+                .call_action("RenderingControl", "GetVolume", params)
+                .await
+                .unwrap();
+        }
+
+        let seen = seen.lock().unwrap();
+        assert!(seen[0].0.as_deref().unwrap().starts_with("upnp-client/"));
+        assert_eq!(seen[0].1, None);
+        assert_eq!(seen[1].0.as_deref(), Some("Renderer-Compatible/1.0"));
+        assert_eq!(seen[1].1.as_deref(), Some("Streaming"));
+    }
+
+    #[derive(Debug)]
+    struct RecordingClient(HttpsClient, Arc<Mutex<Vec<String>>>);
+
+    #[http_client::async_trait]
+    impl http_client::HttpClient for RecordingClient {
+        async fn send(
+            &self,
+            req: http_client::Request,
+        ) -> Result<http_client::Response, http_client::Error> {
+            let request = format!("{} {}", req.method(), req.url().path());
+            self.1.lock().unwrap().push(request);
+            self.0.send(req).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_transport() {
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], |_| {
+            TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")]))
+        });
+        let requests = Arc::new(Mutex::new(vec![]));
+        let transport = RecordingClient(HttpsClient::new(tls::default_config()), requests.clone());
+
+        let device_client =
+            DeviceClient::with_http_client(&url, Client::with_http_client(transport))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+        let params = ActionArgs::new().arg("InstanceID", 0);
+        device_client
+            .call_action("RenderingControl", "GetVolume", params)
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "GET /description.xml",
+                "GET /RenderingControl/scpd.xml",
+                "POST /RenderingControl/control",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replacing_request_headers() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], move |request| {
+            log.lock()
+                .unwrap()
+                .push(request.header("X-Zone").map(str::to_string));
+            TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")]))
+        });
+
+        async_io::block_on(async {
+            let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let clone = device_client.clone();
+            let device_client = (0..100).fold(device_client, |device_client, zone| {
+                device_client.header("X-Zone", &zone.to_string())
+            });
+            for device_client in [device_client, clone] {
+                let params = ActionArgs::new().arg("InstanceID", 0);
+                device_client
+                    .call_action("RenderingControl", "GetVolume", params)
+                    .await
+                    .unwrap();
+            }
+        });
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![Some("99".to_string()), Some("99".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calling_raw_actions() {
+        // X_SetLayout isn't in the SCPD, and its argument is nested XML.
+        let url = serve_device(&["RenderingControl"], &[], |request| {
+            assert_eq!(request.soap_action(), Some("X_SetLayout"));
+            assert!(request
+                .body
+                .contains("<u:X_SetLayout xmlns:u=\"urn:schemas-upnp-org:service:RenderingControl:1\"><Layout><Zone id=\"1\"/></Layout></u:X_SetLayout>"));
+            TestResponse::ok(soap_response("X_SetLayout", &[("Applied", "1")]))
+        });
+
+        let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let response = device_client
+            .call_action_raw(
+                "RenderingControl",
+                "X_SetLayout",
+                r#"<Layout><Zone id="1"/></Layout>"#,
+            )
+            .await
+            .unwrap();
+        assert!(response.contains("<s:Envelope"));
+        assert!(response.contains("<Applied>1</Applied>"));
+    }
+
+    #[tokio::test]
+    async fn test_soap_hooks() {
+        struct Rewrite;
+        impl SoapHook for Rewrite {
+            fn on_request(&self, request: &mut SoapRequest) -> anyhow::Result<()> {
+                request
+                    .headers
+                    .push(("X-Trace".to_string(), request.soap_action.clone()));
+                Ok(())
+            }
+
+            fn on_response(&self, response: &mut SoapResponse) -> anyhow::Result<()> {
+                response.body = response.body.replace("secret", "[redacted]");
+                Ok(())
+            }
+        }
+
+        struct Reject;
+        impl SoapHook for Reject {
+            fn on_request(&self, _: &mut SoapRequest) -> anyhow::Result<()> {
+                Err(anyhow::anyhow!("rejected by hook"))
+            }
+        }
+
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], |request| {
+            assert!(request.header("X-Trace").unwrap().ends_with("#GetVolume\""));
+            TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "secret")]))
+        });
+
+        let params = || HashMap::from([("InstanceID".to_string(), "0".to_string())]);
+        let device_client = DeviceClient::new(&url)
+            .unwrap()
+            .hook(Rewrite)
+            .connect()
+            .await
+            .unwrap();
+        let response = device_client
+            .call_action("RenderingControl", "GetVolume", params())
+            .await
+            .unwrap();
+        assert!(response.contains("<CurrentVolume>[redacted]</CurrentVolume>"));
+
+        let device_client = device_client.hook(Reject);
+        let error = device_client
+            .call_action("RenderingControl", "GetVolume", params())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "rejected by hook");
+    }
+
+    #[tokio::test]
+    async fn test_action_deadlines() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let url = serve_device(&["AVTransport"], &["Seek"], move |_| {
+            // The first seek hangs well past the deadline.
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            TestResponse::ok(soap_response("Seek", &[]))
+        });
+
+        let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let seek = || {
+            HashMap::from([
+                ("InstanceID".to_string(), "0".to_string()),
+                ("Unit".to_string(), "REL_TIME".to_string()),
+                ("Target".to_string(), "00:01:00".to_string()),
+            ])
+        };
+
+        let started = Instant::now();
+        let error = device_client
+            .call_action_with_deadline(
+                "AVTransport",
+                "Seek",
+                seek(),
+                started + Duration::from_millis(200),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("exceeded its deadline"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // The abandoned request doesn't get in the way of the next one.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        device_client
+            .call_action_with_deadline("AVTransport", "Seek", seek(), deadline)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serialized_actions() {
+        let arrivals = Arc::new(Mutex::new(vec![]));
+        let log = arrivals.clone();
+        let url = serve_device(&["AVTransport"], &["Play"], move |request| {
+            let instance = request.argument("InstanceID").unwrap();
+            log.lock().unwrap().push((instance, Instant::now()));
+            TestResponse::ok(soap_response("Play", &[]))
+        });
+
+        let device_client = DeviceClient::new(&url)
+            .unwrap()
+            .serialize_actions(Duration::from_millis(100))
+            .connect()
+            .await
+            .unwrap();
+        let play = |instance: u32| {
+            let device_client = device_client.clone();
+            async move {
+                let params = HashMap::from([
+                    ("InstanceID".to_string(), instance.to_string()),
+                    ("Speed".to_string(), "1".to_string()),
+                ]);
+                device_client
+                    .call_action("AVTransport", "Play", params)
+                    .await
+            }
+        };
+        let (first, second, third) = futures_util::join!(play(0), play(1), play(2));
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+
+        let arrivals = arrivals.lock().unwrap();
+        let order: Vec<_> = arrivals
+            .iter()
+            .map(|(instance, _)| instance.as_str())
+            .collect();
+        assert_eq!(order, ["0", "1", "2"]);
+        for pair in arrivals.windows(2) {
+            assert!(pair[1].1 - pair[0].1 >= Duration::from_millis(90));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reusing_hyper_connections() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let service = make_service_fn(move |_: &AddrStream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {
+                Ok::<_, hyper::Error>(service_fn(|_: Request<Body>| async {
+                    let body = soap_response("Play", &[]);
+                    Ok::<_, hyper::Error>(hyper::Response::new(Body::from(body)))
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}/control", server.local_addr());
+        tokio::spawn(server);
+
+        for (keep_alive, expected) in [(true, 2), (false, 4)] {
+            connections.store(0, Ordering::SeqCst);
+            let device_client = DeviceClient::builder(&url)
+                .local_address(Ipv4Addr::LOCALHOST.into())
+                .keep_alive(keep_alive)
+                .build()
+                .unwrap();
+            // Two POSTs through the surf transport, two M-POSTs through hyper.
+            for use_mpost in [false, false, true, true] {
+                let quirks = Quirks {
+                    use_mpost,
+                    ..Default::default()
+                };
+                device_client
+                    .send_soap_request(
+                        &Url::parse(&url).unwrap(),
+                        "urn:schemas-upnp-org:service:AVTransport:1#Play",
+                        "<Play/>",
+                        quirks,
+                    )
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hyper_request_timeout() {
+        // Accepts connections into its backlog but never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/control", listener.local_addr().unwrap());
+        let device_client = DeviceClient::builder(&url)
+            .timeout(Some(Duration::from_millis(200)))
+            .build()
+            .unwrap();
+        let quirks = Quirks {
+            use_mpost: true,
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let result = device_client
+            .send_soap_request(
+                &Url::parse(&url).unwrap(),
+                "urn:schemas-upnp-org:service:AVTransport:1#Play",
+                "<Play/>",
+                quirks,
+            )
+            .await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+    #[tokio::test]
+    async fn test_rejecting_malformed_notifications() {
+        let mut device_client = DeviceClient::new("http://127.0.0.1:1/desc.xml")
+            .unwrap()
+            .limits(Limits {
+                max_response_size: 1024,
+                ..Default::default()
+            });
+        let port = device_client
+            .ensure_eventing_server(Ipv4Addr::LOCALHOST.into())
+            .await
+            .unwrap();
+        let notify = |sid: Option<&'static str>, body: Vec<u8>| async move {
+            let mut request = Request::builder()
+                .method("NOTIFY")
+                .uri(format!("http://127.0.0.1:{}/", port));
+            if let Some(sid) = sid {
+                request = request.header("SID", sid);
+            }
+            let request = request.body(Body::from(body)).unwrap();
+            hyper::Client::new()
+                .request(request)
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        };
+        let property_set = |variable: &str| {
+            format!(
+                concat!(
+                    r#"<?xml version="1.0"?><e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">"#,
+                    "<e:property>{}</e:property></e:propertyset>"
+                ),
+                variable
+            )
+            .into_bytes()
+        };
+
+        let sid = Some("uuid:1");
+        assert_eq!(notify(sid, property_set("<Shuffle>0</Shuffle>")).await, 200);
+        assert_eq!(
+            notify(None, property_set("<Shuffle>0</Shuffle>")).await,
+            412
+        );
+        assert_eq!(notify(sid, property_set(&"a".repeat(2048))).await, 413);
+        let doctype = [
+            b"<!DOCTYPE x [<!ENTITY e \"e\">]>".to_vec(),
+            property_set(""),
+        ]
+        .concat();
+        assert_eq!(notify(sid, doctype).await, 400);
+        assert_eq!(notify(sid, vec![0xff, 0xfe]).await, 400);
+        assert_eq!(
+            notify(sid, b"<e:propertyset><e:property>".to_vec()).await,
+            400
+        );
+    }
+
+    #[test]
+    fn test_downloads_skip_decompression() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&[7; 1024]).unwrap();
+        let archive = gzip.finish().unwrap();
+        let served = archive.clone();
+        let url = serve(move |request| {
+            assert_eq!(request.header("Accept-Encoding"), Some("identity"));
+            let start = request
+                .header("Range")
+                .map(|range| range[6..range.len() - 1].parse().unwrap())
+                .unwrap_or(0);
+            TestResponse::bytes(served[start..].to_vec())
+                .status_code(if start > 0 { 206 } else { 200 })
+                .header("Content-Encoding", "gzip")
+        });
+        let url = format!("{}/archive.gz", url);
+
+        async_io::block_on(async {
+            let device_client = DeviceClient::new(&url).unwrap();
+            let mut full = vec![];
+            device_client
+                .download_resource(&url, &mut full, &DownloadOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(full, archive);
+
+            let mut rest = vec![];
+            let options = DownloadOptions {
+                offset: 10,
+                ..Default::default()
+            };
+            device_client
+                .download_resource(&url, &mut rest, &options)
+                .await
+                .unwrap();
+            assert_eq!(rest, archive[10..]);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_downloading_through_https_client() {
+        // Bigger than any response is allowed to be.
+        let file = vec![7; Limits::default().max_response_size + 1];
+        let served = file.clone();
+        let url = serve(move |_| TestResponse::bytes(served.clone()));
+        let url = format!("{}/movie.mkv", url);
+
+        // Binding to an interface selects the hyper transport.
+        let device_client = DeviceClient::builder(&url)
+            .local_address(Ipv4Addr::LOCALHOST.into())
+            .timeout(None)
+            .build()
+            .unwrap();
+        let mut sink = vec![];
+        device_client
+            .download_resource(&url, &mut sink, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert!(sink == file);
+    }
+
+    #[tokio::test]
+    async fn test_escaping_argument_values() {
+        let url = serve_device(&["ContentDirectory"], &["Search"], |request| {
+            assert!(request.body.contains("Rock &amp; Roll &lt;Live&gt;"));
+            let criteria = request.argument("SearchCriteria").unwrap();
+            TestResponse::ok(soap_response("Search", &[("Result", &criteria)]))
+        });
+        let value = r#"dc:title = "Rock & Roll <Live>""#;
+        let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let response = device_client
+            .call_action(
+                "ContentDirectory",
+                "Search",
+                ActionArgs::new().arg("SearchCriteria", value),
+            )
+            .await
+            .unwrap();
+        let values = parse_action_response(&response).unwrap();
+        assert_eq!(values["Result"], value);
+    }
+
+    #[test]
+    fn test_calling_actions_without_tokio_runtime() {
+        let url = serve_busy_device(0, "");
+        let response = async_io::block_on(async {
+            let device_client = DeviceClient::new(&url)?.connect().await?;
+            device_client
+                .call_action(
+                    "RenderingControl",
+                    "GetVolume",
+                    ActionArgs::new().arg("InstanceID", 0),
+                )
+                .await
+        })
+        .unwrap();
+        assert!(response.contains("<CurrentVolume>12</CurrentVolume>"));
+    }
+
+    #[test]
+    fn test_mpost_fallback_without_tokio_runtime() {
+        let url = serve_device(&["RenderingControl"], &["GetVolume"], |_| {
+            TestResponse::status(405)
+        });
+        let result = async_io::block_on(async {
+            let device_client = DeviceClient::new(&url)?.connect().await?;
+            device_client
+                .call_action(
+                    "RenderingControl",
+                    "GetVolume",
+                    ActionArgs::new().arg("InstanceID", 0),
+                )
+                .await
+        });
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("tokio runtime"), "{}", error);
+    }
+
+    #[test]
+    fn test_subscribing_without_tokio_runtime() {
+        let url = serve_device(&["AVTransport"], &[], |_| TestResponse::status(200));
+        let result = async_io::block_on(async {
+            let mut device_client = DeviceClient::new(&url)?.connect().await?;
+            device_client.subscribe("AVTransport").await
+        });
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("tokio runtime"), "{}", error);
+    }
+
+    #[test]
+    fn test_zone_id_client_without_tokio_runtime() {
+        // Only the hyper transport can scope connections to the zone.
+        let result = async_io::block_on(async {
+            DeviceClient::new("http://[fe80::1%eth0]:8080/desc.xml")?
+                .connect()
+                .await
+        });
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("tokio runtime"), "{}", error);
+    }
+
+    #[test]
+    fn test_ipv6_literal_device_urls() {
+        let device_client = DeviceClient::new("http://[fe80::1%eth0]:8080/desc.xml").unwrap();
+        assert_eq!(device_client.ip(), "fe80::1");
+        assert_eq!(device_client.zone_id(), Some("eth0"));
+        assert_eq!(
+            device_client.base_url.as_str(),
+            "http://[fe80::1]:8080/desc.xml"
+        );
+
+        let device_client = DeviceClient::new("http://127.0.0.1:8080/desc.xml").unwrap();
+        assert_eq!(
+            device_client.callback_address().unwrap().ip().to_string(),
+            "127.0.0.1"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_link_local_callback_address_scope() {
+        // Any interface with a link-local address will do.
+        let interfaces = std::fs::read_to_string("/proc/net/if_inet6").unwrap_or_default();
+        let Some(interface) = interfaces
+            .lines()
+            .filter(|line| line.starts_with("fe80"))
+            .filter_map(|line| line.split_whitespace().last())
+            .next()
+        else {
+            return;
+        };
+        let url = format!("http://[fe80::1%{}]:8080/desc.xml", interface);
+        let device_client = DeviceClient::new(&url).unwrap();
+        match device_client.callback_address().unwrap() {
+            SocketAddr::V6(address) => {
+                assert_eq!(address.ip().segments()[0], 0xfe80);
+                assert_eq!(address.scope_id(), scope_id(interface));
+                assert_ne!(address.scope_id(), 0);
+            }
+            address => panic!("expected a link-local address, got {}", address),
+        }
+    }
+
+    // The first `busy` control requests are answered 503 with `retry_after`.
+    fn serve_busy_device(mut busy: usize, retry_after: &'static str) -> String {
+        serve_device(&["RenderingControl"], &["GetVolume"], move |_| match busy {
+            0 => TestResponse::ok(soap_response("GetVolume", &[("CurrentVolume", "12")])),
+            _ => {
+                busy -= 1;
+                TestResponse::status(503).header("Retry-After", retry_after)
+            }
+        })
+    }
+
+    #[test]
+    fn test_busy_devices() {
+        let get_volume = |device_client: DeviceClient, deadline: Option<Instant>| async move {
+            let params = ActionArgs::new().arg("InstanceID", 0);
+            match deadline {
+                Some(deadline) => {
+                    device_client
+                        .call_action_with_deadline(
+                            "RenderingControl",
+                            "GetVolume",
+                            params,
+                            deadline,
+                        )
+                        .await
+                }
+                None => {
+                    device_client
+                        .call_action("RenderingControl", "GetVolume", params)
+                        .await
+                }
+            }
+        };
+        let policy = RetryPolicy {
+            max_retries: 2,
+            honor_retry_after: true,
+            ..Default::default()
+        };
+
+        async_io::block_on(async {
+            let url = serve_busy_device(1, "120");
+            let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let err = get_volume(device_client, None).await.unwrap_err();
+            let busy = err.downcast_ref::<DeviceBusy>().unwrap();
+            assert!(busy.retry_after.unwrap() > Duration::from_secs(100));
+
+            // Waiting two minutes would overrun the deadline, fail right away.
+            let url = serve_busy_device(1, "120");
+            let device_client = DeviceClient::new(&url)
+                .unwrap()
+                .retry_policy(policy)
+                .connect()
+                .await
+                .unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let err = get_volume(device_client, Some(deadline)).await.unwrap_err();
+            assert!(err.downcast_ref::<DeviceBusy>().is_some());
+
+            let url = serve_busy_device(2, "0");
+            let device_client = DeviceClient::new(&url)
+                .unwrap()
+                .retry_policy(policy)
+                .connect()
+                .await
+                .unwrap();
+            assert!(get_volume(device_client, None).await.is_ok());
+        });
+    }
+}
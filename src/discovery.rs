@@ -4,9 +4,9 @@ use futures_util::Stream;
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::str;
-use tokio::net::UdpSocket;
 
 use crate::parser::parse_location;
+use crate::runtime::UdpSocket;
 use crate::types::Device;
 
 const DISCOVERY_REQUEST: &str = "M-SEARCH * HTTP/1.1\r\n\
@@ -18,15 +18,18 @@ const DISCOVERY_REQUEST: &str = "M-SEARCH * HTTP/1.1\r\n\
 
 pub async fn discover_pnp_locations() -> Result<impl Stream<Item = Device>> {
     let any: SocketAddr = ([0, 0, 0, 0], 0).into();
-    let socket = UdpSocket::bind(any).await?;
-    socket.join_multicast_v4(Ipv4Addr::new(239, 255, 255, 250), Ipv4Addr::new(0, 0, 0, 0))?;
+    let socket = UdpSocket::bind(any)?;
+    socket.get_ref().join_multicast_v4(
+        &Ipv4Addr::new(239, 255, 255, 250),
+        &Ipv4Addr::new(0, 0, 0, 0),
+    )?;
 
     // Set the socket address to the multicast IP and port for UPnP device discovery
     let socket_addr: SocketAddr = ([239, 255, 255, 250], 1900).into();
 
     // Send the discovery request
     socket
-        .send_to(DISCOVERY_REQUEST.as_bytes(), &socket_addr)
+        .send_to(DISCOVERY_REQUEST.as_bytes(), socket_addr)
         .await?;
 
     Ok(stream! {
@@ -37,7 +40,7 @@ pub async fn discover_pnp_locations() -> Result<impl Stream<Item = Device>> {
                 let (size, _) = socket.recv_from(&mut buf).await?;
                 // Convert the response to a string
                 let response =
-                    str::from_utf8(unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, size) })?;
+                    str::from_utf8(&buf[..size])?;
                 let headers = parse_raw_http_response(response)?;
                 let location = headers.get("location")
                     .ok_or_else(|| anyhow!("Response header missing location"))?
@@ -0,0 +1,203 @@
+use std::future::Future;
+
+use anyhow::{Error, Result};
+use futures_util::{stream, StreamExt};
+
+use crate::{
+    action::ActionArgs,
+    device_client::DeviceClient,
+    media_server::MediaServerClient,
+    parser::parse_time,
+    types::{Filter, Item, SortCriteria},
+};
+
+// Per-target outcomes of a fan-out, in the order the targets were given.
+#[derive(Debug)]
+pub struct FanOutResults<T> {
+    pub results: Vec<Result<T>>,
+}
+
+impl<T> FanOutResults<T> {
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(|result| result.is_ok())
+    }
+
+    pub fn successes(&self) -> Vec<(usize, &T)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| result.as_ref().ok().map(|value| (index, value)))
+            .collect()
+    }
+
+    pub fn failures(&self) -> Vec<(usize, &Error)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| result.as_ref().err().map(|error| (index, error)))
+            .collect()
+    }
+}
+
+// Runs `operation` against every target with at most `max_concurrency` calls
+// in flight, e.g. setting the volume on every renderer in the house.
+pub async fn fan_out<C, F, Fut, T>(
+    targets: impl IntoIterator<Item = C>,
+    max_concurrency: usize,
+    operation: F,
+) -> FanOutResults<T>
+where
+    F: Fn(C) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let results = stream::iter(targets)
+        .map(operation)
+        .buffered(max_concurrency.max(1))
+        .collect()
+        .await;
+    FanOutResults { results }
+}
+
+pub async fn fan_out_action(
+    clients: &[DeviceClient],
+    max_concurrency: usize,
+    service_id: &str,
+    action_name: &str,
+    params: impl Into<ActionArgs>,
+) -> FanOutResults<String> {
+    let params = params.into();
+    fan_out(clients, max_concurrency, |client| {
+        client.call_action(service_id, action_name, params.clone())
+    })
+    .await
+}
+
+// An item found by `search_all`, with the indexes of every server it was
+// found on; `item` is the copy from the first of them.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub item: Item,
+    pub servers: Vec<usize>,
+}
+
+// Runs the same search on every server and merges the matches, folding
+// copies of the same media found on several servers into one hit. Servers
+// that fail are reported alongside.
+pub async fn search_all(
+    servers: &[MediaServerClient],
+    max_concurrency: usize,
+    criteria: &str,
+    filter: &Filter,
+) -> (Vec<SearchHit>, Vec<(usize, Error)>) {
+    let results = fan_out(servers, max_concurrency, |server| async move {
+        let (_, items) = server
+            .search("0", criteria, filter, &SortCriteria::new())
+            .await?;
+        Ok(items)
+    })
+    .await;
+
+    let mut hits: Vec<SearchHit> = vec![];
+    let mut failures = vec![];
+    for (server, result) in results.results.into_iter().enumerate() {
+        match result {
+            Ok(items) => merge_hits(&mut hits, server, items),
+            Err(e) => failures.push((server, e)),
+        }
+    }
+    (hits, failures)
+}
+
+fn merge_hits(hits: &mut Vec<SearchHit>, server: usize, items: Vec<Item>) {
+    for item in items {
+        match hits.iter_mut().find(|hit| same_media(&hit.item, &item)) {
+            Some(hit) if !hit.servers.contains(&server) => hit.servers.push(server),
+            Some(_) => {}
+            None => hits.push(SearchHit {
+                item,
+                servers: vec![server],
+            }),
+        }
+    }
+}
+
+// Servers describe the same file differently, so items are compared on what
+// survives: the title, then the size or, failing that, the duration (to
+// the second) or the artist.
+fn same_media(a: &Item, b: &Item) -> bool {
+    if a.title.trim().to_lowercase() != b.title.trim().to_lowercase() {
+        return false;
+    }
+    if let (Some(a), Some(b)) = (a.size, b.size) {
+        return a == b;
+    }
+    let duration = |item: &Item| item.duration.as_deref().and_then(parse_time);
+    if let (Some(a), Some(b)) = (duration(a), duration(b)) {
+        return a.as_secs() == b.as_secs();
+    }
+    a.artist.as_deref().map(str::to_lowercase) == b.artist.as_deref().map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fan_out, merge_hits};
+    use crate::types::Item;
+    use anyhow::anyhow;
+
+    #[tokio::test]
+    async fn test_fan_out_reports_partial_failures() {
+        let results = fan_out(1..=5, 2, |n| async move {
+            match n % 2 {
+                0 => Err(anyhow!("device {} unreachable", n)),
+                _ => Ok(n * 10),
+            }
+        })
+        .await;
+
+        assert!(!results.all_succeeded());
+        assert_eq!(results.successes(), vec![(0, &10), (2, &30), (4, &50)]);
+        let failures: Vec<usize> = results.failures().iter().map(|(i, _)| *i).collect();
+        assert_eq!(failures, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_merging_search_hits() {
+        let item = |title: &str, size: Option<u64>, duration: Option<&str>| Item {
+            title: title.to_string(),
+            size,
+            duration: duration.map(str::to_string),
+            ..Default::default()
+        };
+        let mut hits = vec![];
+        merge_hits(
+            &mut hits,
+            0,
+            vec![
+                item("Creep", Some(1000), None),
+                item("Karma Police", None, Some("0:04:21.000")),
+            ],
+        );
+        merge_hits(
+            &mut hits,
+            1,
+            vec![
+                item("creep", Some(1000), None),
+                item("Creep", Some(2000), None),
+                item("Karma Police", None, Some("0:04:21.480")),
+            ],
+        );
+
+        let servers: Vec<(&str, Vec<usize>)> = hits
+            .iter()
+            .map(|hit| (hit.item.title.as_str(), hit.servers.clone()))
+            .collect();
+        assert_eq!(
+            servers,
+            [
+                ("Creep", vec![0, 1]),
+                ("Karma Police", vec![0, 1]),
+                ("Creep", vec![1]),
+            ]
+        );
+    }
+}
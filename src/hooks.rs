@@ -0,0 +1,29 @@
+use anyhow::Result;
+use surf::Url;
+
+#[derive(Debug, Clone)]
+pub struct SoapRequest {
+    pub control_url: Url,
+    pub soap_action: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SoapResponse {
+    pub soap_action: String,
+    pub status: u16,
+    pub body: String,
+}
+
+// Observes or rewrites the SOAP traffic of a DeviceClient (logging, redaction,
+// fault injection in tests). Returning an error aborts the action call.
+pub trait SoapHook: Send + Sync {
+    fn on_request(&self, _request: &mut SoapRequest) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_response(&self, _response: &mut SoapResponse) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use async_stream::try_stream;
+use futures_util::Stream;
+
+use crate::{
+    action::{output, ActionArgs, ActionTarget, Boolean},
+    device_client::DeviceClient,
+    parser::parse_action_response,
+    types::{
+        LinkProperties, Pinhole, PinholeError, PortMappingEntry, PortMappingError,
+        PortMappingProtocol, UpnpError,
+    },
+};
+
+const WAN_IP_CONNECTION: &str = "urn:schemas-upnp-org:service:WANIPConnection";
+const WAN_PPP_CONNECTION: &str = "urn:schemas-upnp-org:service:WANPPPConnection";
+const WAN_COMMON_INTERFACE_CONFIG: &str = "urn:schemas-upnp-org:service:WANCommonInterfaceConfig";
+const WAN_IPV6_FIREWALL_CONTROL: &str = "urn:schemas-upnp-org:service:WANIPv6FirewallControl";
+
+// The LeaseTime range WANIPv6FirewallControl allows.
+const MIN_PINHOLE_LEASE: Duration = Duration::from_secs(1);
+const MAX_PINHOLE_LEASE: Duration = Duration::from_secs(86400);
+
+// Client for an InternetGatewayDevice, i.e. a home router. The WAN services
+// live on embedded devices, which `connect` loads along with the root.
+#[derive(Clone)]
+pub struct InternetGatewayClient {
+    device_client: DeviceClient,
+}
+
+impl InternetGatewayClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    pub async fn external_ip(&self) -> Result<IpAddr, Error> {
+        let values = self
+            .call_connection_action("GetExternalIPAddress", ActionArgs::new())
+            .await?;
+        output(&values, "NewExternalIPAddress")
+    }
+
+    // Forwards `external_port` on the gateway to `internal`. A zero `lease`
+    // asks for a permanent mapping, which IGDv2 gateways don't grant. A port
+    // taken by another client fails with `PortMappingError::ConflictInMapping`.
+    pub async fn add_port_mapping(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        internal: SocketAddr,
+        lease: Duration,
+        description: &str,
+    ) -> Result<(), Error> {
+        let params = port_mapping_args(protocol, external_port, internal, lease, description);
+        self.call_connection_action("AddPortMapping", params)
+            .await
+            .map_err(port_mapping_error)?;
+        Ok(())
+    }
+
+    // IGDv2 only: like `add_port_mapping`, but the gateway picks another
+    // external port when `external_port` is taken. Returns the port mapped.
+    pub async fn add_any_port_mapping(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        internal: SocketAddr,
+        lease: Duration,
+        description: &str,
+    ) -> Result<u16, Error> {
+        let params = port_mapping_args(protocol, external_port, internal, lease, description);
+        let values = self
+            .call_connection_action("AddAnyPortMapping", params)
+            .await
+            .map_err(port_mapping_error)?;
+        output(&values, "NewReservedPort")
+    }
+
+    pub async fn delete_port_mapping(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+    ) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("NewRemoteHost", "")
+            .arg("NewExternalPort", external_port)
+            .arg("NewProtocol", protocol);
+        self.call_connection_action("DeletePortMapping", params)
+            .await
+            .map_err(port_mapping_error)?;
+        Ok(())
+    }
+
+    // The gateway's port mappings, read one index at a time until it
+    // answers that the index is past the last entry.
+    pub fn port_mappings(&self) -> impl Stream<Item = Result<PortMappingEntry, Error>> + '_ {
+        try_stream! {
+            for index in 0u32.. {
+                let params = ActionArgs::new().arg("NewPortMappingIndex", index);
+                let values = match self
+                    .call_connection_action("GetGenericPortMappingEntry", params)
+                    .await
+                    .map_err(port_mapping_error)
+                {
+                    Err(e) if is_end_of_table(&e) => break,
+                    result => result?,
+                };
+                yield port_mapping_entry(&values)?;
+            }
+        }
+    }
+
+    // The WAN byte counters. IGDv1 gateways count in 32 bits, wrapping
+    // every 4 GiB, so tools sampling them should allow for that.
+    pub async fn total_bytes_sent(&self) -> Result<u64, Error> {
+        let values = self
+            .call_wan_action(
+                &[WAN_COMMON_INTERFACE_CONFIG],
+                "GetTotalBytesSent",
+                ActionArgs::new(),
+            )
+            .await?;
+        output(&values, "NewTotalBytesSent")
+    }
+
+    pub async fn total_bytes_received(&self) -> Result<u64, Error> {
+        let values = self
+            .call_wan_action(
+                &[WAN_COMMON_INTERFACE_CONFIG],
+                "GetTotalBytesReceived",
+                ActionArgs::new(),
+            )
+            .await?;
+        output(&values, "NewTotalBytesReceived")
+    }
+
+    pub async fn common_link_properties(&self) -> Result<LinkProperties, Error> {
+        let values = self
+            .call_wan_action(
+                &[WAN_COMMON_INTERFACE_CONFIG],
+                "GetCommonLinkProperties",
+                ActionArgs::new(),
+            )
+            .await?;
+        Ok(LinkProperties {
+            access_type: output(&values, "NewWANAccessType")?,
+            upstream_max_bitrate: output(&values, "NewLayer1UpstreamMaxBitRate")?,
+            downstream_max_bitrate: output(&values, "NewLayer1DownstreamMaxBitRate")?,
+            physical_link_status: output(&values, "NewPhysicalLinkStatus")?,
+        })
+    }
+
+    // Opens the IPv6 firewall to `internal` for any remote host. Pinholes
+    // last at most a day: call `update_pinhole` before `lease` runs out.
+    pub async fn add_pinhole(
+        &self,
+        protocol: PortMappingProtocol,
+        internal: SocketAddr,
+        lease: Duration,
+    ) -> Result<Pinhole, Error> {
+        if !internal.is_ipv6() {
+            return Err(anyhow!(
+                "Pinholes are for IPv6 hosts, not {}",
+                internal.ip()
+            ));
+        }
+        let lease = pinhole_lease(lease);
+        let params = ActionArgs::new()
+            .arg("RemoteHost", "")
+            .arg("RemotePort", 0)
+            .arg("InternalClient", internal.ip().to_string())
+            .arg("InternalPort", internal.port())
+            .arg("Protocol", ip_protocol(&protocol)?)
+            .arg("LeaseTime", lease.as_secs());
+        let values = self.call_firewall_action("AddPinhole", params).await?;
+        Ok(Pinhole {
+            id: output(&values, "UniqueID")?,
+            lease,
+        })
+    }
+
+    pub async fn update_pinhole(&self, id: u16, lease: Duration) -> Result<Pinhole, Error> {
+        let lease = pinhole_lease(lease);
+        let params = ActionArgs::new()
+            .arg("UniqueID", id)
+            .arg("NewLeaseTime", lease.as_secs());
+        self.call_firewall_action("UpdatePinhole", params).await?;
+        Ok(Pinhole { id, lease })
+    }
+
+    pub async fn delete_pinhole(&self, id: u16) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("UniqueID", id);
+        self.call_firewall_action("DeletePinhole", params).await?;
+        Ok(())
+    }
+
+    async fn call_firewall_action(
+        &self,
+        action_name: &str,
+        params: ActionArgs,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.call_wan_action(&[WAN_IPV6_FIREWALL_CONTROL], action_name, params)
+            .await
+            .map_err(|error| {
+                let code = error.downcast_ref::<UpnpError>().map(|error| error.code);
+                match code.and_then(PinholeError::from_code) {
+                    Some(pinhole_error) => pinhole_error.into(),
+                    None => error,
+                }
+            })
+    }
+
+    // Gateways have either connection service, WANPPPConnection on PPPoE
+    // links, with the same actions.
+    async fn call_connection_action(
+        &self,
+        action_name: &str,
+        params: ActionArgs,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.call_wan_action(
+            &[WAN_IP_CONNECTION, WAN_PPP_CONNECTION],
+            action_name,
+            params,
+        )
+        .await
+    }
+
+    // Calls the action on the first service of `service_types` the gateway
+    // has. Service ids vary between vendors, so services are looked up by
+    // type.
+    async fn call_wan_action(
+        &self,
+        service_types: &[&str],
+        action_name: &str,
+        params: ActionArgs,
+    ) -> Result<HashMap<String, String>, Error> {
+        let device = self
+            .device_client
+            .device()
+            .ok_or_else(|| anyhow!("Device not connected"))?;
+        let service = service_types
+            .iter()
+            .find_map(|service_type| device.find_service_type(service_type))
+            .ok_or_else(|| {
+                let name = service_types[0].rsplit(':').next().unwrap_or_default();
+                anyhow!("Gateway has no {} service", name)
+            })?;
+        let response = self
+            .device_client
+            .call_action(&service.service_id, action_name, params)
+            .await?;
+        parse_action_response(&response)
+    }
+}
+
+// Any remote host may connect.
+fn port_mapping_args(
+    protocol: PortMappingProtocol,
+    external_port: u16,
+    internal: SocketAddr,
+    lease: Duration,
+    description: &str,
+) -> ActionArgs {
+    ActionArgs::new()
+        .arg("NewRemoteHost", "")
+        .arg("NewExternalPort", external_port)
+        .arg("NewProtocol", protocol)
+        .arg("NewInternalPort", internal.port())
+        .arg("NewInternalClient", internal.ip().to_string())
+        .arg("NewEnabled", true)
+        .arg("NewPortMappingDescription", description)
+        .arg("NewLeaseDuration", lease.as_secs())
+}
+
+fn port_mapping_entry(values: &HashMap<String, String>) -> Result<PortMappingEntry, Error> {
+    let remote_host: String = output(values, "NewRemoteHost")?;
+    let lease: u64 = output(values, "NewLeaseDuration")?;
+    Ok(PortMappingEntry {
+        remote_host: Some(remote_host).filter(|host| !host.is_empty()),
+        external_port: output(values, "NewExternalPort")?,
+        protocol: output(values, "NewProtocol")?,
+        internal_port: output(values, "NewInternalPort")?,
+        internal_client: output(values, "NewInternalClient")?,
+        enabled: output::<Boolean>(values, "NewEnabled")?.0,
+        description: output(values, "NewPortMappingDescription")?,
+        lease_duration: Duration::from_secs(lease),
+    })
+}
+
+// Some gateways answer NoSuchEntryInArray rather than
+// SpecifiedArrayIndexInvalid past the end.
+fn is_end_of_table(error: &Error) -> bool {
+    matches!(
+        error.downcast_ref::<PortMappingError>(),
+        Some(PortMappingError::SpecifiedArrayIndexInvalid | PortMappingError::NoSuchEntry)
+    )
+}
+
+fn pinhole_lease(lease: Duration) -> Duration {
+    Duration::from_secs(lease.as_secs()).clamp(MIN_PINHOLE_LEASE, MAX_PINHOLE_LEASE)
+}
+
+// WANIPv6FirewallControl takes IANA protocol numbers.
+fn ip_protocol(protocol: &PortMappingProtocol) -> Result<u16, Error> {
+    match protocol {
+        PortMappingProtocol::Tcp => Ok(6),
+        PortMappingProtocol::Udp => Ok(17),
+        PortMappingProtocol::Other(other) => other
+            .parse()
+            .map_err(|_| anyhow!("Unsupported protocol {}", other)),
+    }
+}
+
+fn port_mapping_error(error: Error) -> Error {
+    let code = error.downcast_ref::<UpnpError>().map(|error| error.code);
+    match code.and_then(PortMappingError::from_code) {
+        Some(port_mapping_error) => port_mapping_error.into(),
+        None => error,
+    }
+}
+
+impl ActionTarget for InternetGatewayClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InternetGatewayClient;
+    use crate::{
+        device_client::DeviceClient,
+        testing::{serve_device, soap_fault, soap_response, TestResponse},
+        types::{
+            LinkProperties, PhysicalLinkStatus, Pinhole, PinholeError, PortMappingEntry,
+            PortMappingError, PortMappingProtocol, UpnpError, WanAccessType,
+        },
+    };
+    use futures_util::TryStreamExt;
+    use std::time::Duration;
+
+    #[test]
+    fn test_port_mappings() {
+        let url = serve_device(
+            &["WANIPConnection"],
+            &["AddPortMapping", "AddAnyPortMapping", "DeletePortMapping"],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let response = match action.as_str() {
+                    "AddPortMapping" if request.argument("NewExternalPort").unwrap() == "80" => {
+                        return TestResponse::ok(soap_fault(718, "ConflictInMapping"))
+                            .status_code(500)
+                    }
+                    "AddPortMapping" => {
+                        assert_eq!(request.argument("NewProtocol").unwrap(), "UDP");
+                        assert_eq!(
+                            request.argument("NewInternalClient").unwrap(),
+                            "192.168.1.20"
+                        );
+                        assert_eq!(request.argument("NewInternalPort").unwrap(), "5000");
+                        assert_eq!(request.argument("NewLeaseDuration").unwrap(), "3600");
+                        assert_eq!(
+                            request.argument("NewPortMappingDescription").unwrap(),
+                            "Game & chat"
+                        );
+                        soap_response(&action, &[])
+                    }
+                    "AddAnyPortMapping" => soap_response(&action, &[("NewReservedPort", "8081")]),
+                    _ => return TestResponse::ok(soap_fault(501, "ActionFailed")).status_code(500),
+                };
+                TestResponse::ok(response)
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let gateway = InternetGatewayClient::new(client);
+            let internal = "192.168.1.20:5000".parse().unwrap();
+            let lease = Duration::from_secs(3600);
+            gateway
+                .add_port_mapping(
+                    PortMappingProtocol::Udp,
+                    5000,
+                    internal,
+                    lease,
+                    "Game & chat",
+                )
+                .await
+                .unwrap();
+            let error = gateway
+                .add_port_mapping(PortMappingProtocol::Tcp, 80, internal, lease, "Web")
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<PortMappingError>(),
+                Some(&PortMappingError::ConflictInMapping)
+            );
+            let port = gateway
+                .add_any_port_mapping(PortMappingProtocol::Tcp, 8080, internal, lease, "Web")
+                .await
+                .unwrap();
+            assert_eq!(port, 8081);
+            let error = gateway
+                .delete_port_mapping(PortMappingProtocol::Tcp, 8081)
+                .await
+                .unwrap_err();
+            assert_eq!(error.downcast_ref::<UpnpError>().unwrap().code, 501);
+        });
+    }
+
+    #[test]
+    fn test_listing_port_mappings() {
+        let url = serve_device(
+            &["WANIPConnection"],
+            &["GetGenericPortMappingEntry"],
+            |request| match request.argument("NewPortMappingIndex").unwrap().as_str() {
+                "0" => TestResponse::ok(soap_response(
+                    "GetGenericPortMappingEntry",
+                    &[
+                        ("NewRemoteHost", ""),
+                        ("NewExternalPort", "51413"),
+                        ("NewProtocol", "UDP"),
+                        ("NewInternalPort", "51413"),
+                        ("NewInternalClient", "192.168.1.20"),
+                        ("NewEnabled", "1"),
+                        ("NewPortMappingDescription", "Transmission"),
+                        ("NewLeaseDuration", "0"),
+                    ],
+                )),
+                _ => {
+                    TestResponse::ok(soap_fault(713, "SpecifiedArrayIndexInvalid")).status_code(500)
+                }
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let gateway = InternetGatewayClient::new(client);
+            let mappings: Vec<_> = gateway.port_mappings().try_collect().await.unwrap();
+            assert_eq!(
+                mappings,
+                [PortMappingEntry {
+                    remote_host: None,
+                    external_port: 51413,
+                    protocol: PortMappingProtocol::Udp,
+                    internal_port: 51413,
+                    internal_client: "192.168.1.20".to_string(),
+                    enabled: true,
+                    description: "Transmission".to_string(),
+                    lease_duration: Duration::ZERO,
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn test_pinholes() {
+        let url = serve_device(
+            &["WANIPv6FirewallControl"],
+            &["AddPinhole", "UpdatePinhole", "DeletePinhole"],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let response = match action.as_str() {
+                    "AddPinhole" => {
+                        assert_eq!(request.argument("InternalClient").unwrap(), "2001:db8::20");
+                        assert_eq!(request.argument("InternalPort").unwrap(), "22000");
+                        assert_eq!(request.argument("Protocol").unwrap(), "6");
+                        assert_eq!(request.argument("LeaseTime").unwrap(), "86400");
+                        soap_response(&action, &[("UniqueID", "3")])
+                    }
+                    "UpdatePinhole" => {
+                        assert_eq!(request.argument("UniqueID").unwrap(), "3");
+                        assert_eq!(request.argument("NewLeaseTime").unwrap(), "1");
+                        soap_response(&action, &[])
+                    }
+                    _ => return TestResponse::ok(soap_fault(704, "NoSuchEntry")).status_code(500),
+                };
+                TestResponse::ok(response)
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let gateway = InternetGatewayClient::new(client);
+            let internal = "[2001:db8::20]:22000".parse().unwrap();
+            let two_days = Duration::from_secs(2 * 86400);
+            let pinhole = gateway
+                .add_pinhole(PortMappingProtocol::Tcp, internal, two_days)
+                .await
+                .unwrap();
+            assert_eq!(
+                pinhole,
+                Pinhole {
+                    id: 3,
+                    lease: Duration::from_secs(86400)
+                }
+            );
+            let pinhole = gateway
+                .update_pinhole(pinhole.id, Duration::ZERO)
+                .await
+                .unwrap();
+            assert_eq!(pinhole.lease, Duration::from_secs(1));
+            let error = gateway.delete_pinhole(4).await.unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<PinholeError>(),
+                Some(&PinholeError::NoSuchEntry)
+            );
+
+            let ipv4 = "192.168.1.20:22000".parse().unwrap();
+            assert!(gateway
+                .add_pinhole(PortMappingProtocol::Tcp, ipv4, two_days)
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_wan_statistics() {
+        let url = serve_device(
+            &["WANCommonInterfaceConfig"],
+            &[
+                "GetTotalBytesSent",
+                "GetTotalBytesReceived",
+                "GetCommonLinkProperties",
+            ],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "GetTotalBytesSent" => &[("NewTotalBytesSent", "4294967295")],
+                    "GetTotalBytesReceived" => &[("NewTotalBytesReceived", "6442450944")],
+                    _ => &[
+                        ("NewWANAccessType", "Cable"),
+                        ("NewLayer1UpstreamMaxBitRate", "50000000"),
+                        ("NewLayer1DownstreamMaxBitRate", "1000000000"),
+                        ("NewPhysicalLinkStatus", "Up"),
+                    ],
+                };
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let gateway = InternetGatewayClient::new(client);
+            assert_eq!(gateway.total_bytes_sent().await.unwrap(), 4294967295);
+            assert_eq!(gateway.total_bytes_received().await.unwrap(), 6442450944);
+            assert_eq!(
+                gateway.common_link_properties().await.unwrap(),
+                LinkProperties {
+                    access_type: WanAccessType::Cable,
+                    upstream_max_bitrate: 50_000_000,
+                    downstream_max_bitrate: 1_000_000_000,
+                    physical_link_status: PhysicalLinkStatus::Up,
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_external_ip() {
+        let url = serve_device(
+            &["WANPPPConnection"],
+            &["GetExternalIPAddress"],
+            |request| {
+                assert_eq!(request.path, "/WANPPPConnection/control");
+                TestResponse::ok(soap_response(
+                    "GetExternalIPAddress",
+                    &[("NewExternalIPAddress", "203.0.113.7")],
+                ))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let gateway = InternetGatewayClient::new(client);
+            assert_eq!(
+                gateway.external_ip().await.unwrap(),
+                "203.0.113.7".parse::<std::net::IpAddr>().unwrap()
+            );
+        });
+    }
+}
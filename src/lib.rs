@@ -1,15 +1,40 @@
+pub mod action;
+pub mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod codegen;
+mod compression;
 pub mod device_client;
+pub mod device_protection;
 pub mod discovery;
+pub mod fan_out;
+pub mod hooks;
+pub mod igd;
+mod limits;
 pub mod media_renderer;
 pub mod media_server;
+pub mod openhome;
 pub mod parser;
+pub mod resume;
+mod runtime;
+pub mod scheduled_recording;
+pub mod snapshot;
+pub mod sonos;
+pub mod sony;
+#[cfg(test)]
+mod testing;
+pub mod tls;
 pub mod types;
+pub mod validation;
 
-use std::sync::{mpsc::Sender, Mutex};
+pub use elementtree;
+
+use std::sync::Mutex;
 
 use lazy_static::lazy_static;
+use tokio::sync::mpsc::UnboundedSender;
 use types::Event;
 
 lazy_static! {
-    static ref BROADCAST_EVENT: Mutex<Option<Sender<Event>>> = Mutex::new(None);
+    static ref BROADCAST_EVENT: Mutex<Option<UnboundedSender<Event>>> = Mutex::new(None);
 }
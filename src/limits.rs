@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Result};
+use futures_util::AsyncReadExt;
+use hyper::body::HttpBody;
+
+// Size limit of a response body, attached to requests as an extension so the
+// Decompression middleware applies it to the inflated body as well.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyLimit(pub usize);
+
+pub(crate) fn too_large(max_size: usize) -> anyhow::Error {
+    anyhow!("Response body exceeds the {} byte limit", max_size)
+}
+
+pub(crate) async fn read_body(res: &mut surf::Response, max_size: usize) -> Result<Vec<u8>> {
+    if res.len().is_some_and(|len| len > max_size) {
+        return Err(too_large(max_size));
+    }
+    let mut body = Vec::new();
+    res.take_body()
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut body)
+        .await?;
+    match body.len() > max_size {
+        true => Err(too_large(max_size)),
+        false => Ok(body),
+    }
+}
+
+pub(crate) async fn read_hyper_body(mut body: hyper::Body, max_size: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(too_large(max_size));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+// UPnP documents and SOAP messages never carry a DTD (SOAP 1.1 forbids it),
+// so any document type declaration, escaped DIDL-Lite included, is refused
+// before it reaches the XML parsers and their entity expansion.
+pub(crate) fn reject_doctype(xml: &str) -> Result<()> {
+    let upper = xml.to_ascii_uppercase();
+    match upper.contains("<!DOCTYPE") || upper.contains("&LT;!DOCTYPE") {
+        true => Err(anyhow!("Document type declarations are not allowed")),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_hyper_body, reject_doctype};
+
+    #[tokio::test]
+    async fn test_reading_bounded_bodies() {
+        let body = hyper::Body::from(vec![0u8; 1024]);
+        assert_eq!(read_hyper_body(body, 1024).await.unwrap().len(), 1024);
+        let body = hyper::Body::from(vec![0u8; 1025]);
+        assert!(read_hyper_body(body, 1024).await.is_err());
+    }
+
+    #[test]
+    fn test_rejecting_doctype() {
+        assert!(reject_doctype("<root/>").is_ok());
+        let billion_laughs = r#"<?xml version="1.0"?>
+            <!DOCTYPE lolz [<!ENTITY lol "lol"><!ENTITY lol2 "&lol;&lol;&lol;&lol;">]>
+            <root>&lol2;</root>"#;
+        assert!(reject_doctype(billion_laughs).is_err());
+        assert!(reject_doctype("<Result>&lt;!doctype x&gt;</Result>").is_err());
+    }
+}
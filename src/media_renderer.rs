@@ -1,17 +1,24 @@
-use std::{collections::HashMap, sync::mpsc};
+use std::time::Duration;
 
-use anyhow::{Error, Ok};
+use anyhow::{anyhow, Error, Ok};
 use async_stream::stream;
 use futures_util::Stream;
+use tokio::sync::mpsc;
 use xml_builder::{XMLBuilder, XMLElement};
 
 use crate::{
+    action::{output, ActionArgs, ActionTarget, Boolean},
     device_client::DeviceClient,
     parser::{
-        parse_duration, parse_position, parse_supported_protocols, parse_transport_info,
-        parse_volume,
+        parse_action_response, parse_duration, parse_position, parse_supported_protocols,
+        parse_transport_info,
     },
-    types::{Event, LoadOptions, Metadata, ObjectClass, TransportInfo},
+    types::{
+        Event, Item, LoadOptions, Metadata, ObjectClass, PlayMode, PositionInfo, ProtocolInfo,
+        Resource, TransportInfo,
+    },
+    upnp_action,
+    validation::{validate_didl, DlnaWarning},
     BROADCAST_EVENT,
 };
 
@@ -34,52 +41,11 @@ impl MediaRendererClient {
         Self { device_client }
     }
     pub async fn load(&self, url: &str, options: LoadOptions) -> Result<(), Error> {
-        let dlna_features = options.dlna_features.unwrap_or("*".to_string());
-        let content_type = options.content_type.unwrap_or("video/mpeg".to_string());
-        let protocol_info = format!("http-get:*:{}:{}", content_type, dlna_features);
-        let title = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .title;
-        let artist = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .artist;
-        let album = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .album;
-        let album_art_uri = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .album_art_uri;
-        let genre = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .genre;
-
-        let m = Metadata {
-            url: url.to_string(),
-            title,
-            artist,
-            album,
-            album_art_uri,
-            genre,
-            protocol_info,
-        };
-
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("CurrentURI".to_string(), url.to_string());
-        params.insert(
-            "CurrentURIMetaData".to_string(),
-            build_metadata(m, options.object_class.unwrap_or(ObjectClass::Video)),
-        );
+        let (m, object_class) = load_metadata(url, &options);
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("CurrentURI", url)
+            .arg("CurrentURIMetaData", build_didl(m, object_class));
         self.device_client
             .call_action("AVTransport", "SetAVTransportURI", params)
             .await?;
@@ -91,143 +57,112 @@ impl MediaRendererClient {
         Ok(())
     }
 
-    pub async fn play(&self) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("Speed".to_string(), "1".to_string());
-        self.device_client
-            .call_action("AVTransport", "Play", params)
-            .await?;
-        Ok(())
+    // Loads and starts a media server item, choosing the resource that best
+    // matches what this renderer advertises it can play.
+    pub async fn play_item(&self, item: &Item) -> Result<(), Error> {
+        let sink = self.get_supported_protocols().await?;
+        let resource = item
+            .best_resource(&sink)
+            .ok_or_else(|| anyhow!("Renderer can't play any resource of {}", item.title))?;
+        let index = item
+            .resources
+            .iter()
+            .position(|res| std::ptr::eq(res, resource))
+            .unwrap_or_default();
+        let options = LoadOptions {
+            autoplay: true,
+            ..item.to_load_options(index).unwrap_or_default()
+        };
+        self.load(&resource.url, options).await
     }
 
-    pub async fn pause(&self) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        self.device_client
-            .call_action("AVTransport", "Pause", params)
-            .await?;
-        Ok(())
+    upnp_action! {
+        pub async fn play(&self) -> () = "AVTransport"."Play" { "InstanceID": 0, "Speed": 1 };
     }
 
+    upnp_action! {
+        pub async fn pause(&self) -> () = "AVTransport"."Pause" { "InstanceID": 0 };
+    }
+
+    #[deprecated(note = "use `seek_to`, which takes a Duration")]
     pub async fn seek(&self, seconds: u64) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("Unit".to_string(), "REL_TIME".to_string());
-        params.insert("Target".to_string(), format_time(seconds));
+        self.seek_to(Duration::from_secs(seconds)).await
+    }
+
+    // Seeks within the current track, to the millisecond.
+    pub async fn seek_to(&self, position: Duration) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("Unit", "REL_TIME")
+            .arg("Target", position);
         self.device_client
             .call_action("AVTransport", "Seek", params)
             .await?;
         Ok(())
     }
 
-    pub async fn stop(&self) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        self.device_client
-            .call_action("AVTransport", "Stop", params)
-            .await?;
-        Ok(())
+    upnp_action! {
+        pub async fn stop(&self) -> () = "AVTransport"."Stop" { "InstanceID": 0 };
     }
 
-    pub async fn next(&self) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        self.device_client
-            .call_action("AVTransport", "Next", params)
-            .await?;
-        Ok(())
+    upnp_action! {
+        pub async fn next(&self) -> () = "AVTransport"."Next" { "InstanceID": 0 };
     }
 
-    pub async fn previous(&self) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        self.device_client
-            .call_action("AVTransport", "Previous", params)
-            .await?;
-        Ok(())
+    upnp_action! {
+        pub async fn previous(&self) -> () = "AVTransport"."Previous" { "InstanceID": 0 };
     }
 
     pub async fn set_next(&self, url: &str, options: LoadOptions) -> Result<(), Error> {
-        let dlna_features = options.dlna_features.unwrap_or("*".to_string());
-        let content_type = options.content_type.unwrap_or("video/mpeg".to_string());
-        let protocol_info = format!("http-get:*:{}:{}", content_type, dlna_features);
-        let title = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .title;
-        let artist = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .artist;
-        let album = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .album;
-        let album_art_uri = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .album_art_uri;
-        let genre = options
-            .metadata
-            .clone()
-            .unwrap_or(Metadata::default())
-            .genre;
-
-        let m = Metadata {
-            url: url.to_string(),
-            title,
-            artist,
-            protocol_info,
-            album,
-            album_art_uri,
-            genre,
-        };
-
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("NextURI".to_string(), url.to_string());
-        params.insert(
-            "NextURIMetaData".to_string(),
-            build_metadata(m, options.object_class.unwrap_or(ObjectClass::Video)),
-        );
+        let (m, object_class) = load_metadata(url, &options);
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("NextURI", url)
+            .arg("NextURIMetaData", build_didl(m, object_class));
         self.device_client
             .call_action("AVTransport", "SetNextAVTransportURI", params)
             .await?;
         Ok(())
     }
 
-    pub async fn get_volume(&self) -> Result<u8, Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("Channel".to_string(), "Master".to_string());
+    upnp_action! {
+        pub async fn get_play_mode(&self) -> ("PlayMode": PlayMode)
+            = "AVTransport"."GetTransportSettings" { "InstanceID": 0 };
+    }
+
+    upnp_action! {
+        pub async fn set_play_mode(&self, play_mode: PlayMode => "NewPlayMode") -> ()
+            = "AVTransport"."SetPlayMode" { "InstanceID": 0 };
+    }
+
+    upnp_action! {
+        pub async fn get_volume(&self) -> ("CurrentVolume": u8)
+            = "RenderingControl"."GetVolume" { "InstanceID": 0, "Channel": "Master" };
+    }
 
+    upnp_action! {
+        pub async fn set_volume(&self, volume: u32 => "DesiredVolume") -> ()
+            = "RenderingControl"."SetVolume" { "InstanceID": 0, "Channel": "Master" };
+    }
+
+    pub async fn get_mute(&self) -> Result<bool, Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("Channel", "Master");
         let response = self
             .device_client
-            .call_action("RenderingControl", "GetVolume", params)
+            .call_action("RenderingControl", "GetMute", params)
             .await?;
-
-        Ok(parse_volume(response.as_str())?)
+        Ok(output::<Boolean>(&parse_action_response(&response)?, "CurrentMute")?.0)
     }
 
-    pub async fn set_volume(&self, volume: u32) -> Result<(), Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
-        params.insert("Channel".to_string(), "Master".to_string());
-        params.insert("DesiredVolume".to_string(), volume.to_string());
-        self.device_client
-            .call_action("RenderingControl", "SetVolume", params)
-            .await?;
-        Ok(())
+    upnp_action! {
+        pub async fn set_mute(&self, mute: bool => "DesiredMute") -> ()
+            = "RenderingControl"."SetMute" { "InstanceID": 0, "Channel": "Master" };
     }
 
     pub async fn get_supported_protocols(&self) -> Result<Vec<String>, Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
+        let params = ActionArgs::new().arg("InstanceID", 0);
         let response = self
             .device_client
             .call_action("ConnectionManager", "GetProtocolInfo", params)
@@ -235,9 +170,29 @@ impl MediaRendererClient {
         Ok(parse_supported_protocols(response.as_str())?)
     }
 
+    // The Sink protocols, parsed; malformed entries are skipped.
+    pub async fn get_sink_protocol_info(&self) -> Result<Vec<ProtocolInfo>, Error> {
+        let protocols = self.get_supported_protocols().await?;
+        Ok(protocols
+            .iter()
+            .filter_map(|protocol| protocol.parse().ok())
+            .collect())
+    }
+
+    #[deprecated(note = "use `position`, which returns a Duration")]
     pub async fn get_position(&self) -> Result<u32, Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
+        Ok(self.position().await?.as_secs() as u32)
+    }
+
+    pub async fn position(&self) -> Result<Duration, Error> {
+        let position = self.get_position_info().await?;
+        position
+            .rel_time
+            .ok_or_else(|| anyhow!("Invalid response from device"))
+    }
+
+    pub async fn get_position_info(&self) -> Result<PositionInfo, Error> {
+        let params = ActionArgs::new().arg("InstanceID", 0);
         let response = self
             .device_client
             .call_action("AVTransport", "GetPositionInfo", params)
@@ -245,31 +200,57 @@ impl MediaRendererClient {
         Ok(parse_position(response.as_str())?)
     }
 
+    #[deprecated(note = "use `duration`, which returns a Duration")]
     pub async fn get_duration(&self) -> Result<u32, Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
+        Ok(self.duration().await?.as_secs() as u32)
+    }
+
+    pub async fn duration(&self) -> Result<Duration, Error> {
+        let params = ActionArgs::new().arg("InstanceID", 0);
         let response = self
             .device_client
             .call_action("AVTransport", "GetMediaInfo", params)
             .await?;
-        Ok(parse_duration(response.as_str())?)
+        parse_duration(response.as_str())?.ok_or_else(|| anyhow!("Invalid response from device"))
     }
 
     pub async fn subscribe(&mut self) -> impl Stream<Item = Event> {
-        let (tx, rx) = mpsc::channel();
+        let (tx, mut rx) = mpsc::unbounded_channel();
         *BROADCAST_EVENT.lock().unwrap() = Some(tx);
 
         self.device_client.subscribe("AVTransport").await.unwrap();
         stream! {
-            while let Some(event) = rx.recv().into_iter().next() {
+            while let Some(event) = rx.recv().await {
                 yield event;
             }
         }
     }
 
+    // AVTransport:2 and later; older renderers are not asked at all.
+    pub async fn get_drm_state(&self) -> Result<String, Error> {
+        self.require_av_transport(2, "GetDRMState")?;
+        let params = ActionArgs::new().arg("InstanceID", 0);
+        let response = self
+            .device_client
+            .call_action("AVTransport", "GetDRMState", params)
+            .await?;
+        output(&parse_action_response(&response)?, "CurrentDRMState")
+    }
+
+    fn require_av_transport(&self, version: u32, action: &str) -> Result<(), Error> {
+        match self.device_client.service_version("AVTransport") {
+            Some(advertised) if advertised >= version => Ok(()),
+            advertised => Err(anyhow!(
+                "{} requires AVTransport:{}, the renderer advertises {}",
+                action,
+                version,
+                advertised.map_or("no version".to_string(), |v| format!("AVTransport:{}", v))
+            )),
+        }
+    }
+
     pub async fn get_transport_info(&self) -> Result<TransportInfo, Error> {
-        let mut params = HashMap::new();
-        params.insert("InstanceID".to_string(), "0".to_string());
+        let params = ActionArgs::new().arg("InstanceID", 0);
         let response = self
             .device_client
             .call_action("AVTransport", "GetTransportInfo", params)
@@ -278,7 +259,41 @@ impl MediaRendererClient {
     }
 }
 
-fn build_metadata(m: Metadata, media_type: ObjectClass) -> String {
+// The whole control point flow for an item found on a media server: pick the
+// resource `renderer` supports best, describe the item in
+// CurrentURIMetaData, load it and start playback.
+pub async fn cast(item: &Item, renderer: &MediaRendererClient) -> Result<(), Error> {
+    renderer.play_item(item).await
+}
+
+impl ActionTarget for MediaRendererClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+// The DLNA guideline violations in the metadata `load` would send for
+// `url`, to find out why a renderer rejects it.
+pub fn validate_load(url: &str, options: &LoadOptions) -> Result<Vec<DlnaWarning>, Error> {
+    let (m, object_class) = load_metadata(url, options);
+    validate_didl(&build_didl(m, object_class))
+}
+
+pub(crate) fn load_metadata(url: &str, options: &LoadOptions) -> (Metadata, ObjectClass) {
+    let protocol_info = options.protocol_info();
+    let object_class = options
+        .object_class
+        .or(options.metadata.as_ref().and_then(|m| m.object_class))
+        .unwrap_or_else(|| ObjectClass::for_content_type(&protocol_info.content_format));
+    let m = Metadata {
+        url: url.to_string(),
+        protocol_info: protocol_info.to_string(),
+        ..options.metadata.clone().unwrap_or_default()
+    };
+    (m, object_class)
+}
+
+pub(crate) fn build_didl(m: Metadata, media_type: ObjectClass) -> String {
     let mut didl = XMLElement::new("DIDL-Lite");
     didl.add_attribute("xmlns", "urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/");
     didl.add_attribute("xmlns:dc", "http://purl.org/dc/elements/1.1/");
@@ -286,51 +301,153 @@ fn build_metadata(m: Metadata, media_type: ObjectClass) -> String {
     didl.add_attribute("xmlns:dlna", "urn:schemas-dlna-org:metadata-1-0/");
     didl.add_attribute("xmlns:xbmc", "urn:schemas-xbmc-org:metadata-1-0/");
     didl.add_attribute("xmlns:sec", "http://www.sec.co.kr/");
+    let mut declared: Vec<String> = ["dc", "upnp", "dlna", "xbmc", "sec"]
+        .iter()
+        .map(|prefix| prefix.to_string())
+        .collect();
+    for name in m.extra_elements.keys().chain(m.extra_res_attributes.keys()) {
+        if !declared.contains(&name.prefix) {
+            didl.add_attribute(&format!("xmlns:{}", name.prefix), &name.namespace);
+            declared.push(name.prefix.clone());
+        }
+    }
 
     let mut item = XMLElement::new("item");
-    item.add_attribute("id", "0");
-    item.add_attribute("parentID", "-1");
-    item.add_attribute("restricted", "false");
+    item.add_attribute("id", &xml_chars(m.id.as_deref().unwrap_or("0")));
+    item.add_attribute(
+        "parentID",
+        &xml_chars(m.parent_id.as_deref().unwrap_or("-1")),
+    );
+    item.add_attribute(
+        "restricted",
+        if m.restricted.unwrap_or(true) {
+            "1"
+        } else {
+            "0"
+        },
+    );
+
+    item.add_child(text_element("dc:title", &m.title)).unwrap();
+    item.add_child(text_element("upnp:class", media_type.value()))
+        .unwrap();
 
-    let mut title = XMLElement::new("dc:title");
-    title.add_text(m.title).unwrap();
-    item.add_child(title).unwrap();
+    if let Some(value) = &m.artist {
+        item.add_child(text_element("upnp:artist", value)).unwrap();
+    }
+
+    for artist in &m.artists {
+        if artist.role.is_none() && m.artist.as_ref() == Some(&artist.name) {
+            continue;
+        }
+        item.add_child(role_element(
+            "upnp:artist",
+            &artist.name,
+            artist.role.as_deref(),
+        ))
+        .unwrap();
+    }
 
-    let mut class = XMLElement::new("upnp:class");
-    class.add_text(media_type.value().to_owned()).unwrap();
-    item.add_child(class).unwrap();
+    if let Some(value) = m.composer {
+        item.add_child(role_element("upnp:author", &value, Some("Composer")))
+            .unwrap();
+    }
 
-    if let Some(value) = m.artist {
-        let mut artist = XMLElement::new("upnp:artist");
-        artist.add_text(value).unwrap();
-        item.add_child(artist).unwrap();
+    if let Some(value) = m.conductor {
+        item.add_child(role_element("upnp:artist", &value, Some("Conductor")))
+            .unwrap();
     }
 
     if let Some(value) = m.album {
-        let mut album = XMLElement::new("upnp:album");
-        album.add_text(value).unwrap();
-        item.add_child(album).unwrap();
+        item.add_child(text_element("upnp:album", &value)).unwrap();
     }
 
     if let Some(value) = m.album_art_uri {
         let mut album_art = XMLElement::new("upnp:albumArtURI");
         album_art.add_attribute("dlna:profileID", "JPEG_TN");
         album_art.add_attribute("xmlns:dlna", "urn:schemas-dlna-org:metadata-1-0/");
-        album_art.add_text(value).unwrap();
+        album_art.add_text(escape_text(&value)).unwrap();
         item.add_child(album_art).unwrap();
     }
 
     if let Some(value) = m.genre {
-        let mut genre = XMLElement::new("upnp:genre");
-        genre.add_text(value).unwrap();
-        item.add_child(genre).unwrap();
+        item.add_child(text_element("upnp:genre", &value)).unwrap();
     }
 
-    let mut res = XMLElement::new("res");
-    res.add_attribute("protocolInfo", m.protocol_info.as_str());
-    res.add_text(m.url).unwrap();
+    if let Some(value) = m.creator {
+        item.add_child(text_element("dc:creator", &value)).unwrap();
+    }
+
+    if let Some(value) = m.date {
+        item.add_child(text_element("dc:date", &value)).unwrap();
+    }
+
+    if let Some(value) = m.description {
+        item.add_child(text_element("dc:description", &value))
+            .unwrap();
+    }
+
+    if let Some(value) = m.original_track_number {
+        item.add_child(text_element("upnp:originalTrackNumber", &value.to_string()))
+            .unwrap();
+    }
+
+    if let Some(value) = m.series_title {
+        item.add_child(text_element("upnp:seriesTitle", &value))
+            .unwrap();
+    }
+
+    if let Some(value) = m.episode_season {
+        item.add_child(text_element("upnp:episodeSeason", &value.to_string()))
+            .unwrap();
+    }
+
+    if let Some(value) = m.episode_number {
+        item.add_child(text_element("upnp:episodeNumber", &value.to_string()))
+            .unwrap();
+    }
+
+    // Sorted, so the same metadata always serializes the same way.
+    let mut extra_elements: Vec<_> = m.extra_elements.into_iter().collect();
+    extra_elements.sort();
+    for (name, value) in extra_elements {
+        item.add_child(text_element(&name.to_string(), &value))
+            .unwrap();
+    }
+
+    let primary = Resource {
+        url: m.url,
+        protocol_info: m.protocol_info,
+        duration: m.duration,
+        size: m.size,
+        resolution: m.resolution,
+        bitrate: m.bitrate,
+        sample_frequency: m.sample_frequency,
+        nr_audio_channels: m.nr_audio_channels,
+        bits_per_sample: m.bits_per_sample,
+    };
+    let mut res = res_element(&primary);
+    let mut extra_res_attributes: Vec<_> = m.extra_res_attributes.into_iter().collect();
+    extra_res_attributes.sort();
+    for (name, value) in extra_res_attributes {
+        res.add_attribute(&name.to_string(), &xml_chars(&value));
+    }
     item.add_child(res).unwrap();
 
+    // Alternatives follow the resource being played, which renderers take
+    // as the preferred one.
+    for resource in m.resources.iter().filter(|res| res.url != primary.url) {
+        item.add_child(res_element(resource)).unwrap();
+    }
+
+    // The content is XML already, so it goes in unescaped.
+    for desc in m.descs {
+        let mut element = XMLElement::new("desc");
+        element.add_attribute("id", &xml_chars(&desc.id));
+        element.add_attribute("nameSpace", &xml_chars(&desc.name_space));
+        element.add_text(desc.content).unwrap();
+        item.add_child(element).unwrap();
+    }
+
     didl.add_child(item).unwrap();
 
     let mut xml = XMLBuilder::new().build();
@@ -338,15 +455,645 @@ fn build_metadata(m: Metadata, media_type: ObjectClass) -> String {
 
     let mut writer: Vec<u8> = Vec::new();
     xml.generate(&mut writer).unwrap();
-    let metadata = String::from_utf8(writer)
+    String::from_utf8(writer)
         .unwrap()
-        .replace(r#"<?xml version="1.0" encoding="UTF-8"?>"#, "");
-    xml::escape::escape_str_attribute(&metadata).to_string()
+        .replace(r#"<?xml version="1.0" encoding="UTF-8"?>"#, "")
 }
 
-fn format_time(seconds: u64) -> String {
-    let hours = seconds / 3600;
-    let minutes = (seconds % 3600) / 60;
-    let seconds = seconds % 60;
-    format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+fn res_element(resource: &Resource) -> XMLElement {
+    let mut res = XMLElement::new("res");
+    res.add_attribute("protocolInfo", &xml_chars(&resource.protocol_info));
+    if let Some(duration) = &resource.duration {
+        res.add_attribute("duration", &xml_chars(duration));
+    }
+    if let Some(size) = resource.size {
+        res.add_attribute("size", &size.to_string());
+    }
+    if let Some(resolution) = &resource.resolution {
+        res.add_attribute("resolution", &xml_chars(resolution));
+    }
+    if let Some(bitrate) = resource.bitrate {
+        res.add_attribute("bitrate", &bitrate.to_string());
+    }
+    if let Some(sample_frequency) = resource.sample_frequency {
+        res.add_attribute("sampleFrequency", &sample_frequency.to_string());
+    }
+    if let Some(nr_audio_channels) = resource.nr_audio_channels {
+        res.add_attribute("nrAudioChannels", &nr_audio_channels.to_string());
+    }
+    if let Some(bits_per_sample) = resource.bits_per_sample {
+        res.add_attribute("bitsPerSample", &bits_per_sample.to_string());
+    }
+    res.add_text(escape_text(&resource.url)).unwrap();
+    res
+}
+
+// xml_builder escapes attribute values but writes text as is.
+fn text_element(name: &str, value: &str) -> XMLElement {
+    let mut element = XMLElement::new(name);
+    element.add_text(escape_text(value)).unwrap();
+    element
+}
+
+fn role_element(name: &str, value: &str, role: Option<&str>) -> XMLElement {
+    let mut element = text_element(name, value);
+    if let Some(role) = role {
+        element.add_attribute("role", &xml_chars(role));
+    }
+    element
+}
+
+fn escape_text(value: &str) -> String {
+    xml::escape::escape_str_pcdata(&xml_chars(value)).into_owned()
+}
+
+// Drops characters XML 1.0 can't carry at all, even escaped, such as the
+// control characters some taggers leave in titles.
+fn xml_chars(value: &str) -> String {
+    value
+        .chars()
+        .filter(|&c| matches!(c, '\t' | '\n' | '\r' | ' '..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_didl, cast, load_metadata, validate_load, MediaRendererClient};
+    use crate::{
+        device_client::DeviceClient,
+        parser::parse_didl,
+        testing::{serve_device, soap_response, TestResponse},
+        types::{
+            Contributor, Desc, DidlObject, Item, LoadOptions, Metadata, ObjectClass, QName,
+            Resource,
+        },
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_casting_media_server_items() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let url = serve_device(
+            &["AVTransport", "ConnectionManager"],
+            &["GetProtocolInfo", "SetAVTransportURI", "Play"],
+            {
+                let calls = calls.clone();
+                move |request| {
+                    let action = request.soap_action().unwrap().to_string();
+                    let response = match action.as_str() {
+                        "GetProtocolInfo" => soap_response(
+                            "GetProtocolInfo",
+                            &[
+                                ("Source", ""),
+                                ("Sink", "http-get:*:audio/mpeg:*,http-get:*:audio/flac:*"),
+                            ],
+                        ),
+                        "SetAVTransportURI" => {
+                            assert_eq!(
+                                request.argument("CurrentURI").as_deref(),
+                                Some("http://nas/1.flac")
+                            );
+                            let metadata = request.argument("CurrentURIMetaData").unwrap();
+                            assert!(metadata.contains("<dc:title>Blue in Green</dc:title>"));
+                            assert!(metadata.contains(r#"protocolInfo="http-get:*:audio/flac:*""#));
+                            assert!(metadata.contains("<dc:creator>Miles Davis</dc:creator>"));
+                            assert!(metadata.contains("<dc:date>1959-08-17</dc:date>"));
+                            assert!(metadata.contains(
+                                "<upnp:originalTrackNumber>3</upnp:originalTrackNumber>"
+                            ));
+                            assert!(metadata.contains(r#"duration="0:05:37.000""#));
+                            assert!(metadata.contains(r#"size="31337""#));
+                            assert!(metadata.contains(r#"sampleFrequency="96000""#));
+                            assert!(metadata.contains(r#"nrAudioChannels="2""#));
+                            assert!(metadata.contains(r#"bitsPerSample="24""#));
+                            let flac = metadata.find(">http://nas/1.flac</res>").unwrap();
+                            let wav = metadata.find(">http://nas/1.wav</res>").unwrap();
+                            assert!(flac < wav);
+                            assert_eq!(metadata.matches("<res ").count(), 2);
+                            soap_response("SetAVTransportURI", &[])
+                        }
+                        _ => soap_response(&action, &[]),
+                    };
+                    calls.lock().unwrap().push(action);
+                    TestResponse::ok(response)
+                }
+            },
+        );
+
+        let item = Item {
+            title: "Blue in Green".to_string(),
+            resources: vec![
+                Resource {
+                    url: "http://nas/1.wav".to_string(),
+                    protocol_info: "http-get:*:audio/wav:*".to_string(),
+                    ..Default::default()
+                },
+                Resource {
+                    url: "http://nas/1.flac".to_string(),
+                    protocol_info: "http-get:*:audio/flac:*".to_string(),
+                    duration: Some("0:05:37.000".to_string()),
+                    size: Some(31337),
+                    sample_frequency: Some(96000),
+                    nr_audio_channels: Some(2),
+                    bits_per_sample: Some(24),
+                    ..Default::default()
+                },
+            ],
+            creator: Some("Miles Davis".to_string()),
+            date: Some("1959-08-17".to_string()),
+            original_track_number: Some(3),
+            ..Default::default()
+        };
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let renderer = MediaRendererClient::new(client);
+            cast(&item, &renderer).await.unwrap();
+        });
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["GetProtocolInfo", "SetAVTransportURI", "Play"]
+        );
+    }
+
+    #[test]
+    fn test_extended_metadata() {
+        let didl = build_didl(
+            Metadata {
+                url: "http://nas/3.flac".to_string(),
+                title: "Blue in Green".to_string(),
+                artist: Some("Miles Davis".to_string()),
+                album: Some("Kind of Blue".to_string()),
+                album_art_uri: Some("http://nas/cover.jpg".to_string()),
+                genre: Some("Jazz".to_string()),
+                protocol_info: "http-get:*:audio/flac:*".to_string(),
+                original_track_number: Some(3),
+                date: Some("1959-08-17".to_string()),
+                duration: Some("0:05:37.000".to_string()),
+                size: Some(31337),
+                creator: Some("Miles Davis Sextet".to_string()),
+                description: Some("Recorded at Columbia 30th Street Studio".to_string()),
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        let root = elementtree::Element::from_reader(didl.as_bytes()).unwrap();
+        let item = root.children().next().unwrap();
+        let text = |name: &str| {
+            item.children()
+                .find(|c| c.tag().name() == name)
+                .unwrap()
+                .text()
+                .to_string()
+        };
+        assert_eq!(text("album"), "Kind of Blue");
+        assert_eq!(text("albumArtURI"), "http://nas/cover.jpg");
+        assert_eq!(text("genre"), "Jazz");
+        assert_eq!(text("originalTrackNumber"), "3");
+        assert_eq!(text("date"), "1959-08-17");
+        assert_eq!(text("creator"), "Miles Davis Sextet");
+        assert_eq!(
+            text("description"),
+            "Recorded at Columbia 30th Street Studio"
+        );
+
+        let res = item.children().find(|c| c.tag().name() == "res").unwrap();
+        assert_eq!(res.get_attr("duration"), Some("0:05:37.000"));
+        assert_eq!(res.get_attr("size"), Some("31337"));
+        assert_eq!(res.text(), "http://nas/3.flac");
+    }
+
+    #[test]
+    fn test_version_gated_actions() {
+        let serve = |service| {
+            serve_device(&[service], &["GetDRMState"], |request| {
+                assert_eq!(request.soap_action(), Some("GetDRMState"));
+                TestResponse::ok(soap_response("GetDRMState", &[("CurrentDRMState", "OK")]))
+            })
+        };
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&serve("AVTransport:2"))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            assert_eq!(client.service_version("AVTransport"), Some(2));
+            let renderer = MediaRendererClient::new(client);
+            assert_eq!(renderer.get_drm_state().await.unwrap(), "OK");
+
+            let client = DeviceClient::new(&serve("AVTransport"))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            assert_eq!(client.service_version("AVTransport"), Some(1));
+            assert_eq!(client.service_version("RenderingControl"), None);
+            let renderer = MediaRendererClient::new(client);
+            let error = renderer.get_drm_state().await.unwrap_err();
+            assert!(error.to_string().contains("requires AVTransport:2"));
+        });
+    }
+
+    #[test]
+    fn test_inferring_classes_from_content_types() {
+        let classes = Arc::new(Mutex::new(vec![]));
+        let url = serve_device(&["AVTransport"], &["SetAVTransportURI"], {
+            let classes = classes.clone();
+            move |request| {
+                let metadata = request.argument("CurrentURIMetaData").unwrap();
+                let root = elementtree::Element::from_reader(metadata.as_bytes()).unwrap();
+                let item = root.children().next().unwrap();
+                let class = item.children().find(|c| c.tag().name() == "class");
+                classes
+                    .lock()
+                    .unwrap()
+                    .push(class.unwrap().text().to_string());
+                TestResponse::ok(soap_response("SetAVTransportURI", &[]))
+            }
+        });
+
+        let content_type = |content_type: Option<&str>| LoadOptions {
+            content_type: content_type.map(str::to_string),
+            ..Default::default()
+        };
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let renderer = MediaRendererClient::new(client);
+            let url = "http://nas/media";
+            renderer
+                .load(url, content_type(Some("audio/flac")))
+                .await
+                .unwrap();
+            renderer
+                .load(url, content_type(Some("image/jpeg")))
+                .await
+                .unwrap();
+            renderer
+                .load(url, content_type(Some("video/mp4")))
+                .await
+                .unwrap();
+            renderer.load(url, content_type(None)).await.unwrap();
+            let radio = LoadOptions {
+                object_class: Some(ObjectClass::AudioBroadcast),
+                ..content_type(Some("audio/mpeg"))
+            };
+            renderer.load(url, radio).await.unwrap();
+        });
+        assert_eq!(
+            *classes.lock().unwrap(),
+            [
+                "object.item.audioItem.musicTrack",
+                "object.item.imageItem.photo",
+                "object.item.videoItem.movie",
+                "object.item.videoItem.movie",
+                "object.item.audioItem.audioBroadcast",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seeking_to_durations() {
+        let url = serve_device(
+            &["AVTransport"],
+            &["Seek", "GetPositionInfo", "GetMediaInfo"],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let response = match action.as_str() {
+                    "Seek" => {
+                        assert_eq!(request.argument("Unit").as_deref(), Some("REL_TIME"));
+                        assert_eq!(request.argument("Target").as_deref(), Some("00:01:02.250"));
+                        soap_response("Seek", &[])
+                    }
+                    "GetPositionInfo" => {
+                        soap_response("GetPositionInfo", &[("RelTime", "0:01:02.250")])
+                    }
+                    _ => soap_response(&action, &[("MediaDuration", "NOT_IMPLEMENTED")]),
+                };
+                TestResponse::ok(response)
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let renderer = MediaRendererClient::new(client);
+            renderer
+                .seek_to(Duration::from_millis(62_250))
+                .await
+                .unwrap();
+            assert_eq!(
+                renderer.position().await.unwrap(),
+                Duration::from_millis(62_250)
+            );
+            assert!(renderer.duration().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_video_metadata() {
+        let metadata = build_didl(
+            Metadata {
+                url: "http://nas/movie.mkv".to_string(),
+                title: "Big Buck Bunny".to_string(),
+                protocol_info: "http-get:*:video/x-matroska:*".to_string(),
+                duration: Some("0:09:56.000".to_string()),
+                size: Some(276134947),
+                resolution: Some("1920x1080".to_string()),
+                bitrate: Some(462500),
+                ..Default::default()
+            },
+            ObjectClass::from("object.item.videoItem"),
+        );
+        assert!(metadata.contains(r#"<item id="0" parentID="-1" restricted="1">"#));
+        assert!(metadata.contains("object.item.videoItem.movie"));
+        assert!(metadata.contains(r#"duration="0:09:56.000""#));
+        assert!(metadata.contains(r#"size="276134947""#));
+        assert!(metadata.contains(r#"resolution="1920x1080""#));
+        assert!(metadata.contains(r#"bitrate="462500""#));
+    }
+
+    #[test]
+    fn test_alternative_resources() {
+        let resource = |url: &str, protocol_info: &str| Resource {
+            url: url.to_string(),
+            protocol_info: protocol_info.to_string(),
+            ..Default::default()
+        };
+        let didl = build_didl(
+            Metadata {
+                url: "http://nas/movie.mkv".to_string(),
+                title: "Big Buck Bunny".to_string(),
+                protocol_info: "http-get:*:video/x-matroska:*".to_string(),
+                resources: vec![
+                    resource("http://nas/movie.mkv", "http-get:*:video/x-matroska:*"),
+                    Resource {
+                        bitrate: Some(250000),
+                        ..resource("http://nas/movie.mp4", "http-get:*:video/mp4:*")
+                    },
+                    resource("http://nas/movie.srt", "http-get:*:text/srt:*"),
+                ],
+                ..Default::default()
+            },
+            ObjectClass::Video,
+        );
+        let root = elementtree::Element::from_reader(didl.as_bytes()).unwrap();
+        let item = root.children().next().unwrap();
+        let res: Vec<_> = item
+            .children()
+            .filter(|c| c.tag().name() == "res")
+            .map(|res| (res.text(), res.get_attr("protocolInfo").unwrap()))
+            .collect();
+        assert_eq!(
+            res,
+            [
+                ("http://nas/movie.mkv", "http-get:*:video/x-matroska:*"),
+                ("http://nas/movie.mp4", "http-get:*:video/mp4:*"),
+                ("http://nas/movie.srt", "http-get:*:text/srt:*"),
+            ]
+        );
+        let mp4 = item.children().filter(|c| c.tag().name() == "res").nth(1);
+        assert_eq!(mp4.unwrap().get_attr("bitrate"), Some("250000"));
+    }
+
+    #[test]
+    fn test_audio_resource_attributes() {
+        let didl = build_didl(
+            Metadata {
+                url: "http://nas/1.flac".to_string(),
+                title: "Blue in Green".to_string(),
+                protocol_info: "http-get:*:audio/flac:*".to_string(),
+                bitrate: Some(576000),
+                sample_frequency: Some(192000),
+                nr_audio_channels: Some(2),
+                bits_per_sample: Some(24),
+                resources: vec![Resource {
+                    url: "http://nas/1.mp3".to_string(),
+                    protocol_info: "http-get:*:audio/mpeg:*".to_string(),
+                    bitrate: Some(40000),
+                    sample_frequency: Some(44100),
+                    nr_audio_channels: Some(1),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        let item = match parse_didl(&didl).unwrap().remove(0) {
+            DidlObject::Item(item) => item,
+            DidlObject::Container(_) => panic!("expected an item"),
+        };
+        let attributes = |res: &Resource| {
+            (
+                res.bitrate,
+                res.sample_frequency,
+                res.nr_audio_channels,
+                res.bits_per_sample,
+            )
+        };
+        assert_eq!(
+            attributes(&item.resources[0]),
+            (Some(576000), Some(192000), Some(2), Some(24))
+        );
+        assert_eq!(
+            attributes(&item.resources[1]),
+            (Some(40000), Some(44100), Some(1), None)
+        );
+    }
+
+    #[test]
+    fn test_item_attributes() {
+        let didl = build_didl(
+            Metadata {
+                id: Some("64$3&1".to_string()),
+                parent_id: Some("64$3".to_string()),
+                restricted: Some(false),
+                url: "http://nas/1.mp3".to_string(),
+                title: "Blue in Green".to_string(),
+                protocol_info: "http-get:*:audio/mpeg:*".to_string(),
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        let root = elementtree::Element::from_reader(didl.as_bytes()).unwrap();
+        let item = root.children().next().unwrap();
+        assert_eq!(item.get_attr("id"), Some("64$3&1"));
+        assert_eq!(item.get_attr("parentID"), Some("64$3"));
+        assert_eq!(item.get_attr("restricted"), Some("0"));
+
+        let didl = build_didl(
+            Metadata {
+                restricted: Some(true),
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        assert!(didl.contains(r#"<item id="0" parentID="-1" restricted="1">"#));
+    }
+
+    #[test]
+    fn test_escaping_metadata() {
+        let didl = build_didl(
+            Metadata {
+                url: "http://nas/stream?id=1&format=flac".to_string(),
+                title: "Rock & Roll <Live>\u{1}".to_string(),
+                artist: Some("\"Weird Al\" Yankovic".to_string()),
+                protocol_info: "http-get:*:audio/flac:*".to_string(),
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        let root = elementtree::Element::from_reader(didl.as_bytes()).unwrap();
+        let item = root.children().next().unwrap();
+        let text = |name: &str| {
+            item.children()
+                .find(|c| c.tag().name() == name)
+                .unwrap()
+                .text()
+        };
+        assert_eq!(text("title"), "Rock & Roll <Live>");
+        assert_eq!(text("artist"), "\"Weird Al\" Yankovic");
+        assert_eq!(text("res"), "http://nas/stream?id=1&format=flac");
+    }
+
+    #[test]
+    fn test_classical_metadata() {
+        let didl = build_didl(
+            Metadata {
+                url: "http://nas/mahler5.flac".to_string(),
+                title: "Symphony No. 5: I. Trauermarsch".to_string(),
+                artist: Some("Berliner Philharmoniker".to_string()),
+                artists: vec![
+                    Contributor::new("Berliner Philharmoniker", None),
+                    Contributor::new("Berliner Philharmoniker", Some("AlbumArtist")),
+                ],
+                composer: Some("Gustav Mahler".to_string()),
+                conductor: Some("Claudio Abbado".to_string()),
+                protocol_info: "http-get:*:audio/flac:*".to_string(),
+                ..Default::default()
+            },
+            ObjectClass::Audio,
+        );
+        let item = match &parse_didl(&didl).unwrap()[0] {
+            DidlObject::Item(item) => item.clone(),
+            DidlObject::Container(_) => panic!("expected an item"),
+        };
+        assert_eq!(
+            item.contributors,
+            [
+                Contributor::new("Berliner Philharmoniker", None),
+                Contributor::new("Berliner Philharmoniker", Some("AlbumArtist")),
+                Contributor::new("Claudio Abbado", Some("Conductor")),
+            ]
+        );
+        assert_eq!(item.composer.as_deref(), Some("Gustav Mahler"));
+        assert_eq!(item.conductor.as_deref(), Some("Claudio Abbado"));
+        assert_eq!(
+            item.to_load_options(0).unwrap().metadata.unwrap().composer,
+            item.composer
+        );
+    }
+
+    #[test]
+    fn test_desc_round_trip() {
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+            r#"<item id="1" parentID="0"><dc:title>Radio</dc:title>"#,
+            r#"<res protocolInfo="http-get:*:audio/mpeg:*">http://radio/stream</res>"#,
+            r#"<desc id="cdudn" nameSpace="urn:schemas-rinconnetworks-com:metadata-1-0/">SA_RINCON65031_</desc>"#,
+            r#"<desc id="drm" nameSpace="urn:schemas-microsoft-com:WMPNSS-1-0/"><microsoft:license xmlns:microsoft="urn:schemas-microsoft-com:WMPNSS-1-0/" kind="a&amp;b"><microsoft:key>42</microsoft:key></microsoft:license></desc>"#,
+            r#"</item></DIDL-Lite>"#
+        );
+        let item = match &parse_didl(didl).unwrap()[0] {
+            DidlObject::Item(item) => item.clone(),
+            DidlObject::Container(_) => panic!("expected an item"),
+        };
+        assert_eq!(
+            item.descs[0],
+            Desc {
+                id: "cdudn".to_string(),
+                name_space: "urn:schemas-rinconnetworks-com:metadata-1-0/".to_string(),
+                content: "SA_RINCON65031_".to_string(),
+            }
+        );
+        assert_eq!(
+            item.descs[1].content,
+            r#"<microsoft:license xmlns:microsoft="urn:schemas-microsoft-com:WMPNSS-1-0/" kind="a&amp;b"><microsoft:key>42</microsoft:key></microsoft:license>"#
+        );
+
+        let didl = build_didl(
+            item.to_load_options(0).unwrap().metadata.unwrap(),
+            ObjectClass::Audio,
+        );
+        match &parse_didl(&didl).unwrap()[0] {
+            DidlObject::Item(sent) => assert_eq!(sent.descs, item.descs),
+            DidlObject::Container(_) => panic!("expected an item"),
+        }
+    }
+
+    #[test]
+    fn test_metadata_presets() {
+        let options = |metadata: Metadata, content_type: &str| LoadOptions {
+            content_type: Some(content_type.to_string()),
+            metadata: Some(metadata),
+            ..Default::default()
+        };
+
+        let radio = options(
+            Metadata::radio_stream("http://radio/stream", "Jazz FM"),
+            "audio/mpeg",
+        );
+        let (m, object_class) = load_metadata("http://radio/stream", &radio);
+        assert_eq!(object_class, ObjectClass::AudioBroadcast);
+        assert!(validate_load("http://radio/stream", &radio)
+            .unwrap()
+            .is_empty());
+        assert!(build_didl(m, object_class).contains("object.item.audioItem.audioBroadcast"));
+
+        let episode = options(
+            Metadata::tv_episode("http://nas/s01e02.mkv", "Dark", 1, 2, "Lies"),
+            "video/x-matroska",
+        );
+        let (m, object_class) = load_metadata("http://nas/s01e02.mkv", &episode);
+        let didl = build_didl(m, object_class);
+        assert!(didl.contains("object.item.videoItem.movie"));
+        assert!(didl.contains("<upnp:seriesTitle>Dark</upnp:seriesTitle>"));
+        assert!(didl.contains("<upnp:episodeSeason>1</upnp:episodeSeason>"));
+        assert!(didl.contains("<upnp:episodeNumber>2</upnp:episodeNumber>"));
+
+        let track = LoadOptions {
+            object_class: Some(ObjectClass::AudioBook),
+            ..options(
+                Metadata::music_track("http://nas/1.mp3", "Intro", "The xx", "xx"),
+                "audio/mpeg",
+            )
+        };
+        assert_eq!(
+            load_metadata("http://nas/1.mp3", &track).1,
+            ObjectClass::AudioBook
+        );
+    }
+
+    #[test]
+    fn test_vendor_metadata() {
+        let pv = QName::new("http://www.pv.com/pvns/", "pv", "subtitleFileUri");
+        let metadata = build_didl(
+            Metadata {
+                url: "http://nas/movie.mkv".to_string(),
+                extra_elements: HashMap::from([(
+                    QName::new("http://www.sec.co.kr/", "sec", "CaptionInfoEx"),
+                    "http://nas/movie.srt".to_string(),
+                )]),
+                extra_res_attributes: HashMap::from([(pv, "http://nas/movie.srt".to_string())]),
+                ..Default::default()
+            },
+            ObjectClass::Video,
+        );
+        assert!(metadata.contains("<sec:CaptionInfoEx>http://nas/movie.srt</sec:CaptionInfoEx>"));
+        assert!(metadata.contains(r#"xmlns:pv="http://www.pv.com/pvns/""#));
+        assert!(metadata.contains(r#"pv:subtitleFileUri="http://nas/movie.srt""#));
+        assert_eq!(metadata.matches("xmlns:sec=").count(), 1);
+    }
 }
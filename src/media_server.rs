@@ -1,87 +1,1900 @@
-use std::collections::HashMap;
-
 use crate::{
+    action::{output, ActionArgs, ActionTarget},
     device_client::DeviceClient,
-    parser::parse_browse_response,
-    types::{Container, Item},
+    parser::{
+        deserialize_content_directory_with_mode, parse_action_response, parse_container_update_ids,
+        parse_didl_objects, parse_m3u,
+    },
+    runtime::sleep,
+    types::{
+        BrowseFlag, BrowseResult, Container, Criteria, DidlObject, DownloadOptions, Filter, Item,
+        ObjectChange, ParseMode, PathOptions, ServerCapabilities, SortCriteria, TransferProgress,
+        TransferStatus, UpnpClass, WalkOptions,
+    },
+    upnp_action,
+};
+use anyhow::{anyhow, Error};
+use async_stream::{stream, try_stream};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::{future::join_all, AsyncWrite, Stream, TryStreamExt};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use anyhow::Error;
+
+// How many objects paged Browse and Search calls request at once, unless
+// changed with `with_page_size`.
+const BROWSE_PAGE_SIZE: u32 = 100;
+
+const MS_REGISTRAR: &str = "urn:microsoft.com:serviceId:X_MS_MediaReceiverRegistrar";
 
 #[derive(Clone)]
 pub struct MediaServerClient {
     device_client: DeviceClient,
+    cache: Option<Arc<BrowseCache>>,
+    page_size: u32,
+    parse_mode: ParseMode,
+}
+
+// Container listings kept by `browse_children_cached`, keyed by container id
+// along with the UpdateID they were read at. Shared behind an `Arc` so the
+// code receiving ContentDirectory events can invalidate it.
+#[derive(Default)]
+pub struct BrowseCache {
+    entries: Mutex<HashMap<String, (u32, Vec<DidlObject>)>>,
+}
+
+impl BrowseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn invalidate(&self, container_id: &str) {
+        self.entries.lock().unwrap().remove(container_id);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    // Applies a ContainerUpdateIDs event value ("id,updateID,id,updateID..."),
+    // dropping the listings of containers that changed since they were read.
+    pub fn apply_container_update_ids(&self, value: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        for (id, update_id) in parse_container_update_ids(value) {
+            if entries
+                .get(&id)
+                .is_some_and(|(cached, _)| *cached != update_id)
+            {
+                entries.remove(&id);
+            }
+        }
+    }
+
+    // The cached containers whose listing a LastChange event makes stale:
+    // the parents of added and deleted objects, and the containers listing a
+    // modified object.
+    pub fn affected_containers(&self, changes: &[ObjectChange]) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        let mut affected: Vec<String> = vec![];
+        for change in changes {
+            let containers: Vec<&String> = match &change.parent_id {
+                Some(parent_id) => entries
+                    .get_key_value(parent_id)
+                    .map(|(id, _)| id)
+                    .into_iter()
+                    .collect(),
+                None => entries
+                    .iter()
+                    .filter(|(_, (_, children))| {
+                        children.iter().any(|child| child.id() == change.object_id)
+                    })
+                    .map(|(id, _)| id)
+                    .collect(),
+            };
+            for id in containers {
+                if !affected.contains(id) {
+                    affected.push(id.clone());
+                }
+            }
+        }
+        affected
+    }
+
+    pub fn apply_changes(&self, changes: &[ObjectChange]) {
+        for id in self.affected_containers(changes) {
+            self.invalidate(&id);
+        }
+    }
+
+    fn get(&self, container_id: &str) -> Option<(u32, Vec<DidlObject>)> {
+        self.entries.lock().unwrap().get(container_id).cloned()
+    }
+
+    fn insert(&self, container_id: &str, update_id: u32, children: Vec<DidlObject>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(container_id.to_string(), (update_id, children));
+    }
 }
 
 impl MediaServerClient {
     pub fn new(device_client: DeviceClient) -> Self {
-        Self { device_client }
+        Self {
+            device_client,
+            cache: None,
+            page_size: BROWSE_PAGE_SIZE,
+            parse_mode: ParseMode::default(),
+        }
+    }
+
+    // Paging copes with servers answering fewer objects than requested, but
+    // matching their own slice size saves a round trip per page.
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    // Lenient by default; `ParseMode::Strict` reports malformed Browse and
+    // Search results instead of repairing them. What was repaired ends up in
+    // `BrowseResult::warnings`.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: Arc<BrowseCache>) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
     pub async fn browse(
         &self,
         object_id: &str,
-        browse_flag: &str,
+        browse_flag: BrowseFlag,
+        filter: &Filter,
     ) -> Result<(Vec<Container>, Vec<Item>), Error> {
-        let mut params = HashMap::new();
-        params.insert("ObjectID".to_string(), object_id.to_string());
-        params.insert("BrowseFlag".to_string(), browse_flag.to_string());
-        params.insert("Filter".to_string(), "*".to_string());
-        params.insert("StartingIndex".to_string(), "0".to_string());
-        params.insert("RequestedCount".to_string(), "0".to_string());
-        params.insert("SortCriteria".to_string(), "".to_string());
+        let params = ActionArgs::new()
+            .arg("ObjectID", object_id)
+            .arg("BrowseFlag", browse_flag)
+            .arg("Filter", filter)
+            .arg("StartingIndex", 0)
+            .arg("RequestedCount", 0)
+            .arg("SortCriteria", "");
 
         let response = self
             .device_client
             .call_action("ContentDirectory", "Browse", params)
             .await?;
 
-        let ip = self.device_client.ip();
+        self.parse_result(&response)
+    }
 
-        parse_browse_response(&response, &ip)
+    // Lists the direct children of `object_id`, paging through the directory
+    // with StartingIndex/RequestedCount until TotalMatches entries were read.
+    // Within a page, containers come before items.
+    pub fn browse_children<'a>(
+        &'a self,
+        object_id: &'a str,
+    ) -> impl Stream<Item = Result<DidlObject, Error>> + 'a {
+        try_stream! {
+            let pages = self.browse_pages(object_id);
+            futures_util::pin_mut!(pages);
+            while let Some(page) = pages.try_next().await? {
+                for child in page.objects {
+                    yield child;
+                }
+            }
+        }
     }
 
-    pub async fn get_sort_capabilities(&self) -> Result<(), Error> {
-        let params = HashMap::new();
-        self.device_client
+    // Like `browse_children`, but answered from the cache set with
+    // `with_cache` while the container's UpdateID is unchanged, which costs a
+    // single BrowseMetadata request instead of the whole listing.
+    pub async fn browse_children_cached(&self, object_id: &str) -> Result<Vec<DidlObject>, Error> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.browse_children(object_id).try_collect().await,
+        };
+        if let Some((update_id, children)) = cache.get(object_id) {
+            let current = self
+                .browse_page(object_id, BrowseFlag::Metadata, 0, 1)
+                .await?
+                .update_id;
+            if current == Some(update_id) {
+                return Ok(children);
+            }
+            cache.invalidate(object_id);
+        }
+
+        let pages: Vec<BrowseResult> = self.browse_pages(object_id).try_collect().await?;
+        let update_id = pages.first().and_then(|page| page.update_id);
+        let children: Vec<DidlObject> = pages.into_iter().flat_map(|page| page.objects).collect();
+        if let Some(update_id) = update_id {
+            cache.insert(object_id, update_id, children.clone());
+        }
+        Ok(children)
+    }
+
+    fn browse_pages<'a>(
+        &'a self,
+        object_id: &'a str,
+    ) -> impl Stream<Item = Result<BrowseResult, Error>> + 'a {
+        let params = ActionArgs::new()
+            .arg("ObjectID", object_id)
+            .arg("BrowseFlag", BrowseFlag::DirectChildren)
+            .arg("Filter", Filter::all())
+            .arg("StartingIndex", 0)
+            .arg("RequestedCount", 0)
+            .arg("SortCriteria", "");
+        self.pages("Browse", params)
+    }
+
+    // Requests `params` (a Browse or Search) `page_size` objects at a time,
+    // parsing each page only once the previous one has been consumed.
+    fn pages(
+        &self,
+        action: &'static str,
+        mut params: ActionArgs,
+    ) -> impl Stream<Item = Result<BrowseResult, Error>> + '_ {
+        try_stream! {
+            let mut start = 0u32;
+            loop {
+                params.insert("StartingIndex", start);
+                params.insert("RequestedCount", self.page_size);
+                let page = self.request_page(action, params.clone()).await?;
+                let (returned, total) = (page.number_returned, page.total_matches);
+                yield page;
+
+                // Servers may return fewer objects than requested, and
+                // TotalMatches may be 0 when they can't tell: keep paging
+                // until a page comes back empty.
+                start += returned;
+                if returned == 0 || (total > 0 && start >= total) {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn browse_page(
+        &self,
+        object_id: &str,
+        browse_flag: BrowseFlag,
+        start: u32,
+        count: u32,
+    ) -> Result<BrowseResult, Error> {
+        self.browse_range(
+            object_id,
+            browse_flag,
+            &Filter::all(),
+            start,
+            count,
+            &SortCriteria::new(),
+        )
+        .await
+    }
+
+    // A single Browse request for `count` objects from `start` (0 asks for
+    // all of them), with the counts and UpdateID the server answered, for
+    // callers doing their own paging.
+    pub async fn browse_range(
+        &self,
+        object_id: &str,
+        browse_flag: BrowseFlag,
+        filter: &Filter,
+        start: u32,
+        count: u32,
+        sort: &SortCriteria,
+    ) -> Result<BrowseResult, Error> {
+        let params = ActionArgs::new()
+            .arg("ObjectID", object_id)
+            .arg("BrowseFlag", browse_flag)
+            .arg("Filter", filter)
+            .arg("StartingIndex", start)
+            .arg("RequestedCount", count)
+            .arg("SortCriteria", sort);
+        self.request_page("Browse", params).await
+    }
+
+    async fn request_page(&self, action: &str, params: ActionArgs) -> Result<BrowseResult, Error> {
+        let response = self
+            .device_client
+            .call_action("ContentDirectory", action, params)
+            .await?;
+        let values = parse_action_response(&response)?;
+        let result: String = output(&values, "Result")?;
+
+        let (mut objects, warnings) =
+            parse_didl_objects(&result, &self.device_client.ip(), self.parse_mode)?;
+        objects.sort_by_key(|object| matches!(object, DidlObject::Item(_)));
+        Ok(BrowseResult {
+            objects,
+            number_returned: output(&values, "NumberReturned")?,
+            total_matches: output(&values, "TotalMatches")?,
+            update_id: output(&values, "UpdateID").ok(),
+            warnings,
+        })
+    }
+
+    fn parse_result(&self, response: &str) -> Result<(Vec<Container>, Vec<Item>), Error> {
+        let result: String = output(&parse_action_response(response)?, "Result")?;
+        deserialize_content_directory_with_mode(&result, &self.device_client.ip(), self.parse_mode)
+    }
+
+    // The properties the server can sort on; "*" means any.
+    pub async fn sort_capabilities(&self) -> Result<Vec<String>, Error> {
+        let params = ActionArgs::new();
+        let response = self
+            .device_client
             .call_action("ContentDirectory", "GetSortCapabilities", params)
             .await?;
+        let caps: String = output(&parse_action_response(&response)?, "SortCaps")?;
 
-        todo!()
+        Ok(split_capabilities(&caps))
     }
 
-    pub async fn get_system_update_id(&self) -> Result<(), Error> {
-        let params = HashMap::new();
-        self.device_client
+    // Depth-first traversal below `root_id`, yielding the items found. Up to
+    // `options.concurrency` containers are browsed at once.
+    pub fn walk<'a>(
+        &'a self,
+        root_id: &'a str,
+        options: WalkOptions,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        try_stream! {
+            let mut pending = vec![(root_id.to_string(), 0)];
+            while !pending.is_empty() {
+                let split = pending.len().saturating_sub(options.concurrency.max(1));
+                let mut batch = pending.split_off(split);
+                batch.reverse();
+                let listings = join_all(
+                    batch
+                        .iter()
+                        .map(|(id, _)| self.browse_children(id).try_collect::<Vec<_>>()),
+                )
+                .await;
+
+                let mut containers = vec![];
+                for ((_, depth), listing) in batch.into_iter().zip(listings) {
+                    let mut children = vec![];
+                    for child in listing? {
+                        match child {
+                            DidlObject::Container(container) => children.push(container.id),
+                            DidlObject::Item(item) => {
+                                let wanted = options
+                                    .class_filter
+                                    .as_ref()
+                                    .is_none_or(|class| item.derived_from(&UpnpClass::new(class)));
+                                if wanted {
+                                    yield item;
+                                }
+                            }
+                        }
+                    }
+                    if options.max_depth.is_none_or(|max| depth + 1 < max) {
+                        let depth = depth + 1;
+                        containers.push(children.into_iter().map(move |id| (id, depth)));
+                    }
+                }
+                // Keep the first child of the first container on top.
+                for children in containers.into_iter().rev() {
+                    pending.extend(children.rev());
+                }
+            }
+        }
+    }
+
+    // A single object, as returned by BrowseMetadata.
+    pub async fn object(&self, object_id: &str) -> Result<DidlObject, Error> {
+        self.browse_page(object_id, BrowseFlag::Metadata, 0, 1)
+            .await?
+            .objects
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No object {}", object_id))
+    }
+
+    // The entries of a playlist container, references (refID) replaced by
+    // the items they point to. With `fetch_m3u`, playlist items with an M3U
+    // resource are replaced by the entries of the playlist file.
+    pub async fn playlist_items(
+        &self,
+        playlist_id: &str,
+        fetch_m3u: bool,
+    ) -> Result<Vec<Item>, Error> {
+        let children: Vec<DidlObject> = self.browse_children(playlist_id).try_collect().await?;
+        let mut items = vec![];
+        for child in children {
+            let mut item = match child {
+                DidlObject::Item(item) => item,
+                DidlObject::Container(_) => continue,
+            };
+            if let Some(ref_id) = &item.ref_id {
+                if let DidlObject::Item(referenced) = self.object(ref_id).await? {
+                    item = referenced;
+                }
+            }
+            let m3u = item.resources.iter().find(|res| {
+                let url = res.url.to_lowercase();
+                res.protocol_info.to_lowercase().contains("mpegurl")
+                    || url.ends_with(".m3u")
+                    || url.ends_with(".m3u8")
+            });
+            match m3u {
+                Some(m3u) if fetch_m3u && item.is_playlist() => {
+                    let mut content = vec![];
+                    self.device_client
+                        .download_resource(&m3u.url, &mut content, &DownloadOptions::default())
+                        .await?;
+                    items.extend(parse_m3u(&String::from_utf8_lossy(&content), &m3u.url));
+                }
+                _ => items.push(item),
+            }
+        }
+        Ok(items)
+    }
+
+    // Finds the object at a path of titles below the root container, e.g.
+    // "Music/Artists/Radiohead/OK Computer". The last segment may name an
+    // item; the others must name containers.
+    pub async fn resolve_path(&self, path: &str, options: PathOptions) -> Result<String, Error> {
+        let mut id = "0".to_string();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.trim().is_empty()).collect();
+        for (i, segment) in segments.iter().enumerate() {
+            let wanted = options.normalize(segment);
+            let last = i == segments.len() - 1;
+            let found = self
+                .browse_children_cached(&id)
+                .await?
+                .into_iter()
+                .find_map(|child| match child {
+                    DidlObject::Container(container)
+                        if options.normalize(&container.title) == wanted =>
+                    {
+                        Some(container.id)
+                    }
+                    DidlObject::Item(item) if last && options.normalize(&item.title) == wanted => {
+                        Some(item.id)
+                    }
+                    _ => None,
+                });
+            id = found.ok_or_else(|| anyhow!("No {} in {}", segment, segments[..i].join("/")))?;
+        }
+        Ok(id)
+    }
+
+    // The library generation: servers bump it whenever any object changes.
+    pub async fn system_update_id(&self) -> Result<u32, Error> {
+        let params = ActionArgs::new();
+        let response = self
+            .device_client
             .call_action("ContentDirectory", "GetSystemUpdateID", params)
             .await?;
 
-        todo!()
+        output(&parse_action_response(&response)?, "Id")
     }
 
-    pub async fn get_search_capabilities(&self) -> Result<(), Error> {
-        let params = HashMap::new();
-        self.device_client
+    // Polls SystemUpdateID every `interval`, yielding the new value whenever
+    // it differs from the previous one. Failed polls are yielded as errors
+    // and polling goes on.
+    pub fn watch_updates(&self, interval: Duration) -> impl Stream<Item = Result<u32, Error>> + '_ {
+        stream! {
+            let mut current = None;
+            loop {
+                match self.system_update_id().await {
+                    Ok(id) if current.is_some_and(|current| current != id) => {
+                        current = Some(id);
+                        yield Ok(id);
+                    }
+                    Ok(id) => current = Some(id),
+                    Err(e) => yield Err(e),
+                }
+                sleep(interval).await;
+            }
+        }
+    }
+
+    // The properties the server can search on, e.g. ["dc:title", "upnp:artist"].
+    // A server answering "*" supports every property; an empty list means it
+    // can't search at all.
+    pub async fn search_capabilities(&self) -> Result<Vec<String>, Error> {
+        let params = ActionArgs::new();
+        let response = self
+            .device_client
             .call_action("ContentDirectory", "GetSearchCapabilities", params)
             .await?;
+        let caps: String = output(&parse_action_response(&response)?, "SearchCaps")?;
 
-        todo!()
+        Ok(split_capabilities(&caps))
     }
 
-    pub async fn search(&self) -> Result<(), Error> {
-        let params = HashMap::new();
-        self.device_client
+    // Probes what the server supports, so applications can hide searching,
+    // sorting or editing up front. Optional actions missing from the SCPD
+    // aren't called.
+    pub async fn capabilities(&self) -> Result<ServerCapabilities, Error> {
+        let has_action = |action| self.device_client.has_action("ContentDirectory", action);
+        let mut capabilities = ServerCapabilities {
+            create_object: has_action("CreateObject"),
+            destroy_object: has_action("DestroyObject"),
+            update_object: has_action("UpdateObject"),
+            import_resource: has_action("ImportResource"),
+            export_resource: has_action("ExportResource"),
+            ..Default::default()
+        };
+        if has_action("GetSearchCapabilities") {
+            capabilities.search = self.search_capabilities().await?;
+        }
+        if has_action("GetSortCapabilities") {
+            capabilities.sort = self.sort_capabilities().await?;
+        }
+        if has_action("GetFeatureList") {
+            let response = self
+                .device_client
+                .call_action("ContentDirectory", "GetFeatureList", ActionArgs::new())
+                .await?;
+            capabilities.features =
+                Some(output(&parse_action_response(&response)?, "FeatureList")?);
+        }
+        Ok(capabilities)
+    }
+
+    // Searches `container_id` and its descendants, `criteria` being a
+    // ContentDirectory search expression such as
+    // `upnp:artist contains "Miles"` ("*" matches everything). A non-empty
+    // `sort` is checked against the server's sort capabilities first.
+    pub async fn search(
+        &self,
+        container_id: &str,
+        criteria: &str,
+        filter: &Filter,
+        sort: &SortCriteria,
+    ) -> Result<(Vec<Container>, Vec<Item>), Error> {
+        if !sort.is_empty() {
+            sort.validate(&self.sort_capabilities().await?)
+                .map_err(|e| anyhow!(e))?;
+        }
+
+        let params = ActionArgs::new()
+            .arg("ContainerID", container_id)
+            .arg("SearchCriteria", criteria)
+            .arg("Filter", filter)
+            .arg("StartingIndex", 0)
+            .arg("RequestedCount", 0)
+            .arg("SortCriteria", sort);
+
+        let response = self
+            .device_client
             .call_action("ContentDirectory", "Search", params)
             .await?;
 
-        todo!()
+        self.parse_result(&response)
+    }
+
+    // Like `browse_range`, for a Search.
+    pub async fn search_range(
+        &self,
+        container_id: &str,
+        criteria: &str,
+        filter: &Filter,
+        start: u32,
+        count: u32,
+        sort: &SortCriteria,
+    ) -> Result<BrowseResult, Error> {
+        let params = ActionArgs::new()
+            .arg("ContainerID", container_id)
+            .arg("SearchCriteria", criteria)
+            .arg("Filter", filter)
+            .arg("StartingIndex", start)
+            .arg("RequestedCount", count)
+            .arg("SortCriteria", sort);
+        self.request_page("Search", params).await
+    }
+
+    // Like `search`, with typed criteria checked against the server's search
+    // capabilities before anything is sent.
+    pub async fn search_matching(
+        &self,
+        container_id: &str,
+        criteria: &Criteria,
+        filter: &Filter,
+        sort: &SortCriteria,
+    ) -> Result<(Vec<Container>, Vec<Item>), Error> {
+        if criteria != &Criteria::All {
+            criteria
+                .validate(&self.search_capabilities().await?)
+                .map_err(|e| anyhow!(e))?;
+        }
+        self.search(container_id, &criteria.to_string(), filter, sort)
+            .await
+    }
+
+    // Like `search`, but streams the matches page by page, for searches
+    // matching more objects than should be held in memory at once.
+    pub fn search_stream<'a>(
+        &'a self,
+        container_id: &'a str,
+        criteria: &'a str,
+        filter: &'a Filter,
+        sort: &'a SortCriteria,
+    ) -> impl Stream<Item = Result<DidlObject, Error>> + 'a {
+        try_stream! {
+            if !sort.is_empty() {
+                sort.validate(&self.sort_capabilities().await?)
+                    .map_err(|e| anyhow!(e))?;
+            }
+            let params = ActionArgs::new()
+                .arg("ContainerID", container_id)
+                .arg("SearchCriteria", criteria)
+                .arg("Filter", filter)
+                .arg("StartingIndex", 0)
+                .arg("RequestedCount", 0)
+                .arg("SortCriteria", sort);
+            let pages = self.pages("Search", params);
+            futures_util::pin_mut!(pages);
+            while let Some(page) = pages.try_next().await? {
+                for child in page.objects {
+                    yield child;
+                }
+            }
+        }
+    }
+
+    // Creates an object under `container_id` from a DIDL-Lite document
+    // holding a single item or container, whose id is left empty for the
+    // server to assign. Returns the new id and the object as stored.
+    pub async fn create_object(
+        &self,
+        container_id: &str,
+        elements: &str,
+    ) -> Result<(String, String), Error> {
+        let params = ActionArgs::new()
+            .arg("ContainerID", container_id)
+            .arg("Elements", elements);
+        let response = self
+            .device_client
+            .call_action("ContentDirectory", "CreateObject", params)
+            .await?;
+        let values = parse_action_response(&response)?;
+
+        Ok((output(&values, "ObjectID")?, output(&values, "Result")?))
+    }
+
+    upnp_action! {
+        pub async fn destroy_object(&self, object_id: &str => "ObjectID") -> ()
+            = "ContentDirectory"."DestroyObject";
+    }
+
+    // Downloads the item's playable resource (`Item::url`) into `writer`,
+    // see `DeviceClient::download_resource`.
+    pub async fn download<W>(
+        &self,
+        item: &Item,
+        writer: &mut W,
+        options: &DownloadOptions,
+    ) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let url = match item.url.is_empty() {
+            true => item.resources.first().map(|res| res.url.as_str()),
+            false => Some(item.url.as_str()),
+        }
+        .ok_or_else(|| anyhow!("{} has no resource to download", item.title))?;
+        self.device_client
+            .download_resource(url, writer, options)
+            .await
+    }
+
+    // Samsung AllShare servers and TVs keep a resume position per video in
+    // their ContentDirectory, read and written with these vendor actions.
+    pub async fn samsung_bookmark(&self, object_id: &str) -> Result<Duration, Error> {
+        let params = ActionArgs::new().arg("ObjectID", object_id);
+        let response = self
+            .device_client
+            .call_action("ContentDirectory", "X_GetBookmark", params)
+            .await?;
+        let seconds: u64 = output(&parse_action_response(&response)?, "PosSecond")?;
+
+        Ok(Duration::from_secs(seconds))
     }
 
-    pub async fn update_object(&self) -> Result<(), Error> {
-        let params = HashMap::new();
+    pub async fn set_samsung_bookmark(
+        &self,
+        object_id: &str,
+        position: Duration,
+    ) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("CategoryType", "VIDEO")
+            .arg("RID", "0")
+            .arg("ObjectID", object_id)
+            .arg("PosSecond", position.as_secs());
+        self.device_client
+            .call_action("ContentDirectory", "X_SetBookmark", params)
+            .await?;
+        Ok(())
+    }
+
+    // Windows Media Player sharing only serves devices the user allowed,
+    // which it tracks through the Microsoft registrar service.
+    pub fn has_registrar(&self) -> bool {
+        self.device_client
+            .device()
+            .and_then(|device| device.find_service(MS_REGISTRAR))
+            .is_some()
+    }
+
+    // Whether browsing will be refused until the user allows this device
+    // in the server's sharing settings. `device_id` may be empty, as most
+    // clients send.
+    pub async fn requires_authorization(&self, device_id: &str) -> Result<bool, Error> {
+        if !self.has_registrar() {
+            return Ok(false);
+        }
+        Ok(!self.is_authorized(device_id).await?)
+    }
+
+    pub async fn is_authorized(&self, device_id: &str) -> Result<bool, Error> {
+        self.registrar_check("IsAuthorized", device_id).await
+    }
+
+    pub async fn is_validated(&self, device_id: &str) -> Result<bool, Error> {
+        self.registrar_check("IsValidated", device_id).await
+    }
+
+    // Sends a registration request message, returning the server's reply.
+    pub async fn register_device(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let params = ActionArgs::new().arg("RegistrationReqMsg", STANDARD.encode(request));
+        let response = self
+            .device_client
+            .call_action(MS_REGISTRAR, "RegisterDevice", params)
+            .await?;
+        let reply: String = output(&parse_action_response(&response)?, "RegistrationRespMsg")?;
+        Ok(STANDARD.decode(reply.trim())?)
+    }
+
+    async fn registrar_check(&self, action: &str, device_id: &str) -> Result<bool, Error> {
+        let params = ActionArgs::new().arg("DeviceID", device_id);
+        let response = self
+            .device_client
+            .call_action(MS_REGISTRAR, action, params)
+            .await?;
+        let result: i32 = output(&parse_action_response(&response)?, "Result")?;
+        Ok(result != 0)
+    }
+
+    // Asks the server to fetch `source_uri` into `destination_uri`, usually
+    // the importUri of a `res` created with `create_object`.
+    pub async fn import_resource(
+        &self,
+        source_uri: &str,
+        destination_uri: &str,
+    ) -> Result<Transfer, Error> {
+        self.start_transfer("ImportResource", source_uri, destination_uri)
+            .await
+    }
+
+    // Asks the server to push its `source_uri` to `destination_uri`.
+    pub async fn export_resource(
+        &self,
+        source_uri: &str,
+        destination_uri: &str,
+    ) -> Result<Transfer, Error> {
+        self.start_transfer("ExportResource", source_uri, destination_uri)
+            .await
+    }
+
+    async fn start_transfer(
+        &self,
+        action: &str,
+        source_uri: &str,
+        destination_uri: &str,
+    ) -> Result<Transfer, Error> {
+        let params = ActionArgs::new()
+            .arg("SourceURI", source_uri)
+            .arg("DestinationURI", destination_uri);
+        let response = self
+            .device_client
+            .call_action("ContentDirectory", action, params)
+            .await?;
+
+        Ok(Transfer {
+            media_server: self.clone(),
+            id: output(&parse_action_response(&response)?, "TransferID")?,
+        })
+    }
+
+    // Edits an object's metadata. Each change pairs the current XML fragment
+    // with its replacement, e.g. ("<dc:title>Ol</dc:title>",
+    // "<dc:title>Old</dc:title>"); an empty current fragment adds the new
+    // one, an empty new fragment deletes the current one.
+    pub async fn update_object(
+        &self,
+        object_id: &str,
+        changes: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let current: Vec<&str> = changes.iter().map(|(current, _)| *current).collect();
+        let new: Vec<&str> = changes.iter().map(|(_, new)| *new).collect();
+        let params = ActionArgs::new()
+            .arg("ObjectID", object_id)
+            .arg("CurrentTagValue", tag_value_list(&current).as_str())
+            .arg("NewTagValue", tag_value_list(&new).as_str());
         self.device_client
             .call_action("ContentDirectory", "UpdateObject", params)
             .await?;
+        Ok(())
+    }
+}
+
+// A transfer started with `import_resource` or `export_resource`.
+#[derive(Clone)]
+pub struct Transfer {
+    media_server: MediaServerClient,
+    id: u32,
+}
+
+impl Transfer {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub async fn progress(&self) -> Result<TransferProgress, Error> {
+        let params = ActionArgs::new().arg("TransferID", self.id);
+        let response = self
+            .media_server
+            .device_client
+            .call_action("ContentDirectory", "GetTransferProgress", params)
+            .await?;
+        let values = parse_action_response(&response)?;
+
+        Ok(TransferProgress {
+            status: output(&values, "TransferStatus")?,
+            length: output(&values, "TransferLength").unwrap_or(0),
+            total: output(&values, "TransferTotal").ok(),
+        })
+    }
+
+    pub async fn stop(&self) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("TransferID", self.id);
+        self.media_server
+            .device_client
+            .call_action("ContentDirectory", "StopTransferResource", params)
+            .await?;
+        Ok(())
+    }
+
+    // Polls progress every `interval` until the transfer completes; an
+    // error if the server reports it stopped or failed.
+    pub async fn wait(&self, interval: Duration) -> Result<TransferProgress, Error> {
+        loop {
+            let progress = self.progress().await?;
+            match progress.status {
+                TransferStatus::InProgress => sleep(interval).await,
+                TransferStatus::Completed => return Ok(progress),
+                status => return Err(anyhow!("Transfer {} ended with {:?}", self.id, status)),
+            }
+        }
+    }
+}
+
+// CSV of XML fragments, commas inside a fragment escaped with a backslash.
+fn tag_value_list(fragments: &[&str]) -> String {
+    let list: Vec<String> = fragments
+        .iter()
+        .map(|fragment| fragment.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect();
+    list.join(",")
+}
+
+fn split_capabilities(caps: &str) -> Vec<String> {
+    caps.split(',')
+        .map(str::trim)
+        .filter(|cap| !cap.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl ActionTarget for MediaServerClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BrowseCache, MediaServerClient};
+    use crate::{
+        device_client::DeviceClient,
+        testing::{serve, serve_device, soap_response, TestResponse},
+        types::{
+            BrowseFlag, Criteria, DidlObject, DownloadOptions, Filter, Item, ObjectChange,
+            ObjectChangeKind, PathOptions, SortCriteria, TransferProgress, TransferStatus,
+            WalkOptions,
+        },
+    };
+    use futures_util::{pin_mut, StreamExt, TryStreamExt};
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    #[test]
+    fn test_browse_flags() {
+        let url = serve_device(&["ContentDirectory"], &["Browse"], |request| {
+            assert_eq!(request.argument("ObjectID").as_deref(), Some("0"));
+            let didl = match request.argument("BrowseFlag").as_deref() {
+                Some("BrowseMetadata") => {
+                    r#"<container id="0" parentID="-1" childCount="2"><dc:title>Root</dc:title></container>"#
+                }
+                Some("BrowseDirectChildren") => {
+                    r#"<container id="1" parentID="0"><dc:title>Music</dc:title></container><item id="2" parentID="0"><dc:title>Readme</dc:title></item>"#
+                }
+                flag => panic!("unexpected BrowseFlag {:?}", flag),
+            };
+            let didl = format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">{}</DIDL-Lite>"#,
+                didl
+            );
+            TestResponse::ok(soap_response(
+                "Browse",
+                &[
+                    ("Result", &didl),
+                    ("NumberReturned", "1"),
+                    ("TotalMatches", "1"),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+
+            let (containers, items) = media_server
+                .browse("0", BrowseFlag::Metadata, &Filter::all())
+                .await
+                .unwrap();
+            assert_eq!(containers.len(), 1);
+            assert_eq!(containers[0].id, "0");
+            assert_eq!(containers[0].title, "Root");
+            assert_eq!(containers[0].child_count, Some(2));
+            assert!(items.is_empty());
+
+            let (containers, items) = media_server
+                .browse("0", BrowseFlag::DirectChildren, &Filter::all())
+                .await
+                .unwrap();
+            assert_eq!(containers[0].title, "Music");
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].id, "2");
+            assert_eq!(items[0].parent_id, "0");
+        });
+    }
+
+    #[test]
+    fn test_browse_children_pages() {
+        let url = serve_device(&["ContentDirectory"], &["Browse"], |request| {
+            assert_eq!(request.soap_action(), Some("Browse"));
+            assert_eq!(request.argument("ObjectID").as_deref(), Some("1"));
+            let start: usize = request.argument("StartingIndex").unwrap().parse().unwrap();
+            let count: usize = request.argument("RequestedCount").unwrap().parse().unwrap();
+            let end = (start + count).min(250);
+            let didl: String = (start..end)
+                .map(|i| {
+                    format!(
+                        r#"<item id="1/{0}" parentID="1"><dc:title>Track {0}</dc:title></item>"#,
+                        i
+                    )
+                })
+                .collect();
+            let didl = format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">{}</DIDL-Lite>"#,
+                didl
+            );
+            TestResponse::ok(soap_response(
+                "Browse",
+                &[
+                    ("Result", &didl),
+                    ("NumberReturned", &(end - start).to_string()),
+                    ("TotalMatches", "250"),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let children = media_server.browse_children("1");
+            pin_mut!(children);
+            let mut ids = vec![];
+            while let Some(child) = children.next().await {
+                match child.unwrap() {
+                    DidlObject::Item(item) => ids.push(item.id),
+                    DidlObject::Container(_) => panic!("unexpected container"),
+                }
+            }
+            assert_eq!(ids.len(), 250);
+            assert_eq!(ids[0], "1/0");
+            assert_eq!(ids[249], "1/249");
+
+            let page = media_server
+                .browse_range(
+                    "1",
+                    BrowseFlag::DirectChildren,
+                    &Filter::all(),
+                    240,
+                    100,
+                    &SortCriteria::new(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(page.number_returned, 10);
+            assert_eq!(page.total_matches, 250);
+            assert_eq!(page.update_id, Some(1));
+            assert_eq!(page.items().next().unwrap().id, "1/240");
+            assert_eq!(page.containers().count(), 0);
+        });
+    }
+
+    #[test]
+    fn test_search() {
+        let url = serve_device(
+            &["ContentDirectory"],
+            &["Search", "GetSortCapabilities", "GetSearchCapabilities"],
+            |request| {
+                if request.soap_action() == Some("GetSearchCapabilities") {
+                    return TestResponse::ok(soap_response(
+                        "GetSearchCapabilities",
+                        &[("SearchCaps", "upnp:class,upnp:artist")],
+                    ));
+                }
+                if request.soap_action() == Some("GetSortCapabilities") {
+                    return TestResponse::ok(soap_response(
+                        "GetSortCapabilities",
+                        &[("SortCaps", "dc:title,upnp:album")],
+                    ));
+                }
+                assert_eq!(request.soap_action(), Some("Search"));
+                assert_eq!(request.argument("ContainerID").as_deref(), Some("0"));
+                assert_eq!(
+                    request.argument("SearchCriteria").as_deref(),
+                    Some(r#"upnp:artist contains "Miles""#)
+                );
+                assert_eq!(
+                    request.argument("SortCriteria").as_deref(),
+                    Some("+dc:title,-upnp:album")
+                );
+                let didl = concat!(
+                    r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+                    r#"<item id="7" parentID="3"><dc:title>So What</dc:title><upnp:artist>Miles Davis</upnp:artist></item>"#,
+                    r#"</DIDL-Lite>"#
+                );
+                TestResponse::ok(soap_response(
+                    "Search",
+                    &[
+                        ("Result", didl),
+                        ("NumberReturned", "1"),
+                        ("TotalMatches", "1"),
+                        ("UpdateID", "1"),
+                    ],
+                ))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let sort = SortCriteria::new()
+                .ascending("dc:title")
+                .descending("upnp:album");
+            let (containers, items) = media_server
+                .search(
+                    "0",
+                    r#"upnp:artist contains "Miles""#,
+                    &Filter::all(),
+                    &sort,
+                )
+                .await
+                .unwrap();
+            assert!(containers.is_empty());
+            assert_eq!(items[0].title, "So What");
+            assert_eq!(items[0].artist.as_deref(), Some("Miles Davis"));
+
+            let unsupported = SortCriteria::new().descending("dc:date");
+            let error = media_server
+                .search("0", "*", &Filter::all(), &unsupported)
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), "Server can't sort on dc:date");
+
+            let (_, items) = media_server
+                .search_matching(
+                    "0",
+                    &Criteria::contains("upnp:artist", "Miles"),
+                    &Filter::all(),
+                    &sort,
+                )
+                .await
+                .unwrap();
+            assert_eq!(items[0].title, "So What");
+
+            let by_title = Criteria::class_derived_from("object.item.audioItem")
+                .and(Criteria::contains("dc:title", "live"));
+            let error = media_server
+                .search_matching("0", &by_title, &Filter::all(), &sort)
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), "Server can't search on dc:title");
+        });
+    }
+
+    // Answers every Search with no matches, recording its SearchCriteria.
+    fn serve_empty_search(criteria: Arc<Mutex<Vec<String>>>) -> String {
+        serve_device(&["ContentDirectory"], &["Search"], move |request| {
+            let didl =
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/"></DIDL-Lite>"#;
+            criteria
+                .lock()
+                .unwrap()
+                .push(request.argument("SearchCriteria").unwrap());
+            TestResponse::ok(soap_response(
+                "Search",
+                &[
+                    ("Result", didl),
+                    ("NumberReturned", "0"),
+                    ("TotalMatches", "0"),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        })
+    }
+
+    #[test]
+    fn test_search_ranges() {
+        let url = serve_device(&["ContentDirectory"], &["Search"], |request| {
+            assert_eq!(request.argument("ContainerID").as_deref(), Some("0"));
+            assert_eq!(request.argument("SearchCriteria").as_deref(), Some("*"));
+            assert_eq!(request.argument("StartingIndex").as_deref(), Some("20"));
+            assert_eq!(request.argument("RequestedCount").as_deref(), Some("10"));
+            assert_eq!(
+                request.argument("SortCriteria").as_deref(),
+                Some("-dc:date")
+            );
+            let didl = concat!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+                r#"<container id="3" parentID="0"><dc:title>Albums</dc:title></container>"#,
+                r#"<item id="21" parentID="3"><dc:title>Track 21</dc:title></item>"#,
+                r#"</DIDL-Lite>"#
+            );
+            // No UpdateID, as some servers answer Search.
+            TestResponse::ok(soap_response(
+                "Search",
+                &[
+                    ("Result", didl),
+                    ("NumberReturned", "2"),
+                    ("TotalMatches", "22"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let page = media_server
+                .search_range(
+                    "0",
+                    "*",
+                    &Filter::all(),
+                    20,
+                    10,
+                    &SortCriteria::new().descending("dc:date"),
+                )
+                .await
+                .unwrap();
+            assert_eq!(page.number_returned, 2);
+            assert_eq!(page.total_matches, 22);
+            assert_eq!(page.update_id, None);
+            assert_eq!(page.objects.len(), 2);
+            assert_eq!(page.containers().next().unwrap().title, "Albums");
+            assert_eq!(page.items().next().unwrap().id, "21");
+        });
+    }
+
+    #[test]
+    fn test_search_criteria_with_ampersands() {
+        let criteria = Arc::new(Mutex::new(vec![]));
+        let url = serve_empty_search(criteria.clone());
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let query = r#"dc:title contains "Rock & Roll""#;
+            let (_, items) = media_server
+                .search("0", query, &Filter::all(), &SortCriteria::new())
+                .await
+                .unwrap();
+            assert!(items.is_empty());
+            assert_eq!(*criteria.lock().unwrap(), [query]);
+        });
+    }
+
+    #[test]
+    fn test_search_criteria_operators() {
+        let criteria = Arc::new(Mutex::new(vec![]));
+        let url = serve_empty_search(criteria.clone());
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let before = Criteria::lt("dc:date", "2000-01-01")
+                .and(Criteria::eq("upnp:album", r#"Salt & "Pepper""#));
+            media_server
+                .search(
+                    "0",
+                    &before.to_string(),
+                    &Filter::all(),
+                    &SortCriteria::new(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                *criteria.lock().unwrap(),
+                [r#"dc:date < "2000-01-01" and upnp:album = "Salt & \"Pepper\"""#]
+            );
+        });
+    }
+
+    #[test]
+    fn test_search_capabilities() {
+        let url = serve_device(&["ContentDirectory"], &["GetSearchCapabilities"], |_| {
+            TestResponse::ok(soap_response(
+                "GetSearchCapabilities",
+                &[("SearchCaps", "dc:title, upnp:artist,upnp:class,@refID")],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            assert_eq!(
+                media_server.search_capabilities().await.unwrap(),
+                vec!["dc:title", "upnp:artist", "upnp:class", "@refID"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_watching_system_update_id() {
+        let mut ids = [3, 3, 4, 4, 4, 9].into_iter();
+        let url = serve_device(&["ContentDirectory"], &["GetSystemUpdateID"], move |_| {
+            let id = ids.next().unwrap_or(9).to_string();
+            TestResponse::ok(soap_response("GetSystemUpdateID", &[("Id", &id)]))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let updates = media_server.watch_updates(Duration::from_millis(1));
+            pin_mut!(updates);
+            assert_eq!(updates.next().await.unwrap().unwrap(), 4);
+            assert_eq!(updates.next().await.unwrap().unwrap(), 9);
+        });
+    }
+
+    #[test]
+    fn test_walking_a_library() {
+        let url = serve_device(&["ContentDirectory"], &["Browse"], |request| {
+            let children = match request.argument("ObjectID").unwrap().as_str() {
+                "0" => vec![
+                    ("container", "A", "object.container"),
+                    ("item", "i0", "object.item.audioItem.musicTrack"),
+                    ("container", "C", "object.container"),
+                ],
+                "A" => vec![
+                    ("container", "B", "object.container"),
+                    ("item", "a1", "object.item.videoItem.movie"),
+                ],
+                "B" => vec![("item", "b1", "object.item.audioItem.musicTrack")],
+                "C" => vec![("item", "c1", "object.item.audioItem.musicTrack")],
+                id => panic!("unexpected object {}", id),
+            };
+            let didl: String = children
+                .iter()
+                .map(|(element, id, class)| {
+                    format!(
+                        r#"<{0} id="{1}" parentID="x"><dc:title>{1}</dc:title><upnp:class>{2}</upnp:class></{0}>"#,
+                        element, id, class
+                    )
+                })
+                .collect();
+            let didl = format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">{}</DIDL-Lite>"#,
+                didl
+            );
+            let count = children.len().to_string();
+            TestResponse::ok(soap_response(
+                "Browse",
+                &[
+                    ("Result", &didl),
+                    ("NumberReturned", &count),
+                    ("TotalMatches", &count),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let walk = |options| async {
+                let items: Vec<Item> = media_server.walk("0", options).try_collect().await.unwrap();
+                items.into_iter().map(|item| item.id).collect::<Vec<_>>()
+            };
+
+            assert_eq!(walk(WalkOptions::default()).await, ["i0", "a1", "b1", "c1"]);
+            let mut concurrent = walk(WalkOptions {
+                concurrency: 4,
+                ..Default::default()
+            })
+            .await;
+            concurrent.sort();
+            assert_eq!(concurrent, ["a1", "b1", "c1", "i0"]);
+            assert_eq!(
+                walk(WalkOptions {
+                    max_depth: Some(2),
+                    ..Default::default()
+                })
+                .await,
+                ["i0", "a1", "c1"]
+            );
+            assert_eq!(
+                walk(WalkOptions {
+                    class_filter: Some("object.item.audioItem".to_string()),
+                    ..Default::default()
+                })
+                .await,
+                ["i0", "b1", "c1"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_creating_and_destroying_objects() {
+        let url = serve_device(
+            &["ContentDirectory"],
+            &["CreateObject", "DestroyObject"],
+            |request| match request.soap_action() {
+                Some("CreateObject") => {
+                    assert_eq!(request.argument("ContainerID").as_deref(), Some("12"));
+                    assert!(request
+                        .argument("Elements")
+                        .unwrap()
+                        .contains("<dc:title>Road trip</dc:title>"));
+                    TestResponse::ok(soap_response(
+                        "CreateObject",
+                        &[("ObjectID", "12$7"), ("Result", "<DIDL-Lite/>")],
+                    ))
+                }
+                _ => {
+                    assert_eq!(request.argument("ObjectID").as_deref(), Some("12$7"));
+                    TestResponse::ok(soap_response("DestroyObject", &[]))
+                }
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let didl = concat!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+                r#"<container id="" parentID="12" restricted="0"><dc:title>Road trip</dc:title>"#,
+                r#"<upnp:class>object.container.playlistContainer</upnp:class></container></DIDL-Lite>"#
+            );
+            let (id, _) = media_server.create_object("12", didl).await.unwrap();
+            assert_eq!(id, "12$7");
+            media_server.destroy_object(&id).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_importing_resources() {
+        let mut polls = 0;
+        let url = serve_device(
+            &["ContentDirectory"],
+            &["ImportResource", "GetTransferProgress"],
+            move |request| match request.soap_action() {
+                Some("ImportResource") => {
+                    assert_eq!(
+                        request.argument("SourceURI").as_deref(),
+                        Some("http://10.0.0.5/recording.ts?channel=5&quality=hd")
+                    );
+                    assert_eq!(
+                        request.argument("DestinationURI").as_deref(),
+                        Some("http://nas/import?id=12&part=1")
+                    );
+                    TestResponse::ok(soap_response("ImportResource", &[("TransferID", "4")]))
+                }
+                _ => {
+                    assert_eq!(request.argument("TransferID").as_deref(), Some("4"));
+                    polls += 1;
+                    let (status, length) = match polls {
+                        1 => ("IN_PROGRESS", "1024"),
+                        _ => ("COMPLETED", "4096"),
+                    };
+                    TestResponse::ok(soap_response(
+                        "GetTransferProgress",
+                        &[
+                            ("TransferStatus", status),
+                            ("TransferLength", length),
+                            ("TransferTotal", "4096"),
+                        ],
+                    ))
+                }
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let transfer = media_server
+                .import_resource(
+                    "http://10.0.0.5/recording.ts?channel=5&quality=hd",
+                    "http://nas/import?id=12&part=1",
+                )
+                .await
+                .unwrap();
+            assert_eq!(transfer.id(), 4);
+            let progress = transfer.wait(Duration::from_millis(1)).await.unwrap();
+            assert_eq!(
+                progress,
+                TransferProgress {
+                    status: TransferStatus::Completed,
+                    length: 4096,
+                    total: Some(4096),
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_updating_objects() {
+        let url = serve_device(&["ContentDirectory"], &["UpdateObject"], |request| {
+            assert_eq!(request.argument("ObjectID").as_deref(), Some("64$3"));
+            assert_eq!(
+                request.argument("CurrentTagValue").as_deref(),
+                Some("<dc:title>Rock and Roll</dc:title>,<upnp:genre>Rock\\, Pop</upnp:genre>,")
+            );
+            assert_eq!(
+                request.argument("NewTagValue").as_deref(),
+                Some("<dc:title>Rock &amp; Roll</dc:title>,,<upnp:rating>5</upnp:rating>")
+            );
+            TestResponse::ok(soap_response("UpdateObject", &[]))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            media_server
+                .update_object(
+                    "64$3",
+                    &[
+                        (
+                            "<dc:title>Rock and Roll</dc:title>",
+                            "<dc:title>Rock &amp; Roll</dc:title>",
+                        ),
+                        ("<upnp:genre>Rock, Pop</upnp:genre>", ""),
+                        ("", "<upnp:rating>5</upnp:rating>"),
+                    ],
+                )
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_caching_browse_results() {
+        let update_id = Arc::new(AtomicU32::new(1));
+        let listings = Arc::new(AtomicU32::new(0));
+        let url = serve_device(&["ContentDirectory"], &["Browse"], {
+            let update_id = update_id.clone();
+            let listings = listings.clone();
+            move |request| {
+                let didl = match request.argument("BrowseFlag").as_deref() {
+                    Some("BrowseDirectChildren") => {
+                        listings.fetch_add(1, Ordering::SeqCst);
+                        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/"><item id="1" parentID="7"><dc:title>One</dc:title></item></DIDL-Lite>"#
+                    }
+                    _ => {
+                        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/"><container id="7" parentID="0"><dc:title>Seven</dc:title></container></DIDL-Lite>"#
+                    }
+                };
+                TestResponse::ok(soap_response(
+                    "Browse",
+                    &[
+                        ("Result", didl),
+                        ("NumberReturned", "1"),
+                        ("TotalMatches", "1"),
+                        ("UpdateID", &update_id.load(Ordering::SeqCst).to_string()),
+                    ],
+                ))
+            }
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let cache = Arc::new(BrowseCache::new());
+            let media_server = MediaServerClient::new(client).with_cache(cache.clone());
+
+            for _ in 0..3 {
+                assert_eq!(
+                    media_server
+                        .browse_children_cached("7")
+                        .await
+                        .unwrap()
+                        .len(),
+                    1
+                );
+            }
+            assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+            update_id.store(2, Ordering::SeqCst);
+            media_server.browse_children_cached("7").await.unwrap();
+            assert_eq!(listings.load(Ordering::SeqCst), 2);
+
+            let modified = [ObjectChange {
+                kind: ObjectChangeKind::Modified,
+                object_id: "1".to_string(),
+                parent_id: None,
+                update_id: Some(3),
+            }];
+            assert_eq!(cache.affected_containers(&modified), ["7"]);
+            cache.apply_changes(&modified);
+            assert!(cache.get("7").is_none());
+
+            media_server.browse_children_cached("7").await.unwrap();
+            cache.apply_container_update_ids("7,3,12,1");
+            assert!(cache.get("7").is_none());
+        });
+    }
+
+    #[test]
+    fn test_samsung_bookmarks() {
+        let mut saved = String::from("0");
+        let url = serve_device(
+            &["ContentDirectory"],
+            &["X_GetBookmark", "X_SetBookmark"],
+            move |request| {
+                assert_eq!(request.argument("ObjectID").as_deref(), Some("V$12"));
+                match request.soap_action() {
+                    Some("X_SetBookmark") => {
+                        assert_eq!(request.argument("CategoryType").as_deref(), Some("VIDEO"));
+                        saved = request.argument("PosSecond").unwrap();
+                        TestResponse::ok(soap_response("X_SetBookmark", &[]))
+                    }
+                    _ => TestResponse::ok(soap_response("X_GetBookmark", &[("PosSecond", &saved)])),
+                }
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            media_server
+                .set_samsung_bookmark("V$12", Duration::from_secs(1312))
+                .await
+                .unwrap();
+            assert_eq!(
+                media_server.samsung_bookmark("V$12").await.unwrap(),
+                Duration::from_secs(1312)
+            );
+        });
+    }
+
+    #[test]
+    fn test_media_receiver_registrar() {
+        let url = serve_device(
+            &[
+                "ContentDirectory",
+                "microsoft.com:X_MS_MediaReceiverRegistrar",
+            ],
+            &["IsAuthorized", "IsValidated", "RegisterDevice"],
+            |request| {
+                assert_eq!(request.path, "/X_MS_MediaReceiverRegistrar/control");
+                let action = request.soap_action().unwrap().to_string();
+                let response = match action.as_str() {
+                    "RegisterDevice" => {
+                        assert_eq!(request.argument("RegistrationReqMsg").unwrap(), "AQID");
+                        soap_response(&action, &[("RegistrationRespMsg", "BAU=")])
+                    }
+                    "IsAuthorized" => soap_response(&action, &[("Result", "0")]),
+                    _ => soap_response(&action, &[("Result", "1")]),
+                };
+                TestResponse::ok(response)
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            assert!(media_server.has_registrar());
+            assert!(media_server.requires_authorization("").await.unwrap());
+            assert!(media_server.is_validated("").await.unwrap());
+            assert_eq!(
+                media_server.register_device(&[1, 2, 3]).await.unwrap(),
+                [4, 5]
+            );
+        });
+
+        let url = serve_device(&["ContentDirectory"], &[], |_| TestResponse::status(500));
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            assert!(!media_server.requires_authorization("").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_streaming_search_results() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let url = serve_device(&["ContentDirectory"], &["Search"], {
+            let requests = requests.clone();
+            move |request| {
+                requests.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(request.argument("RequestedCount").as_deref(), Some("5"));
+                // The server slices results at 3 objects per response.
+                let start: usize = request.argument("StartingIndex").unwrap().parse().unwrap();
+                let end = (start + 3).min(10);
+                let didl: String = (start..end)
+                    .map(|i| format!(r#"<item id="{}" parentID="0"></item>"#, i))
+                    .collect();
+                let didl = format!(
+                    r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/">{}</DIDL-Lite>"#,
+                    didl
+                );
+                TestResponse::ok(soap_response(
+                    "Search",
+                    &[
+                        ("Result", &didl),
+                        ("NumberReturned", &(end - start).to_string()),
+                        ("TotalMatches", "10"),
+                        ("UpdateID", "1"),
+                    ],
+                ))
+            }
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client).with_page_size(5);
+            let (filter, sort) = (Filter::all(), SortCriteria::new());
+
+            let first: Vec<DidlObject> = media_server
+                .search_stream("0", "*", &filter, &sort)
+                .take(2)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(first.len(), 2);
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+            let all: Vec<DidlObject> = media_server
+                .search_stream("0", "*", &filter, &sort)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(all.len(), 10);
+            assert_eq!(requests.load(Ordering::SeqCst), 5);
+        });
+    }
+
+    #[test]
+    fn test_resolving_paths() {
+        let url = serve_device(&["ContentDirectory"], &["Browse"], |request| {
+            let children = match request.argument("ObjectID").unwrap().as_str() {
+                "0" => {
+                    r#"<container id="1" parentID="0"><dc:title>Music</dc:title></container><container id="2" parentID="0"><dc:title>Video</dc:title></container>"#
+                }
+                "1" => {
+                    r#"<container id="11" parentID="1"><dc:title>Artists</dc:title></container>"#
+                }
+                "11" => {
+                    r#"<container id="111" parentID="11"><dc:title>Radiohead</dc:title></container>"#
+                }
+                "111" => {
+                    r#"<container id="1111" parentID="111"><dc:title>OK  Computer</dc:title></container><item id="1112" parentID="111"><dc:title>Creep</dc:title></item>"#
+                }
+                _ => "",
+            };
+            let didl = format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">{}</DIDL-Lite>"#,
+                children
+            );
+            let count = didl.matches(" id=").count().to_string();
+            TestResponse::ok(soap_response(
+                "Browse",
+                &[
+                    ("Result", &didl),
+                    ("NumberReturned", &count),
+                    ("TotalMatches", &count),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let exact = PathOptions::default();
+            let ignore_case = PathOptions { ignore_case: true };
+            assert_eq!(
+                media_server
+                    .resolve_path("Music/Artists/Radiohead/OK Computer", exact)
+                    .await
+                    .unwrap(),
+                "1111"
+            );
+            assert_eq!(
+                media_server
+                    .resolve_path("/music/artists/radiohead/creep", ignore_case)
+                    .await
+                    .unwrap(),
+                "1112"
+            );
+            let error = media_server
+                .resolve_path("Music/Artists/Blur", exact)
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), "No Blur in Music/Artists");
+        });
+    }
+
+    #[test]
+    fn test_probing_capabilities() {
+        let url = serve_device(
+            &["ContentDirectory"],
+            &[
+                "Browse",
+                "GetSearchCapabilities",
+                "GetSortCapabilities",
+                "DestroyObject",
+            ],
+            |request| match request.soap_action() {
+                Some("GetSearchCapabilities") => TestResponse::ok(soap_response(
+                    "GetSearchCapabilities",
+                    &[("SearchCaps", "dc:title,upnp:class")],
+                )),
+                Some("GetSortCapabilities") => {
+                    TestResponse::ok(soap_response("GetSortCapabilities", &[("SortCaps", "")]))
+                }
+                action => panic!("unexpected {:?}", action),
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let capabilities = media_server.capabilities().await.unwrap();
+            assert_eq!(capabilities.search, ["dc:title", "upnp:class"]);
+            assert!(capabilities.sort.is_empty());
+            assert!(capabilities.features.is_none());
+            assert!(capabilities.destroy_object);
+            assert!(!capabilities.create_object);
+        });
+    }
+
+    #[test]
+    fn test_downloading_items() {
+        const CONTENT: &str = "0123456789abcdefghij";
+        let url = serve_device(&["ContentDirectory"], &[], |request| {
+            match request.header("Range") {
+                Some(range) => {
+                    let start: usize = range
+                        .trim_start_matches("bytes=")
+                        .trim_end_matches('-')
+                        .parse()
+                        .unwrap();
+                    TestResponse::ok(CONTENT[start..].to_string())
+                        .status_code(206)
+                        .header("Content-Range", &format!("bytes {}-19/20", start))
+                }
+                None => TestResponse::ok(CONTENT.to_string()),
+            }
+        });
+        let resource = url.replace("/description.xml", "/ContentDirectory/control");
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+            let item = Item {
+                url: resource,
+                ..Default::default()
+            };
+
+            let mut full = vec![];
+            let reported = Arc::new(Mutex::new(vec![]));
+            let options = DownloadOptions {
+                progress: Some(Arc::new({
+                    let reported = reported.clone();
+                    move |done, total| reported.lock().unwrap().push((done, total))
+                })),
+                ..Default::default()
+            };
+            assert_eq!(
+                media_server
+                    .download(&item, &mut full, &options)
+                    .await
+                    .unwrap(),
+                20
+            );
+            assert_eq!(full, CONTENT.as_bytes());
+            assert_eq!(reported.lock().unwrap().last(), Some(&(20, Some(20))));
+
+            let mut rest = vec![];
+            let options = DownloadOptions {
+                offset: 12,
+                ..Default::default()
+            };
+            assert_eq!(
+                media_server
+                    .download(&item, &mut rest, &options)
+                    .await
+                    .unwrap(),
+                8
+            );
+            assert_eq!(rest, b"cdefghij");
+        });
+    }
+
+    #[test]
+    fn test_listing_playlists() {
+        let files =
+            serve(|_| TestResponse::ok("#EXTM3U\n#EXTINF:60,Intro\nintro.mp3\n".to_string()));
+        let m3u = format!("{}/files/mix.m3u", files);
+        let url = serve_device(&["ContentDirectory"], &["Browse"], move |request| {
+            let didl = match request.argument("ObjectID").unwrap().as_str() {
+                "P" => format!(
+                    concat!(
+                        r#"<item id="P$1" parentID="P" refID="T1"><dc:title>ref</dc:title><upnp:class>object.item.audioItem</upnp:class></item>"#,
+                        r#"<item id="P$2" parentID="P"><dc:title>Mix</dc:title><upnp:class>object.item.playlistItem</upnp:class>"#,
+                        r#"<res protocolInfo="http-get:*:audio/x-mpegurl:*">{}</res></item>"#
+                    ),
+                    m3u
+                ),
+                "T1" => r#"<item id="T1" parentID="A"><dc:title>Airbag</dc:title><upnp:class>object.item.audioItem.musicTrack</upnp:class><res protocolInfo="http-get:*:audio/mpeg:*">http://nas/airbag.mp3</res></item>"#.to_string(),
+                id => panic!("unexpected object {}", id),
+            };
+            let didl = format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">{}</DIDL-Lite>"#,
+                didl
+            );
+            let count = didl.matches("<item ").count().to_string();
+            TestResponse::ok(soap_response(
+                "Browse",
+                &[
+                    ("Result", &didl),
+                    ("NumberReturned", &count),
+                    ("TotalMatches", &count),
+                    ("UpdateID", "1"),
+                ],
+            ))
+        });
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let media_server = MediaServerClient::new(client);
+
+            let items = media_server.playlist_items("P", false).await.unwrap();
+            let titles: Vec<&str> = items.iter().map(|item| item.title.as_str()).collect();
+            assert_eq!(titles, ["Airbag", "Mix"]);
+            assert!(items[1].is_playlist());
 
-        todo!()
+            let items = media_server.playlist_items("P", true).await.unwrap();
+            let titles: Vec<&str> = items.iter().map(|item| item.title.as_str()).collect();
+            assert_eq!(titles, ["Airbag", "Intro"]);
+            assert!(items[1].url.ends_with("/files/intro.mp3"));
+        });
     }
 }
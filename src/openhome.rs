@@ -0,0 +1,689 @@
+use anyhow::Error;
+use async_stream::stream;
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    action::{output, ActionArgs, ActionTarget, Boolean},
+    device_client::DeviceClient,
+    media_renderer::{build_didl, load_metadata},
+    parser::{parse_action_response, parse_id_array, parse_source_list, parse_track_list},
+    types::{
+        Event, LoadOptions, OpenHomeEvent, OpenHomeTransportState, PlaylistTrack, ProductSource,
+    },
+    upnp_action, BROADCAST_EVENT,
+};
+
+const PLAYLIST: &str = "urn:av-openhome-org:serviceId:Playlist";
+const VOLUME: &str = "urn:av-openhome-org:serviceId:Volume";
+const PRODUCT: &str = "urn:av-openhome-org:serviceId:Product";
+const RADIO: &str = "urn:av-openhome-org:serviceId:Radio";
+
+// Client for the Playlist service of OpenHome renderers (Linn and others),
+// which play their own queue of tracks rather than AVTransport's one URI.
+// Tracks are addressed by the id the renderer gives them on insertion.
+#[derive(Clone)]
+pub struct PlaylistClient {
+    device_client: DeviceClient,
+}
+
+impl PlaylistClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    upnp_action! {
+        pub async fn play(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."Play";
+    }
+
+    upnp_action! {
+        pub async fn pause(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."Pause";
+    }
+
+    upnp_action! {
+        pub async fn stop(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."Stop";
+    }
+
+    upnp_action! {
+        pub async fn next(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."Next";
+    }
+
+    upnp_action! {
+        pub async fn previous(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."Previous";
+    }
+
+    // Starts playing the track.
+    upnp_action! {
+        pub async fn seek_id(&self, id: u32 => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Playlist"."SeekId";
+    }
+
+    upnp_action! {
+        pub async fn delete_id(&self, id: u32 => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Playlist"."DeleteId";
+    }
+
+    upnp_action! {
+        pub async fn delete_all(&self) -> () = "urn:av-openhome-org:serviceId:Playlist"."DeleteAll";
+    }
+
+    upnp_action! {
+        pub async fn transport_state(&self) -> ("Value": OpenHomeTransportState)
+            = "urn:av-openhome-org:serviceId:Playlist"."TransportState";
+    }
+
+    // The track playing, 0 when there is none.
+    upnp_action! {
+        pub async fn id(&self) -> ("Value": u32) = "urn:av-openhome-org:serviceId:Playlist"."Id";
+    }
+
+    // Adds `url` after the track `after_id` (0 for the start of the
+    // playlist), described like `MediaRendererClient::load` does. Returns
+    // the new track's id.
+    pub async fn insert(
+        &self,
+        after_id: u32,
+        url: &str,
+        options: &LoadOptions,
+    ) -> Result<u32, Error> {
+        let (m, object_class) = load_metadata(url, options);
+        let params = ActionArgs::new()
+            .arg("AfterId", after_id)
+            .arg("Uri", url)
+            .arg("Metadata", build_didl(m, object_class));
+        let response = self
+            .device_client
+            .call_action(PLAYLIST, "Insert", params)
+            .await?;
+        output(&parse_action_response(&response)?, "NewId")
+    }
+
+    // The ids of the playlist's tracks, in order.
+    pub async fn id_array(&self) -> Result<Vec<u32>, Error> {
+        let response = self
+            .device_client
+            .call_action(PLAYLIST, "IdArray", ActionArgs::new())
+            .await?;
+        let values = parse_action_response(&response)?;
+        parse_id_array(values.get("Array").map(String::as_str).unwrap_or_default())
+    }
+
+    pub async fn read_list(&self, ids: &[u32]) -> Result<Vec<PlaylistTrack>, Error> {
+        let ids: Vec<String> = ids.iter().map(u32::to_string).collect();
+        let params = ActionArgs::new().arg("IdList", ids.join(" "));
+        let response = self
+            .device_client
+            .call_action(PLAYLIST, "ReadList", params)
+            .await?;
+        let track_list: String = output(&parse_action_response(&response)?, "TrackList")?;
+        parse_track_list(&track_list)
+    }
+
+    // The whole playlist, in order.
+    pub async fn tracks(&self) -> Result<Vec<PlaylistTrack>, Error> {
+        let ids = self.id_array().await?;
+        let mut tracks = self.read_list(&ids).await?;
+        tracks.sort_by_key(|track| ids.iter().position(|id| *id == track.id));
+        Ok(tracks)
+    }
+
+    // TransportState, Id and IdArray changes, as `Event::OpenHome`.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = Event>, Error> {
+        subscribe(&mut self.device_client, PLAYLIST).await
+    }
+}
+
+impl ActionTarget for PlaylistClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+// Client for the OpenHome Volume service. Volumes run from 0 to the
+// device's VolumeMax, and never beyond VolumeLimit, which the user may set
+// lower to protect speakers.
+#[derive(Clone)]
+pub struct VolumeClient {
+    device_client: DeviceClient,
+}
+
+impl VolumeClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    upnp_action! {
+        pub async fn volume(&self) -> ("Value": u32) = "urn:av-openhome-org:serviceId:Volume"."Volume";
+    }
+
+    upnp_action! {
+        pub async fn set_volume(&self, volume: u32 => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Volume"."SetVolume";
+    }
+
+    upnp_action! {
+        pub async fn volume_inc(&self) -> () = "urn:av-openhome-org:serviceId:Volume"."VolumeInc";
+    }
+
+    upnp_action! {
+        pub async fn volume_dec(&self) -> () = "urn:av-openhome-org:serviceId:Volume"."VolumeDec";
+    }
+
+    upnp_action! {
+        pub async fn volume_limit(&self) -> ("Value": u32)
+            = "urn:av-openhome-org:serviceId:Volume"."VolumeLimit";
+    }
+
+    pub async fn mute(&self) -> Result<bool, Error> {
+        let response = self
+            .device_client
+            .call_action(VOLUME, "Mute", ActionArgs::new())
+            .await?;
+        Ok(output::<Boolean>(&parse_action_response(&response)?, "Value")?.0)
+    }
+
+    upnp_action! {
+        pub async fn set_mute(&self, mute: bool => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Volume"."SetMute";
+    }
+
+    // Volume, Mute and VolumeLimit changes, as `Event::OpenHome`.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = Event>, Error> {
+        subscribe(&mut self.device_client, VOLUME).await
+    }
+}
+
+impl ActionTarget for VolumeClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+// Client for the OpenHome Product service: the device's sources (inputs)
+// and standby.
+#[derive(Clone)]
+pub struct ProductClient {
+    device_client: DeviceClient,
+}
+
+impl ProductClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    pub async fn sources(&self) -> Result<Vec<ProductSource>, Error> {
+        let response = self
+            .device_client
+            .call_action(PRODUCT, "SourceXml", ActionArgs::new())
+            .await?;
+        let source_xml: String = output(&parse_action_response(&response)?, "Value")?;
+        parse_source_list(&source_xml)
+    }
+
+    upnp_action! {
+        pub async fn source_index(&self) -> ("Value": u32)
+            = "urn:av-openhome-org:serviceId:Product"."SourceIndex";
+    }
+
+    upnp_action! {
+        pub async fn set_source_index(&self, index: u32 => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Product"."SetSourceIndex";
+    }
+
+    pub async fn set_source_by_name(&self, name: &str) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("Value", name);
+        self.device_client
+            .call_action(PRODUCT, "SetSourceIndexByName", params)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn standby(&self) -> Result<bool, Error> {
+        let response = self
+            .device_client
+            .call_action(PRODUCT, "Standby", ActionArgs::new())
+            .await?;
+        Ok(output::<Boolean>(&parse_action_response(&response)?, "Value")?.0)
+    }
+
+    upnp_action! {
+        pub async fn set_standby(&self, standby: bool => "Value") -> ()
+            = "urn:av-openhome-org:serviceId:Product"."SetStandby";
+    }
+
+    // Standby and SourceIndex changes, as `Event::OpenHome`.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = Event>, Error> {
+        subscribe(&mut self.device_client, PRODUCT).await
+    }
+}
+
+impl ActionTarget for ProductClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+// Client for the OpenHome Radio service, playing internet radio channels
+// from the device's presets.
+#[derive(Clone)]
+pub struct RadioClient {
+    device_client: DeviceClient,
+}
+
+impl RadioClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    upnp_action! {
+        pub async fn play(&self) -> () = "urn:av-openhome-org:serviceId:Radio"."Play";
+    }
+
+    upnp_action! {
+        pub async fn pause(&self) -> () = "urn:av-openhome-org:serviceId:Radio"."Pause";
+    }
+
+    upnp_action! {
+        pub async fn stop(&self) -> () = "urn:av-openhome-org:serviceId:Radio"."Stop";
+    }
+
+    upnp_action! {
+        pub async fn transport_state(&self) -> ("Value": OpenHomeTransportState)
+            = "urn:av-openhome-org:serviceId:Radio"."TransportState";
+    }
+
+    // The preset selected, 0 when the channel was set by URI.
+    upnp_action! {
+        pub async fn id(&self) -> ("Value": u32) = "urn:av-openhome-org:serviceId:Radio"."Id";
+    }
+
+    // Selects the preset `id`, whose `uri` the device checks against it.
+    pub async fn set_id(&self, id: u32, uri: &str) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("Value", id).arg("Uri", uri);
+        self.device_client
+            .call_action(RADIO, "SetId", params)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn play_preset(&self, preset: &PlaylistTrack) -> Result<(), Error> {
+        self.set_id(preset.id, &preset.uri).await?;
+        self.play().await
+    }
+
+    // The presets, in order. Empty preset slots are left out.
+    pub async fn presets(&self) -> Result<Vec<PlaylistTrack>, Error> {
+        let response = self
+            .device_client
+            .call_action(RADIO, "IdArray", ActionArgs::new())
+            .await?;
+        let values = parse_action_response(&response)?;
+        let ids: Vec<String> =
+            parse_id_array(values.get("Array").map(String::as_str).unwrap_or_default())?
+                .iter()
+                .filter(|id| **id != 0)
+                .map(u32::to_string)
+                .collect();
+        let params = ActionArgs::new().arg("IdList", ids.join(" "));
+        let response = self
+            .device_client
+            .call_action(RADIO, "ReadList", params)
+            .await?;
+        let channel_list: String = output(&parse_action_response(&response)?, "ChannelList")?;
+        parse_track_list(&channel_list)
+    }
+
+    // TransportState and Id changes, as `Event::OpenHome`.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = Event>, Error> {
+        subscribe(&mut self.device_client, RADIO).await
+    }
+}
+
+impl ActionTarget for RadioClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+async fn subscribe(
+    device_client: &mut DeviceClient,
+    service_id: &str,
+) -> Result<impl Stream<Item = Event>, Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    *BROADCAST_EVENT.lock().unwrap() = Some(tx);
+
+    device_client.subscribe(service_id).await?;
+    Ok(stream! {
+        while let Some(event) = rx.recv().await {
+            yield event;
+        }
+    })
+}
+
+// The event for a variable of an OpenHome event body, if it's one the
+// crate models.
+pub(crate) fn openhome_event(sid: &str, name: &str, value: &str) -> Option<OpenHomeEvent> {
+    let sid = sid.to_string();
+    match name {
+        "TransportState" => Some(OpenHomeEvent::TransportState {
+            sid,
+            transport_state: value.parse().ok()?,
+        }),
+        "Id" => Some(OpenHomeEvent::Id {
+            sid,
+            id: value.trim().parse().ok()?,
+        }),
+        "IdArray" => Some(OpenHomeEvent::IdArray {
+            sid,
+            ids: parse_id_array(value).ok()?,
+        }),
+        "Volume" => Some(OpenHomeEvent::Volume {
+            sid,
+            volume: value.trim().parse().ok()?,
+        }),
+        "Mute" => Some(OpenHomeEvent::Mute {
+            sid,
+            mute: value.parse::<Boolean>().ok()?.0,
+        }),
+        "VolumeLimit" => Some(OpenHomeEvent::VolumeLimit {
+            sid,
+            volume_limit: value.trim().parse().ok()?,
+        }),
+        "Standby" => Some(OpenHomeEvent::Standby {
+            sid,
+            standby: value.parse::<Boolean>().ok()?.0,
+        }),
+        "SourceIndex" => Some(OpenHomeEvent::SourceIndex {
+            sid,
+            index: value.trim().parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{openhome_event, PlaylistClient, ProductClient, RadioClient, VolumeClient};
+    use crate::{
+        device_client::DeviceClient,
+        parser::parse_property_set,
+        testing::{notify, serve_device, soap_response, TestResponse, SUBSCRIPTIONS},
+        types::{
+            Event, LoadOptions, Metadata, OpenHomeEvent, OpenHomeTransportState, ProductSource,
+        },
+    };
+    use futures_util::StreamExt;
+    use std::{thread, time::Duration};
+
+    const TRACK_LIST: &str = concat!(
+        "<TrackList>",
+        "<Entry><Id>7</Id><Uri>http://nas/2.flac</Uri><Metadata></Metadata></Entry>",
+        "<Entry><Id>4</Id><Uri>http://nas/1.flac</Uri><Metadata>",
+        "&lt;DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" ",
+        "xmlns:dc=\"http://purl.org/dc/elements/1.1/\"&gt;&lt;item id=\"4\"&gt;",
+        "&lt;dc:title&gt;So What&lt;/dc:title&gt;&lt;/item&gt;&lt;/DIDL-Lite&gt;",
+        "</Metadata></Entry>",
+        "</TrackList>"
+    );
+
+    #[test]
+    fn test_playlist() {
+        let url = serve_device(
+            &["av-openhome-org:Playlist"],
+            &["IdArray", "ReadList", "Insert", "SeekId", "TransportState"],
+            |request| {
+                assert_eq!(request.path, "/Playlist/control");
+                let action = request.soap_action().unwrap().to_string();
+                let response = match action.as_str() {
+                    // Ids 4 and 7.
+                    "IdArray" => {
+                        soap_response(&action, &[("Token", "2"), ("Array", "AAAABAAAAAc=")])
+                    }
+                    "ReadList" => {
+                        assert_eq!(request.argument("IdList").unwrap(), "4 7");
+                        soap_response(&action, &[("TrackList", TRACK_LIST)])
+                    }
+                    "Insert" => {
+                        assert_eq!(request.argument("AfterId").unwrap(), "7");
+                        assert_eq!(
+                            request.argument("Uri").unwrap(),
+                            "http://nas/3.flac?a=1&b=2"
+                        );
+                        assert!(request
+                            .argument("Metadata")
+                            .unwrap()
+                            .contains("<dc:title>Blue in Green</dc:title>"));
+                        soap_response(&action, &[("NewId", "8")])
+                    }
+                    "SeekId" => {
+                        assert_eq!(request.argument("Value").unwrap(), "8");
+                        soap_response(&action, &[])
+                    }
+                    _ => soap_response(&action, &[("Value", "Buffering")]),
+                };
+                TestResponse::ok(response)
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let playlist = PlaylistClient::new(client);
+            let tracks = playlist.tracks().await.unwrap();
+            let ids: Vec<u32> = tracks.iter().map(|track| track.id).collect();
+            assert_eq!(ids, [4, 7]);
+            assert_eq!(tracks[0].uri, "http://nas/1.flac");
+            assert_eq!(tracks[0].metadata.as_ref().unwrap().title, "So What");
+            assert!(tracks[1].metadata.is_none());
+
+            let options = LoadOptions {
+                metadata: Some(Metadata {
+                    title: "Blue in Green".to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let id = playlist
+                .insert(7, "http://nas/3.flac?a=1&b=2", &options)
+                .await
+                .unwrap();
+            playlist.seek_id(id).await.unwrap();
+            assert_eq!(
+                playlist.transport_state().await.unwrap(),
+                OpenHomeTransportState::Buffering
+            );
+        });
+    }
+
+    #[test]
+    fn test_volume_and_product() {
+        let url = serve_device(
+            &["av-openhome-org:Volume", "av-openhome-org:Product"],
+            &[
+                "Volume",
+                "SetMute",
+                "Mute",
+                "SourceXml",
+                "SetSourceIndexByName",
+                "Standby",
+            ],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "Volume" => &[("Value", "42")],
+                    "SetMute" => {
+                        assert_eq!(request.argument("Value").unwrap(), "1");
+                        &[]
+                    }
+                    "Mute" => &[("Value", "true")],
+                    "SourceXml" => &[("Value", SOURCE_XML)],
+                    "SetSourceIndexByName" => {
+                        assert_eq!(request.path, "/Product/control");
+                        assert_eq!(request.argument("Value").unwrap(), "Radio");
+                        &[]
+                    }
+                    _ => &[("Value", "false")],
+                };
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let volume = VolumeClient::new(client.clone());
+            assert_eq!(volume.volume().await.unwrap(), 42);
+            volume.set_mute(true).await.unwrap();
+            assert!(volume.mute().await.unwrap());
+
+            let product = ProductClient::new(client);
+            assert_eq!(
+                product.sources().await.unwrap(),
+                [
+                    ProductSource {
+                        name: "Playlist".to_string(),
+                        source_type: "Playlist".to_string(),
+                        visible: true,
+                    },
+                    ProductSource {
+                        name: "Radio".to_string(),
+                        source_type: "Radio".to_string(),
+                        visible: true,
+                    },
+                    ProductSource {
+                        name: "Spdif".to_string(),
+                        source_type: "Digital".to_string(),
+                        visible: false,
+                    },
+                ]
+            );
+            product.set_source_by_name("Radio").await.unwrap();
+            assert!(!product.standby().await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_radio_presets() {
+        let url = serve_device(
+            &["av-openhome-org:Radio"],
+            &["IdArray", "ReadList", "SetId", "Play"],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    // Ids 2, 0 (an empty slot) and 5.
+                    "IdArray" => &[("Token", "1"), ("Array", "AAAAAgAAAAAAAAAF")],
+                    "ReadList" => {
+                        assert_eq!(request.argument("IdList").unwrap(), "2 5");
+                        &[("ChannelList", CHANNEL_LIST)]
+                    }
+                    "SetId" => {
+                        assert_eq!(request.argument("Value").unwrap(), "5");
+                        assert_eq!(request.argument("Uri").unwrap(), "http://radio/fip.mp3");
+                        &[]
+                    }
+                    _ => &[],
+                };
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let radio = RadioClient::new(client);
+            let presets = radio.presets().await.unwrap();
+            assert_eq!(presets.len(), 2);
+            assert_eq!(presets[1].id, 5);
+            assert_eq!(presets[1].uri, "http://radio/fip.mp3");
+            assert_eq!(presets[1].metadata.as_ref().unwrap().title, "FIP");
+            radio.play_preset(&presets[1]).await.unwrap();
+        });
+    }
+
+    const CHANNEL_LIST: &str = concat!(
+        "<ChannelList>",
+        "<Entry><Id>2</Id><Metadata></Metadata></Entry>",
+        "<Entry><Id>5</Id><Metadata>",
+        "&lt;DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" ",
+        "xmlns:dc=\"http://purl.org/dc/elements/1.1/\"&gt;&lt;item id=\"5\"&gt;",
+        "&lt;dc:title&gt;FIP&lt;/dc:title&gt;",
+        "&lt;res protocolInfo=\"http-get:*:audio/mpeg:*\"&gt;http://radio/fip.mp3&lt;/res&gt;",
+        "&lt;/item&gt;&lt;/DIDL-Lite&gt;",
+        "</Metadata></Entry>",
+        "</ChannelList>"
+    );
+
+    const SOURCE_XML: &str = concat!(
+        "<SourceList>",
+        "<Source><Name>Playlist</Name><Type>Playlist</Type><Visible>true</Visible></Source>",
+        "<Source><Name>Radio</Name><Type>Radio</Type><Visible>true</Visible></Source>",
+        "<Source><Name>Spdif</Name><Type>Digital</Type><Visible>false</Visible></Source>",
+        "</SourceList>"
+    );
+
+    // On a single-threaded runtime, so waiting for events must not block the
+    // eventing server.
+    #[tokio::test]
+    async fn test_playlist_subscription() {
+        let _subscriptions = SUBSCRIPTIONS.lock().await;
+        let url = serve_device(&["av-openhome-org:Playlist"], &[], |request| {
+            assert_eq!(request.method, "SUBSCRIBE");
+            let callback = request.header("CALLBACK").unwrap().to_string();
+            thread::spawn(move || {
+                notify(
+                    &callback,
+                    "uuid:1",
+                    "<TransportState>Playing</TransportState>",
+                )
+            });
+            TestResponse::status(200).header("SID", "uuid:1")
+        });
+        let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+        let mut playlist = PlaylistClient::new(device_client);
+
+        let events = playlist.subscribe().await.unwrap();
+        futures_util::pin_mut!(events);
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap();
+        assert!(matches!(
+            event,
+            Some(Event::OpenHome(OpenHomeEvent::TransportState {
+                transport_state: OpenHomeTransportState::Playing,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_playlist_events() {
+        let body = concat!(
+            r#"<?xml version="1.0"?><e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">"#,
+            "<e:property><TransportState>Playing</TransportState></e:property>",
+            "<e:property><Id>4</Id></e:property>",
+            "<e:property><IdArray>AAAABAAAAAc=</IdArray></e:property>",
+            "<e:property><Shuffle>false</Shuffle></e:property>",
+            "<e:property><Mute>1</Mute></e:property>",
+            "<e:property><VolumeLimit>80</VolumeLimit></e:property>",
+            "</e:propertyset>"
+        );
+        let events: Vec<OpenHomeEvent> = parse_property_set(body)
+            .unwrap()
+            .iter()
+            .filter_map(|(name, value)| openhome_event("uuid:1", name, value))
+            .collect();
+        assert!(matches!(
+            &events[..],
+            [
+                OpenHomeEvent::TransportState {
+                    transport_state: OpenHomeTransportState::Playing,
+                    ..
+                },
+                OpenHomeEvent::Id { id: 4, .. },
+                OpenHomeEvent::IdArray { ids, .. },
+                OpenHomeEvent::Mute { mute: true, .. },
+                OpenHomeEvent::VolumeLimit {
+                    volume_limit: 80,
+                    ..
+                },
+            ] if ids == &[4, 7]
+        ));
+    }
+}
@@ -1,20 +1,48 @@
-use std::str::Split;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
 use std::time::Duration;
 
-use crate::types::{Action, Argument, Container, Device, Item, Metadata, Service, TransportInfo};
+use crate::action::Boolean;
+use crate::compression::Decompression;
+use crate::limits::{read_body, reject_doctype, BodyLimit};
+use crate::types::{
+    Action, Argument, Container, Contributor, Desc, Device, DidlObject, Item, Limits, Metadata,
+    ObjectChange, ObjectChangeKind, ParseMode, ParseWarning, PlayMode, PlaylistTrack, PositionInfo,
+    ProductSource, ProtectionProtocols, RecordSchedule, Resource, Service, ServiceDescription,
+    StateVariable, TransportInfo, TransportState, UpnpError, UpnpTime, ZoneGroup, ZoneGroupMember,
+};
 use anyhow::{anyhow, Result};
-use elementtree::Element;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use elementtree::{Element, QName};
 use surf::{http::Method, Client, Config, Url};
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
 use xml::reader::XmlEvent;
 use xml::EventReader;
 
-pub async fn parse_location(location: &str) -> Result<Device> {
+pub(crate) fn default_http_client() -> Result<Client> {
     let client: Client = Config::new()
         .set_timeout(Some(Duration::from_secs(5)))
         .try_into()?;
-    let req = surf::Request::new(Method::Get, location.parse()?);
-    let xml_root = client
-        .recv_string(req)
+    Ok(client.with(Decompression))
+}
+
+pub async fn parse_location(location: &str) -> Result<Device> {
+    parse_location_with_client(&default_http_client()?, location).await
+}
+
+pub async fn parse_location_with_client(client: &Client, location: &str) -> Result<Device> {
+    parse_location_with_limits(client, location, &Limits::default()).await
+}
+
+pub async fn parse_location_with_limits(
+    client: &Client,
+    location: &str,
+    limits: &Limits,
+) -> Result<Device> {
+    let (location, _) = split_zone_id(location);
+    let location = location.as_str();
+    let xml_root = fetch_xml(client, location, limits.max_description_size)
         .await
         .map_err(|e| anyhow!("Failed to retrieve xml from device endpoint: {}", e))?;
 
@@ -70,14 +98,165 @@ pub async fn parse_location(location: &str) -> Result<Device> {
         "{urn:schemas-upnp-org:device-1-0}device/{urn:schemas-upnp-org:device-1-0}UDN",
     )?;
 
-    let base_url = location.split('/').take(3).collect::<Vec<&str>>().join("/");
-    device.services = parse_services(&base_url, &xml_root).await?;
+    let base_url = resolve_base_url(location, &xml_root)?;
+    let root = parse_element(&xml_root, Some(DEVICE_NS))?;
+    let root_device = root
+        .find((DEVICE_NS, "device"))
+        .ok_or_else(|| anyhow!("Invalid response from device"))?;
+    device.services = parse_service_list(&base_url, root_device)?;
+    load_service_actions(client, &mut device.services, limits).await?;
+    device.devices = parse_device_list(location, &base_url, root_device)?;
+    for embedded in &mut device.devices {
+        load_device_actions(client, embedded, limits).await?;
+    }
 
     Ok(device)
 }
 
+async fn fetch_xml(client: &Client, url: &str, max_size: usize) -> Result<String> {
+    let mut req = surf::Request::new(Method::Get, url.parse()?);
+    req.set_ext(BodyLimit(max_size));
+    let mut res = client.send(req).await.map_err(|e| anyhow!(e.to_string()))?;
+    let content_type = res.header("Content-Type").map(|v| v.as_str().to_string());
+    let body = read_body(&mut res, max_size).await?;
+    let xml = decode_xml(&body, content_type.as_deref());
+    reject_doctype(&xml)?;
+    Ok(xml)
+}
+
+// Old NAS firmwares answer in ISO-8859-1/Windows-1252, either declared in the
+// Content-Type/XML prolog or silently. Everything is normalized to UTF-8 and
+// the prolog rewritten so the XML parsers don't try to decode it again.
+pub fn decode_xml(body: &[u8], content_type: Option<&str>) -> String {
+    let charset = content_type
+        .and_then(|content_type| {
+            content_type
+                .split(';')
+                .map(str::trim)
+                .find_map(|param| param.strip_prefix("charset="))
+        })
+        .map(|charset| charset.trim_matches('"').to_ascii_lowercase())
+        .or_else(|| declared_xml_encoding(body));
+
+    let xml = match charset.as_deref() {
+        Some("iso-8859-1") | Some("latin1") | Some("windows-1252") | Some("cp1252") => {
+            decode_windows_1252(body)
+        }
+        _ => match std::str::from_utf8(body) {
+            Ok(xml) => xml.to_string(),
+            Err(_) => decode_windows_1252(body),
+        },
+    };
+
+    match charset {
+        Some(charset) if charset != "utf-8" => rewrite_declared_encoding(xml),
+        _ => xml,
+    }
+}
+
+fn declared_xml_encoding(body: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(128)]);
+    let range = declared_encoding_range(&head)?;
+    Some(head[range].to_ascii_lowercase())
+}
+
+fn rewrite_declared_encoding(mut xml: String) -> String {
+    if let Some(range) = declared_encoding_range(&xml) {
+        xml.replace_range(range, "UTF-8");
+    }
+    xml
+}
+
+fn declared_encoding_range(xml: &str) -> Option<Range<usize>> {
+    if !xml.starts_with("<?xml") {
+        return None;
+    }
+    let prolog = &xml[..xml.find("?>")?];
+    let start = prolog.find("encoding=")? + "encoding=".len();
+    let quote = prolog[start..].chars().next()?;
+    let end = prolog[start + 1..].find(quote)? + start + 1;
+    Some(start + 1..end)
+}
+
+// Windows-1252 is a superset of the printable ISO-8859-1 range, only
+// 0x80-0x9F differ from the code points of the same value.
+fn decode_windows_1252(body: &[u8]) -> String {
+    const HIGH: [char; 32] = [
+        '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}',
+        '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}',
+        '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}',
+        '\u{178}',
+    ];
+    body.iter()
+        .map(|&b| match b {
+            0x80..=0x9F => HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+const DEVICE_NS: &str = "urn:schemas-upnp-org:device-1-0";
+const SERVICE_NS: &str = "urn:schemas-upnp-org:service-1-0";
+const SOAP_NS: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+const SOAP_12_NS: &str = "http://www.w3.org/2003/05/soap-envelope";
+const DIDL_NS: &str = "urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const UPNP_NS: &str = "urn:schemas-upnp-org:metadata-1-0/upnp/";
+pub(crate) const SRS_NS: &str = "urn:schemas-upnp-org:av:srs";
+
+// Parses a document whose elements are then looked up by namespace URI, so
+// whatever prefixes the device chose don't matter. Namespaces are normalized
+// first, as devices get them slightly wrong: elements without any get
+// `default_ns`, known namespaces are matched regardless of case and trailing
+// slash, and SOAP 1.2 envelopes read as SOAP 1.1 ones.
+fn parse_element(xml: &str, default_ns: Option<&str>) -> Result<Element> {
+    let mut root = Element::from_reader(xml.as_bytes()).map_err(located_error)?;
+    normalize_namespaces(&mut root, default_ns);
+    Ok(root)
+}
+
+// elementtree counts lines and columns from 0 and leaves the position out
+// of some messages.
+fn located_error(error: elementtree::Error) -> anyhow::Error {
+    match error {
+        elementtree::Error::MalformedXml { msg, pos }
+        | elementtree::Error::UnexpectedEvent { msg, pos } => anyhow!(
+            "Malformed XML at line {}, column {}: {}",
+            pos.line() + 1,
+            pos.column() + 1,
+            msg
+        ),
+        error => error.into(),
+    }
+}
+
+fn normalize_namespaces(element: &mut Element, default_ns: Option<&str>) {
+    let name = element.tag().name().to_string();
+    let namespace = match element.tag().ns() {
+        None | Some("") => default_ns.map(str::to_string),
+        Some(ns) => Some(canonical_namespace(ns).unwrap_or(ns).to_string()),
+    };
+    if namespace.as_deref() != element.tag().ns() {
+        element.set_tag(&QName::from_ns_name(namespace.as_deref(), &name));
+    }
+    for child in element.children_mut() {
+        normalize_namespaces(child, default_ns);
+    }
+}
+
+fn canonical_namespace(ns: &str) -> Option<&'static str> {
+    let ns = ns.trim().trim_end_matches('/');
+    if ns.eq_ignore_ascii_case(SOAP_12_NS) {
+        return Some(SOAP_NS);
+    }
+    [DEVICE_NS, SERVICE_NS, SOAP_NS, DIDL_NS, DC_NS, UPNP_NS]
+        .into_iter()
+        .find(|known| known.trim_end_matches('/').eq_ignore_ascii_case(ns))
+}
+
 fn parse_attribute(xml_root: &str, xml_name: &str) -> Result<String> {
-    let root = Element::from_reader(xml_root.as_bytes())?;
+    let root = parse_element(xml_root, Some(DEVICE_NS))?;
     let mut xml_name = xml_name.split('/');
     match root.find(
         xml_name
@@ -91,9 +270,7 @@ fn parse_attribute(xml_root: &str, xml_name: &str) -> Result<String> {
                     .ok_or_else(|| anyhow!("xml_name ended unexpectedly"))?,
             );
             match element {
-                Some(element) => {
-                    return Ok(element.text().to_string());
-                }
+                Some(element) => Ok(element.text().to_string()),
                 None => Ok("".to_string()),
             }
         }
@@ -102,40 +279,64 @@ fn parse_attribute(xml_root: &str, xml_name: &str) -> Result<String> {
 }
 
 pub async fn parse_services(base_url: &str, xml_root: &str) -> Result<Vec<Service>> {
-    let root = Element::from_reader(xml_root.as_bytes())?;
+    parse_services_with_client(&default_http_client()?, base_url, xml_root).await
+}
+
+pub async fn parse_services_with_client(
+    client: &Client,
+    base_url: &str,
+    xml_root: &str,
+) -> Result<Vec<Service>> {
+    let root = parse_element(xml_root, Some(DEVICE_NS))?;
     let device = root
-        .find("{urn:schemas-upnp-org:device-1-0}device")
+        .find((DEVICE_NS, "device"))
         .ok_or_else(|| anyhow!("Invalid response from device"))?;
 
-    let mut services_with_actions: Vec<Service> = vec![];
-    if let Some(service_list) = device.find("{urn:schemas-upnp-org:device-1-0}serviceList") {
-        let xml_services = service_list.children();
+    let mut services = parse_service_list(base_url, device)?;
+    load_service_actions(client, &mut services, &Limits::default()).await?;
+    Ok(services)
+}
+
+async fn load_service_actions(
+    client: &Client,
+    services: &mut [Service],
+    limits: &Limits,
+) -> Result<()> {
+    for service in services {
+        service.actions = fetch_service_description_with_limits(client, &service.scpd_url, limits)
+            .await?
+            .actions;
+    }
+    Ok(())
+}
 
-        let mut services = Vec::new();
-        for xml_service in xml_services {
+fn parse_service_list(base_url: &str, device: &Element) -> Result<Vec<Service>> {
+    let mut services = Vec::new();
+    if let Some(service_list) = device.find((DEVICE_NS, "serviceList")) {
+        for xml_service in service_list.children() {
             let mut service = Service {
                 service_type: xml_service
-                    .find("{urn:schemas-upnp-org:device-1-0}serviceType")
+                    .find((DEVICE_NS, "serviceType"))
                     .ok_or_else(|| anyhow!("Service missing serviceType"))?
                     .text()
                     .to_string(),
                 service_id: xml_service
-                    .find("{urn:schemas-upnp-org:device-1-0}serviceId")
+                    .find((DEVICE_NS, "serviceId"))
                     .ok_or_else(|| anyhow!("Service missing serviceId"))?
                     .text()
                     .to_string(),
                 control_url: xml_service
-                    .find("{urn:schemas-upnp-org:device-1-0}controlURL")
+                    .find((DEVICE_NS, "controlURL"))
                     .ok_or_else(|| anyhow!("Service missing controlURL"))?
                     .text()
                     .to_string(),
                 event_sub_url: xml_service
-                    .find("{urn:schemas-upnp-org:device-1-0}eventSubURL")
+                    .find((DEVICE_NS, "eventSubURL"))
                     .ok_or_else(|| anyhow!("Service missing eventSubURL"))?
                     .text()
                     .to_string(),
                 scpd_url: xml_service
-                    .find("{urn:schemas-upnp-org:device-1-0}SCPDURL")
+                    .find((DEVICE_NS, "SCPDURL"))
                     .ok_or_else(|| anyhow!("Service missing SCPDURL"))?
                     .text()
                     .to_string(),
@@ -148,15 +349,133 @@ pub async fn parse_services(base_url: &str, xml_root: &str) -> Result<Vec<Servic
 
             services.push(service);
         }
+    }
+    Ok(services)
+}
+
+pub async fn parse_embedded_devices_with_client(
+    client: &Client,
+    location: &str,
+    base_url: &str,
+    xml_root: &str,
+) -> Result<Vec<Device>> {
+    let root = parse_element(xml_root, Some(DEVICE_NS))?;
+    let device = root
+        .find((DEVICE_NS, "device"))
+        .ok_or_else(|| anyhow!("Invalid response from device"))?;
+
+    let mut devices = parse_device_list(location, base_url, device)?;
+    for device in &mut devices {
+        load_device_actions(client, device, &Limits::default()).await?;
+    }
+    Ok(devices)
+}
+
+async fn load_device_actions(client: &Client, device: &mut Device, limits: &Limits) -> Result<()> {
+    load_service_actions(client, &mut device.services, limits).await?;
+    for embedded in &mut device.devices {
+        Box::pin(load_device_actions(client, embedded, limits)).await?;
+    }
+    Ok(())
+}
+
+fn parse_device_list(location: &str, base_url: &str, device: &Element) -> Result<Vec<Device>> {
+    let mut devices = Vec::new();
+    if let Some(device_list) = device.find((DEVICE_NS, "deviceList")) {
+        for xml_device in device_list.children() {
+            let text = |name: &str| {
+                xml_device
+                    .find(format!("{{urn:schemas-upnp-org:device-1-0}}{}", name).as_str())
+                    .map(|element| element.text().to_string())
+                    .unwrap_or_default()
+            };
+            let optional_text = |name: &str| match text(name) {
+                value if value.is_empty() => None,
+                value => Some(value),
+            };
+            devices.push(Device {
+                location: location.to_string(),
+                device_type: text("deviceType"),
+                friendly_name: text("friendlyName"),
+                manufacturer: text("manufacturer"),
+                manufacturer_url: optional_text("manufacturerURL"),
+                model_description: optional_text("modelDescription"),
+                model_name: text("modelName"),
+                model_number: optional_text("modelNumber"),
+                services: parse_service_list(base_url, xml_device)?,
+                devices: parse_device_list(location, base_url, xml_device)?,
+                udn: text("UDN"),
+            });
+        }
+    }
+    Ok(devices)
+}
 
-        for service in &services {
-            let mut service = service.clone();
-            service.actions = parse_service_description(&service.scpd_url).await?;
-            services_with_actions.push(service);
+// The url crate rejects zone IDs in IPv6 literals, so they are split off:
+// "http://[fe80::1%eth0]:8080/ctrl" (or the RFC 6874 "%25eth0" form) becomes
+// "http://[fe80::1]:8080/ctrl" and Some("eth0"). Zones are local to the host
+// and never sent to devices. Only the host is looked at; brackets and percent
+// signs in the path or query are left alone.
+pub fn split_zone_id(url: &str) -> (String, Option<String>) {
+    let host_start = url.find("://").map_or(0, |scheme| scheme + 3);
+    let host_end = url[host_start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |end| host_start + end);
+    let host = &url[host_start..host_end];
+    let (start, end) = match (host.find('['), host.find(']')) {
+        (Some(start), Some(end)) if start < end => (host_start + start, host_start + end),
+        _ => return (url.to_string(), None),
+    };
+    let host = &url[start + 1..end];
+    match host.split_once('%') {
+        Some((address, zone)) => {
+            let zone = zone
+                .strip_prefix("25")
+                .filter(|zone| !zone.is_empty())
+                .unwrap_or(zone);
+            (
+                format!("{}[{}]{}", &url[..start], address, &url[end + 1..]),
+                Some(zone.to_string()),
+            )
         }
+        None => (url.to_string(), None),
     }
+}
 
-    Ok(services_with_actions)
+// Interface index for a zone ID, either numeric ("3") or an interface name
+// ("eth0"). 0, the unscoped default, when no such interface exists.
+pub fn scope_id(zone: &str) -> u32 {
+    if let Ok(index) = zone.parse() {
+        return index;
+    }
+    match std::ffi::CString::new(zone) {
+        Ok(name) => interface_index(&name),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(unix)]
+fn interface_index(name: &std::ffi::CStr) -> u32 {
+    // SAFETY: `name` is a valid NUL-terminated string for the whole call.
+    unsafe { libc::if_nametoindex(name.as_ptr()) }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &std::ffi::CStr) -> u32 {
+    0
+}
+
+// Relative URLs in a description are resolved against <URLBase> when the
+// device provides one, and against the description URL itself otherwise.
+fn resolve_base_url(location: &str, xml_root: &str) -> Result<String> {
+    let root = parse_element(xml_root, Some(DEVICE_NS))?;
+    match root
+        .find((DEVICE_NS, "URLBase"))
+        .map(|url_base| url_base.text().trim())
+    {
+        Some(url_base) if !url_base.is_empty() => build_absolute_url(location, url_base),
+        _ => Ok(location.to_string()),
+    }
 }
 
 fn build_absolute_url(base_url: &str, relative_url: &str) -> Result<String> {
@@ -165,18 +484,50 @@ fn build_absolute_url(base_url: &str, relative_url: &str) -> Result<String> {
 }
 
 pub async fn parse_service_description(scpd_url: &str) -> Result<Vec<Action>> {
-    let client: Client = Config::new()
-        .set_timeout(Some(Duration::from_secs(5)))
-        .try_into()?;
-    let req = surf::Request::new(Method::Get, scpd_url.parse()?);
+    parse_service_description_with_client(&default_http_client()?, scpd_url).await
+}
 
-    let xml_root = client
-        .recv_string(req)
+pub async fn parse_service_description_with_client(
+    client: &Client,
+    scpd_url: &str,
+) -> Result<Vec<Action>> {
+    Ok(fetch_service_description_with_client(client, scpd_url)
+        .await?
+        .actions)
+}
+
+pub async fn fetch_service_description(scpd_url: &str) -> Result<ServiceDescription> {
+    fetch_service_description_with_client(&default_http_client()?, scpd_url).await
+}
+
+pub async fn fetch_service_description_with_client(
+    client: &Client,
+    scpd_url: &str,
+) -> Result<ServiceDescription> {
+    fetch_service_description_with_limits(client, scpd_url, &Limits::default()).await
+}
+
+pub async fn fetch_service_description_with_limits(
+    client: &Client,
+    scpd_url: &str,
+    limits: &Limits,
+) -> Result<ServiceDescription> {
+    let xml_root = fetch_xml(client, scpd_url, limits.max_scpd_size)
         .await
         .map_err(|e| anyhow!("Failed to retrieve xml response from device: {}", e))?;
-    let root = Element::from_reader(xml_root.as_bytes())?;
+    parse_scpd(&xml_root)
+}
+
+pub fn parse_scpd(xml_root: &str) -> Result<ServiceDescription> {
+    let root = parse_element(xml_root, Some(SERVICE_NS))?;
+    Ok(ServiceDescription {
+        actions: parse_action_list(&root)?,
+        state_variables: parse_service_state_table(&root)?,
+    })
+}
 
-    let action_list = match root.find("{urn:schemas-upnp-org:service-1-0}actionList") {
+fn parse_action_list(root: &Element) -> Result<Vec<Action>> {
+    let action_list = match root.find((SERVICE_NS, "actionList")) {
         Some(action_list) => action_list,
         None => return Ok(vec![]),
     };
@@ -185,28 +536,28 @@ pub async fn parse_service_description(scpd_url: &str) -> Result<Vec<Action>> {
     for xml_action in action_list.children() {
         let mut action = Action {
             name: xml_action
-                .find("{urn:schemas-upnp-org:service-1-0}name")
+                .find((SERVICE_NS, "name"))
                 .ok_or_else(|| anyhow!("Service::Action missing name"))?
                 .text()
                 .to_string(),
             arguments: vec![],
         };
 
-        if let Some(arguments) = xml_action.find("{urn:schemas-upnp-org:service-1-0}argumentList") {
+        if let Some(arguments) = xml_action.find((SERVICE_NS, "argumentList")) {
             for xml_argument in arguments.children() {
                 let argument = Argument {
                     name: xml_argument
-                        .find("{urn:schemas-upnp-org:service-1-0}name")
+                        .find((SERVICE_NS, "name"))
                         .ok_or_else(|| anyhow!("Service::Action::Argument missing name"))?
                         .text()
                         .to_string(),
                     direction: xml_argument
-                        .find("{urn:schemas-upnp-org:service-1-0}direction")
+                        .find((SERVICE_NS, "direction"))
                         .ok_or_else(|| anyhow!("Service::Action::Argument missing direction"))?
                         .text()
                         .to_string(),
                     related_state_variable: xml_argument
-                        .find("{urn:schemas-upnp-org:service-1-0}relatedStateVariable")
+                        .find((SERVICE_NS, "relatedStateVariable"))
                         .ok_or_else(|| {
                             anyhow!("Service::Action::Argument missing relatedStateVariable")
                         })?
@@ -221,26 +572,56 @@ pub async fn parse_service_description(scpd_url: &str) -> Result<Vec<Action>> {
     Ok(actions)
 }
 
+fn parse_service_state_table(root: &Element) -> Result<Vec<StateVariable>> {
+    let state_table = match root.find((SERVICE_NS, "serviceStateTable")) {
+        Some(state_table) => state_table,
+        None => return Ok(vec![]),
+    };
+
+    let mut state_variables = Vec::new();
+    for xml_variable in state_table.children() {
+        state_variables.push(StateVariable {
+            name: xml_variable
+                .find((SERVICE_NS, "name"))
+                .ok_or_else(|| anyhow!("Service::StateVariable missing name"))?
+                .text()
+                .to_string(),
+            data_type: xml_variable
+                .find((SERVICE_NS, "dataType"))
+                .ok_or_else(|| anyhow!("Service::StateVariable missing dataType"))?
+                .text()
+                .trim()
+                .to_string(),
+            send_events: xml_variable
+                .get_attr("sendEvents")
+                .map(|value| value == "yes")
+                .unwrap_or(false),
+            default_value: xml_variable
+                .find((SERVICE_NS, "defaultValue"))
+                .map(|value| value.text().to_string()),
+            allowed_values: xml_variable
+                .find((SERVICE_NS, "allowedValueList"))
+                .map(|list| list.children().map(|v| v.text().to_string()).collect())
+                .unwrap_or_default(),
+        });
+    }
+    Ok(state_variables)
+}
+
 pub fn parse_volume(xml_root: &str) -> Result<u8> {
     let parser = EventReader::from_str(xml_root);
     let mut in_current_volume = false;
     let mut current_volume: Option<u8> = None;
     for e in parser {
         match e {
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "CurrentVolume" {
-                    in_current_volume = true;
-                }
+            Ok(XmlEvent::StartElement { name, .. }) if name.local_name == "CurrentVolume" => {
+                in_current_volume = true;
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "CurrentVolume" {
-                    in_current_volume = false;
-                }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "CurrentVolume" => {
+                in_current_volume = false;
             }
-            Ok(XmlEvent::Characters(volume)) => {
-                if in_current_volume {
-                    current_volume = Some(volume.parse()?);
-                }
+            Ok(XmlEvent::Characters(volume)) if in_current_volume => {
+                current_volume = Some(volume.parse()?);
             }
             _ => {}
         }
@@ -248,71 +629,134 @@ pub fn parse_volume(xml_root: &str) -> Result<u8> {
     current_volume.ok_or_else(|| anyhow!("Invalid response from device"))
 }
 
-pub fn parse_duration(xml_root: &str) -> Result<u32> {
+// GetMediaInfo's MediaDuration; `None` when the renderer doesn't know it,
+// e.g. for live streams.
+pub fn parse_duration(xml_root: &str) -> Result<Option<Duration>> {
+    let values = parse_action_response(xml_root)?;
+    let duration = values
+        .get("MediaDuration")
+        .ok_or_else(|| anyhow!("Invalid response from device"))?;
+    Ok(parse_time(duration))
+}
+
+pub fn parse_position(xml_root: &str) -> Result<PositionInfo> {
+    let values = parse_action_response(xml_root)?;
+    let value = |name: &str| {
+        values
+            .get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty() && *value != "NOT_IMPLEMENTED")
+    };
+    Ok(PositionInfo {
+        track: value("Track").and_then(|track| track.parse().ok()),
+        track_duration: value("TrackDuration").and_then(parse_time),
+        track_metadata: value("TrackMetaData").map(str::to_string),
+        track_uri: value("TrackURI").map(str::to_string),
+        rel_time: value("RelTime").and_then(parse_time),
+        abs_time: value("AbsTime").and_then(parse_time),
+        rel_count: value("RelCount").and_then(parse_count),
+        abs_count: value("AbsCount").and_then(parse_count),
+    })
+}
+
+// RelCount and AbsCount; i4 in AVTransport:1 and ui4 in :2, both with their
+// maximum standing in for NOT_IMPLEMENTED.
+fn parse_count(value: &str) -> Option<u32> {
+    let count: i64 = value.parse().ok()?;
+    match count {
+        2147483647 | 4294967295 => None,
+        count => u32::try_from(count).ok(),
+    }
+}
+
+pub fn parse_query_state_variable(xml_root: &str) -> Result<String> {
     let parser = EventReader::from_str(xml_root);
-    let mut in_duration = false;
-    let mut duration: Option<String> = None;
+    let mut in_return = false;
+    let mut value: Option<String> = None;
     for e in parser {
         match e {
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "MediaDuration" {
-                    in_duration = true;
-                }
+            Ok(XmlEvent::StartElement { name, .. }) if name.local_name == "return" => {
+                in_return = true;
+                value = Some("".to_string());
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "MediaDuration" {
-                    in_duration = false;
-                }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "return" => {
+                in_return = false;
             }
-            Ok(XmlEvent::Characters(duration_str)) => {
-                if in_duration {
-                    let duration_str = duration_str.replace(':', "");
-                    duration = Some(duration_str);
-                }
+            Ok(XmlEvent::Characters(characters)) if in_return => {
+                value = Some(characters);
             }
             _ => {}
         }
     }
+    value.ok_or_else(|| anyhow!("Invalid response from device"))
+}
 
-    let duration = duration.ok_or_else(|| anyhow!("Invalid response from device"))?;
-    let hours = duration[0..2].parse::<u32>()?;
-    let minutes = duration[2..4].parse::<u32>()?;
-    let seconds = duration[4..6].parse::<u32>()?;
-    Ok(hours * 3600 + minutes * 60 + seconds)
+// The `*Response` element of an action response as a navigable tree, for
+// reading vendor extension elements the crate doesn't model.
+pub fn parse_response_element(xml_root: &str) -> Result<Element> {
+    let root = parse_element(xml_root, None)?;
+    let body = root
+        .find((SOAP_NS, "Body"))
+        .ok_or_else(|| anyhow!("Response missing SOAP body"))?;
+    let element = body
+        .children()
+        .next()
+        .ok_or_else(|| anyhow!("Response body is empty"))?;
+    match element.tag().name() {
+        "Fault" => parse_action_response(xml_root).map(|_| element.clone()),
+        _ => Ok(element.clone()),
+    }
 }
 
-pub fn parse_position(xml_root: &str) -> Result<u32> {
+// Output arguments of an action response by name, or the UPnP error carried
+// by a SOAP fault.
+pub fn parse_action_response(xml_root: &str) -> Result<HashMap<String, String>> {
     let parser = EventReader::from_str(xml_root);
-    let mut in_position = false;
-    let mut position_iter: Split<'_, &str>;
-    let mut position: Option<String> = None;
+    let mut values = HashMap::new();
+    let mut in_response = false;
+    let mut in_fault = false;
+    let mut current: Option<String> = None;
+    let mut error_code: Option<String> = None;
+    let mut error_description: Option<String> = None;
     for e in parser {
         match e {
             Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "RelTime" {
-                    in_position = true;
-                }
-            }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "RelTime" {
-                    in_position = false;
+                if name.local_name.ends_with("Response") && !in_response {
+                    in_response = true;
+                } else if name.local_name == "Fault" {
+                    in_fault = true;
+                } else if in_response || in_fault {
+                    current = Some(name.local_name.clone());
+                    if in_response {
+                        values.insert(name.local_name, "".to_string());
+                    }
                 }
             }
-            Ok(XmlEvent::Characters(position_str)) => {
-                if in_position {
-                    position = Some(position_str);
-                }
+            Ok(XmlEvent::EndElement { .. }) => {
+                current = None;
             }
+            Ok(XmlEvent::Characters(value)) => match (&current, in_fault) {
+                (Some(name), false) => {
+                    values.insert(name.clone(), value);
+                }
+                (Some(name), true) if name == "errorCode" => error_code = Some(value),
+                (Some(name), true) if name == "errorDescription" => error_description = Some(value),
+                _ => {}
+            },
             _ => {}
         }
     }
 
-    let position = position.ok_or_else(|| anyhow!("Invalid response from device"))?;
-    position_iter = position.split(":");
-    let hours = position_iter.next().unwrap_or("0").parse::<u32>()?;
-    let minutes = position_iter.next().unwrap_or("0").parse::<u32>()?;
-    let seconds = position_iter.next().unwrap_or("0").parse::<u32>()?;
-    Ok(hours * 3600 + minutes * 60 + seconds)
+    match in_fault {
+        true => Err(UpnpError {
+            code: error_code
+                .and_then(|code| code.trim().parse().ok())
+                .unwrap_or_default(),
+            description: error_description.unwrap_or_default(),
+        }
+        .into()),
+        false => Ok(values),
+    }
 }
 
 pub fn parse_supported_protocols(xml_root: &str) -> Result<Vec<String>> {
@@ -321,25 +765,187 @@ pub fn parse_supported_protocols(xml_root: &str) -> Result<Vec<String>> {
     let mut protocols: String = "".to_string();
     for e in parser {
         match e {
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "Sink" {
-                    in_protocol = true;
-                }
+            Ok(XmlEvent::StartElement { name, .. }) if name.local_name == "Sink" => {
+                in_protocol = true;
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "Sink" {
-                    in_protocol = false;
-                }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "Sink" => {
+                in_protocol = false;
             }
-            Ok(XmlEvent::Characters(protocol)) => {
-                if in_protocol {
-                    protocols = protocol;
-                }
+            Ok(XmlEvent::Characters(protocol)) if in_protocol => {
+                protocols = protocol;
             }
             _ => {}
         }
     }
-    Ok(protocols.split(',').map(|s| s.to_string()).collect())
+    Ok(protocols
+        .split(',')
+        .map(str::trim)
+        .filter(|protocol| !protocol.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+// The variables of a GENA event body, in order. Services without
+// LastChange, like OpenHome's, send each changed variable as a property.
+pub fn parse_property_set(xml: &str) -> Result<Vec<(String, String)>> {
+    let root = parse_element(xml, None)?;
+    Ok(root
+        .children()
+        .flat_map(|property| property.children())
+        .map(|variable| {
+            (
+                variable.tag().name().to_string(),
+                variable.text().to_string(),
+            )
+        })
+        .collect())
+}
+
+// An OpenHome IdArray: base64 of big-endian 32 bit track ids.
+pub fn parse_id_array(value: &str) -> Result<Vec<u32>> {
+    let bytes = STANDARD.decode(value.trim())?;
+    if bytes.len() % 4 != 0 {
+        return Err(anyhow!("Invalid id array of {} bytes", bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|id| u32::from_be_bytes([id[0], id[1], id[2], id[3]]))
+        .collect())
+}
+
+// The SourceXml of an OpenHome Product, sources in index order.
+pub fn parse_source_list(xml: &str) -> Result<Vec<ProductSource>> {
+    let root = parse_element(xml, None)?;
+    Ok(root
+        .find_all("Source")
+        .map(|source| {
+            let text = |name: &str| {
+                source
+                    .find(name)
+                    .map(|child| child.text().trim().to_string())
+                    .unwrap_or_default()
+            };
+            ProductSource {
+                name: text("Name"),
+                source_type: text("Type"),
+                visible: !matches!(text("Visible").parse(), Ok(Boolean(false))),
+            }
+        })
+        .collect())
+}
+
+// The record schedules of a ScheduledRecording BrowseRecordSchedules or
+// GetRecordSchedule Result.
+pub fn parse_record_schedules(xml: &str) -> Result<Vec<RecordSchedule>> {
+    let root = parse_element(xml, Some(SRS_NS))?;
+    Ok(root
+        .find_all((SRS_NS, "item"))
+        .map(|item| {
+            let text = |name: &str| {
+                item.find((SRS_NS, name))
+                    .map(|child| child.text().trim().to_string())
+            };
+            RecordSchedule {
+                id: item.get_attr("id").unwrap_or_default().to_string(),
+                title: text("title").unwrap_or_default(),
+                class: text("class").unwrap_or_default(),
+                channel_id: text("scheduledChannelID"),
+                start: text("scheduledStartDateTime"),
+                duration: text("scheduledDuration").and_then(|value| parse_srs_duration(&value)),
+                priority: text("priority"),
+                state: text("scheduleState"),
+            }
+        })
+        .collect())
+}
+
+// An SRS duration: P[nD]hh:mm:ss.
+pub(crate) fn parse_srs_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().strip_prefix('P')?;
+    let (days, time) = match value.split_once('D') {
+        Some((days, time)) => (days.parse::<u64>().ok()?, time),
+        None => (0, value),
+    };
+    let time: UpnpTime = time.parse().ok()?;
+    Some(Duration::from_secs(days * 86400) + time.0)
+}
+
+// A DeviceProtection ProtocolList, e.g.
+// <SupportedProtocols><Introduction><Name>WPS</Name></Introduction>
+// <Login><Name>PKCS5</Name></Login></SupportedProtocols>
+pub fn parse_protection_protocols(xml: &str) -> Result<ProtectionProtocols> {
+    let root = parse_element(xml, None)?;
+    let names = |kind: &str| {
+        root.find_all(kind)
+            .flat_map(|protocol| protocol.find_all("Name"))
+            .map(|name| name.text().trim().to_string())
+            .collect()
+    };
+    Ok(ProtectionProtocols {
+        introduction: names("Introduction"),
+        login: names("Login"),
+    })
+}
+
+// A Sonos ZoneGroupState. Older firmware sends the ZoneGroups element as the
+// root, newer firmware wraps it in a ZoneGroupState one.
+pub fn parse_zone_group_state(xml: &str) -> Result<Vec<ZoneGroup>> {
+    let root = parse_element(xml, None)?;
+    let groups = match root.find("ZoneGroups") {
+        Some(groups) => groups,
+        None => &root,
+    };
+    Ok(groups
+        .find_all("ZoneGroup")
+        .map(|group| {
+            let attr = |element: &Element, name: &str| {
+                element.get_attr(name).unwrap_or_default().to_string()
+            };
+            ZoneGroup {
+                id: attr(group, "ID"),
+                coordinator: attr(group, "Coordinator"),
+                members: group
+                    .find_all("ZoneGroupMember")
+                    .map(|member| ZoneGroupMember {
+                        player_id: attr(member, "UUID"),
+                        zone_name: attr(member, "ZoneName"),
+                        location: attr(member, "Location"),
+                        invisible: matches!(member.get_attr("Invisible"), Some("1")),
+                    })
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+// The TrackList of an OpenHome Playlist ReadList response, or the
+// ChannelList of a Radio one. Radio entries have no Uri, so it's taken from
+// the metadata. Entries whose metadata isn't usable DIDL-Lite have none.
+pub fn parse_track_list(xml: &str) -> Result<Vec<PlaylistTrack>> {
+    let root = parse_element(xml, None)?;
+    root.find_all("Entry")
+        .map(|entry| {
+            let text = |name: &str| entry.find(name).map(|child| child.text().to_string());
+            let metadata =
+                parse_didl_with_mode(&text("Metadata").unwrap_or_default(), ParseMode::Lenient)
+                    .ok()
+                    .and_then(|objects| {
+                        objects.into_iter().find_map(|object| match object {
+                            DidlObject::Item(item) => Some(item),
+                            DidlObject::Container(_) => None,
+                        })
+                    });
+            let uri = text("Uri").or_else(|| {
+                let resource = metadata.as_ref()?.resources.first()?;
+                Some(resource.url.clone())
+            });
+            Ok(PlaylistTrack {
+                id: text("Id").unwrap_or_default().trim().parse()?,
+                uri: uri.unwrap_or_default(),
+                metadata,
+            })
+        })
+        .collect()
 }
 
 pub fn parse_last_change(xml_root: &str) -> Result<Option<String>> {
@@ -348,20 +954,14 @@ pub fn parse_last_change(xml_root: &str) -> Result<Option<String>> {
     let mut in_last_change = false;
     for e in parser {
         match e {
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "LastChange" {
-                    in_last_change = true;
-                }
+            Ok(XmlEvent::StartElement { name, .. }) if name.local_name == "LastChange" => {
+                in_last_change = true;
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "LastChange" {
-                    in_last_change = false;
-                }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "LastChange" => {
+                in_last_change = false;
             }
-            Ok(XmlEvent::Characters(last_change)) => {
-                if in_last_change {
-                    result = Some(last_change);
-                }
+            Ok(XmlEvent::Characters(last_change)) if in_last_change => {
+                result = Some(last_change);
             }
             _ => {}
         }
@@ -369,9 +969,9 @@ pub fn parse_last_change(xml_root: &str) -> Result<Option<String>> {
     Ok(result)
 }
 
-pub fn parse_current_play_mode(xml_root: &str) -> Result<Option<String>> {
+pub fn parse_current_play_mode(xml_root: &str) -> Result<Option<PlayMode>> {
     let parser = EventReader::from_str(xml_root);
-    let mut current_play_mode: Option<String> = None;
+    let mut current_play_mode: Option<PlayMode> = None;
     for e in parser.into_iter().flatten() {
         if let XmlEvent::StartElement {
             name, attributes, ..
@@ -380,7 +980,7 @@ pub fn parse_current_play_mode(xml_root: &str) -> Result<Option<String>> {
             if name.local_name == "CurrentPlayMode" {
                 for attr in attributes {
                     if attr.name.local_name == "val" {
-                        current_play_mode = Some(attr.value);
+                        current_play_mode = attr.value.parse().ok();
                     }
                 }
             }
@@ -389,9 +989,9 @@ pub fn parse_current_play_mode(xml_root: &str) -> Result<Option<String>> {
     Ok(current_play_mode)
 }
 
-pub fn parse_transport_state(xml_root: &str) -> Result<Option<String>> {
+pub fn parse_transport_state(xml_root: &str) -> Result<Option<TransportState>> {
     let parser = EventReader::from_str(xml_root);
-    let mut transport_state: Option<String> = None;
+    let mut transport_state: Option<TransportState> = None;
     for e in parser.into_iter().flatten() {
         if let XmlEvent::StartElement {
             name, attributes, ..
@@ -400,7 +1000,7 @@ pub fn parse_transport_state(xml_root: &str) -> Result<Option<String>> {
             if name.local_name == "TransportState" {
                 for attr in attributes {
                     if attr.name.local_name == "val" {
-                        transport_state = Some(attr.value);
+                        transport_state = attr.value.parse().ok();
                     }
                 }
             }
@@ -534,20 +1134,14 @@ pub fn parse_browse_response(xml: &str, ip: &str) -> Result<(Vec<Container>, Vec
 
     for e in parser {
         match e {
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == "Result" {
-                    in_result = true;
-                }
+            Ok(XmlEvent::StartElement { name, .. }) if name.local_name == "Result" => {
+                in_result = true;
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "Result" {
-                    in_result = false;
-                }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "Result" => {
+                in_result = false;
             }
-            Ok(XmlEvent::Characters(value)) => {
-                if in_result {
-                    result = deserialize_content_directory(&value, ip)?;
-                }
+            Ok(XmlEvent::Characters(value)) if in_result => {
+                result = deserialize_content_directory(&value, ip)?;
             }
             _ => {}
         }
@@ -555,164 +1149,561 @@ pub fn parse_browse_response(xml: &str, ip: &str) -> Result<(Vec<Container>, Vec
     Ok(result)
 }
 
-pub fn deserialize_content_directory(xml: &str, ip: &str) -> Result<(Vec<Container>, Vec<Item>)> {
-    let parser = EventReader::from_str(xml);
-    let mut in_container = false;
-    let mut in_item = false;
-    let mut in_title = false;
-    let mut in_artist = false;
-    let mut in_album = false;
-    let mut in_album_art = false;
-    let mut in_genre = false;
-    let mut in_class = false;
-    let mut in_res = false;
-    let mut containers: Vec<Container> = Vec::new();
-    let mut items: Vec<Item> = Vec::new();
+// Properties some servers send without their dc: or upnp: namespace end
+// up in the DIDL-Lite one.
+fn didl_text(element: &Element, ns: &str, name: &str) -> Option<String> {
+    element
+        .find((ns, name))
+        .or_else(|| element.find((DIDL_NS, name)))
+        .map(|child| child.text().trim().to_string())
+}
 
-    for e in parser {
-        match e {
-            Ok(XmlEvent::StartElement {
-                name, attributes, ..
-            }) => {
-                if name.local_name == "container" {
-                    in_container = true;
-                    let mut container = Container::default();
-                    for attr in attributes.clone() {
-                        if attr.name.local_name == "id" {
-                            container.id = attr.value.clone();
-                        }
-                        if attr.name.local_name == "parentID" {
-                            container.parent_id = attr.value.clone();
-                        }
-                    }
-                    containers.push(container);
-                }
-                if name.local_name == "item" {
-                    in_item = true;
-                    let mut item = Item::default();
-                    for attr in attributes.clone() {
-                        if attr.name.local_name == "id" {
-                            item.id = attr.value.clone();
-                        }
-                        if attr.name.local_name == "parentID" {
-                            item.parent_id = attr.value.clone();
-                        }
-                    }
-                    items.push(item);
-                }
-                if name.local_name == "title" {
-                    in_title = true;
-                }
-                if name.local_name == "artist" {
-                    in_artist = true;
-                }
-                if name.local_name == "album" {
-                    in_album = true;
-                }
-                if name.local_name == "albumArtURI" {
-                    in_album_art = true;
-                }
-                if name.local_name == "genre" {
-                    in_genre = true;
-                }
-                if name.local_name == "class" {
-                    in_class = true;
-                }
-                if name.local_name == "res" {
-                    for attr in attributes {
-                        if attr.name.local_name == "protocolInfo"
-                            && (attr.value.clone().contains("audio")
-                                || attr.value.clone().contains("video"))
-                        {
-                            items.last_mut().unwrap().protocol_info = attr.value.clone();
-                        }
-                        if attr.name.local_name == "size" {
-                            items.last_mut().unwrap().size = Some(attr.value.parse::<u64>()?);
-                        }
-                        if attr.name.local_name == "duration" {
-                            items.last_mut().unwrap().duration = Some(attr.value.clone());
-                        }
-                    }
-                    in_res = true;
-                }
+fn didl_flag(element: &Element, name: &str) -> bool {
+    matches!(element.get_attr(name), Some("1") | Some("true"))
+}
+
+// Escapes a DIDL-Lite document for a SOAP argument value written by hand.
+// `ActionArgs` values are escaped when the envelope is built, so they take
+// the document as it is.
+pub fn escape_metadata(didl: &str) -> String {
+    escape_str_attribute(didl).into_owned()
+}
+
+// Turns a metadata value from a device back into a DIDL-Lite document,
+// undoing as many rounds of escaping as it went through: none once the
+// response is parsed, one for raw SOAP text, more for the renderers that
+// escape metadata again before putting it in a response or LastChange.
+pub fn unescape_metadata(value: &str) -> String {
+    let mut didl = value.trim().to_string();
+    while didl.starts_with("&lt;") || didl.starts_with("&amp;") {
+        didl = unescape_entities(&didl);
+    }
+    didl
+}
+
+// Undoes further rounds of escaping (the document then starts with
+// "&lt;") and escapes ampersands that don't start an entity.
+fn repair_didl(xml: &str, warnings: &mut Vec<ParseWarning>) -> String {
+    let xml = xml.trim();
+    let xml = match xml.starts_with("&lt;") {
+        true => {
+            warnings.push(ParseWarning::DoubleEscaped);
+            unescape_metadata(xml)
+        }
+        false => xml.to_string(),
+    };
+    let mut repaired = String::with_capacity(xml.len());
+    let mut unescaped_ampersand = false;
+    for (i, c) in xml.char_indices() {
+        repaired.push(c);
+        if c == '&' && !starts_with_entity(&xml[i + 1..]) {
+            repaired.push_str("amp;");
+            unescaped_ampersand = true;
+        }
+    }
+    if unescaped_ampersand {
+        warnings.push(ParseWarning::UnescapedAmpersand);
+    }
+    repaired
+}
+
+fn starts_with_entity(rest: &str) -> bool {
+    let name = match rest.split_once(';') {
+        Some((name, _)) => name,
+        None => return false,
+    };
+    match name.strip_prefix('#') {
+        Some(code) => match code.strip_prefix('x') {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+    }
+}
+
+fn unescape_entities(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = ["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"]
+            .iter()
+            .zip(["&", "<", ">", "\"", "'"])
+            .find(|(entity, _)| rest.starts_with(*entity));
+        match entity {
+            Some((entity, c)) => {
+                unescaped.push_str(c);
+                rest = &rest[entity.len()..];
             }
-            Ok(XmlEvent::EndElement { name }) => {
-                if name.local_name == "container" {
-                    in_container = false;
-                }
-                if name.local_name == "item" {
-                    in_item = false;
-                }
-                if name.local_name == "title" {
-                    in_title = false;
-                }
-                if name.local_name == "artist" {
-                    in_artist = false;
-                }
-                if name.local_name == "album" {
-                    in_album = false;
-                }
-                if name.local_name == "albumArtURI" {
-                    in_album_art = false;
-                }
-                if name.local_name == "genre" {
-                    in_genre = false;
-                }
-                if name.local_name == "class" {
-                    in_class = false;
-                }
-                if name.local_name == "res" {
-                    in_res = false;
-                }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
             }
-            Ok(XmlEvent::Characters(value)) => {
-                if in_container {
-                    if let Some(container) = containers.last_mut() {
-                        if in_title {
-                            container.title = value.clone();
-                        }
-                        if in_class {
-                            container.object_class = Some(value.as_str().into());
-                        }
-                    }
-                }
-                if in_item {
-                    if let Some(item) = items.last_mut() {
-                        if in_title {
-                            item.title = value.clone();
-                        }
-                        if in_artist {
-                            item.artist = Some(value.clone());
-                        }
-                        if in_album {
-                            item.album = Some(value.clone());
-                        }
-                        if in_album_art {
-                            item.album_art_uri = Some(value.clone());
-                        }
-                        if in_genre {
-                            item.genre = Some(value.clone());
-                        }
-                        if in_class {
-                            item.object_class = Some(value.clone().as_str().into());
-                        }
-                        if in_res
-                            && item.url.is_empty()
-                            && value.contains(ip)
-                            && (item.protocol_info.contains("audio")
-                                || item.protocol_info.contains("video"))
-                        {
-                            item.url = value.clone();
-                        }
-                    }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// See `UpnpTime` for the accepted formats; sentinels such as NOT_IMPLEMENTED
+// give `None`.
+pub(crate) fn parse_time(value: &str) -> Option<Duration> {
+    value.parse::<UpnpTime>().ok().map(Duration::from)
+}
+
+fn parse_descs(element: &Element) -> Vec<Desc> {
+    element
+        .find_all((DIDL_NS, "desc"))
+        .map(|desc| Desc {
+            id: desc.get_attr("id").unwrap_or_default().to_string(),
+            name_space: desc.get_attr("nameSpace").unwrap_or_default().to_string(),
+            content: inner_xml(desc).trim().to_string(),
+        })
+        .collect()
+}
+
+// The XML inside `element`, its namespaces declared again so it stands on
+// its own.
+fn inner_xml(element: &Element) -> String {
+    let mut xml = escape_str_pcdata(element.text()).into_owned();
+    for child in element.children() {
+        let mut prefixes = vec![];
+        prefixed_namespaces(child, &mut prefixes);
+        write_element(child, None, &prefixes, true, &mut xml);
+        xml.push_str(&escape_str_pcdata(child.tail()));
+    }
+    xml
+}
+
+fn prefixed_namespaces(element: &Element, prefixes: &mut Vec<(String, String)>) {
+    let namespaces = std::iter::once(element.tag())
+        .chain(element.attrs().map(|(name, _)| name))
+        .filter_map(|name| name.ns());
+    for ns in namespaces {
+        match element.get_namespace_prefix(ns) {
+            Some(prefix) if !prefix.is_empty() && prefix != "xml" => {
+                let namespace = (prefix.to_string(), ns.to_string());
+                if !prefixes.contains(&namespace) {
+                    prefixes.push(namespace);
                 }
             }
             _ => {}
         }
     }
+    for child in element.children() {
+        prefixed_namespaces(child, prefixes);
+    }
+}
+
+// Elements in a namespace without a prefix are written with a default
+// namespace declaration wherever it changes.
+fn write_element(
+    element: &Element,
+    default_ns: Option<&str>,
+    prefixes: &[(String, String)],
+    declare_prefixes: bool,
+    xml: &mut String,
+) {
+    let prefixed = |name: &QName| {
+        let prefix = name
+            .ns()
+            .and_then(|ns| prefixes.iter().find(|(_, url)| url == ns));
+        match prefix {
+            Some((prefix, _)) => format!("{}:{}", prefix, name.name()),
+            None => name.name().to_string(),
+        }
+    };
+    let tag = element.tag();
+    let name = prefixed(tag);
+    xml.push('<');
+    xml.push_str(&name);
+    for (prefix, url) in prefixes.iter().filter(|_| declare_prefixes) {
+        xml.push_str(&format!(
+            " xmlns:{}=\"{}\"",
+            prefix,
+            escape_str_attribute(url)
+        ));
+    }
+    let mut own_default = default_ns;
+    if name == tag.name() && tag.ns() != default_ns {
+        own_default = tag.ns();
+        xml.push_str(&format!(
+            " xmlns=\"{}\"",
+            escape_str_attribute(tag.ns().unwrap_or_default())
+        ));
+    }
+    for (attr, value) in element.attrs() {
+        xml.push_str(&format!(
+            " {}=\"{}\"",
+            prefixed(attr),
+            escape_str_attribute(value)
+        ));
+    }
+    xml.push('>');
+    xml.push_str(&escape_str_pcdata(element.text()));
+    for child in element.children() {
+        write_element(child, own_default, prefixes, false, xml);
+        xml.push_str(&escape_str_pcdata(child.tail()));
+    }
+    xml.push_str(&format!("</{}>", name));
+}
+
+// Checks values while parsing DIDL-Lite: in strict mode one that doesn't
+// parse is an error, in lenient mode it's dropped with a warning.
+struct Diagnostics {
+    mode: ParseMode,
+    warnings: Vec<ParseWarning>,
+}
+
+impl Diagnostics {
+    fn value<T>(
+        &mut self,
+        object_id: &str,
+        property: &str,
+        value: Option<&str>,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let value = match value.map(str::trim) {
+            Some(value) if !value.is_empty() => value,
+            _ => return Ok(None),
+        };
+        if let Some(parsed) = parse(value) {
+            return Ok(Some(parsed));
+        }
+        let warning = ParseWarning::InvalidValue {
+            object_id: object_id.to_string(),
+            property: property.to_string(),
+            value: value.to_string(),
+        };
+        match self.mode {
+            ParseMode::Strict => Err(anyhow!("{}", warning)),
+            ParseMode::Lenient => {
+                self.warnings.push(warning);
+                Ok(None)
+            }
+        }
+    }
+
+    fn number<T: FromStr>(
+        &mut self,
+        object_id: &str,
+        property: &str,
+        value: Option<&str>,
+    ) -> Result<Option<T>> {
+        self.value(object_id, property, value, |value| value.parse().ok())
+    }
+}
+
+fn parse_resources(
+    element: &Element,
+    object_id: &str,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<Resource>> {
+    element
+        .find_all((DIDL_NS, "res"))
+        .map(|res| {
+            Ok(Resource {
+                url: res.text().trim().to_string(),
+                protocol_info: res.get_attr("protocolInfo").unwrap_or_default().to_string(),
+                duration: res.get_attr("duration").map(str::to_string),
+                size: diagnostics.number(object_id, "res@size", res.get_attr("size"))?,
+                resolution: res.get_attr("resolution").map(str::to_string),
+                bitrate: diagnostics.number(object_id, "res@bitrate", res.get_attr("bitrate"))?,
+                sample_frequency: diagnostics.number(
+                    object_id,
+                    "res@sampleFrequency",
+                    res.get_attr("sampleFrequency"),
+                )?,
+                nr_audio_channels: diagnostics.number(
+                    object_id,
+                    "res@nrAudioChannels",
+                    res.get_attr("nrAudioChannels"),
+                )?,
+                bits_per_sample: diagnostics.number(
+                    object_id,
+                    "res@bitsPerSample",
+                    res.get_attr("bitsPerSample"),
+                )?,
+            })
+        })
+        .collect()
+}
+
+// Parses a DIDL-Lite document, as found in the Result of Browse and Search
+// responses. `url` is set from the first audio or video resource, preferring
+// one served from `ip`.
+pub fn deserialize_content_directory(xml: &str, ip: &str) -> Result<(Vec<Container>, Vec<Item>)> {
+    deserialize_content_directory_with_mode(xml, ip, ParseMode::default())
+}
+
+pub fn deserialize_content_directory_with_mode(
+    xml: &str,
+    ip: &str,
+    mode: ParseMode,
+) -> Result<(Vec<Container>, Vec<Item>)> {
+    let mut containers: Vec<Container> = Vec::new();
+    let mut items: Vec<Item> = Vec::new();
+    let (objects, _) = parse_didl_objects(xml, ip, mode)?;
+    for object in objects {
+        match object {
+            DidlObject::Container(container) => containers.push(container),
+            DidlObject::Item(item) => items.push(item),
+        }
+    }
     Ok((containers, items))
 }
 
+// Parses any DIDL-Lite document, e.g. a renderer's CurrentTrackMetaData or
+// a playlist from another control point, keeping the objects in document
+// order. Renderers without metadata answer an empty string or
+// "NOT_IMPLEMENTED", which gives no objects.
+pub fn parse_didl(xml: &str) -> Result<Vec<DidlObject>> {
+    parse_didl_with_mode(xml, ParseMode::default())
+}
+
+pub fn parse_didl_with_mode(xml: &str, mode: ParseMode) -> Result<Vec<DidlObject>> {
+    Ok(parse_didl_with_warnings(xml, mode)?.0)
+}
+
+// Like `parse_didl_with_mode`, also returning what lenient parsing had to
+// recover from, to track down misbehaving servers.
+pub fn parse_didl_with_warnings(
+    xml: &str,
+    mode: ParseMode,
+) -> Result<(Vec<DidlObject>, Vec<ParseWarning>)> {
+    match xml.trim() {
+        "" | "NOT_IMPLEMENTED" => Ok((vec![], vec![])),
+        xml => parse_didl_objects(xml, "", mode),
+    }
+}
+
+pub(crate) fn parse_didl_objects(
+    xml: &str,
+    ip: &str,
+    mode: ParseMode,
+) -> Result<(Vec<DidlObject>, Vec<ParseWarning>)> {
+    let mut diagnostics = Diagnostics {
+        mode,
+        warnings: vec![],
+    };
+    let root = match parse_element(xml, Some(DIDL_NS)) {
+        Ok(root) => root,
+        Err(e) if mode == ParseMode::Lenient => {
+            let repaired = repair_didl(xml, &mut diagnostics.warnings);
+            parse_element(&repaired, Some(DIDL_NS)).map_err(|_| e)?
+        }
+        Err(e) => return Err(e),
+    };
+    let mut objects = Vec::new();
+
+    for element in root.children() {
+        let id = element.get_attr("id").unwrap_or_default();
+        let class = didl_text(element, UPNP_NS, "class").unwrap_or_default();
+        let contributors: Vec<Contributor> = element
+            .find_all((UPNP_NS, "artist"))
+            .map(|artist| Contributor::new(artist.text().trim(), artist.get_attr("role")))
+            .collect();
+        let artists: Vec<String> = contributors
+            .iter()
+            .map(|artist| artist.name.clone())
+            .collect();
+        let authors: Vec<Contributor> = element
+            .find_all((UPNP_NS, "author"))
+            .map(|author| Contributor::new(author.text().trim(), author.get_attr("role")))
+            .collect();
+        let with_role = |role: &str| {
+            authors
+                .iter()
+                .chain(&contributors)
+                .find(|contributor| contributor.has_role(role))
+                .map(|contributor| contributor.name.clone())
+        };
+        let composer = with_role("Composer");
+        let conductor = with_role("Conductor");
+        let resources = parse_resources(element, id, &mut diagnostics)?;
+        let descs = parse_descs(element);
+        let original_track_number = diagnostics.number(
+            id,
+            "upnp:originalTrackNumber",
+            didl_text(element, UPNP_NS, "originalTrackNumber").as_deref(),
+        )?;
+        let object_update_id = diagnostics.number(
+            id,
+            "upnp:objectUpdateID",
+            didl_text(element, UPNP_NS, "objectUpdateID").as_deref(),
+        )?;
+        match element.tag().name() {
+            "container" => objects.push(DidlObject::Container(Container {
+                id: id.to_string(),
+                parent_id: element.get_attr("parentID").unwrap_or_default().to_string(),
+                title: didl_text(element, DC_NS, "title").unwrap_or_default(),
+                creator: didl_text(element, DC_NS, "creator"),
+                restricted: didl_flag(element, "restricted"),
+                searchable: didl_flag(element, "searchable"),
+                child_count: diagnostics.number(
+                    id,
+                    "childCount",
+                    element.get_attr("childCount"),
+                )?,
+                album_art_uri: didl_text(element, UPNP_NS, "albumArtURI"),
+                album: didl_text(element, UPNP_NS, "album"),
+                artist: artists.first().cloned(),
+                genre: didl_text(element, UPNP_NS, "genre"),
+                date: didl_text(element, DC_NS, "date"),
+                original_track_number,
+                protocol_info: resources.first().map(|res| res.protocol_info.clone()),
+                url: resources.first().map(|res| res.url.clone()),
+                object_class: Some(class.as_str().into()),
+                object_update_id,
+                container_update_id: diagnostics.number(
+                    id,
+                    "upnp:containerUpdateID",
+                    didl_text(element, UPNP_NS, "containerUpdateID").as_deref(),
+                )?,
+                class,
+                artists,
+                contributors,
+                composer,
+                conductor,
+                resources,
+                descs,
+            })),
+            "item" => {
+                let media = resources.iter().filter(|res| {
+                    res.protocol_info.contains("audio") || res.protocol_info.contains("video")
+                });
+                let playable = media
+                    .clone()
+                    .find(|res| res.url.contains(ip))
+                    .or_else(|| media.clone().next())
+                    .cloned()
+                    .unwrap_or_default();
+                let last_playback_position = diagnostics.value(
+                    id,
+                    "upnp:lastPlaybackPosition",
+                    didl_text(element, UPNP_NS, "lastPlaybackPosition").as_deref(),
+                    parse_time,
+                )?;
+                objects.push(DidlObject::Item(Item {
+                    id: id.to_string(),
+                    parent_id: element.get_attr("parentID").unwrap_or_default().to_string(),
+                    title: didl_text(element, DC_NS, "title").unwrap_or_default(),
+                    creator: didl_text(element, DC_NS, "creator"),
+                    restricted: didl_flag(element, "restricted"),
+                    searchable: didl_flag(element, "searchable"),
+                    album_art_uri: didl_text(element, UPNP_NS, "albumArtURI"),
+                    album: didl_text(element, UPNP_NS, "album"),
+                    artist: artists.first().cloned(),
+                    genre: didl_text(element, UPNP_NS, "genre"),
+                    date: didl_text(element, DC_NS, "date"),
+                    original_track_number,
+                    protocol_info: playable.protocol_info,
+                    url: playable.url,
+                    size: playable.size,
+                    duration: playable.duration,
+                    object_class: Some(class.as_str().into()),
+                    last_playback_position,
+                    object_update_id,
+                    ref_id: element.get_attr("refID").map(str::to_string),
+                    class,
+                    artists,
+                    contributors,
+                    composer,
+                    conductor,
+                    resources,
+                    descs,
+                }))
+            }
+            _ => {}
+        }
+    }
+    Ok((objects, diagnostics.warnings))
+}
+
+// An M3U playlist as items, `#EXTINF` lines providing titles and durations.
+// Relative entries are resolved against `base_url`, the playlist's URL.
+pub fn parse_m3u(content: &str, base_url: &str) -> Vec<Item> {
+    let base = Url::parse(base_url).ok();
+    let mut items = vec![];
+    let mut info: Option<(Option<u64>, String)> = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (duration, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let duration = duration.trim().parse::<i64>().ok();
+            info = Some((
+                duration.and_then(|d| u64::try_from(d).ok()),
+                title.trim().to_string(),
+            ));
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let url = match &base {
+            Some(base) => base
+                .join(line)
+                .map(String::from)
+                .unwrap_or(line.to_string()),
+            None => line.to_string(),
+        };
+        let (duration, title) = info.take().unwrap_or((None, String::new()));
+        let title = match title.is_empty() {
+            true => url.rsplit('/').next().unwrap_or_default().to_string(),
+            false => title,
+        };
+        let duration = duration.map(|secs| UpnpTime(Duration::from_secs(secs)).to_string());
+        items.push(Item {
+            title,
+            url: url.clone(),
+            duration: duration.clone(),
+            resources: vec![Resource {
+                url,
+                duration,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+    }
+    items
+}
+
+// The ContainerUpdateIDs state variable: "id,updateID,id,updateID...".
+pub fn parse_container_update_ids(value: &str) -> Vec<(String, u32)> {
+    let values: Vec<&str> = value.split(',').map(str::trim).collect();
+    values
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [id, update_id] => Some((id.to_string(), update_id.parse().ok()?)),
+            _ => None,
+        })
+        .collect()
+}
+
+// The ContentDirectory:3 LastChange state variable, listing objAdd,
+// objMod and objDel entries.
+pub fn parse_object_changes(xml: &str) -> Result<Vec<ObjectChange>> {
+    let root = Element::from_reader(xml.as_bytes())?;
+    let changes = root
+        .children()
+        .filter_map(|change| {
+            let kind = match change.tag().name() {
+                "objAdd" => ObjectChangeKind::Added,
+                "objMod" => ObjectChangeKind::Modified,
+                "objDel" => ObjectChangeKind::Deleted,
+                _ => return None,
+            };
+            Some(ObjectChange {
+                kind,
+                object_id: change.get_attr("objID")?.to_string(),
+                parent_id: change.get_attr("objParentID").map(str::to_string),
+                update_id: change.get_attr("updateID").and_then(|id| id.parse().ok()),
+            })
+        })
+        .collect();
+    Ok(changes)
+}
+
 pub fn parse_transport_info(xml: &str) -> Result<TransportInfo> {
     let parser = EventReader::from_str(xml);
     let mut in_transport_state = false;
@@ -746,10 +1737,10 @@ pub fn parse_transport_info(xml: &str) -> Result<TransportInfo> {
             }
             Ok(XmlEvent::Characters(value)) => {
                 if in_transport_state {
-                    transport_info.current_transport_state = value.clone();
+                    transport_info.current_transport_state = value.parse()?;
                 }
                 if in_transport_status {
-                    transport_info.current_transport_status = value.clone();
+                    transport_info.current_transport_status = value.parse()?;
                 }
                 if in_transport_play_speed {
                     transport_info.current_speed = value.clone();
@@ -763,11 +1754,36 @@ pub fn parse_transport_info(xml: &str) -> Result<TransportInfo> {
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::parse_services;
+    use crate::parser::{
+        build_absolute_url, decode_xml, deserialize_content_directory,
+        deserialize_content_directory_with_mode, escape_metadata, parse_action_response,
+        parse_container_update_ids, parse_device_list, parse_didl, parse_didl_with_warnings,
+        parse_duration, parse_m3u, parse_object_changes, parse_position,
+        parse_query_state_variable, parse_response_element, parse_scpd, parse_services, parse_time,
+        resolve_base_url, scope_id, split_zone_id, unescape_metadata, DEVICE_NS,
+    };
+    use crate::types::{
+        DidlObject, ObjectChangeKind, ObjectClass, ParseMode, ParseWarning, UpnpError,
+    };
+    use elementtree::Element;
+    use std::time::Duration;
+
+    #[test]
+    fn test_decoding_latin1_response() {
+        let body =
+            b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><r><Title>Caf\xe9 \x80</Title></r>";
+        let xml = decode_xml(body, None);
+        assert_eq!(
+            xml,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><r><Title>Caf\u{e9} \u{20ac}</Title></r>"
+        );
+
+        assert_eq!(decode_xml(b"<r>\xe9</r>", None), "<r>\u{e9}</r>");
+    }
 
     #[tokio::test]
     async fn test_parsing_device_without_service_list() {
-        const XML_ROOT: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        const XML_ROOT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
         <root xmlns="urn:schemas-upnp-org:device-1-0">
             <specVersion>
                 <major>1</major>
@@ -795,4 +1811,496 @@ mod tests {
             .unwrap();
         assert_eq!(result.len(), 0);
     }
+
+    #[test]
+    fn test_parsing_embedded_devices() {
+        const XML_ROOT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <root xmlns="urn:schemas-upnp-org:device-1-0">
+            <device>
+                <deviceType>urn:schemas-upnp-org:device:Basic:1</deviceType>
+                <friendlyName>Living Room TV</friendlyName>
+                <UDN>uuid:root</UDN>
+                <deviceList>
+                    <device>
+                        <deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>
+                        <friendlyName>Living Room TV Renderer</friendlyName>
+                        <manufacturer>ACME</manufacturer>
+                        <modelName>TV</modelName>
+                        <UDN>uuid:renderer</UDN>
+                        <serviceList>
+                            <service>
+                                <serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType>
+                                <serviceId>urn:upnp-org:serviceId:AVTransport</serviceId>
+                                <SCPDURL>/renderer/avt.xml</SCPDURL>
+                                <controlURL>/renderer/avt/control</controlURL>
+                                <eventSubURL>/renderer/avt/event</eventSubURL>
+                            </service>
+                        </serviceList>
+                    </device>
+                </deviceList>
+            </device>
+        </root>"#;
+
+        let root = Element::from_reader(XML_ROOT.as_bytes()).unwrap();
+        let device = root.find((DEVICE_NS, "device")).unwrap();
+        let devices =
+            parse_device_list("http://xxxxxx:1337/desc.xml", "http://xxxxxx:1337", device).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].udn, "uuid:renderer");
+        assert_eq!(devices[0].model_description, None);
+        assert_eq!(
+            devices[0].services[0].control_url,
+            "http://xxxxxx:1337/renderer/avt/control"
+        );
+    }
+
+    #[test]
+    fn test_resolving_urls_against_url_base() {
+        const WITH_URL_BASE: &str = r#"<root xmlns="urn:schemas-upnp-org:device-1-0">
+            <URLBase>http://192.168.1.10:49152/upnp/</URLBase>
+        </root>"#;
+        const WITHOUT_URL_BASE: &str = r#"<root xmlns="urn:schemas-upnp-org:device-1-0"/>"#;
+        let location = "http://192.168.1.10:8080/desc/device.xml";
+
+        let base_url = resolve_base_url(location, WITH_URL_BASE).unwrap();
+        assert_eq!(
+            build_absolute_url(&base_url, "avt/control").unwrap(),
+            "http://192.168.1.10:49152/upnp/avt/control"
+        );
+        assert_eq!(
+            build_absolute_url(&base_url, "/avt/control").unwrap(),
+            "http://192.168.1.10:49152/avt/control"
+        );
+
+        let base_url = resolve_base_url(location, WITHOUT_URL_BASE).unwrap();
+        assert_eq!(
+            build_absolute_url(&base_url, "avt/control").unwrap(),
+            "http://192.168.1.10:8080/desc/avt/control"
+        );
+        assert_eq!(
+            build_absolute_url(&base_url, "http://192.168.1.11/control").unwrap(),
+            "http://192.168.1.11/control"
+        );
+    }
+
+    #[test]
+    fn test_parsing_query_state_variable_response() {
+        const RESPONSE: &str = r#"<?xml version="1.0"?>
+        <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+            <s:Body>
+                <u:QueryStateVariableResponse xmlns:u="urn:schemas-upnp-org:control-1-0">
+                    <return>PLAYING</return>
+                </u:QueryStateVariableResponse>
+            </s:Body>
+        </s:Envelope>"#;
+        assert_eq!(parse_query_state_variable(RESPONSE).unwrap(), "PLAYING");
+        assert!(parse_query_state_variable("<s:Envelope/>").is_err());
+    }
+
+    #[test]
+    fn test_parsing_action_response() {
+        const RESPONSE: &str = r#"<?xml version="1.0"?>
+        <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+            <s:Body>
+                <u:GetVolumeResponse xmlns:u="urn:schemas-upnp-org:service:RenderingControl:1">
+                    <CurrentVolume>42</CurrentVolume>
+                    <Extra></Extra>
+                </u:GetVolumeResponse>
+            </s:Body>
+        </s:Envelope>"#;
+        let values = parse_action_response(RESPONSE).unwrap();
+        assert_eq!(values["CurrentVolume"], "42");
+        assert_eq!(values["Extra"], "");
+
+        const FAULT: &str = r#"<?xml version="1.0"?>
+        <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+            <s:Body>
+                <s:Fault>
+                    <faultcode>s:Client</faultcode>
+                    <faultstring>UPnPError</faultstring>
+                    <detail>
+                        <UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+                            <errorCode>701</errorCode>
+                            <errorDescription>Transition not available</errorDescription>
+                        </UPnPError>
+                    </detail>
+                </s:Fault>
+            </s:Body>
+        </s:Envelope>"#;
+        let err = parse_action_response(FAULT).unwrap_err();
+        assert_eq!(err.to_string(), "UPnP error 701: Transition not available");
+        assert_eq!(err.downcast_ref::<UpnpError>().unwrap().code, 701);
+    }
+
+    #[test]
+    fn test_parsing_response_element() {
+        const RESPONSE: &str = r#"<?xml version="1.0"?>
+        <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+            <s:Body>
+                <u:GetMediaInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1">
+                    <NrTracks>1</NrTracks>
+                    <x:Extension xmlns:x="urn:vendor" kind="radio"><x:Station>Jazz</x:Station></x:Extension>
+                </u:GetMediaInfoResponse>
+            </s:Body>
+        </s:Envelope>"#;
+        let element = parse_response_element(RESPONSE).unwrap();
+        assert_eq!(element.tag().name(), "GetMediaInfoResponse");
+        assert_eq!(element.find("NrTracks").unwrap().text(), "1");
+        let extension = element.find("{urn:vendor}Extension").unwrap();
+        assert_eq!(extension.get_attr("kind"), Some("radio"));
+        assert_eq!(
+            extension.find("{urn:vendor}Station").unwrap().text(),
+            "Jazz"
+        );
+
+        assert!(parse_response_element("<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><s:Fault/></s:Body></s:Envelope>").is_err());
+    }
+
+    #[test]
+    fn test_parsing_with_other_prefixes_and_namespaces() {
+        const SOAP_ENV: &str = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">
+            <SOAP-ENV:Body>
+                <m:GetVolumeResponse xmlns:m="urn:schemas-upnp-org:service:RenderingControl:1">
+                    <CurrentVolume>12</CurrentVolume>
+                </m:GetVolumeResponse>
+            </SOAP-ENV:Body>
+        </SOAP-ENV:Envelope>"#;
+        let element = parse_response_element(SOAP_ENV).unwrap();
+        assert_eq!(element.tag().name(), "GetVolumeResponse");
+        assert_eq!(element.find("CurrentVolume").unwrap().text(), "12");
+
+        const SOAP_12: &str = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope">
+            <env:Body><u:PlayResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1"/></env:Body>
+        </env:Envelope>"#;
+        let element = parse_response_element(SOAP_12).unwrap();
+        assert_eq!(element.tag().name(), "PlayResponse");
+
+        const SCPD: &str = r#"<scpd>
+            <actionList><action><name>Play</name></action></actionList>
+            <serviceStateTable/>
+        </scpd>"#;
+        let scpd = parse_scpd(SCPD).unwrap();
+        assert_eq!(scpd.actions[0].name, "Play");
+
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite" xmlns:dc="http://purl.org/dc/elements/1.1" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp">"#,
+            r#"<item id="1" parentID="0"><dc:title>Intro</dc:title><class>object.item.audioItem.musicTrack</class>"#,
+            r#"<res protocolInfo="http-get:*:audio/mpeg:*">http://nas/intro.mp3</res></item>"#,
+            r#"</DIDL-Lite>"#
+        );
+        match &parse_didl(didl).unwrap()[0] {
+            DidlObject::Item(item) => {
+                assert_eq!(item.title, "Intro");
+                assert_eq!(item.object_class, Some(ObjectClass::Audio));
+                assert_eq!(item.url, "http://nas/intro.mp3");
+            }
+            DidlObject::Container(_) => panic!("expected an item"),
+        }
+    }
+
+    #[test]
+    fn test_splitting_ipv6_zone_ids() {
+        assert_eq!(
+            split_zone_id("http://[fe80::1%eth0]:8080/ctrl"),
+            (
+                "http://[fe80::1]:8080/ctrl".to_string(),
+                Some("eth0".to_string())
+            )
+        );
+        assert_eq!(
+            split_zone_id("http://[fe80::1%253]:8080/ctrl"),
+            (
+                "http://[fe80::1]:8080/ctrl".to_string(),
+                Some("3".to_string())
+            )
+        );
+        assert_eq!(
+            split_zone_id("http://[2001:db8::1]:8080/ctrl"),
+            ("http://[2001:db8::1]:8080/ctrl".to_string(), None)
+        );
+        assert_eq!(
+            split_zone_id("http://10.0.0.2/ctl?q=[a%20b]"),
+            ("http://10.0.0.2/ctl?q=[a%20b]".to_string(), None)
+        );
+        assert_eq!(
+            build_absolute_url("http://[2001:db8::1]:8080/desc.xml", "/ctrl").unwrap(),
+            "http://[2001:db8::1]:8080/ctrl"
+        );
+    }
+
+    #[test]
+    fn test_resolving_zone_scope_ids() {
+        assert_eq!(scope_id("3"), 3);
+        assert_eq!(scope_id("no-such-if0"), 0);
+        #[cfg(target_os = "linux")]
+        assert_eq!(scope_id("lo"), 1);
+    }
+
+    #[test]
+    fn test_deserializing_content_directory() {
+        let didl = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">
+            <container id="64" parentID="0" restricted="1" searchable="1" childCount="12">
+                <dc:title>Albums</dc:title>
+                <upnp:class>object.container.storageFolder</upnp:class>
+            </container>
+            <item id="64$1" parentID="64" restricted="1">
+                <dc:title>Blue in Green</dc:title>
+                <dc:creator>Miles Davis</dc:creator>
+                <upnp:artist>Miles Davis</upnp:artist>
+                <upnp:artist role="Performer">Bill Evans</upnp:artist>
+                <upnp:album>Kind of Blue</upnp:album>
+                <upnp:albumArtURI>http://192.168.1.2:8200/AlbumArt/1.jpg</upnp:albumArtURI>
+                <upnp:class>object.item.audioItem.musicTrack</upnp:class>
+                <upnp:lastPlaybackPosition>0:01:02.5</upnp:lastPlaybackPosition>
+                <res protocolInfo="http-get:*:image/jpeg:*">http://192.168.1.2:8200/Thumb/1.jpg</res>
+                <res protocolInfo="http-get:*:audio/flac:*" size="31337" duration="0:05:37.000">http://192.168.1.2:8200/MediaItems/1.flac</res>
+            </item>
+        </DIDL-Lite>"#;
+        let (containers, items) = deserialize_content_directory(didl, "192.168.1.2").unwrap();
+
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].id, "64");
+        assert_eq!(containers[0].title, "Albums");
+        assert_eq!(containers[0].class, "object.container.storageFolder");
+        assert_eq!(containers[0].child_count, Some(12));
+        assert!(containers[0].searchable);
+
+        let item = &items[0];
+        assert_eq!(item.parent_id, "64");
+        assert_eq!(item.title, "Blue in Green");
+        assert_eq!(item.creator.as_deref(), Some("Miles Davis"));
+        assert_eq!(item.artists, vec!["Miles Davis", "Bill Evans"]);
+        assert_eq!(item.album.as_deref(), Some("Kind of Blue"));
+        assert_eq!(item.class, "object.item.audioItem.musicTrack");
+        assert_eq!(item.resources.len(), 2);
+        assert_eq!(item.url, "http://192.168.1.2:8200/MediaItems/1.flac");
+        assert_eq!(item.size, Some(31337));
+        assert_eq!(item.duration.as_deref(), Some("0:05:37.000"));
+        assert_eq!(
+            item.last_playback_position,
+            Some(Duration::from_millis(62_500))
+        );
+    }
+
+    #[test]
+    fn test_parsing_times() {
+        assert_eq!(parse_time("0:04:21"), Some(Duration::from_secs(261)));
+        assert_eq!(
+            parse_time("+01:02:03.5"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(parse_time("123:00:00"), Some(Duration::from_secs(442_800)));
+        assert_eq!(
+            parse_time("0:00:01.1/4"),
+            Some(Duration::from_millis(1_250))
+        );
+        assert_eq!(
+            parse_time("0:00:01.123456789"),
+            Some(Duration::new(1, 123_456_789))
+        );
+        assert_eq!(parse_time("0:00:01.4/4"), None);
+        assert_eq!(parse_time("-0:00:10"), None);
+        assert_eq!(parse_time("NOT_IMPLEMENTED"), None);
+        assert_eq!(parse_time(""), None);
+
+        let response = |duration: &str| {
+            format!(
+                concat!(
+                    r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>"#,
+                    r#"<u:GetMediaInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1">"#,
+                    "<NrTracks>1</NrTracks><MediaDuration>{}</MediaDuration>",
+                    "</u:GetMediaInfoResponse></s:Body></s:Envelope>"
+                ),
+                duration
+            )
+        };
+        assert_eq!(
+            parse_duration(&response("100:00:00.000")).unwrap(),
+            Some(Duration::from_secs(360_000))
+        );
+        assert_eq!(parse_duration(&response("NOT_IMPLEMENTED")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parsing_position_info() {
+        let response = |rel_time: &str| {
+            format!(
+                concat!(
+                    r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>"#,
+                    r#"<u:GetPositionInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1">"#,
+                    "<Track>3</Track><TrackDuration>0:04:21.480</TrackDuration><TrackMetaData></TrackMetaData>",
+                    "<TrackURI>http://nas/3.flac</TrackURI><RelTime>{}</RelTime><AbsTime>NOT_IMPLEMENTED</AbsTime>",
+                    "<RelCount>524288</RelCount><AbsCount>2147483647</AbsCount>",
+                    "</u:GetPositionInfoResponse></s:Body></s:Envelope>"
+                ),
+                rel_time
+            )
+        };
+
+        let position = parse_position(&response("0:01:02.250")).unwrap();
+        assert_eq!(position.track, Some(3));
+        assert_eq!(
+            position.track_duration,
+            Some(Duration::from_millis(261_480))
+        );
+        assert_eq!(position.rel_time, Some(Duration::from_millis(62_250)));
+        assert_eq!(position.abs_time, None);
+        assert_eq!(position.track_metadata, None);
+        assert_eq!(position.track_uri.as_deref(), Some("http://nas/3.flac"));
+        assert_eq!(position.rel_count, Some(524_288));
+        assert_eq!(position.abs_count, None);
+
+        assert_eq!(parse_position(&response("")).unwrap().rel_time, None);
+    }
+
+    #[test]
+    fn test_parsing_didl() {
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+            r#"<item id="1" parentID="0"><dc:title>Intro</dc:title><upnp:class>object.item.audioItem.musicTrack</upnp:class>"#,
+            r#"<res protocolInfo="http-get:*:audio/mpeg:*" duration="0:01:00" bitrate="40000" sampleFrequency="44100" nrAudioChannels="2">http://nas/intro.mp3</res></item>"#,
+            r#"<container id="2" parentID="0"><dc:title>Bonus</dc:title><upnp:class>object.container</upnp:class></container>"#,
+            r#"<item id="3" parentID="0"><dc:title>Outro</dc:title></item>"#,
+            r#"</DIDL-Lite>"#
+        );
+        let objects = parse_didl(didl).unwrap();
+        let ids: Vec<&str> = objects.iter().map(|object| object.id()).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        match &objects[0] {
+            DidlObject::Item(item) => {
+                assert_eq!(item.title, "Intro");
+                assert_eq!(item.url, "http://nas/intro.mp3");
+                assert_eq!(item.duration.as_deref(), Some("0:01:00"));
+                let res = &item.resources[0];
+                assert_eq!(res.bitrate, Some(40000));
+                assert_eq!(res.sample_frequency, Some(44100));
+                assert_eq!(res.nr_audio_channels, Some(2));
+                assert_eq!(res.bits_per_sample, None);
+            }
+            DidlObject::Container(_) => panic!("expected an item"),
+        }
+
+        assert!(parse_didl("NOT_IMPLEMENTED").unwrap().is_empty());
+        assert!(parse_didl("").unwrap().is_empty());
+        assert!(parse_didl("<DIDL-Lite").is_err());
+    }
+
+    #[test]
+    fn test_repairing_malformed_didl() {
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+            r#"<item id="1" parentID="0"><dc:title>Simon & Garfunkel &amp; friends</dc:title></item></DIDL-Lite>"#
+        );
+        let title = |xml: &str, mode| {
+            let (_, items) = deserialize_content_directory_with_mode(xml, "", mode).unwrap();
+            items[0].title.clone()
+        };
+        assert_eq!(
+            title(didl, ParseMode::Lenient),
+            "Simon & Garfunkel & friends"
+        );
+        assert!(deserialize_content_directory_with_mode(didl, "", ParseMode::Strict).is_err());
+
+        let escaped = r#"&lt;DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/"&gt;&lt;item id="1" parentID="0"&gt;&lt;dc:title&gt;Rock &amp;amp; Roll&lt;/dc:title&gt;&lt;/item&gt;&lt;/DIDL-Lite&gt;"#;
+        assert_eq!(title(escaped, ParseMode::Lenient), "Rock & Roll");
+        assert!(deserialize_content_directory_with_mode(escaped, "", ParseMode::Strict).is_err());
+
+        let (_, warnings) = parse_didl_with_warnings(didl, ParseMode::Lenient).unwrap();
+        assert_eq!(warnings, [ParseWarning::UnescapedAmpersand]);
+        let (_, warnings) = parse_didl_with_warnings(escaped, ParseMode::Lenient).unwrap();
+        assert_eq!(warnings, [ParseWarning::DoubleEscaped]);
+        let error = parse_didl_with_warnings(didl, ParseMode::Strict).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Malformed XML at line 1, column 152:"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_metadata_escaping_round_trip() {
+        let didl = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/"><item id="1"><dc:title>Rock &amp; Roll</dc:title></item></DIDL-Lite>"#;
+        let escaped = escape_metadata(didl);
+        assert!(escaped.starts_with("&lt;DIDL-Lite xmlns=&quot;"));
+        assert!(escaped.contains("Rock &amp;amp; Roll"));
+        assert_eq!(unescape_metadata(&escaped), didl);
+        assert_eq!(unescape_metadata(&escape_metadata(&escaped)), didl);
+        assert_eq!(unescape_metadata(didl), didl);
+        assert_eq!(unescape_metadata("NOT_IMPLEMENTED"), "NOT_IMPLEMENTED");
+    }
+
+    #[test]
+    fn test_invalid_didl_values() {
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+            r#"<item id="7" parentID="0"><upnp:originalTrackNumber>3</upnp:originalTrackNumber>"#,
+            r#"<upnp:lastPlaybackPosition>soon</upnp:lastPlaybackPosition>"#,
+            r#"<res protocolInfo="http-get:*:audio/mpeg:*" size="12 MB">http://nas/7.mp3</res></item>"#,
+            r#"</DIDL-Lite>"#
+        );
+        let (objects, warnings) = parse_didl_with_warnings(didl, ParseMode::Lenient).unwrap();
+        match &objects[0] {
+            DidlObject::Item(item) => {
+                assert_eq!(item.original_track_number, Some(3));
+                assert_eq!(item.size, None);
+                assert_eq!(item.last_playback_position, None);
+                assert_eq!(item.url, "http://nas/7.mp3");
+            }
+            DidlObject::Container(_) => panic!("expected an item"),
+        }
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::InvalidValue {
+                    object_id: "7".to_string(),
+                    property: "res@size".to_string(),
+                    value: "12 MB".to_string(),
+                },
+                ParseWarning::InvalidValue {
+                    object_id: "7".to_string(),
+                    property: "upnp:lastPlaybackPosition".to_string(),
+                    value: "soon".to_string(),
+                },
+            ]
+        );
+
+        let error = parse_didl_with_warnings(didl, ParseMode::Strict).unwrap_err();
+        assert_eq!(error.to_string(), r#"Invalid res@size "12 MB" in object 7"#);
+    }
+
+    #[test]
+    fn test_parsing_content_directory_changes() {
+        assert_eq!(
+            parse_container_update_ids("12,4, 7,9,3"),
+            [("12".to_string(), 4), ("7".to_string(), 9)]
+        );
+
+        let last_change = r#"<StateEvent xmlns="urn:schemas-upnp-org:av:cds-event">
+            <objAdd objParentID="12" objClass="object.item.audioItem" objID="12$9" updateID="201" stUpdate="0"/>
+            <objMod objID="12$3" updateID="202" stUpdate="0"/>
+            <objDel objParentID="7" objID="7$1" updateID="203" stUpdate="0"/>
+        </StateEvent>"#;
+        let changes = parse_object_changes(last_change).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].kind, ObjectChangeKind::Added);
+        assert_eq!(changes[0].parent_id.as_deref(), Some("12"));
+        assert_eq!(changes[1].object_id, "12$3");
+        assert_eq!(changes[1].parent_id, None);
+        assert_eq!(changes[2].kind, ObjectChangeKind::Deleted);
+        assert_eq!(changes[2].update_id, Some(203));
+    }
+
+    #[test]
+    fn test_parsing_m3u_playlists() {
+        let m3u = "#EXTM3U\n#EXTINF:263,Radiohead - Airbag\n01 Airbag.mp3\n\nhttp://other/2.flac\n";
+        let items = parse_m3u(m3u, "http://nas:8200/playlists/ok.m3u");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title, "Radiohead - Airbag");
+        assert_eq!(items[0].url, "http://nas:8200/playlists/01%20Airbag.mp3");
+        assert_eq!(items[0].duration.as_deref(), Some("00:04:23"));
+        assert_eq!(items[1].title, "2.flac");
+        assert_eq!(items[1].url, "http://other/2.flac");
+    }
 }
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+
+use crate::{action::ActionTarget, media_server::MediaServerClient, types::Item};
+
+// Local resume positions, keyed by item id and resource URI.
+pub trait ResumeStore: Send + Sync {
+    fn load(&self, item_id: &str, url: &str) -> Option<Duration>;
+    fn save(&self, item_id: &str, url: &str, position: Duration) -> Result<()>;
+}
+
+#[derive(Default)]
+pub struct MemoryResumeStore {
+    positions: Mutex<HashMap<(String, String), Duration>>,
+}
+
+impl MemoryResumeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ResumeStore for MemoryResumeStore {
+    fn load(&self, item_id: &str, url: &str) -> Option<Duration> {
+        self.positions
+            .lock()
+            .unwrap()
+            .get(&(item_id.to_string(), url.to_string()))
+            .copied()
+    }
+
+    fn save(&self, item_id: &str, url: &str, position: Duration) -> Result<()> {
+        self.positions
+            .lock()
+            .unwrap()
+            .insert((item_id.to_string(), url.to_string()), position);
+        Ok(())
+    }
+}
+
+// Positions kept in a JSON file, rewritten on every save.
+pub struct FileResumeStore {
+    path: PathBuf,
+    positions: Mutex<Vec<(String, String, u64)>>,
+}
+
+impl FileResumeStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let positions = match fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            positions: Mutex::new(positions),
+        })
+    }
+}
+
+impl ResumeStore for FileResumeStore {
+    fn load(&self, item_id: &str, url: &str) -> Option<Duration> {
+        self.positions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(id, u, _)| id == item_id && u == url)
+            .map(|(_, _, seconds)| Duration::from_secs(*seconds))
+    }
+
+    fn save(&self, item_id: &str, url: &str, position: Duration) -> Result<()> {
+        let mut positions = self.positions.lock().unwrap();
+        positions.retain(|(id, u, _)| !(id == item_id && u == url));
+        positions.push((item_id.to_string(), url.to_string(), position.as_secs()));
+        fs::write(&self.path, serde_json::to_vec(&*positions)?)?;
+        Ok(())
+    }
+}
+
+// Where to resume an item: the server's own record when it keeps one
+// (upnp:lastPlaybackPosition, Samsung bookmarks), else the local store.
+// Saved positions always go to the local store, and to the server too when
+// it takes Samsung bookmarks.
+pub struct ResumePositions<S: ResumeStore> {
+    media_server: MediaServerClient,
+    store: S,
+}
+
+impl<S: ResumeStore> ResumePositions<S> {
+    pub fn new(media_server: MediaServerClient, store: S) -> Self {
+        Self {
+            media_server,
+            store,
+        }
+    }
+
+    pub async fn position(&self, item: &Item) -> Result<Option<Duration>> {
+        if let Some(position) = item.last_playback_position {
+            return Ok(Some(position));
+        }
+        if self.has_action("X_GetBookmark") {
+            let position = self.media_server.samsung_bookmark(&item.id).await?;
+            if !position.is_zero() {
+                return Ok(Some(position));
+            }
+        }
+        Ok(self.store.load(&item.id, &item.url))
+    }
+
+    pub async fn save(&self, item: &Item, position: Duration) -> Result<()> {
+        if self.has_action("X_SetBookmark") {
+            self.media_server
+                .set_samsung_bookmark(&item.id, position)
+                .await?;
+        }
+        self.store.save(&item.id, &item.url, position)
+    }
+
+    fn has_action(&self, action: &str) -> bool {
+        self.media_server
+            .device_client()
+            .has_action("ContentDirectory", action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileResumeStore, ResumeStore};
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_resume_store() {
+        let path = std::env::temp_dir().join(format!("upnp-resume-{}.json", std::process::id()));
+        let store = FileResumeStore::open(&path).unwrap();
+        assert_eq!(store.load("1", "http://nas/1.mkv"), None);
+        store
+            .save("1", "http://nas/1.mkv", Duration::from_secs(90))
+            .unwrap();
+        store
+            .save("1", "http://nas/1.mkv", Duration::from_secs(120))
+            .unwrap();
+        store
+            .save("2", "http://nas/2.mkv", Duration::from_secs(5))
+            .unwrap();
+
+        let reopened = FileResumeStore::open(&path).unwrap();
+        assert_eq!(
+            reopened.load("1", "http://nas/1.mkv"),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(reopened.load("1", "http://nas/1-transcoded.mkv"), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
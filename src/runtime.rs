@@ -0,0 +1,32 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures_util::future::{select, Either};
+
+// Timers and sockets driven by async-io's own reactor thread instead of a
+// particular runtime's, so discovery and SOAP control work the same under
+// tokio, async-std or smol. Eventing, M-POST and `tls::HttpsClient` go
+// through hyper and still need to run inside a tokio runtime; they fail with
+// an error outside of one.
+
+pub(crate) type UdpSocket = async_io::Async<std::net::UdpSocket>;
+
+pub(crate) async fn sleep(duration: Duration) {
+    Timer::after(duration).await;
+}
+
+pub(crate) async fn sleep_until(deadline: Instant) {
+    Timer::at(deadline).await;
+}
+
+// `None` when the deadline passes first; the future is dropped.
+pub(crate) async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Option<F::Output> {
+    futures_util::pin_mut!(future);
+    match select(future, Timer::at(deadline)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
+
+use crate::{
+    action::{output, ActionArgs, ActionTarget},
+    device_client::DeviceClient,
+    parser::{parse_action_response, parse_record_schedules, SRS_NS},
+    types::{ManualRecording, RecordSchedule, UpnpTime},
+};
+
+const SCHEDULES_PAGE_SIZE: u32 = 100;
+
+// Client for the ScheduledRecording service of PVRs and tuners, which
+// records programs according to schedules and keeps them as record tasks.
+#[derive(Clone)]
+pub struct ScheduledRecordingClient {
+    device_client: DeviceClient,
+}
+
+impl ScheduledRecordingClient {
+    pub fn new(device_client: DeviceClient) -> Self {
+        Self { device_client }
+    }
+
+    pub async fn record_schedules(&self) -> Result<Vec<RecordSchedule>, Error> {
+        let mut schedules = vec![];
+        loop {
+            let params = ActionArgs::new()
+                .arg("Filter", "*")
+                .arg("StartingIndex", schedules.len())
+                .arg("RequestedCount", SCHEDULES_PAGE_SIZE)
+                .arg("SortCriteria", "");
+            let response = self
+                .device_client
+                .call_action("ScheduledRecording", "BrowseRecordSchedules", params)
+                .await?;
+            let values = parse_action_response(&response)?;
+            let page = parse_record_schedules(&output::<String>(&values, "Result")?)?;
+            let total: usize = output(&values, "TotalMatches")?;
+            let done = page.is_empty();
+            schedules.extend(page);
+            if done || schedules.len() >= total {
+                return Ok(schedules);
+            }
+        }
+    }
+
+    pub async fn record_schedule(&self, id: &str) -> Result<RecordSchedule, Error> {
+        let params = ActionArgs::new()
+            .arg("RecordScheduleID", id)
+            .arg("Filter", "*");
+        let response = self
+            .device_client
+            .call_action("ScheduledRecording", "GetRecordSchedule", params)
+            .await?;
+        let result: String = output(&parse_action_response(&response)?, "Result")?;
+        parse_record_schedules(&result)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No record schedule {}", id))
+    }
+
+    // Schedules `recording`, returning the new schedule's id.
+    pub async fn create_record_schedule(
+        &self,
+        recording: &ManualRecording,
+    ) -> Result<String, Error> {
+        let params = ActionArgs::new().arg("Elements", build_elements(recording));
+        let response = self
+            .device_client
+            .call_action("ScheduledRecording", "CreateRecordSchedule", params)
+            .await?;
+        output(&parse_action_response(&response)?, "RecordScheduleID")
+    }
+
+    // Deletes the schedule along with its pending record tasks. Recordings
+    // already made stay in the ContentDirectory.
+    pub async fn delete_record_schedule(&self, id: &str) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("RecordScheduleID", id);
+        let response = self
+            .device_client
+            .call_action("ScheduledRecording", "DeleteRecordSchedule", params)
+            .await?;
+        parse_action_response(&response)?;
+        Ok(())
+    }
+}
+
+impl ActionTarget for ScheduledRecordingClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+// The Elements of a CreateRecordSchedule: an srs document with a single
+// item and no id, which the service assigns.
+fn build_elements(recording: &ManualRecording) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><srs xmlns="{}"><item>"#,
+            "<title>{}</title>",
+            "<class>OBJECT.RECORDSCHEDULE.DIRECT.MANUAL</class>",
+            r#"<scheduledChannelID type="{}">{}</scheduledChannelID>"#,
+            "<scheduledStartDateTime>{}</scheduledStartDateTime>",
+            "<scheduledDuration>{}</scheduledDuration>",
+            "</item></srs>"
+        ),
+        SRS_NS,
+        escape_str_pcdata(&recording.title),
+        escape_str_attribute(&recording.channel_type),
+        escape_str_pcdata(&recording.channel_id),
+        escape_str_pcdata(&recording.start),
+        format_srs_duration(recording.duration),
+    )
+}
+
+fn format_srs_duration(duration: Duration) -> String {
+    let days = duration.as_secs() / 86400;
+    let time = UpnpTime(Duration::from_secs(duration.as_secs() % 86400));
+    match days {
+        0 => format!("P{}", time),
+        days => format!("P{}D{}", days, time),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_srs_duration, ScheduledRecordingClient};
+    use crate::{
+        device_client::DeviceClient,
+        parser::{parse_record_schedules, parse_srs_duration},
+        testing::{serve_device, soap_response, TestResponse},
+        types::{ManualRecording, RecordSchedule},
+    };
+
+    const SCHEDULES: &str = concat!(
+        r#"<srs xmlns="urn:schemas-upnp-org:av:srs">"#,
+        r#"<item id="12"><title>News</title>"#,
+        "<class>OBJECT.RECORDSCHEDULE.DIRECT.MANUAL</class>",
+        r#"<scheduledChannelID type="DIGITAL">5.1</scheduledChannelID>"#,
+        "<scheduledStartDateTime>2026-10-15T20:00:00</scheduledStartDateTime>",
+        "<scheduledDuration>P00:30:00</scheduledDuration>",
+        "<scheduleState>OPERATIONAL</scheduleState></item>",
+        r#"<item id="13"><title>Marathon</title>"#,
+        "<class>OBJECT.RECORDSCHEDULE.DIRECT.MANUAL</class>",
+        "<scheduledDuration>P1D02:00:00</scheduledDuration></item>",
+        "</srs>"
+    );
+
+    #[test]
+    fn test_srs_durations() {
+        for (value, seconds) in [("P00:30:00", 1800), ("P1D02:00:00", 93600)] {
+            let duration = Duration::from_secs(seconds);
+            assert_eq!(parse_srs_duration(value), Some(duration));
+            assert_eq!(format_srs_duration(duration), value);
+        }
+        assert_eq!(parse_srs_duration("00:30:00"), None);
+    }
+
+    #[test]
+    fn test_record_schedules() {
+        let url = serve_device(
+            &["ScheduledRecording"],
+            &[
+                "BrowseRecordSchedules",
+                "CreateRecordSchedule",
+                "DeleteRecordSchedule",
+            ],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "BrowseRecordSchedules" => &[
+                        ("Result", SCHEDULES),
+                        ("NumberReturned", "2"),
+                        ("TotalMatches", "2"),
+                        ("UpdateID", "7"),
+                    ],
+                    "CreateRecordSchedule" => {
+                        let elements = request.argument("Elements").unwrap();
+                        let schedules = parse_record_schedules(&elements).unwrap();
+                        assert_eq!(schedules[0].title, "Tom & Jerry");
+                        assert_eq!(schedules[0].channel_id.as_deref(), Some("5.1"));
+                        assert_eq!(schedules[0].duration, Some(Duration::from_secs(1800)));
+                        &[
+                            ("RecordScheduleID", "14"),
+                            ("Result", ""),
+                            ("UpdateID", "8"),
+                        ]
+                    }
+                    _ => {
+                        assert_eq!(request.argument("RecordScheduleID").unwrap(), "14");
+                        &[]
+                    }
+                };
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let recorder = ScheduledRecordingClient::new(client);
+            let schedules = recorder.record_schedules().await.unwrap();
+            assert_eq!(
+                schedules[0],
+                RecordSchedule {
+                    id: "12".to_string(),
+                    title: "News".to_string(),
+                    class: "OBJECT.RECORDSCHEDULE.DIRECT.MANUAL".to_string(),
+                    channel_id: Some("5.1".to_string()),
+                    start: Some("2026-10-15T20:00:00".to_string()),
+                    duration: Some(Duration::from_secs(1800)),
+                    priority: None,
+                    state: Some("OPERATIONAL".to_string()),
+                }
+            );
+            assert_eq!(schedules[1].duration, Some(Duration::from_secs(93600)));
+
+            let recording = ManualRecording {
+                title: "Tom & Jerry".to_string(),
+                channel_id: "5.1".to_string(),
+                channel_type: "DIGITAL".to_string(),
+                start: "2026-10-16T08:00:00".to_string(),
+                duration: Duration::from_secs(1800),
+            };
+            let id = recorder.create_record_schedule(&recording).await.unwrap();
+            assert_eq!(id, "14");
+            recorder.delete_record_schedule(&id).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use futures_util::TryStreamExt;
+
+use crate::{
+    media_server::MediaServerClient,
+    types::{Item, WalkOptions},
+};
+
+// The items below a container at one point in time, keyed by object id, for
+// sync tools comparing a library against what they saw last run.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub items: BTreeMap<String, Item>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<Item>,
+    pub removed: Vec<Item>,
+    // (before, after) pairs of items whose metadata or resources changed.
+    pub changed: Vec<(Item, Item)>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Snapshot {
+    pub async fn take(media_server: &MediaServerClient, root_id: &str) -> Result<Self> {
+        let items: Vec<Item> = media_server
+            .walk(root_id, WalkOptions::default())
+            .try_collect()
+            .await?;
+        Ok(Self::from_items(items))
+    }
+
+    pub fn from_items(items: impl IntoIterator<Item = Item>) -> Self {
+        Self {
+            items: items
+                .into_iter()
+                .map(|item| (item.id.clone(), item))
+                .collect(),
+        }
+    }
+
+    // What happened between this snapshot and `newer`. Servers reusing an id
+    // for a different file show up as changed, since resource URIs are
+    // compared along with the metadata.
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for (id, item) in &newer.items {
+            match self.items.get(id) {
+                None => diff.added.push(item.clone()),
+                Some(old) if fingerprint(old) != fingerprint(item) => {
+                    diff.changed.push((old.clone(), item.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = self
+            .items
+            .iter()
+            .filter(|(id, _)| !newer.items.contains_key(*id))
+            .map(|(_, item)| item.clone())
+            .collect();
+        diff
+    }
+}
+
+fn fingerprint(item: &Item) -> impl PartialEq + '_ {
+    let resources: Vec<(&str, Option<u64>)> = item
+        .resources
+        .iter()
+        .map(|res| (res.url.as_str(), res.size))
+        .collect();
+    (
+        &item.parent_id,
+        &item.title,
+        &item.class,
+        &item.artists,
+        &item.album,
+        &item.genre,
+        resources,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::types::{Item, Resource};
+
+    fn item(id: &str, title: &str, url: &str) -> Item {
+        Item {
+            id: id.to_string(),
+            title: title.to_string(),
+            resources: vec![Resource {
+                url: url.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diffing_snapshots() {
+        let before = Snapshot::from_items([
+            item("1", "One", "http://nas/1.mp3"),
+            item("2", "Two", "http://nas/2.mp3"),
+            item("3", "Three", "http://nas/3.mp3"),
+        ]);
+        let after = Snapshot::from_items([
+            item("1", "One", "http://nas/1.mp3"),
+            item("2", "Two", "http://nas/2.flac"),
+            item("4", "Four", "http://nas/4.mp3"),
+        ]);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].id, "4");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].id, "3");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].1.resources[0].url, "http://nas/2.flac");
+        assert!(after.diff(&after).is_empty());
+    }
+}
@@ -0,0 +1,644 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use async_stream::stream;
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    action::{output, ActionArgs, ActionTarget},
+    device_client::DeviceClient,
+    media_renderer::{build_didl, load_metadata, MediaRendererClient},
+    parser::{parse_action_response, parse_zone_group_state},
+    types::{
+        Device, Event, LoadOptions, QueuedTracks, SonosEvent, SonosSnapshot, TransportState,
+        ZoneGroup,
+    },
+    BROADCAST_EVENT,
+};
+
+// Whether `device` is a Sonos player, which extends AVTransport with a
+// queue and grouping. Sonos UDNs are "uuid:RINCON_" and the MAC address.
+pub fn is_sonos(device: &Device) -> bool {
+    device
+        .manufacturer
+        .to_ascii_lowercase()
+        .starts_with("sonos")
+        || device.udn.starts_with("uuid:RINCON_")
+}
+
+// Client for the Sonos-specific parts of a Sonos player, which otherwise
+// works as a `MediaRendererClient`. Creating one fails for other devices.
+#[derive(Clone)]
+pub struct SonosClient {
+    device_client: DeviceClient,
+}
+
+impl SonosClient {
+    pub fn new(device_client: DeviceClient) -> Result<Self, Error> {
+        let device = device_client
+            .device()
+            .ok_or_else(|| anyhow!("Device not connected"))?;
+        if !is_sonos(device) {
+            return Err(anyhow!("{} is not a Sonos device", device.friendly_name));
+        }
+        Ok(Self { device_client })
+    }
+
+    // The player's id, e.g. "RINCON_000E58A0123401400", as used in the
+    // x-rincon URIs addressing it.
+    pub fn player_id(&self) -> String {
+        let udn = self
+            .device_client
+            .device()
+            .map(|device| device.udn.as_str())
+            .unwrap_or_default();
+        udn.trim_start_matches("uuid:").to_string()
+    }
+
+    // Adds `url` to the queue at track `position`, 0 for the end, or right
+    // after the current track when `as_next` is set.
+    pub async fn add_uri_to_queue(
+        &self,
+        url: &str,
+        options: &LoadOptions,
+        position: u32,
+        as_next: bool,
+    ) -> Result<QueuedTracks, Error> {
+        let (m, object_class) = load_metadata(url, options);
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("EnqueuedURI", url)
+            .arg("EnqueuedURIMetaData", build_didl(m, object_class))
+            .arg("DesiredFirstTrackNumberEnqueued", position)
+            .arg("EnqueueAsNext", as_next);
+        let response = self
+            .device_client
+            .call_action("AVTransport", "AddURIToQueue", params)
+            .await?;
+        let values = parse_action_response(&response)?;
+        Ok(QueuedTracks {
+            first_track: output(&values, "FirstTrackNumberEnqueued")?,
+            tracks_added: output(&values, "NumTracksAdded")?,
+            queue_length: output(&values, "NewQueueLength")?,
+        })
+    }
+
+    pub async fn remove_track_from_queue(&self, track: u32) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("ObjectID", format!("Q:0/{}", track))
+            .arg("UpdateID", 0);
+        self.device_client
+            .call_action("AVTransport", "RemoveTrackFromQueue", params)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn clear_queue(&self) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("InstanceID", 0);
+        self.device_client
+            .call_action("AVTransport", "RemoveAllTracksFromQueue", params)
+            .await?;
+        Ok(())
+    }
+
+    // Moves `count` tracks from track `start` to before track `insert_before`.
+    pub async fn reorder_tracks_in_queue(
+        &self,
+        start: u32,
+        count: u32,
+        insert_before: u32,
+    ) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("StartingIndex", start)
+            .arg("NumberOfTracks", count)
+            .arg("InsertBefore", insert_before)
+            .arg("UpdateID", 0);
+        self.device_client
+            .call_action("AVTransport", "ReorderTracksInQueue", params)
+            .await?;
+        Ok(())
+    }
+
+    // Saves the queue as a Sonos playlist, returning its object id.
+    pub async fn save_queue(&self, title: &str) -> Result<String, Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("Title", title)
+            .arg("ObjectID", "");
+        let response = self
+            .device_client
+            .call_action("AVTransport", "SaveQueue", params)
+            .await?;
+        output(&parse_action_response(&response)?, "AssignedObjectID")
+    }
+
+    // Switches the player from a single URI to its queue and starts playing
+    // at `track`.
+    pub async fn play_from_queue(&self, track: u32) -> Result<(), Error> {
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg(
+                "CurrentURI",
+                format!("x-rincon-queue:{}#0", self.player_id()),
+            )
+            .arg("CurrentURIMetaData", "");
+        self.device_client
+            .call_action("AVTransport", "SetAVTransportURI", params)
+            .await?;
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("Unit", "TRACK_NR")
+            .arg("Target", track);
+        self.device_client
+            .call_action("AVTransport", "Seek", params)
+            .await?;
+        let params = ActionArgs::new().arg("InstanceID", 0).arg("Speed", 1);
+        self.device_client
+            .call_action("AVTransport", "Play", params)
+            .await?;
+        Ok(())
+    }
+
+    // Captures what the player is doing, e.g. before interrupting it with an
+    // announcement. Grouped players only keep their group, volume and mute,
+    // as the coordinator owns the rest.
+    pub async fn snapshot(&self) -> Result<SonosSnapshot, Error> {
+        let renderer = MediaRendererClient::new(self.device_client.clone());
+        let params = ActionArgs::new().arg("InstanceID", 0);
+        let response = self
+            .device_client
+            .call_action("AVTransport", "GetMediaInfo", params)
+            .await?;
+        let media_info = parse_action_response(&response)?;
+        let mut snapshot = SonosSnapshot {
+            uri: output(&media_info, "CurrentURI")?,
+            metadata: output(&media_info, "CurrentURIMetaData")?,
+            volume: renderer.get_volume().await?,
+            mute: renderer.get_mute().await?,
+            ..Default::default()
+        };
+        if snapshot.is_grouped() {
+            return Ok(snapshot);
+        }
+
+        snapshot.transport_state = renderer.get_transport_info().await?.current_transport_state;
+        snapshot.play_mode = renderer.get_play_mode().await?;
+        let position = renderer.get_position_info().await?;
+        if snapshot.is_queue() {
+            snapshot.track = position.track;
+        }
+        if position.track_duration.unwrap_or_default() > Duration::ZERO {
+            snapshot.position = position.rel_time;
+        }
+        Ok(snapshot)
+    }
+
+    // Puts the player back to `snapshot`, resuming playback if it was
+    // playing.
+    pub async fn restore(&self, snapshot: &SonosSnapshot) -> Result<(), Error> {
+        let renderer = MediaRendererClient::new(self.device_client.clone());
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("CurrentURI", &snapshot.uri)
+            .arg("CurrentURIMetaData", &snapshot.metadata);
+        self.device_client
+            .call_action("AVTransport", "SetAVTransportURI", params)
+            .await?;
+        renderer.set_volume(snapshot.volume.into()).await?;
+        renderer.set_mute(snapshot.mute).await?;
+        if snapshot.is_grouped() {
+            return Ok(());
+        }
+
+        if snapshot.is_queue() {
+            renderer.set_play_mode(snapshot.play_mode.clone()).await?;
+            if let Some(track) = snapshot.track {
+                let params = ActionArgs::new()
+                    .arg("InstanceID", 0)
+                    .arg("Unit", "TRACK_NR")
+                    .arg("Target", track);
+                self.device_client
+                    .call_action("AVTransport", "Seek", params)
+                    .await?;
+            }
+        }
+        if let Some(position) = snapshot.position {
+            renderer.seek_to(position).await?;
+        }
+        if snapshot.transport_state == TransportState::Playing {
+            renderer.play().await?;
+        }
+        Ok(())
+    }
+
+    // The household's zone groups, as known to this player.
+    pub async fn zone_groups(&self) -> Result<Vec<ZoneGroup>, Error> {
+        let response = self
+            .device_client
+            .call_action("ZoneGroupTopology", "GetZoneGroupState", ActionArgs::new())
+            .await?;
+        let state: String = output(&parse_action_response(&response)?, "ZoneGroupState")?;
+        parse_zone_group_state(&state)
+    }
+
+    // The group this player is in.
+    pub async fn zone_group(&self) -> Result<ZoneGroup, Error> {
+        let player_id = self.player_id();
+        self.zone_groups()
+            .await?
+            .into_iter()
+            .find(|group| {
+                group
+                    .members
+                    .iter()
+                    .any(|member| member.player_id == player_id)
+            })
+            .ok_or_else(|| anyhow!("{} is in no zone group", player_id))
+    }
+
+    // Makes this player join the group coordinated by `coordinator`, a
+    // player id, to play whatever the group plays.
+    pub async fn join(&self, coordinator: &str) -> Result<(), Error> {
+        let coordinator = coordinator.trim_start_matches("uuid:");
+        let params = ActionArgs::new()
+            .arg("InstanceID", 0)
+            .arg("CurrentURI", format!("x-rincon:{}", coordinator))
+            .arg("CurrentURIMetaData", "");
+        self.device_client
+            .call_action("AVTransport", "SetAVTransportURI", params)
+            .await?;
+        Ok(())
+    }
+
+    // Takes this player out of its group, leaving it stopped in a group of
+    // its own.
+    pub async fn unjoin(&self) -> Result<(), Error> {
+        let params = ActionArgs::new().arg("InstanceID", 0);
+        self.device_client
+            .call_action("AVTransport", "BecomeCoordinatorOfStandaloneGroup", params)
+            .await?;
+        Ok(())
+    }
+
+    // Events the zone groups whenever they change, starting with the
+    // current ones.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = Event>, Error> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        *BROADCAST_EVENT.lock().unwrap() = Some(tx);
+
+        self.device_client.subscribe("ZoneGroupTopology").await?;
+        Ok(stream! {
+            while let Some(event) = rx.recv().await {
+                yield event;
+            }
+        })
+    }
+}
+
+// The event for a variable of a Sonos event body, if it's one the crate
+// models.
+pub(crate) fn sonos_event(sid: &str, name: &str, value: &str) -> Option<SonosEvent> {
+    match name {
+        "ZoneGroupState" => Some(SonosEvent::ZoneGroupState {
+            sid: sid.to_string(),
+            groups: parse_zone_group_state(value).ok()?,
+        }),
+        _ => None,
+    }
+}
+
+impl ActionTarget for SonosClient {
+    fn device_client(&self) -> &DeviceClient {
+        &self.device_client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{sonos_event, SonosClient};
+    use crate::{
+        device_client::DeviceClient,
+        parser::parse_property_set,
+        testing::{serve_device, serve_device_as, soap_response, TestResponse, SUBSCRIPTIONS},
+        types::{
+            LoadOptions, PlayMode, QueuedTracks, SonosEvent, SonosSnapshot, TransportState,
+            ZoneGroupMember,
+        },
+    };
+
+    const PLAYER: &str = "uuid:RINCON_000E58A0123401400";
+
+    const ZONE_GROUP_STATE: &str = concat!(
+        "<ZoneGroupState><ZoneGroups>",
+        r#"<ZoneGroup Coordinator="RINCON_000E58A0123401400" ID="RINCON_000E58A0123401400:58">"#,
+        r#"<ZoneGroupMember UUID="RINCON_000E58A0123401400" ZoneName="Living Room" "#,
+        r#"Location="http://192.168.1.20:1400/xml/device_description.xml"/>"#,
+        r#"<ZoneGroupMember UUID="RINCON_000E58B0123401400" ZoneName="Kitchen" "#,
+        r#"Location="http://192.168.1.21:1400/xml/device_description.xml"/>"#,
+        "</ZoneGroup>",
+        r#"<ZoneGroup Coordinator="RINCON_000E58C0123401400" ID="RINCON_000E58C0123401400:12">"#,
+        r#"<ZoneGroupMember UUID="RINCON_000E58C0123401400" ZoneName="Bedroom" "#,
+        r#"Location="http://192.168.1.22:1400/xml/device_description.xml" Invisible="1"/>"#,
+        "</ZoneGroup>",
+        "</ZoneGroups></ZoneGroupState>"
+    );
+
+    #[test]
+    fn test_queue() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = calls.clone();
+        let url = serve_device_as(
+            PLAYER,
+            "Sonos, Inc.",
+            &["AVTransport"],
+            &[
+                "AddURIToQueue",
+                "RemoveTrackFromQueue",
+                "ReorderTracksInQueue",
+                "SaveQueue",
+                "SetAVTransportURI",
+                "Seek",
+                "Play",
+            ],
+            move |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let argument = |name: &str| request.argument(name).unwrap();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "AddURIToQueue" => {
+                        assert_eq!(argument("EnqueuedURI"), "http://nas/1.flac");
+                        assert_eq!(argument("EnqueueAsNext"), "1");
+                        &[
+                            ("FirstTrackNumberEnqueued", "3"),
+                            ("NumTracksAdded", "1"),
+                            ("NewQueueLength", "3"),
+                        ]
+                    }
+                    "RemoveTrackFromQueue" => {
+                        assert_eq!(argument("ObjectID"), "Q:0/2");
+                        &[]
+                    }
+                    "ReorderTracksInQueue" => {
+                        assert_eq!(argument("StartingIndex"), "2");
+                        assert_eq!(argument("InsertBefore"), "1");
+                        &[]
+                    }
+                    "SaveQueue" => &[("AssignedObjectID", "SQ:12")],
+                    "SetAVTransportURI" => {
+                        assert_eq!(
+                            argument("CurrentURI"),
+                            "x-rincon-queue:RINCON_000E58A0123401400#0"
+                        );
+                        &[]
+                    }
+                    "Seek" => {
+                        assert_eq!(argument("Unit"), "TRACK_NR");
+                        assert_eq!(argument("Target"), "3");
+                        &[]
+                    }
+                    _ => &[],
+                };
+                recorded.lock().unwrap().push(action.clone());
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let sonos = SonosClient::new(client).unwrap();
+            let queued = sonos
+                .add_uri_to_queue("http://nas/1.flac", &LoadOptions::default(), 0, true)
+                .await
+                .unwrap();
+            assert_eq!(
+                queued,
+                QueuedTracks {
+                    first_track: 3,
+                    tracks_added: 1,
+                    queue_length: 3,
+                }
+            );
+            sonos.remove_track_from_queue(2).await.unwrap();
+            sonos.reorder_tracks_in_queue(2, 1, 1).await.unwrap();
+            assert_eq!(sonos.save_queue("Party").await.unwrap(), "SQ:12");
+            sonos.play_from_queue(queued.first_track).await.unwrap();
+        });
+        assert_eq!(
+            calls.lock().unwrap()[4..],
+            ["SetAVTransportURI", "Seek", "Play"]
+        );
+    }
+
+    #[test]
+    fn test_detecting_sonos() {
+        let url = serve_device(&["AVTransport"], &[], |_| TestResponse::status(500));
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            assert!(SonosClient::new(client).is_err());
+        });
+    }
+
+    #[test]
+    fn test_zone_groups() {
+        let url = serve_device_as(
+            PLAYER,
+            "Sonos, Inc.",
+            &["AVTransport", "ZoneGroupTopology"],
+            &[
+                "GetZoneGroupState",
+                "SetAVTransportURI",
+                "BecomeCoordinatorOfStandaloneGroup",
+            ],
+            |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "GetZoneGroupState" => {
+                        assert_eq!(request.path, "/ZoneGroupTopology/control");
+                        &[("ZoneGroupState", ZONE_GROUP_STATE)]
+                    }
+                    "SetAVTransportURI" => {
+                        assert_eq!(
+                            request.argument("CurrentURI").unwrap(),
+                            "x-rincon:RINCON_000E58C0123401400"
+                        );
+                        &[]
+                    }
+                    _ => &[],
+                };
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let sonos = SonosClient::new(client).unwrap();
+            let groups = sonos.zone_groups().await.unwrap();
+            assert_eq!(groups.len(), 2);
+            assert_eq!(groups[1].members[0].zone_name, "Bedroom");
+            assert!(groups[1].members[0].invisible);
+
+            let group = sonos.zone_group().await.unwrap();
+            assert_eq!(group.id, "RINCON_000E58A0123401400:58");
+            assert_eq!(
+                group.coordinator().map(|member| member.zone_name.as_str()),
+                Some("Living Room")
+            );
+            assert_eq!(
+                group.members[1],
+                ZoneGroupMember {
+                    player_id: "RINCON_000E58B0123401400".to_string(),
+                    zone_name: "Kitchen".to_string(),
+                    location: "http://192.168.1.21:1400/xml/device_description.xml".to_string(),
+                    invisible: false,
+                }
+            );
+
+            sonos.join("uuid:RINCON_000E58C0123401400").await.unwrap();
+            sonos.unjoin().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_zone_group_events() {
+        // Older firmware sends ZoneGroups as the root.
+        let state = ZONE_GROUP_STATE
+            .trim_start_matches("<ZoneGroupState>")
+            .trim_end_matches("</ZoneGroupState>");
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0"?><e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">"#,
+                "<e:property><ZoneGroupState>{}</ZoneGroupState></e:property>",
+                "<e:property><ThirdPartyMediaServersX></ThirdPartyMediaServersX></e:property>",
+                "</e:propertyset>"
+            ),
+            xml::escape::escape_str_pcdata(state)
+        );
+        let events: Vec<SonosEvent> = parse_property_set(&body)
+            .unwrap()
+            .iter()
+            .filter_map(|(name, value)| sonos_event("uuid:1", name, value))
+            .collect();
+        assert!(matches!(
+            &events[..],
+            [SonosEvent::ZoneGroupState { groups, .. }]
+                if groups.len() == 2 && groups[0].members.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_subscribing_without_zone_group_topology() {
+        let url = serve_device_as(PLAYER, "Sonos, Inc.", &["AVTransport"], &[], |_| {
+            TestResponse::status(200)
+        });
+
+        async_io::block_on(async {
+            let _subscriptions = SUBSCRIPTIONS.lock().await;
+            let device_client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let mut sonos = SonosClient::new(device_client).unwrap();
+            assert!(sonos.subscribe().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = calls.clone();
+        let url = serve_device_as(
+            PLAYER,
+            "Sonos, Inc.",
+            &["AVTransport", "RenderingControl"],
+            &[
+                "GetMediaInfo",
+                "GetVolume",
+                "GetMute",
+                "GetTransportInfo",
+                "GetTransportSettings",
+                "GetPositionInfo",
+                "SetAVTransportURI",
+                "SetVolume",
+                "SetMute",
+                "SetPlayMode",
+                "Seek",
+                "Play",
+            ],
+            move |request| {
+                let action = request.soap_action().unwrap().to_string();
+                let values: &[(&str, &str)] = match action.as_str() {
+                    "GetMediaInfo" => &[
+                        ("CurrentURI", "x-rincon-queue:RINCON_000E58A0123401400#0"),
+                        ("CurrentURIMetaData", ""),
+                    ],
+                    "GetVolume" => &[("CurrentVolume", "25")],
+                    "GetMute" => &[("CurrentMute", "0")],
+                    "GetTransportInfo" => &[
+                        ("CurrentTransportState", "PLAYING"),
+                        ("CurrentTransportStatus", "OK"),
+                        ("CurrentSpeed", "1"),
+                    ],
+                    "GetTransportSettings" => &[
+                        ("PlayMode", "SHUFFLE"),
+                        ("RecQualityMode", "NOT_IMPLEMENTED"),
+                    ],
+                    "GetPositionInfo" => &[
+                        ("Track", "4"),
+                        ("TrackDuration", "0:03:20"),
+                        ("RelTime", "0:01:05"),
+                    ],
+                    "Seek" => {
+                        let argument = |name: &str| request.argument(name).unwrap();
+                        recorded.lock().unwrap().push(format!(
+                            "Seek {} {}",
+                            argument("Unit"),
+                            argument("Target")
+                        ));
+                        &[]
+                    }
+                    _ => &[],
+                };
+                if action.starts_with("Set") || action == "Play" {
+                    recorded.lock().unwrap().push(action.clone());
+                }
+                TestResponse::ok(soap_response(&action, values))
+            },
+        );
+
+        async_io::block_on(async {
+            let client = DeviceClient::new(&url).unwrap().connect().await.unwrap();
+            let sonos = SonosClient::new(client).unwrap();
+            let snapshot = sonos.snapshot().await.unwrap();
+            assert_eq!(
+                snapshot,
+                SonosSnapshot {
+                    uri: "x-rincon-queue:RINCON_000E58A0123401400#0".to_string(),
+                    metadata: String::new(),
+                    transport_state: TransportState::Playing,
+                    play_mode: PlayMode::Shuffle,
+                    track: Some(4),
+                    position: Some(Duration::from_secs(65)),
+                    volume: 25,
+                    mute: false,
+                }
+            );
+            sonos.restore(&snapshot).await.unwrap();
+        });
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "SetAVTransportURI",
+                "SetVolume",
+                "SetMute",
+                "SetPlayMode",
+                "Seek TRACK_NR 4",
+                "Seek REL_TIME 00:01:05",
+                "Play",
+            ]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use xml::{escape::escape_str_pcdata, reader::XmlEvent, EventReader};
+
+// In-process device for tests: a blocking HTTP/1.1 server answering one
+// request at a time, with every service sharing the same action list.
+
+// Held by tests that subscribe, as all subscriptions share one event stream.
+pub(crate) static SUBSCRIPTIONS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub(crate) struct TestRequest {
+    // Numbers the TCP connections the server accepted, from 0.
+    pub connection: usize,
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn soap_action(&self) -> Option<&str> {
+        let action = self.header("SOAPACTION")?.trim_matches('"');
+        action.rsplit_once('#').map(|(_, action)| action)
+    }
+
+    pub fn argument(&self, name: &str) -> Option<String> {
+        let mut in_argument = false;
+        let mut value = None;
+        for e in EventReader::from_str(&self.body) {
+            match e {
+                Ok(XmlEvent::StartElement { name: element, .. }) if element.local_name == name => {
+                    in_argument = true;
+                    value = Some(String::new());
+                }
+                Ok(XmlEvent::EndElement { name: element }) if element.local_name == name => {
+                    in_argument = false;
+                }
+                Ok(XmlEvent::Characters(text)) if in_argument => value = Some(text),
+                _ => {}
+            }
+        }
+        value
+    }
+}
+
+pub(crate) struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn ok(body: String) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), "text/xml".to_string())],
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    // Binary bodies, e.g. compressed ones.
+    pub fn bytes(body: Vec<u8>) -> Self {
+        Self {
+            body,
+            ..Self::status(200)
+        }
+    }
+
+    pub fn status_code(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// Serves a root device exposing `services` (e.g. "ContentDirectory"), with
+// control requests handed to `control`. Returns the description URL.
+pub(crate) fn serve_device(
+    services: &[&str],
+    actions: &[&str],
+    control: impl FnMut(&TestRequest) -> TestResponse + Send + 'static,
+) -> String {
+    serve_device_as("uuid:test", "Test", services, actions, control)
+}
+
+// Same as `serve_device`, for tests of vendor detection.
+pub(crate) fn serve_device_as(
+    udn: &str,
+    manufacturer: &str,
+    services: &[&str],
+    actions: &[&str],
+    mut control: impl FnMut(&TestRequest) -> TestResponse + Send + 'static,
+) -> String {
+    let description = description(udn, manufacturer, services);
+    let scpd = scpd(actions);
+    serve(move |request| match request.path.as_str() {
+        path if path.ends_with("/control") || path.ends_with("/event") => control(request),
+        path if path.ends_with("/scpd.xml") => TestResponse::ok(scpd.clone()),
+        _ => TestResponse::ok(description.clone()),
+    }) + "/description.xml"
+}
+
+pub(crate) fn serve(handler: impl FnMut(&TestRequest) -> TestResponse + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler = Arc::new(Mutex::new(handler));
+    thread::spawn(move || {
+        for (connection, stream) in listener.incoming().enumerate() {
+            let handler = handler.clone();
+            thread::spawn(move || {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                // Keep-alive: serve requests until the client hangs up or asks
+                // for the connection to be closed.
+                while let Some(request) = read_request(&mut reader, connection) {
+                    let response = (handler.lock().unwrap())(&request);
+                    let close = request
+                        .header("Connection")
+                        .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+                    let mut head = format!("HTTP/1.1 {} Test\r\n", response.status);
+                    if close {
+                        head.push_str("Connection: close\r\n");
+                    }
+                    for (name, value) in &response.headers {
+                        head.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                    head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+                    let mut message = head.into_bytes();
+                    message.extend_from_slice(&response.body);
+                    if stream.write_all(&message).is_err() || close {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    format!("http://{}", address)
+}
+
+fn read_request(reader: &mut impl BufRead, connection: usize) -> Option<TestRequest> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).ok()? == 0 {
+        return None;
+    }
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+            None => break,
+        }
+    }
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    let mut request_line = request_line.split_whitespace();
+    Some(TestRequest {
+        connection,
+        method: request_line.next().unwrap_or("GET").to_string(),
+        path: request_line.next().unwrap_or("/").to_string(),
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    })
+}
+
+// Sends the device's NOTIFY of `properties` (a list of "<Name>value</Name>")
+// to the "<http://host:port/>" callback of a SUBSCRIBE.
+pub(crate) fn notify(callback: &str, sid: &str, properties: &str) {
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0"?><e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">"#,
+            "<e:property>{}</e:property></e:propertyset>"
+        ),
+        properties
+    );
+    let host = callback
+        .trim_start_matches("<http://")
+        .trim_end_matches("/>");
+    let mut stream = TcpStream::connect(host).unwrap();
+    let request = format!(
+        concat!(
+            "NOTIFY / HTTP/1.1\r\nHost: {}\r\nNT: upnp:event\r\nNTS: upnp:propchange\r\n",
+            "SID: {}\r\nSEQ: 0\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n",
+            "Connection: close\r\n\r\n{}"
+        ),
+        host,
+        sid,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    stream.read_to_end(&mut vec![]).unwrap();
+}
+
+pub(crate) fn soap_response(action: &str, values: &[(&str, &str)]) -> String {
+    let values: String = values
+        .iter()
+        .map(|(name, value)| format!("<{0}>{1}</{0}>", name, escape_str_pcdata(value)))
+        .collect();
+    format!(
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>"#,
+            r#"<u:{0}Response xmlns:u="urn:test">{1}</u:{0}Response>"#,
+            r#"</s:Body></s:Envelope>"#
+        ),
+        action, values
+    )
+}
+
+pub(crate) fn soap_fault(code: u32, description: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>"#,
+            r#"<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>"#,
+            r#"<detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0">"#,
+            r#"<errorCode>{}</errorCode><errorDescription>{}</errorDescription>"#,
+            r#"</UPnPError></detail></s:Fault></s:Body></s:Envelope>"#
+        ),
+        code, description
+    )
+}
+
+// Services of other domains are given as e.g. "av-openhome-org:Playlist", and
+// versions other than 1 as e.g. "AVTransport:2".
+fn description(udn: &str, manufacturer: &str, services: &[&str]) -> String {
+    let services: String = services
+        .iter()
+        .map(|service| {
+            let (service, version) = match service.rsplit_once(':') {
+                Some((service, version)) if version.parse::<u32>().is_ok() => (service, version),
+                _ => (*service, "1"),
+            };
+            let (type_domain, id_domain, service) = match service.split_once(':') {
+                Some((domain, service)) => (domain, domain, service),
+                None => ("schemas-upnp-org", "upnp-org", service),
+            };
+            format!(
+                concat!(
+                    "<service>",
+                    "<serviceType>urn:{1}:service:{0}:{3}</serviceType>",
+                    "<serviceId>urn:{2}:serviceId:{0}</serviceId>",
+                    "<controlURL>/{0}/control</controlURL>",
+                    "<eventSubURL>/{0}/event</eventSubURL>",
+                    "<SCPDURL>/{0}/scpd.xml</SCPDURL>",
+                    "</service>"
+                ),
+                service, type_domain, id_domain, version
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            r#"<?xml version="1.0"?><root xmlns="urn:schemas-upnp-org:device-1-0"><device>"#,
+            "<deviceType>urn:schemas-upnp-org:device:Test:1</deviceType>",
+            "<friendlyName>Test</friendlyName><manufacturer>{}</manufacturer>",
+            "<modelName>Test</modelName><UDN>{}</UDN>",
+            "<serviceList>{}</serviceList></device></root>"
+        ),
+        manufacturer, udn, services
+    )
+}
+
+fn scpd(actions: &[&str]) -> String {
+    let actions: String = actions
+        .iter()
+        .map(|action| format!("<action><name>{}</name></action>", action))
+        .collect();
+    format!(
+        r#"<?xml version="1.0"?><scpd xmlns="urn:schemas-upnp-org:service-1-0"><actionList>{}</actionList></scpd>"#,
+        actions
+    )
+}
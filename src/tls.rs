@@ -0,0 +1,469 @@
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr, SocketAddrV6},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::SystemTime,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compression::Identity,
+    limits::{too_large, BodyLimit},
+    runtime,
+    types::Limits,
+};
+use async_stream::try_stream;
+use futures_util::{AsyncBufRead, AsyncRead, AsyncReadExt, Stream};
+use http_client::http_types::StatusCode;
+use hyper::{
+    body::{Bytes, HttpBody},
+    client::HttpConnector,
+    service::Service,
+    Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, Error, RootCertStore, ServerName,
+};
+
+// Trusts exactly one device certificate (DER encoded), whatever its issuer,
+// which is how self-signed Device Protection certificates are usually handled.
+pub fn pinned_certificate_config(certificate: Vec<u8>) -> ClientConfig {
+    verifier_config(Arc::new(PinnedCertificate(Certificate(certificate))))
+}
+
+pub fn verifier_config(verifier: Arc<dyn ServerCertVerifier>) -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth()
+}
+
+pub(crate) fn default_config() -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth()
+}
+
+pub(crate) fn https_connector(
+    tls_config: ClientConfig,
+    local_address: Option<IpAddr>,
+    scope_id: u32,
+) -> HttpsConnector<ScopedConnector> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_local_address(local_address);
+    HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(ScopedConnector {
+            http,
+            local_address,
+            scope_id,
+        })
+}
+
+// Without keep-alive, connections go back to no pool and are closed after
+// each request.
+pub(crate) fn hyper_client(
+    connector: HttpsConnector<ScopedConnector>,
+    keep_alive: bool,
+) -> hyper::Client<HttpsConnector<ScopedConnector>> {
+    let mut builder = hyper::Client::builder();
+    if !keep_alive {
+        builder.pool_max_idle_per_host(0);
+    }
+    builder.build(connector)
+}
+
+// IPv6 literals in a URI can't carry a zone, so connections to them are
+// scoped to the interface `scope_id` here; everything else goes through
+// hyper's connector.
+#[derive(Debug, Clone)]
+pub(crate) struct ScopedConnector {
+    http: HttpConnector,
+    local_address: Option<IpAddr>,
+    scope_id: u32,
+}
+
+impl Service<Uri> for ScopedConnector {
+    type Response = tokio::net::TcpStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let address = uri
+            .host()
+            .and_then(|host| {
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse()
+                    .ok()
+            })
+            .filter(|_| self.scope_id != 0);
+        let Some(address) = address else {
+            let connecting = self.http.call(uri);
+            return Box::pin(async move { Ok(connecting.await?) });
+        };
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+        let device = SocketAddrV6::new(address, port, 0, self.scope_id);
+        let local_address = self.local_address;
+        let scope_id = self.scope_id;
+        Box::pin(async move {
+            let socket = tokio::net::TcpSocket::new_v6()?;
+            if let Some(IpAddr::V6(local_address)) = local_address {
+                socket.bind(SocketAddrV6::new(local_address, 0, 0, scope_id).into())?;
+            }
+            let stream = socket.connect(SocketAddr::V6(device)).await?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        })
+    }
+}
+
+struct PinnedCertificate(Certificate);
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        match end_entity == &self.0 {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(Error::General(
+                "Device certificate does not match the pinned certificate".to_string(),
+            )),
+        }
+    }
+}
+
+// surf transport backed by hyper + rustls. Unlike the default one it can
+// connect to https URLs whose host is an IP address, as device URLs usually are.
+#[derive(Debug, Clone)]
+pub struct HttpsClient {
+    connector: HttpsConnector<ScopedConnector>,
+    client: hyper::Client<HttpsConnector<ScopedConnector>>,
+    timeout: Duration,
+}
+
+impl HttpsClient {
+    pub fn new(tls_config: ClientConfig) -> Self {
+        Self::with_local_address(tls_config, None)
+    }
+
+    // Binds outgoing connections to `local_address`, selecting the network
+    // interface used to reach devices on multi-homed hosts.
+    pub fn with_local_address(tls_config: ClientConfig, local_address: Option<IpAddr>) -> Self {
+        Self::with_scope_id(tls_config, local_address, 0)
+    }
+
+    // Reaches link-local IPv6 devices through the interface `scope_id`, as
+    // resolved by `parser::scope_id` from the zone of the device URL.
+    pub fn with_scope_id(
+        tls_config: ClientConfig,
+        local_address: Option<IpAddr>,
+        scope_id: u32,
+    ) -> Self {
+        let connector = https_connector(tls_config, local_address, scope_id);
+        Self {
+            client: hyper_client(connector.clone(), true),
+            connector,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.client = hyper_client(self.connector.clone(), keep_alive);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[http_client::async_trait]
+impl http_client::HttpClient for HttpsClient {
+    async fn send(
+        &self,
+        mut req: http_client::Request,
+    ) -> Result<http_client::Response, http_client::Error> {
+        // hyper's connector registers sockets with tokio's reactor and would
+        // panic outside of it.
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(http_client::Error::from_str(
+                StatusCode::InternalServerError,
+                "https and interface-scoped requests need a tokio runtime",
+            ));
+        }
+        let mut builder = hyper::Request::builder()
+            .method(req.method().as_ref())
+            .uri(req.url().as_str());
+        for (name, values) in req.iter() {
+            for value in values.iter() {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+        }
+        // Stream the body through rather than buffering it, so large uploads
+        // don't have to fit in memory.
+        let body = req.take_body();
+        if let (Some(len), false) = (body.len(), req.header("Content-Length").is_some()) {
+            builder = builder.header("Content-Length", len);
+        }
+        let request = builder.body(hyper::Body::wrap_stream(body_stream(body)))?;
+
+        // Downloads are passed on whatever their size.
+        let max_size = match req.ext().get::<Identity>() {
+            Some(_) => None,
+            None => Some(
+                req.ext()
+                    .get::<BodyLimit>()
+                    .map(|limit| limit.0)
+                    .unwrap_or(Limits::default().max_response_size),
+            ),
+        };
+        let response = match Instant::now().checked_add(self.timeout) {
+            Some(deadline) => runtime::timeout_at(deadline, self.client.request(request))
+                .await
+                .ok_or_else(|| {
+                    http_client::Error::from_str(StatusCode::GatewayTimeout, "Request timed out")
+                })??,
+            None => self.client.request(request).await?,
+        };
+
+        let status: u16 = response.status().into();
+        let mut res = http_client::Response::new(status);
+        for (name, value) in response.headers() {
+            res.append_header(name.as_str(), value.to_str()?);
+        }
+        let len = HttpBody::size_hint(response.body())
+            .exact()
+            .map(|len| len as usize);
+        let reader = HyperBodyReader {
+            body: response.into_body(),
+            chunk: Bytes::new(),
+            received: 0,
+            max_size,
+        };
+        res.set_body(http_client::Body::from_reader(reader, len));
+        Ok(res)
+    }
+}
+
+// Streams a response body on to surf as it arrives, failing once it grows
+// past `max_size`.
+struct HyperBodyReader {
+    body: hyper::Body,
+    chunk: Bytes,
+    received: usize,
+    max_size: Option<usize>,
+}
+
+impl AsyncBufRead for HyperBodyReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        while this.chunk.is_empty() {
+            match ready!(Pin::new(&mut this.body).poll_data(cx)) {
+                Some(Ok(chunk)) => {
+                    this.received += chunk.len();
+                    if let Some(max_size) = this.max_size.filter(|&max| this.received > max) {
+                        return Poll::Ready(Err(io::Error::other(too_large(max_size))));
+                    }
+                    this.chunk = chunk;
+                }
+                Some(Err(err)) => return Poll::Ready(Err(io::Error::other(err))),
+                None => break,
+            }
+        }
+        Poll::Ready(Ok(&this.chunk))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.chunk = this.chunk.slice(amt..);
+    }
+}
+
+impl AsyncRead for HyperBodyReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let chunk = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let read = chunk.len().min(buf.len());
+        buf[..read].copy_from_slice(&chunk[..read]);
+        self.consume(read);
+        Poll::Ready(Ok(read))
+    }
+}
+
+fn body_stream(
+    mut body: http_client::Body,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + 'static {
+    try_stream! {
+        loop {
+            let mut chunk = vec![0; 64 * 1024];
+            let read = body.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            chunk.truncate(read);
+            yield chunk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_config, pinned_certificate_config, HttpsClient};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use hyper::{
+        server::conn::Http,
+        service::{make_service_fn, service_fn},
+        Body, Response, Server,
+    };
+    use rustls::{Certificate, PrivateKey};
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    #[tokio::test]
+    async fn test_https_client_plain_http_roundtrip() {
+        let service = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<Body>| async move {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                Ok::<_, hyper::Error>(
+                    Response::builder()
+                        .header("Content-Type", "text/xml")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}/control", server.local_addr());
+        tokio::spawn(server);
+
+        let client = surf::Client::with_http_client(HttpsClient::new(default_config()));
+        let mut res = client.post(url).body_string("<ok/>".into()).await.unwrap();
+        assert_eq!(res.header("Content-Type").unwrap().as_str(), "text/xml");
+        assert_eq!(res.body_string().await.unwrap(), "<ok/>");
+    }
+
+    #[tokio::test]
+    async fn test_https_client_timeout() {
+        // Accepts connections into its backlog but never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/control", listener.local_addr().unwrap());
+
+        let client = HttpsClient::new(default_config()).timeout(Duration::from_millis(200));
+        let client = surf::Client::with_http_client(client);
+        let started = Instant::now();
+        let error = client.get(url).await.unwrap_err();
+        assert_eq!(error.status(), 504);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    // Self-signed P-256 certificates for 127.0.0.1, base64 DER.
+    const DEVICE_CERTIFICATE: &str = "MIIBkzCCATqgAwIBAgIUHm41M+TiW9obGW06AxTwgMOBVDIwCgYIKoZIzj0EAwIwFjEUMBIGA1UEAwwLdXBucC1kZXZpY2UwIBcNMjYxMDE1MDkzOTAzWhgPMjEyNjA5MjEwOTM5MDNaMBYxFDASBgNVBAMMC3VwbnAtZGV2aWNlMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE0jFTrkSPEm3d0vULDe0LQ6UGI72EnMIUu8cyr6T2ipw572WRj0dSj6R7+BUAh8o4SmREHhoqfBTeZPhzQlAV/qNkMGIwHQYDVR0OBBYEFNCF0o1W1zIO4mgOEF7+mVWGIsSgMB8GA1UdIwQYMBaAFNCF0o1W1zIO4mgOEF7+mVWGIsSgMA8GA1UdEwEB/wQFMAMBAf8wDwYDVR0RBAgwBocEfwAAATAKBggqhkjOPQQDAgNHADBEAiAcJgxOzzy7FosNvMw8CkBfHQydeKKmK0KEBUE8JqFnowIgEILWOp3lLyWp6EejRPghiMbkiB+7S/RrOEDrEbJXqcM=";
+    const DEVICE_KEY: &str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQgNNappwGA8s6M+29orHc9Klg56BYx1c/UOha+ppZemVChRANCAATSMVOuRI8Sbd3S9QsN7QtDpQYjvYScwhS7xzKvpPaKnDnvZZGPR1KPpHv4FQCHyjhKZEQeGip8FN5k+HNCUBX+";
+    const OTHER_CERTIFICATE: &str = "MIIBkzCCATqgAwIBAgIUA/inIDzroj9jmkwjQM4J4Am5ojkwCgYIKoZIzj0EAwIwFjEUMBIGA1UEAwwLdXBucC1kZXZpY2UwIBcNMjYxMDE1MDkzOTAzWhgPMjEyNjA5MjEwOTM5MDNaMBYxFDASBgNVBAMMC3VwbnAtZGV2aWNlMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEcfysJJKNb4vSz7Wx36ndctMkZtRDctDNAHu3HeQvM3z02+NuWq1EizrmVZsuo+r3ezWwkHOXlKLI7PJE+PE8N6NkMGIwHQYDVR0OBBYEFH3g7AmC9kLrkYw0apwBF3QDlZq2MB8GA1UdIwQYMBaAFH3g7AmC9kLrkYw0apwBF3QDlZq2MA8GA1UdEwEB/wQFMAMBAf8wDwYDVR0RBAgwBocEfwAAATAKBggqhkjOPQQDAgNHADBEAiBi1k1e2SGQENV5OgpH9I/yh/Sh03kSfWi48VY76mqpCQIgKrCMQJDFXhMQY/Nifb02A+ZLo715/iRpinWFSIlR4+s=";
+
+    // An https device presenting DEVICE_CERTIFICATE; returns its URL.
+    async fn serve_https() -> String {
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(STANDARD.decode(DEVICE_CERTIFICATE).unwrap())],
+                PrivateKey(STANDARD.decode(DEVICE_KEY).unwrap()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/description.xml", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // Failed handshakes are the point of some tests.
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        let service = service_fn(|_: hyper::Request<Body>| async {
+                            Ok::<_, hyper::Error>(Response::new(Body::from("<root/>")))
+                        });
+                        let _ = Http::new().serve_connection(stream, service).await;
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_pinned_certificate_handshake() {
+        let url = serve_https().await;
+
+        let pinned = pinned_certificate_config(STANDARD.decode(DEVICE_CERTIFICATE).unwrap());
+        let client = surf::Client::with_http_client(HttpsClient::new(pinned));
+        let mut res = client.get(&url).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "<root/>");
+
+        let other = pinned_certificate_config(STANDARD.decode(OTHER_CERTIFICATE).unwrap());
+        let client = surf::Client::with_http_client(HttpsClient::new(other));
+        assert!(client.get(&url).await.is_err());
+
+        // Nothing is trusted by default, self-signed or not.
+        let client = surf::Client::with_http_client(HttpsClient::new(default_config()));
+        assert!(client.get(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_https_client_streams_request_body() {
+        let service = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<Body>| async move {
+                let chunked = req.headers().get("Transfer-Encoding").is_some();
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                Ok::<_, hyper::Error>(Response::new(Body::from(format!(
+                    "{} {}",
+                    chunked,
+                    body.len()
+                ))))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}/import", server.local_addr());
+        tokio::spawn(server);
+
+        let client = surf::Client::with_http_client(HttpsClient::new(default_config()));
+        let upload = futures_util::io::Cursor::new(vec![7u8; 200 * 1024]);
+        let body = surf::Body::from_reader(futures_util::io::BufReader::new(upload), None);
+        let mut res = client.post(url).body(body).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "true 204800");
+    }
+}
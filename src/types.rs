@@ -1,8 +1,10 @@
-use std::fmt::Display;
+use std::{borrow::Cow, collections::HashMap, fmt::Display, sync::Arc, time::Duration};
 
 use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
 
+use crate::action::ActionValue;
+
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct Device {
     pub location: String,
@@ -14,9 +16,59 @@ pub struct Device {
     pub model_name: String,
     pub model_number: Option<String>,
     pub services: Vec<Service>,
+    pub devices: Vec<Device>,
     pub udn: String,
 }
 
+impl Device {
+    // Depth-first search through this device and its embedded devices, matching
+    // either the UDN or the device type (with or without the version suffix).
+    pub fn find_device(&self, udn_or_type: &str) -> Option<&Device> {
+        let matches = self.udn == udn_or_type
+            || self.device_type == udn_or_type
+            || self
+                .device_type
+                .rsplit_once(':')
+                .map(|(device_type, _)| device_type == udn_or_type)
+                .unwrap_or(false);
+        match matches {
+            true => Some(self),
+            false => self
+                .devices
+                .iter()
+                .find_map(|device| device.find_device(udn_or_type)),
+        }
+    }
+
+    // Like `find_device`, by service type with or without the version suffix.
+    pub fn find_service_type(&self, service_type: &str) -> Option<&Service> {
+        self.services
+            .iter()
+            .find(|s| {
+                s.service_type == service_type
+                    || s.service_type
+                        .rsplit_once(':')
+                        .is_some_and(|(base, _)| base == service_type)
+            })
+            .or_else(|| {
+                self.devices
+                    .iter()
+                    .find_map(|device| device.find_service_type(service_type))
+            })
+    }
+
+    pub fn find_service(&self, service_id: &str) -> Option<&Service> {
+        self.services
+            .iter()
+            .find(|s| s.service_id == service_id)
+            .or_else(|| {
+                self.devices
+                    .iter()
+                    .find_map(|device| device.find_service(service_id))
+            })
+    }
+}
+
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct Service {
     pub service_type: String,
@@ -27,6 +79,14 @@ pub struct Service {
     pub actions: Vec<Action>,
 }
 
+impl Service {
+    // The version advertised in the service URN, e.g. 2 for
+    // "urn:schemas-upnp-org:service:AVTransport:2".
+    pub fn version(&self) -> Option<u32> {
+        self.service_type.rsplit(':').next()?.parse().ok()
+    }
+}
+
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct Action {
     pub name: String,
@@ -40,22 +100,161 @@ pub struct Argument {
     pub related_state_variable: String,
 }
 
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct StateVariable {
+    pub name: String,
+    pub data_type: String,
+    pub send_events: bool,
+    pub default_value: Option<String>,
+    pub allowed_values: Vec<String>,
+}
+
+// Everything an SCPD document describes about a service.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct ServiceDescription {
+    pub actions: Vec<Action>,
+    pub state_variables: Vec<StateVariable>,
+}
+
+impl ServiceDescription {
+    pub fn state_variable(&self, name: &str) -> Option<&StateVariable> {
+        self.state_variables.iter().find(|v| v.name == name)
+    }
+}
+
+// Upper bounds on what devices may send, so a misbehaving or malicious device
+// on the LAN can't exhaust the memory of a long-running control point.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Limits {
+    pub max_description_size: usize,
+    pub max_scpd_size: usize,
+    pub max_response_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_description_size: 1024 * 1024,
+            max_scpd_size: 1024 * 1024,
+            max_response_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Quirks {
+    pub unquoted_soap_action: bool,
+    pub omit_content_type_charset: bool,
+    pub auto_retry: bool,
+    pub use_mpost: bool,
+}
+
+impl Quirks {
+    pub fn alternatives(&self) -> Vec<Quirks> {
+        [(true, false), (false, true), (true, true)]
+            .iter()
+            .map(|(toggle_quotes, toggle_charset)| Quirks {
+                unquoted_soap_action: self.unquoted_soap_action ^ toggle_quotes,
+                omit_content_type_charset: self.omit_content_type_charset ^ toggle_charset,
+                auto_retry: self.auto_retry,
+                use_mpost: self.use_mpost,
+            })
+            .collect()
+    }
+}
+
+// Retries actions that failed before a response was received (connection
+// refused/reset, timeouts), waiting `backoff` times the attempt number. With
+// `honor_retry_after`, 503 responses are retried too, after the delay the
+// device asked for.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub honor_retry_after: bool,
+}
+
+// A device answered 503, e.g. while rescanning its library. Returned wrapped
+// in `anyhow::Error`; use `downcast_ref::<DeviceBusy>()` to tell it apart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeviceBusy {
+    pub retry_after: Option<Duration>,
+}
+
+impl Display for DeviceBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(
+                f,
+                "Device busy, retry after {} seconds",
+                retry_after.as_secs()
+            ),
+            None => write!(f, "Device busy"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceBusy {}
+
+// The error a device reported in a SOAP fault, e.g. 701 for a transition
+// AVTransport can't make now. Returned wrapped in `anyhow::Error`; use
+// `downcast_ref::<UpnpError>()` to read the code.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UpnpError {
+    pub code: u32,
+    pub description: String,
+}
+
+impl Display for UpnpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "UPnP error {}: {}", self.code, self.description)
+    }
+}
+
+impl std::error::Error for UpnpError {}
+
+// The upnp:class sent with media cast to a renderer. Strict renderers pick
+// their player from it, e.g. a live stream needs a broadcast class for the
+// renderer not to expect a duration.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ObjectClass {
     Audio,
+    AudioBroadcast,
+    AudioBook,
     Video,
+    VideoBroadcast,
+    MusicVideoClip,
     Image,
     Container,
 }
 
+const OBJECT_CLASSES: [ObjectClass; 8] = [
+    ObjectClass::Audio,
+    ObjectClass::AudioBroadcast,
+    ObjectClass::AudioBook,
+    ObjectClass::Video,
+    ObjectClass::VideoBroadcast,
+    ObjectClass::MusicVideoClip,
+    ObjectClass::Image,
+    ObjectClass::Container,
+];
+
+// Subclasses map to their media kind, so a plain videoItem or a vendor's
+// subclass of musicTrack is still played as video or audio.
 impl From<&str> for ObjectClass {
     fn from(value: &str) -> Self {
-        match value {
-            "object.item.audioItem.musicTrack" => ObjectClass::Audio,
-            "object.item.videoItem.movie" => ObjectClass::Video,
-            "object.item.imageItem.photo" => ObjectClass::Image,
-            "object.container" => ObjectClass::Container,
-            _ => ObjectClass::Container,
+        let class = UpnpClass::new(value);
+        if let Some(exact) = OBJECT_CLASSES.iter().find(|c| c.value() == class.as_str()) {
+            *exact
+        } else if class.derived_from(&UpnpClass::AUDIO_ITEM) {
+            ObjectClass::Audio
+        } else if class.derived_from(&UpnpClass::VIDEO_ITEM) {
+            ObjectClass::Video
+        } else if class.derived_from(&UpnpClass::IMAGE_ITEM) {
+            ObjectClass::Image
+        } else {
+            ObjectClass::Container
         }
     }
 }
@@ -64,158 +263,2436 @@ impl ObjectClass {
     pub fn value(&self) -> &'static str {
         match self {
             ObjectClass::Audio => "object.item.audioItem.musicTrack",
+            ObjectClass::AudioBroadcast => "object.item.audioItem.audioBroadcast",
+            ObjectClass::AudioBook => "object.item.audioItem.audioBook",
             ObjectClass::Video => "object.item.videoItem.movie",
+            ObjectClass::VideoBroadcast => "object.item.videoItem.videoBroadcast",
+            ObjectClass::MusicVideoClip => "object.item.videoItem.musicVideoClip",
             ObjectClass::Image => "object.item.imageItem.photo",
             ObjectClass::Container => "object.container",
         }
     }
+
+    // The class to use when none was given: by the top-level mime type,
+    // video otherwise.
+    pub fn for_content_type(content_type: &str) -> Self {
+        match content_type.split('/').next().unwrap_or_default() {
+            "audio" => ObjectClass::Audio,
+            "image" => ObjectClass::Image,
+            _ => ObjectClass::Video,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Metadata {
-    pub url: String,
-    pub title: String,
-    pub artist: Option<String>,
-    pub album: Option<String>,
-    pub album_art_uri: Option<String>,
-    pub genre: Option<String>,
-    pub protocol_info: String,
+// A upnp:class. Classes form a hierarchy by dotted prefix, so a musicTrack
+// is also an audioItem, an item and an object; servers add vendor
+// subclasses the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpnpClass(Cow<'static, str>);
+
+impl UpnpClass {
+    pub const OBJECT: UpnpClass = UpnpClass::from_static("object");
+    pub const ITEM: UpnpClass = UpnpClass::from_static("object.item");
+    pub const AUDIO_ITEM: UpnpClass = UpnpClass::from_static("object.item.audioItem");
+    pub const MUSIC_TRACK: UpnpClass = UpnpClass::from_static("object.item.audioItem.musicTrack");
+    pub const VIDEO_ITEM: UpnpClass = UpnpClass::from_static("object.item.videoItem");
+    pub const MOVIE: UpnpClass = UpnpClass::from_static("object.item.videoItem.movie");
+    pub const IMAGE_ITEM: UpnpClass = UpnpClass::from_static("object.item.imageItem");
+    pub const PHOTO: UpnpClass = UpnpClass::from_static("object.item.imageItem.photo");
+    pub const PLAYLIST_ITEM: UpnpClass = UpnpClass::from_static("object.item.playlistItem");
+    pub const CONTAINER: UpnpClass = UpnpClass::from_static("object.container");
+    pub const PLAYLIST_CONTAINER: UpnpClass =
+        UpnpClass::from_static("object.container.playlistContainer");
+    pub const MUSIC_ALBUM: UpnpClass = UpnpClass::from_static("object.container.album.musicAlbum");
+    pub const STORAGE_FOLDER: UpnpClass = UpnpClass::from_static("object.container.storageFolder");
+
+    pub const fn from_static(class: &'static str) -> Self {
+        Self(Cow::Borrowed(class))
+    }
+
+    pub fn new(class: &str) -> Self {
+        Self(Cow::Owned(class.trim().to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn parent(&self) -> Option<UpnpClass> {
+        self.0
+            .rsplit_once('.')
+            .map(|(parent, _)| UpnpClass::new(parent))
+    }
+
+    // True for the class itself and all of its subclasses.
+    pub fn derived_from(&self, ancestor: &UpnpClass) -> bool {
+        match self.0.strip_prefix(ancestor.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.'),
+            None => false,
+        }
+    }
+
+    // Search criteria matching this class and its subclasses.
+    pub fn search_criteria(&self) -> Criteria {
+        Criteria::class_derived_from(&self.0)
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct LoadOptions {
-    pub dlna_features: Option<String>,
-    pub content_type: Option<String>,
-    pub object_class: Option<ObjectClass>,
-    pub metadata: Option<Metadata>,
-    pub autoplay: bool,
+impl From<&str> for UpnpClass {
+    fn from(value: &str) -> Self {
+        UpnpClass::new(value)
+    }
 }
 
-#[derive(Debug)]
-pub enum AVTransportEvent {
-    AVTransportURIMetaData {
-        sid: String,
-        url: String,
-        title: String,
-        artist: Option<String>,
-        album: Option<String>,
-        album_art_uri: Option<String>,
-        genre: Option<String>,
-    },
-    CurrentPlayMode {
-        sid: String,
-        play_mode: String,
-    },
-    CurrentTrackMetadata {
-        sid: String,
-        url: String,
-        title: String,
-        artist: Option<String>,
-        album: Option<String>,
-        album_art_uri: Option<String>,
-        genre: Option<String>,
-    },
-    TransportState {
-        sid: String,
-        transport_state: String,
-    },
+impl Display for UpnpClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
-#[derive(Debug)]
-pub enum Event {
-    AVTransport(AVTransportEvent),
+// How forgiving DIDL-Lite parsing is. `Lenient` retries documents that fail
+// to parse after undoing the usual server bugs: a Result escaped twice
+// (Twonky, some Synology builds) or bare `&` in titles (older MiniDLNA).
+// It also drops values that don't parse, such as a size of "12 MB", where
+// `Strict` fails naming the object and property. Either way, what was
+// recovered from is reported as `ParseWarning`s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    Strict,
+    #[default]
+    Lenient,
 }
 
-impl Display for Event {
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ParseWarning {
+    DoubleEscaped,
+    UnescapedAmpersand,
+    InvalidValue {
+        object_id: String,
+        property: String,
+        value: String,
+    },
+}
+
+impl Display for ParseWarning {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Event::AVTransport(event) => match event {
-                AVTransportEvent::AVTransportURIMetaData {
-                    sid,
-                    url,
-                    title,
-                    artist,
-                    album,
-                    album_art_uri,
-                    genre,
-                } => write!(
-                    f,
-                    "AVTransportEvent::AVTransportURIMetaData {{\n sid: {},\n url: {},\n title: {},\n artist: {:?},\n album: {:?},\n album_art_uri: {:?},\n genre: {:?}\n }}",
-                    sid.bright_green(), url.bright_green(), title.bright_green(), artist.bright_green(), album.bright_green(), album_art_uri.bright_green(), genre.bright_green()
-                ),
-                AVTransportEvent::CurrentPlayMode { sid, play_mode } => {
-                    write!(f, "AVTransportEvent::CurrentPlayMode {{\n sid: {}, play_mode: {}\n }}", sid.bright_green(), play_mode.bright_green())
-                }
-                AVTransportEvent::CurrentTrackMetadata {
-                    sid,
-                    url,
-                    title,
-                    artist,
-                    album,
-                    album_art_uri,
-                    genre,
-                } => write!(
-                    f,
-                    "AVTransportEvent::CurrentTrackMetadata {{\n sid: {},\n url: {},\n title: {},\n artist: {:?},\n album: {:?},\n album_art_uri: {:?},\n genre: {:?}\n }}",
-                    sid.bright_green(), url.bright_green(), title.bright_green(), artist.bright_green(), album.bright_green(), album_art_uri.bright_green(), genre.bright_green()
-                ),
-                AVTransportEvent::TransportState {
-                    sid,
-                    transport_state,
-                } => write!(
-                    f,
-                    "AVTransportEvent::TransportState {{\n sid: {},\n transport_state: {}\n }}",
-                    sid.bright_green(), transport_state.bright_green()
-                ),
-            },
+            ParseWarning::DoubleEscaped => f.write_str("DIDL-Lite was escaped twice"),
+            ParseWarning::UnescapedAmpersand => f.write_str("DIDL-Lite has unescaped '&'"),
+            ParseWarning::InvalidValue {
+                object_id,
+                property,
+                value,
+            } => write!(
+                f,
+                "Invalid {} \"{}\" in object {}",
+                property, value, object_id
+            ),
         }
     }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Container {
-    pub id: String,
-    pub parent_id: String,
-    pub title: String,
-    pub creator: Option<String>,
-    pub restricted: bool,
-    pub searchable: bool,
-    pub child_count: Option<u32>,
-    pub album_art_uri: Option<String>,
-    pub album: Option<String>,
-    pub artist: Option<String>,
-    pub genre: Option<String>,
-    pub date: Option<String>,
-    pub original_track_number: Option<u32>,
-    pub protocol_info: Option<String>,
-    pub url: Option<String>,
-    pub object_class: Option<ObjectClass>,
+// ContentDirectory Browse mode: the object itself, or its direct children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowseFlag {
+    Metadata,
+    DirectChildren,
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Item {
-    pub id: String,
-    pub parent_id: String,
-    pub title: String,
-    pub creator: Option<String>,
-    pub restricted: bool,
-    pub searchable: bool,
-    pub album_art_uri: Option<String>,
-    pub album: Option<String>,
-    pub artist: Option<String>,
-    pub genre: Option<String>,
-    pub date: Option<String>,
-    pub original_track_number: Option<u32>,
-    pub protocol_info: String,
-    pub url: String,
-    pub size: Option<u64>,
-    pub duration: Option<String>,
-    pub object_class: Option<ObjectClass>,
+impl BrowseFlag {
+    pub fn value(&self) -> &'static str {
+        match self {
+            BrowseFlag::Metadata => "BrowseMetadata",
+            BrowseFlag::DirectChildren => "BrowseDirectChildren",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct TransportInfo {
-    pub current_transport_state: String,
-    pub current_transport_status: String,
-    pub current_speed: String,
+impl ActionValue for BrowseFlag {
+    fn to_action_value(&self) -> String {
+        self.value().to_string()
+    }
+}
+
+// ContentDirectory Filter: which properties Browse and Search return. `all()`
+// asks for everything; otherwise only the listed properties (besides the
+// required ones) are returned, e.g. "upnp:artist", "res@duration" or vendor
+// properties such as "sec:dcmInfo".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    properties: Option<Vec<String>>,
+}
+
+impl Filter {
+    pub fn all() -> Self {
+        Self { properties: None }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            properties: Some(vec![]),
+        }
+    }
+
+    pub fn property(mut self, property: &str) -> Self {
+        self.properties
+            .get_or_insert_with(Vec::new)
+            .push(property.to_string());
+        self
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.properties {
+            Some(properties) => write!(f, "{}", properties.join(",")),
+            None => write!(f, "*"),
+        }
+    }
+}
+
+impl ActionValue for Filter {
+    fn to_action_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+// ContentDirectory SortCriteria, e.g. "+upnp:album,-dc:date".
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortCriteria {
+    keys: Vec<(bool, String)>,
+}
+
+impl SortCriteria {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ascending(mut self, property: &str) -> Self {
+        self.keys.push((true, property.to_string()));
+        self
+    }
+
+    pub fn descending(mut self, property: &str) -> Self {
+        self.keys.push((false, property.to_string()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|(_, property)| property.as_str())
+    }
+
+    // Checks every key against the server's GetSortCapabilities answer.
+    pub fn validate(&self, capabilities: &[String]) -> Result<(), String> {
+        if capabilities.iter().any(|cap| cap == "*") {
+            return Ok(());
+        }
+        match self
+            .properties()
+            .find(|property| !capabilities.iter().any(|cap| cap == property))
+        {
+            Some(property) => Err(format!("Server can't sort on {}", property)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for SortCriteria {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|(ascending, property)| match ascending {
+                true => format!("+{}", property),
+                false => format!("-{}", property),
+            })
+            .collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+impl ActionValue for SortCriteria {
+    fn to_action_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+// ContentDirectory SearchCriteria, built from comparisons joined with
+// `and`/`or`, e.g.
+// `Criteria::class_derived_from("object.item.audioItem").and(Criteria::contains("dc:title", "live"))`.
+// Values are quoted and escaped for the search grammar when rendered; XML
+// escaping is left to the envelope, like any argument value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Criteria {
+    All,
+    Compare {
+        property: String,
+        operator: &'static str,
+        value: String,
+    },
+    Exists {
+        property: String,
+        exists: bool,
+    },
+    And(Box<Criteria>, Box<Criteria>),
+    Or(Box<Criteria>, Box<Criteria>),
+}
+
+impl Criteria {
+    fn compare(property: &str, operator: &'static str, value: &str) -> Self {
+        Criteria::Compare {
+            property: property.to_string(),
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    pub fn eq(property: &str, value: &str) -> Self {
+        Self::compare(property, "=", value)
+    }
+
+    pub fn ne(property: &str, value: &str) -> Self {
+        Self::compare(property, "!=", value)
+    }
+
+    pub fn lt(property: &str, value: &str) -> Self {
+        Self::compare(property, "<", value)
+    }
+
+    pub fn le(property: &str, value: &str) -> Self {
+        Self::compare(property, "<=", value)
+    }
+
+    pub fn gt(property: &str, value: &str) -> Self {
+        Self::compare(property, ">", value)
+    }
+
+    pub fn ge(property: &str, value: &str) -> Self {
+        Self::compare(property, ">=", value)
+    }
+
+    pub fn contains(property: &str, value: &str) -> Self {
+        Self::compare(property, "contains", value)
+    }
+
+    pub fn does_not_contain(property: &str, value: &str) -> Self {
+        Self::compare(property, "doesNotContain", value)
+    }
+
+    pub fn derived_from(property: &str, value: &str) -> Self {
+        Self::compare(property, "derivedfrom", value)
+    }
+
+    pub fn class_derived_from(class: &str) -> Self {
+        Self::derived_from("upnp:class", class)
+    }
+
+    pub fn exists(property: &str, exists: bool) -> Self {
+        Criteria::Exists {
+            property: property.to_string(),
+            exists,
+        }
+    }
+
+    // "*" can't be combined with anything, so it's folded away: everything
+    // and `c` is `c`, everything or `c` is everything.
+    pub fn and(self, other: Criteria) -> Self {
+        match (self, other) {
+            (Criteria::All, other) => other,
+            (criteria, Criteria::All) => criteria,
+            (a, b) => Criteria::And(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn or(self, other: Criteria) -> Self {
+        match (self, other) {
+            (Criteria::All, _) | (_, Criteria::All) => Criteria::All,
+            (a, b) => Criteria::Or(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn properties(&self) -> Vec<&str> {
+        match self {
+            Criteria::All => vec![],
+            Criteria::Compare { property, .. } | Criteria::Exists { property, .. } => {
+                vec![property.as_str()]
+            }
+            Criteria::And(a, b) | Criteria::Or(a, b) => {
+                let mut properties = a.properties();
+                for property in b.properties() {
+                    if !properties.contains(&property) {
+                        properties.push(property);
+                    }
+                }
+                properties
+            }
+        }
+    }
+
+    // Checks every property against the server's GetSearchCapabilities
+    // answer.
+    pub fn validate(&self, capabilities: &[String]) -> Result<(), String> {
+        if self.properties().is_empty() || capabilities.iter().any(|cap| cap == "*") {
+            return Ok(());
+        }
+        match self
+            .properties()
+            .into_iter()
+            .find(|property| !capabilities.iter().any(|cap| cap == property))
+        {
+            Some(property) => Err(format!("Server can't search on {}", property)),
+            None => Ok(()),
+        }
+    }
+
+    // Operands joined with a different operator are parenthesized, as `and`
+    // binds tighter than `or`.
+    fn fmt_operand(&self, parent: &Criteria, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let nested = matches!(
+            (parent, self),
+            (Criteria::And(..), Criteria::Or(..)) | (Criteria::Or(..), Criteria::And(..))
+        );
+        match nested {
+            true => write!(f, "({})", self),
+            false => write!(f, "{}", self),
+        }
+    }
+}
+
+impl Display for Criteria {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Criteria::All => write!(f, "*"),
+            Criteria::Compare {
+                property,
+                operator,
+                value,
+            } => {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "{} {} \"{}\"", property, operator, value)
+            }
+            Criteria::Exists { property, exists } => write!(f, "{} exists {}", property, exists),
+            Criteria::And(a, b) | Criteria::Or(a, b) => {
+                let operator = match self {
+                    Criteria::And(..) => "and",
+                    _ => "or",
+                };
+                a.fmt_operand(self, f)?;
+                write!(f, " {} ", operator)?;
+                b.fmt_operand(self, f)
+            }
+        }
+    }
+}
+
+impl ActionValue for Criteria {
+    fn to_action_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+// How `MediaServerClient::walk` traverses a library. `max_depth` counts
+// levels below the root (1 lists only the root's children) and
+// `class_filter` keeps items whose upnp:class is derived from it, e.g.
+// "object.item.audioItem".
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    pub max_depth: Option<usize>,
+    pub class_filter: Option<String>,
+    pub concurrency: usize,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            class_filter: None,
+            concurrency: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TransferStatus {
+    InProgress,
+    Completed,
+    Stopped,
+    Error,
+}
+
+impl std::str::FromStr for TransferStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "IN_PROGRESS" => Ok(TransferStatus::InProgress),
+            "COMPLETED" => Ok(TransferStatus::Completed),
+            "STOPPED" => Ok(TransferStatus::Stopped),
+            "ERROR" => Ok(TransferStatus::Error),
+            _ => Err(format!("Unknown transfer status {}", value)),
+        }
+    }
+}
+
+// GetTransferProgress answer: bytes transferred so far, out of `total` when
+// the server knows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransferProgress {
+    pub status: TransferStatus,
+    pub length: u64,
+    pub total: Option<u64>,
+}
+
+// What a ContentDirectory supports, from `MediaServerClient::capabilities`.
+// `features` is the raw GetFeatureList document of ContentDirectory:2+
+// servers.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServerCapabilities {
+    pub search: Vec<String>,
+    pub sort: Vec<String>,
+    pub features: Option<String>,
+    pub create_object: bool,
+    pub destroy_object: bool,
+    pub update_object: bool,
+    pub import_resource: bool,
+    pub export_resource: bool,
+}
+
+pub type DownloadProgress = Arc<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+// How `DeviceClient::download_resource` fetches a file. Downloads resume at
+// `offset` with a Range request; `progress` is called after each chunk with
+// the bytes written so far and the expected total, when known.
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    pub offset: u64,
+    pub max_bytes_per_second: Option<u64>,
+    pub progress: Option<DownloadProgress>,
+}
+
+impl std::fmt::Debug for DownloadOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DownloadOptions")
+            .field("offset", &self.offset)
+            .field("max_bytes_per_second", &self.max_bytes_per_second)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+// How `MediaServerClient::resolve_path` compares path segments with titles.
+// Surrounding and repeated whitespace is always ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathOptions {
+    pub ignore_case: bool,
+}
+
+impl PathOptions {
+    pub fn normalize(&self, title: &str) -> String {
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        match self.ignore_case {
+            true => title.to_lowercase(),
+            false => title,
+        }
+    }
+}
+
+// A upnp:artist (or upnp:author) with its role attribute, e.g. "Performer",
+// "AlbumArtist" or "Composer".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Contributor {
+    pub name: String,
+    pub role: Option<String>,
+}
+
+impl Contributor {
+    pub fn new(name: &str, role: Option<&str>) -> Self {
+        Contributor {
+            name: name.to_string(),
+            role: role.map(str::to_string),
+        }
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.role
+            .as_deref()
+            .is_some_and(|own| own.eq_ignore_ascii_case(role))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata {
+    // The item's id and parentID attributes, "0" and "-1" by default.
+    pub id: Option<String>,
+    pub parent_id: Option<String>,
+    // The DLNA guidelines want restricted="1", the default, for anything a
+    // renderer is asked to play.
+    pub restricted: Option<bool>,
+    // Used when `LoadOptions::object_class` isn't set.
+    pub object_class: Option<ObjectClass>,
+    pub url: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_art_uri: Option<String>,
+    pub genre: Option<String>,
+    pub protocol_info: String,
+    pub original_track_number: Option<u32>,
+    pub series_title: Option<String>,
+    pub episode_season: Option<u32>,
+    pub episode_number: Option<u32>,
+    pub date: Option<String>,
+    // H+:MM:SS[.F+], as in res@duration.
+    pub duration: Option<String>,
+    pub size: Option<u64>,
+    pub creator: Option<String>,
+    // Further upnp:artist elements, sent after `artist`, e.g. the performers
+    // of a classical recording.
+    pub artists: Vec<Contributor>,
+    // Sent as upnp:author role="Composer" and upnp:artist role="Conductor".
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub description: Option<String>,
+    // WxH, as in res@resolution.
+    pub resolution: Option<String>,
+    // Bytes per second, as in res@bitrate.
+    pub bitrate: Option<u32>,
+    // Hz, as in res@sampleFrequency.
+    pub sample_frequency: Option<u32>,
+    pub nr_audio_channels: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    // Vendor properties without a field of their own, e.g. sec:CaptionInfoEx
+    // as extra elements of the item or pv:subtitleFileUri as extra
+    // attributes of its res.
+    #[cfg_attr(feature = "serde", serde(with = "qname_map"))]
+    pub extra_elements: HashMap<QName, String>,
+    #[cfg_attr(feature = "serde", serde(with = "qname_map"))]
+    pub extra_res_attributes: HashMap<QName, String>,
+    // Vendor `<desc>` blocks, sent as they are.
+    pub descs: Vec<Desc>,
+    // Other ways of fetching the same media (a transcoded stream, a
+    // subtitle file...), sent as further `<res>` elements after the one
+    // described by the fields above.
+    pub resources: Vec<Resource>,
+}
+
+// Starting points for the usual kinds of media, with the class renderers
+// expect for them and the fields they display.
+impl Metadata {
+    pub fn music_track(url: &str, title: &str, artist: &str, album: &str) -> Self {
+        Metadata {
+            object_class: Some(ObjectClass::Audio),
+            url: url.to_string(),
+            title: title.to_string(),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn movie(url: &str, title: &str) -> Self {
+        Metadata {
+            object_class: Some(ObjectClass::Video),
+            url: url.to_string(),
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn tv_episode(url: &str, series: &str, season: u32, episode: u32, title: &str) -> Self {
+        Metadata {
+            series_title: Some(series.to_string()),
+            episode_season: Some(season),
+            episode_number: Some(episode),
+            ..Metadata::movie(url, title)
+        }
+    }
+
+    // Live streams have no duration or size, and renderers that see one
+    // try to seek.
+    pub fn radio_stream(url: &str, station: &str) -> Self {
+        Metadata {
+            object_class: Some(ObjectClass::AudioBroadcast),
+            url: url.to_string(),
+            title: station.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// Tags read from a media file by whatever library the application uses
+// (id3, metaflac, lofty...), for `Metadata::from_tags`. Everything is
+// optional; the defaults report nothing.
+pub trait TagSource {
+    fn title(&self) -> Option<String> {
+        None
+    }
+
+    fn artist(&self) -> Option<String> {
+        None
+    }
+
+    fn album(&self) -> Option<String> {
+        None
+    }
+
+    fn genre(&self) -> Option<String> {
+        None
+    }
+
+    fn track_number(&self) -> Option<u32> {
+        None
+    }
+
+    // Where the renderer can fetch the cover from, typically a URL the
+    // application serves the embedded picture at.
+    fn album_art_uri(&self) -> Option<String> {
+        None
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn mime_type(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Metadata {
+    // The URL is left for the caller to fill in. The mime type sets the
+    // protocolInfo and, unless it's missing, the object class.
+    pub fn from_tags(tags: &impl TagSource) -> Self {
+        let mime_type = tags.mime_type();
+        Metadata {
+            object_class: mime_type.as_deref().map(ObjectClass::for_content_type),
+            title: tags.title().unwrap_or_default(),
+            artist: tags.artist(),
+            album: tags.album(),
+            genre: tags.genre(),
+            original_track_number: tags.track_number(),
+            album_art_uri: tags.album_art_uri(),
+            duration: tags
+                .duration()
+                .map(|duration| UpnpTime(duration).to_string()),
+            protocol_info: mime_type
+                .map(|mime_type| ProtocolInfo::http_get(&mime_type).to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+// A namespaced XML name, e.g. `QName::new("http://www.sec.co.kr/", "sec",
+// "CaptionInfoEx")`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QName {
+    pub namespace: String,
+    pub prefix: String,
+    pub local_name: String,
+}
+
+impl QName {
+    pub fn new(namespace: &str, prefix: &str, local_name: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            prefix: prefix.to_string(),
+            local_name: local_name.to_string(),
+        }
+    }
+}
+
+impl Display for QName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.prefix, self.local_name)
+    }
+}
+
+// JSON object keys must be strings, so maps keyed by QName are serialized
+// as lists of pairs.
+#[cfg(feature = "serde")]
+mod qname_map {
+    use super::QName;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        map: &HashMap<QName, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut pairs: Vec<(&QName, &String)> = map.iter().collect();
+        pairs.sort();
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<QName, String>, D::Error> {
+        let pairs: Vec<(QName, String)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LoadOptions {
+    pub dlna_features: Option<String>,
+    pub content_type: Option<String>,
+    pub object_class: Option<ObjectClass>,
+    pub metadata: Option<Metadata>,
+    pub autoplay: bool,
+}
+
+impl LoadOptions {
+    // The content type and DLNA features of `protocol_info`.
+    pub fn with_protocol_info(mut self, protocol_info: &ProtocolInfo) -> Self {
+        self.content_type = Some(protocol_info.content_format.clone());
+        self.dlna_features = Some(protocol_info.additional_info.clone());
+        self
+    }
+
+    pub fn protocol_info(&self) -> ProtocolInfo {
+        ProtocolInfo {
+            additional_info: self.dlna_features.clone().unwrap_or("*".to_string()),
+            ..ProtocolInfo::http_get(self.content_type.as_deref().unwrap_or("video/mpeg"))
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AVTransportEvent {
+    AVTransportURIMetaData {
+        sid: String,
+        url: String,
+        title: String,
+        artist: Option<String>,
+        album: Option<String>,
+        album_art_uri: Option<String>,
+        genre: Option<String>,
+    },
+    CurrentPlayMode {
+        sid: String,
+        play_mode: PlayMode,
+    },
+    CurrentTrackMetadata {
+        sid: String,
+        url: String,
+        title: String,
+        artist: Option<String>,
+        album: Option<String>,
+        album_art_uri: Option<String>,
+        genre: Option<String>,
+    },
+    TransportState {
+        sid: String,
+        transport_state: TransportState,
+    },
+}
+
+// Changes evented by Sonos-specific services.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SonosEvent {
+    // The household's groups, sent whenever players join or leave one.
+    ZoneGroupState { sid: String, groups: Vec<ZoneGroup> },
+}
+
+// Changes evented by OpenHome services, which send each variable as is
+// rather than in LastChange.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OpenHomeEvent {
+    TransportState {
+        sid: String,
+        transport_state: OpenHomeTransportState,
+    },
+    // The playlist track now playing.
+    Id {
+        sid: String,
+        id: u32,
+    },
+    // The playlist's track ids, after tracks were inserted or deleted.
+    IdArray {
+        sid: String,
+        ids: Vec<u32>,
+    },
+    Volume {
+        sid: String,
+        volume: u32,
+    },
+    Mute {
+        sid: String,
+        mute: bool,
+    },
+    VolumeLimit {
+        sid: String,
+        volume_limit: u32,
+    },
+    Standby {
+        sid: String,
+        standby: bool,
+    },
+    // The source selected, by index in the Product source list.
+    SourceIndex {
+        sid: String,
+        index: u32,
+    },
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Event {
+    AVTransport(AVTransportEvent),
+    OpenHome(OpenHomeEvent),
+    Sonos(SonosEvent),
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::AVTransport(event) => match event {
+                AVTransportEvent::AVTransportURIMetaData {
+                    sid,
+                    url,
+                    title,
+                    artist,
+                    album,
+                    album_art_uri,
+                    genre,
+                } => write!(
+                    f,
+                    "AVTransportEvent::AVTransportURIMetaData {{\n sid: {},\n url: {},\n title: {},\n artist: {:?},\n album: {:?},\n album_art_uri: {:?},\n genre: {:?}\n }}",
+                    sid.bright_green(), url.bright_green(), title.bright_green(), artist.bright_green(), album.bright_green(), album_art_uri.bright_green(), genre.bright_green()
+                ),
+                AVTransportEvent::CurrentPlayMode { sid, play_mode } => {
+                    write!(f, "AVTransportEvent::CurrentPlayMode {{\n sid: {}, play_mode: {}\n }}", sid.bright_green(), play_mode.bright_green())
+                }
+                AVTransportEvent::CurrentTrackMetadata {
+                    sid,
+                    url,
+                    title,
+                    artist,
+                    album,
+                    album_art_uri,
+                    genre,
+                } => write!(
+                    f,
+                    "AVTransportEvent::CurrentTrackMetadata {{\n sid: {},\n url: {},\n title: {},\n artist: {:?},\n album: {:?},\n album_art_uri: {:?},\n genre: {:?}\n }}",
+                    sid.bright_green(), url.bright_green(), title.bright_green(), artist.bright_green(), album.bright_green(), album_art_uri.bright_green(), genre.bright_green()
+                ),
+                AVTransportEvent::TransportState {
+                    sid,
+                    transport_state,
+                } => write!(
+                    f,
+                    "AVTransportEvent::TransportState {{\n sid: {},\n transport_state: {}\n }}",
+                    sid.bright_green(), transport_state.bright_green()
+                ),
+            },
+            Event::OpenHome(event) => match event {
+                OpenHomeEvent::TransportState {
+                    sid,
+                    transport_state,
+                } => write!(
+                    f,
+                    "OpenHomeEvent::TransportState {{\n sid: {},\n transport_state: {}\n }}",
+                    sid.bright_green(), transport_state.bright_green()
+                ),
+                OpenHomeEvent::Id { sid, id } => write!(
+                    f,
+                    "OpenHomeEvent::Id {{\n sid: {},\n id: {}\n }}",
+                    sid.bright_green(), id.bright_green()
+                ),
+                OpenHomeEvent::IdArray { sid, ids } => write!(
+                    f,
+                    "OpenHomeEvent::IdArray {{\n sid: {},\n ids: {:?}\n }}",
+                    sid.bright_green(), ids.bright_green()
+                ),
+                OpenHomeEvent::Volume { sid, volume } => write!(
+                    f,
+                    "OpenHomeEvent::Volume {{\n sid: {},\n volume: {}\n }}",
+                    sid.bright_green(), volume.bright_green()
+                ),
+                OpenHomeEvent::Mute { sid, mute } => write!(
+                    f,
+                    "OpenHomeEvent::Mute {{\n sid: {},\n mute: {}\n }}",
+                    sid.bright_green(), mute.bright_green()
+                ),
+                OpenHomeEvent::VolumeLimit { sid, volume_limit } => write!(
+                    f,
+                    "OpenHomeEvent::VolumeLimit {{\n sid: {},\n volume_limit: {}\n }}",
+                    sid.bright_green(), volume_limit.bright_green()
+                ),
+                OpenHomeEvent::Standby { sid, standby } => write!(
+                    f,
+                    "OpenHomeEvent::Standby {{\n sid: {},\n standby: {}\n }}",
+                    sid.bright_green(), standby.bright_green()
+                ),
+                OpenHomeEvent::SourceIndex { sid, index } => write!(
+                    f,
+                    "OpenHomeEvent::SourceIndex {{\n sid: {},\n index: {}\n }}",
+                    sid.bright_green(), index.bright_green()
+                ),
+            },
+            Event::Sonos(event) => match event {
+                SonosEvent::ZoneGroupState { sid, groups } => write!(
+                    f,
+                    "SonosEvent::ZoneGroupState {{\n sid: {},\n groups: {:?}\n }}",
+                    sid.bright_green(), groups.bright_green()
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Container {
+    pub id: String,
+    pub parent_id: String,
+    pub title: String,
+    pub creator: Option<String>,
+    pub restricted: bool,
+    pub searchable: bool,
+    pub child_count: Option<u32>,
+    pub album_art_uri: Option<String>,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub original_track_number: Option<u32>,
+    pub protocol_info: Option<String>,
+    pub url: Option<String>,
+    pub object_class: Option<ObjectClass>,
+    pub class: String,
+    pub artists: Vec<String>,
+    // Every upnp:artist, with its role.
+    pub contributors: Vec<Contributor>,
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub resources: Vec<Resource>,
+    pub object_update_id: Option<u32>,
+    pub container_update_id: Option<u32>,
+    pub descs: Vec<Desc>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Item {
+    pub id: String,
+    pub parent_id: String,
+    pub title: String,
+    pub creator: Option<String>,
+    pub restricted: bool,
+    pub searchable: bool,
+    pub album_art_uri: Option<String>,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub original_track_number: Option<u32>,
+    pub protocol_info: String,
+    pub url: String,
+    pub size: Option<u64>,
+    pub duration: Option<String>,
+    pub object_class: Option<ObjectClass>,
+    pub class: String,
+    pub artists: Vec<String>,
+    // Every upnp:artist, with its role.
+    pub contributors: Vec<Contributor>,
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub resources: Vec<Resource>,
+    pub last_playback_position: Option<Duration>,
+    pub object_update_id: Option<u32>,
+    pub ref_id: Option<String>,
+    pub descs: Vec<Desc>,
+}
+
+impl Container {
+    pub fn upnp_class(&self) -> UpnpClass {
+        UpnpClass::new(&self.class)
+    }
+
+    pub fn derived_from(&self, class: &UpnpClass) -> bool {
+        self.upnp_class().derived_from(class)
+    }
+
+    pub fn is_playlist(&self) -> bool {
+        self.derived_from(&UpnpClass::PLAYLIST_CONTAINER)
+    }
+}
+
+impl Item {
+    pub fn upnp_class(&self) -> UpnpClass {
+        UpnpClass::new(&self.class)
+    }
+
+    pub fn derived_from(&self, class: &UpnpClass) -> bool {
+        self.upnp_class().derived_from(class)
+    }
+
+    // An item standing for a playlist file, typically with an M3U resource.
+    pub fn is_playlist(&self) -> bool {
+        self.derived_from(&UpnpClass::PLAYLIST_ITEM)
+    }
+
+    // Options for `MediaRendererClient::load` playing the `res_index`th
+    // resource: its mime type and DLNA features (the third and fourth
+    // protocolInfo fields) and the item's metadata. `None` if there's no such
+    // resource.
+    pub fn to_load_options(&self, res_index: usize) -> Option<LoadOptions> {
+        let resource = self.resources.get(res_index)?;
+        let protocol = resource.protocol();
+        Some(LoadOptions {
+            content_type: protocol.as_ref().map(|p| p.content_format.clone()),
+            dlna_features: protocol.as_ref().map(|p| p.additional_info.clone()),
+            object_class: self.object_class,
+            metadata: Some(Metadata {
+                id: Some(self.id.clone()).filter(|id| !id.is_empty()),
+                parent_id: Some(self.parent_id.clone()).filter(|id| !id.is_empty()),
+                object_class: self.object_class,
+                url: resource.url.clone(),
+                title: self.title.clone(),
+                artist: self.artist.clone(),
+                album: self.album.clone(),
+                album_art_uri: self.album_art_uri.clone(),
+                genre: self.genre.clone(),
+                protocol_info: resource.protocol_info.clone(),
+                original_track_number: self.original_track_number,
+                date: self.date.clone(),
+                duration: resource.duration.clone(),
+                size: resource.size,
+                creator: self.creator.clone(),
+                artists: self.contributors.clone(),
+                composer: self.composer.clone(),
+                conductor: self.conductor.clone(),
+                description: None,
+                resolution: resource.resolution.clone(),
+                bitrate: resource.bitrate,
+                sample_frequency: resource.sample_frequency,
+                nr_audio_channels: resource.nr_audio_channels,
+                bits_per_sample: resource.bits_per_sample,
+                resources: self.resources.clone(),
+                descs: self.descs.clone(),
+                ..Default::default()
+            }),
+            autoplay: false,
+        })
+    }
+
+    // Picks the resource a renderer accepting `sink` (its GetProtocolInfo Sink
+    // entries) should play: an exact mime type and DLNA profile match wins
+    // over a transcoded variant, which wins over a wildcard match.
+    pub fn best_resource(&self, sink: &[String]) -> Option<&Resource> {
+        let mut best: Option<(u8, &Resource)> = None;
+        for resource in &self.resources {
+            let score = sink
+                .iter()
+                .map(|accepted| resource_score(&resource.protocol_info, accepted))
+                .max()
+                .unwrap_or(0);
+            if score > best.map_or(0, |(best, _)| best) {
+                best = Some((score, resource));
+            }
+        }
+        best.map(|(_, resource)| resource)
+    }
+}
+
+// A protocolInfo string, "<protocol>:<network>:<contentFormat>:<additionalInfo>",
+// as in res@protocolInfo and GetProtocolInfo. For DLNA media the last field
+// holds `;`-separated DLNA.ORG_* parameters, e.g.
+// "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_OP=01;DLNA.ORG_CI=0".
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProtocolInfo {
+    pub protocol: String,
+    pub network: String,
+    pub content_format: String,
+    pub additional_info: String,
+}
+
+impl ProtocolInfo {
+    // Plain HTTP streaming of `content_format`, with no DLNA parameters.
+    pub fn http_get(content_format: &str) -> Self {
+        Self {
+            protocol: "http-get".to_string(),
+            network: "*".to_string(),
+            content_format: content_format.to_string(),
+            additional_info: "*".to_string(),
+        }
+    }
+
+    pub fn dlna_param(&self, name: &str) -> Option<&str> {
+        self.additional_info
+            .split(';')
+            .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    // Replaces the parameter if present, appends it otherwise.
+    pub fn with_dlna_param(mut self, name: &str, value: &str) -> Self {
+        let mut params: Vec<String> = self
+            .additional_info
+            .split(';')
+            .filter(|param| !param.is_empty() && *param != "*")
+            .map(str::to_string)
+            .collect();
+        let param = format!("{}={}", name, value);
+        match params
+            .iter_mut()
+            .find(|existing| existing.split('=').next() == Some(name))
+        {
+            Some(existing) => *existing = param,
+            None => params.push(param),
+        }
+        self.additional_info = params.join(";");
+        self
+    }
+
+    // DLNA.ORG_PN, the media format profile, e.g. "AVC_MP4_HP_HD_AAC".
+    pub fn profile(&self) -> Option<&str> {
+        self.dlna_param("DLNA.ORG_PN")
+    }
+
+    pub fn with_profile(self, profile: &str) -> Self {
+        self.with_dlna_param("DLNA.ORG_PN", profile)
+    }
+
+    // DLNA.ORG_OP: whether the server supports time-based and byte-range
+    // seeking.
+    pub fn operations(&self) -> Option<DlnaOperations> {
+        let op = self.dlna_param("DLNA.ORG_OP")?.as_bytes();
+        match op {
+            [time_seek, range] => Some(DlnaOperations {
+                time_seek: *time_seek == b'1',
+                range: *range == b'1',
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn with_operations(self, operations: DlnaOperations) -> Self {
+        let op = format!("{}{}", operations.time_seek as u8, operations.range as u8);
+        self.with_dlna_param("DLNA.ORG_OP", &op)
+    }
+
+    // DLNA.ORG_CI: whether the content is converted (transcoded) from the
+    // original.
+    pub fn converted(&self) -> Option<bool> {
+        match self.dlna_param("DLNA.ORG_CI")? {
+            "0" => Some(false),
+            "1" => Some(true),
+            _ => None,
+        }
+    }
+
+    pub fn with_converted(self, converted: bool) -> Self {
+        self.with_dlna_param("DLNA.ORG_CI", if converted { "1" } else { "0" })
+    }
+
+    // DLNA.ORG_FLAGS: 32 hex digits, of which only the first 8 are used.
+    pub fn flags(&self) -> Option<DlnaFlags> {
+        let flags = self.dlna_param("DLNA.ORG_FLAGS")?;
+        u32::from_str_radix(flags.get(..8)?, 16).ok().map(DlnaFlags)
+    }
+
+    pub fn with_flags(self, flags: DlnaFlags) -> Self {
+        self.with_dlna_param("DLNA.ORG_FLAGS", &flags.to_string())
+    }
+
+    // How well this (a resource's) protocolInfo satisfies `accepted`, an
+    // entry of a renderer's Sink: 3 for the same mime type and DLNA profile,
+    // 2 for the same but transcoded, 1 for a wildcard or profile mismatch,
+    // 0 if unplayable.
+    pub fn match_score(&self, accepted: &ProtocolInfo) -> u8 {
+        if self.protocol != accepted.protocol && accepted.protocol != "*" {
+            return 0;
+        }
+        if self
+            .content_format
+            .eq_ignore_ascii_case(&accepted.content_format)
+        {
+            let profile = accepted.profile();
+            let profile_matches = profile.is_none() || profile == self.profile();
+            let transcoded = self.converted() == Some(true);
+            return match (profile_matches, transcoded) {
+                (true, false) => 3,
+                (true, true) => 2,
+                (false, _) => 1,
+            };
+        }
+        let wildcard = accepted.content_format == "*"
+            || accepted
+                .content_format
+                .strip_suffix("/*")
+                .is_some_and(|kind| self.content_format.starts_with(&format!("{}/", kind)));
+        match wildcard {
+            true => 1,
+            false => 0,
+        }
+    }
+}
+
+impl std::str::FromStr for ProtocolInfo {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = value.trim().splitn(4, ':').collect();
+        match fields[..] {
+            [protocol, network, content_format, ref rest @ ..] if !content_format.is_empty() => {
+                Ok(ProtocolInfo {
+                    protocol: protocol.to_string(),
+                    network: network.to_string(),
+                    content_format: content_format.to_string(),
+                    additional_info: rest.first().unwrap_or(&"*").to_string(),
+                })
+            }
+            _ => Err(format!("Invalid protocolInfo {}", value)),
+        }
+    }
+}
+
+impl Display for ProtocolInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.protocol, self.network, self.content_format, self.additional_info
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DlnaOperations {
+    pub time_seek: bool,
+    pub range: bool,
+}
+
+// The primary DLNA.ORG_FLAGS bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DlnaFlags(pub u32);
+
+impl DlnaFlags {
+    pub const SENDER_PACED: DlnaFlags = DlnaFlags(1 << 31);
+    pub const TIME_BASED_SEEK: DlnaFlags = DlnaFlags(1 << 30);
+    pub const BYTE_BASED_SEEK: DlnaFlags = DlnaFlags(1 << 29);
+    pub const PLAY_CONTAINER: DlnaFlags = DlnaFlags(1 << 28);
+    pub const S0_INCREASING: DlnaFlags = DlnaFlags(1 << 27);
+    pub const SN_INCREASING: DlnaFlags = DlnaFlags(1 << 26);
+    pub const RTSP_PAUSE: DlnaFlags = DlnaFlags(1 << 25);
+    pub const STREAMING_TRANSFER: DlnaFlags = DlnaFlags(1 << 24);
+    pub const INTERACTIVE_TRANSFER: DlnaFlags = DlnaFlags(1 << 23);
+    pub const BACKGROUND_TRANSFER: DlnaFlags = DlnaFlags(1 << 22);
+    pub const CONNECTION_STALL: DlnaFlags = DlnaFlags(1 << 21);
+    pub const DLNA_V15: DlnaFlags = DlnaFlags(1 << 20);
+
+    pub fn contains(&self, flags: DlnaFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl std::ops::BitOr for DlnaFlags {
+    type Output = DlnaFlags;
+
+    fn bitor(self, other: DlnaFlags) -> DlnaFlags {
+        DlnaFlags(self.0 | other.0)
+    }
+}
+
+impl Display for DlnaFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:08X}{}", self.0, "0".repeat(24))
+    }
+}
+
+fn resource_score(protocol_info: &str, accepted: &str) -> u8 {
+    match (
+        protocol_info.parse::<ProtocolInfo>(),
+        accepted.parse::<ProtocolInfo>(),
+    ) {
+        (Ok(offered), Ok(accepted)) => offered.match_score(&accepted),
+        _ => 0,
+    }
+}
+
+// A DIDL-Lite `<desc>` block: vendor metadata such as Sonos's cdudn or
+// Microsoft's WMDRM data, kept verbatim. `content` is the XML inside it,
+// whose meaning `name_space` identifies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Desc {
+    pub id: String,
+    pub name_space: String,
+    pub content: String,
+}
+
+// A DIDL-Lite `<res>` element: one way of fetching the object's content.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Resource {
+    pub url: String,
+    pub protocol_info: String,
+    pub duration: Option<String>,
+    pub size: Option<u64>,
+    pub resolution: Option<String>,
+    pub bitrate: Option<u32>,
+    pub sample_frequency: Option<u32>,
+    pub nr_audio_channels: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+}
+
+impl Resource {
+    // `protocol_info` parsed; `None` if the server sent a malformed one.
+    pub fn protocol(&self) -> Option<ProtocolInfo> {
+        self.protocol_info.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ObjectChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+// One entry of a ContentDirectory:3 LastChange event. `parent_id` is only
+// sent for additions and deletions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectChange {
+    pub kind: ObjectChangeKind,
+    pub object_id: String,
+    pub parent_id: Option<String>,
+    pub update_id: Option<u32>,
+}
+
+// An entry of a ContentDirectory listing.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DidlObject {
+    Container(Container),
+    Item(Item),
+}
+
+impl DidlObject {
+    pub fn id(&self) -> &str {
+        match self {
+            DidlObject::Container(container) => &container.id,
+            DidlObject::Item(item) => &item.id,
+        }
+    }
+
+    pub fn derived_from(&self, class: &UpnpClass) -> bool {
+        match self {
+            DidlObject::Container(container) => container.derived_from(class),
+            DidlObject::Item(item) => item.derived_from(class),
+        }
+    }
+}
+
+// One Browse or Search response: the objects (containers first) and the
+// server's bookkeeping. `total_matches` may be 0 when the server can't
+// tell; `update_id` is the container's (or the system's) UpdateID.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BrowseResult {
+    pub objects: Vec<DidlObject>,
+    pub number_returned: u32,
+    pub total_matches: u32,
+    pub update_id: Option<u32>,
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl BrowseResult {
+    pub fn containers(&self) -> impl Iterator<Item = &Container> {
+        self.objects.iter().filter_map(|object| match object {
+            DidlObject::Container(container) => Some(container),
+            DidlObject::Item(_) => None,
+        })
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &Item> {
+        self.objects.iter().filter_map(|object| match object {
+            DidlObject::Item(item) => Some(item),
+            DidlObject::Container(_) => None,
+        })
+    }
+}
+
+// A time as UPnP AV writes them, e.g. in Seek targets, res@duration or
+// RelTime: H+:MM:SS[.F+] or H+:MM:SS.F0/F1, optionally with a leading '+'.
+// Parsing also accepts MM:SS and bare seconds, and keeps fractions to the
+// nanosecond; negative times and NOT_IMPLEMENTED are errors. It's written
+// as HH:MM:SS, with milliseconds only when there are some.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpnpTime(pub Duration);
+
+impl std::str::FromStr for UpnpTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time {}", value);
+        let trimmed = value.trim();
+        let trimmed = trimmed.strip_prefix('+').unwrap_or(trimmed);
+        let (time, fraction) = match trimmed.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (trimmed, None),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+
+        let parts: Vec<&str> = time.split(':').collect();
+        if parts.len() > 3 || !parts.iter().all(|part| digits(part)) {
+            return Err(invalid());
+        }
+        let mut seconds = 0u64;
+        for part in parts {
+            seconds = seconds
+                .checked_mul(60)
+                .and_then(|seconds| seconds.checked_add(part.parse().ok()?))
+                .ok_or_else(invalid)?;
+        }
+
+        let nanos = match fraction.map(|fraction| (fraction, fraction.split_once('/'))) {
+            None => 0,
+            Some((_, Some((numerator, denominator))))
+                if digits(numerator) && digits(denominator) =>
+            {
+                let numerator: u128 = numerator.parse().map_err(|_| invalid())?;
+                let denominator: u128 = denominator.parse().map_err(|_| invalid())?;
+                if numerator >= denominator {
+                    return Err(invalid());
+                }
+                (numerator * 1_000_000_000 / denominator) as u32
+            }
+            Some((fraction, None)) if digits(fraction) => format!("{:0<9}", fraction)[..9]
+                .parse()
+                .map_err(|_| invalid())?,
+            Some(_) => return Err(invalid()),
+        };
+        Ok(UpnpTime(Duration::new(seconds, nanos)))
+    }
+}
+
+impl Display for UpnpTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.0.as_secs();
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            (seconds % 3600) / 60,
+            seconds % 60
+        )?;
+        match self.0.subsec_millis() {
+            0 => Ok(()),
+            millis => write!(f, ".{:03}", millis),
+        }
+    }
+}
+
+impl ActionValue for UpnpTime {
+    fn to_action_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl From<Duration> for UpnpTime {
+    fn from(duration: Duration) -> Self {
+        UpnpTime(duration)
+    }
+}
+
+impl From<UpnpTime> for Duration {
+    fn from(time: UpnpTime) -> Self {
+        time.0
+    }
+}
+
+// GetPositionInfo, with values a renderer left empty or answered
+// NOT_IMPLEMENTED for as `None`. Times keep their fractional seconds; the
+// counters are in whatever unit the renderer counts, often bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PositionInfo {
+    pub track: Option<u32>,
+    pub track_duration: Option<Duration>,
+    pub track_metadata: Option<String>,
+    pub track_uri: Option<String>,
+    pub rel_time: Option<Duration>,
+    pub abs_time: Option<Duration>,
+    pub rel_count: Option<u32>,
+    pub abs_count: Option<u32>,
+}
+
+// A state variable with an allowedValueList, matched ignoring case. Values
+// outside the spec's list, like Sonos's TRANSITIONING variants or SHUFFLE_NOREPEAT, are
+// kept as `Other`.
+macro_rules! allowed_values {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $value:literal),* $(,)? }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            Other(String),
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::Other(value) => value,
+                }
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = std::convert::Infallible;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                let value = value.trim();
+                $(if value.eq_ignore_ascii_case($value) {
+                    return Ok($name::$variant);
+                })*
+                Ok($name::Other(value.to_string()))
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl ActionValue for $name {
+            fn to_action_value(&self) -> String {
+                self.as_str().to_string()
+            }
+        }
+    };
+}
+
+allowed_values! {
+    pub enum TransportState {
+        Stopped = "STOPPED",
+        Playing = "PLAYING",
+        Transitioning = "TRANSITIONING",
+        PausedPlayback = "PAUSED_PLAYBACK",
+        PausedRecording = "PAUSED_RECORDING",
+        Recording = "RECORDING",
+        #[default]
+        NoMediaPresent = "NO_MEDIA_PRESENT",
+    }
+}
+
+allowed_values! {
+    pub enum TransportStatus {
+        #[default]
+        Ok = "OK",
+        ErrorOccurred = "ERROR_OCCURRED",
+    }
+}
+
+allowed_values! {
+    pub enum PlayMode {
+        #[default]
+        Normal = "NORMAL",
+        Shuffle = "SHUFFLE",
+        RepeatOne = "REPEAT_ONE",
+        RepeatAll = "REPEAT_ALL",
+        Random = "RANDOM",
+        Direct1 = "DIRECT_1",
+        Intro = "INTRO",
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransportInfo {
+    pub current_transport_state: TransportState,
+    pub current_transport_status: TransportStatus,
+    pub current_speed: String,
+}
+
+allowed_values! {
+    pub enum PortMappingProtocol {
+        #[default]
+        Tcp = "TCP",
+        Udp = "UDP",
+    }
+}
+
+// The WANIPConnection errors a port mapping request can fail with, from
+// the `UpnpError` code. Returned wrapped in `anyhow::Error` in its place;
+// other codes are left as the `UpnpError`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PortMappingError {
+    NotAuthorized,
+    // Past the last entry of `GetGenericPortMappingEntry`.
+    SpecifiedArrayIndexInvalid,
+    NoSuchEntry,
+    WildcardNotPermittedInRemoteHost,
+    WildcardNotPermittedInExternalPort,
+    // Another client holds the external port.
+    ConflictInMapping,
+    SamePortValuesRequired,
+    OnlyPermanentLeasesSupported,
+    RemoteHostOnlySupportsWildcard,
+    ExternalPortOnlySupportsWildcard,
+    NoPortMapsAvailable,
+    ConflictWithOtherMechanisms,
+    WildcardNotPermittedInInternalPort,
+}
+
+impl PortMappingError {
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            606 => PortMappingError::NotAuthorized,
+            713 => PortMappingError::SpecifiedArrayIndexInvalid,
+            714 => PortMappingError::NoSuchEntry,
+            715 => PortMappingError::WildcardNotPermittedInRemoteHost,
+            716 => PortMappingError::WildcardNotPermittedInExternalPort,
+            718 => PortMappingError::ConflictInMapping,
+            724 => PortMappingError::SamePortValuesRequired,
+            725 => PortMappingError::OnlyPermanentLeasesSupported,
+            726 => PortMappingError::RemoteHostOnlySupportsWildcard,
+            727 => PortMappingError::ExternalPortOnlySupportsWildcard,
+            728 => PortMappingError::NoPortMapsAvailable,
+            729 => PortMappingError::ConflictWithOtherMechanisms,
+            732 => PortMappingError::WildcardNotPermittedInInternalPort,
+            _ => return None,
+        })
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            PortMappingError::NotAuthorized => 606,
+            PortMappingError::SpecifiedArrayIndexInvalid => 713,
+            PortMappingError::NoSuchEntry => 714,
+            PortMappingError::WildcardNotPermittedInRemoteHost => 715,
+            PortMappingError::WildcardNotPermittedInExternalPort => 716,
+            PortMappingError::ConflictInMapping => 718,
+            PortMappingError::SamePortValuesRequired => 724,
+            PortMappingError::OnlyPermanentLeasesSupported => 725,
+            PortMappingError::RemoteHostOnlySupportsWildcard => 726,
+            PortMappingError::ExternalPortOnlySupportsWildcard => 727,
+            PortMappingError::NoPortMapsAvailable => 728,
+            PortMappingError::ConflictWithOtherMechanisms => 729,
+            PortMappingError::WildcardNotPermittedInInternalPort => 732,
+        }
+    }
+}
+
+impl Display for PortMappingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            PortMappingError::NotAuthorized => "Action not authorized",
+            PortMappingError::SpecifiedArrayIndexInvalid => "Port mapping index out of range",
+            PortMappingError::NoSuchEntry => "No such port mapping",
+            PortMappingError::WildcardNotPermittedInRemoteHost => {
+                "Wildcard not permitted in remote host"
+            }
+            PortMappingError::WildcardNotPermittedInExternalPort => {
+                "Wildcard not permitted in external port"
+            }
+            PortMappingError::ConflictInMapping => "External port already mapped to another client",
+            PortMappingError::SamePortValuesRequired => {
+                "Internal and external ports must be the same"
+            }
+            PortMappingError::OnlyPermanentLeasesSupported => "Only permanent leases supported",
+            PortMappingError::RemoteHostOnlySupportsWildcard => {
+                "Remote host only supports wildcard"
+            }
+            PortMappingError::ExternalPortOnlySupportsWildcard => {
+                "External port only supports wildcard"
+            }
+            PortMappingError::NoPortMapsAvailable => "No port mappings available",
+            PortMappingError::ConflictWithOtherMechanisms => {
+                "Port mapping conflicts with another mechanism"
+            }
+            PortMappingError::WildcardNotPermittedInInternalPort => {
+                "Wildcard not permitted in internal port"
+            }
+        };
+        write!(f, "UPnP error {}: {}", self.code(), description)
+    }
+}
+
+impl std::error::Error for PortMappingError {}
+
+allowed_values! {
+    pub enum WanAccessType {
+        Dsl = "DSL",
+        Pots = "POTS",
+        Cable = "Cable",
+        #[default]
+        Ethernet = "Ethernet",
+    }
+}
+
+allowed_values! {
+    pub enum PhysicalLinkStatus {
+        Up = "Up",
+        #[default]
+        Down = "Down",
+        Initializing = "Initializing",
+        Unavailable = "Unavailable",
+    }
+}
+
+// What WANCommonInterfaceConfig reports about the gateway's WAN link.
+// Bitrates are in bits per second.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LinkProperties {
+    pub access_type: WanAccessType,
+    pub upstream_max_bitrate: u32,
+    pub downstream_max_bitrate: u32,
+    pub physical_link_status: PhysicalLinkStatus,
+}
+
+// An IPv6 firewall pinhole opened by `InternetGatewayClient::add_pinhole`.
+// `lease` is what was asked for, clamped to what gateways accept; the
+// pinhole closes unless updated before it runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Pinhole {
+    pub id: u16,
+    pub lease: Duration,
+}
+
+// The WANIPv6FirewallControl errors, from the `UpnpError` code, returned in
+// its place.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PinholeError {
+    NotAuthorized,
+    PinholeSpaceExhausted,
+    FirewallDisabled,
+    InboundPinholeNotAllowed,
+    NoSuchEntry,
+    ProtocolNotSupported,
+    InternalPortWildcardingNotAllowed,
+    ProtocolWildcardingNotAllowed,
+    WildcardNotPermittedInSrcIp,
+    NoTrafficReceived,
+}
+
+impl PinholeError {
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            606 => PinholeError::NotAuthorized,
+            701 => PinholeError::PinholeSpaceExhausted,
+            702 => PinholeError::FirewallDisabled,
+            703 => PinholeError::InboundPinholeNotAllowed,
+            704 => PinholeError::NoSuchEntry,
+            705 => PinholeError::ProtocolNotSupported,
+            706 => PinholeError::InternalPortWildcardingNotAllowed,
+            707 => PinholeError::ProtocolWildcardingNotAllowed,
+            708 => PinholeError::WildcardNotPermittedInSrcIp,
+            709 => PinholeError::NoTrafficReceived,
+            _ => return None,
+        })
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            PinholeError::NotAuthorized => 606,
+            PinholeError::PinholeSpaceExhausted => 701,
+            PinholeError::FirewallDisabled => 702,
+            PinholeError::InboundPinholeNotAllowed => 703,
+            PinholeError::NoSuchEntry => 704,
+            PinholeError::ProtocolNotSupported => 705,
+            PinholeError::InternalPortWildcardingNotAllowed => 706,
+            PinholeError::ProtocolWildcardingNotAllowed => 707,
+            PinholeError::WildcardNotPermittedInSrcIp => 708,
+            PinholeError::NoTrafficReceived => 709,
+        }
+    }
+}
+
+impl Display for PinholeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            PinholeError::NotAuthorized => "Action not authorized",
+            PinholeError::PinholeSpaceExhausted => "No room for another pinhole",
+            PinholeError::FirewallDisabled => "Firewall disabled",
+            PinholeError::InboundPinholeNotAllowed => "Inbound pinholes not allowed",
+            PinholeError::NoSuchEntry => "No such pinhole",
+            PinholeError::ProtocolNotSupported => "Protocol not supported",
+            PinholeError::InternalPortWildcardingNotAllowed => {
+                "Wildcard not permitted in internal port"
+            }
+            PinholeError::ProtocolWildcardingNotAllowed => "Wildcard not permitted in protocol",
+            PinholeError::WildcardNotPermittedInSrcIp => "Wildcard not permitted in remote host",
+            PinholeError::NoTrafficReceived => "No traffic received",
+        };
+        write!(f, "UPnP error {}: {}", self.code(), description)
+    }
+}
+
+impl std::error::Error for PinholeError {}
+
+// An entry of the gateway's NAT table. A zero `lease_duration` is a
+// permanent mapping.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PortMappingEntry {
+    // None when any remote host may connect.
+    pub remote_host: Option<String>,
+    pub external_port: u16,
+    pub protocol: PortMappingProtocol,
+    pub internal_port: u16,
+    pub internal_client: String,
+    pub enabled: bool,
+    pub description: String,
+    pub lease_duration: Duration,
+}
+
+allowed_values! {
+    pub enum OpenHomeTransportState {
+        Playing = "Playing",
+        Paused = "Paused",
+        #[default]
+        Stopped = "Stopped",
+        Buffering = "Buffering",
+    }
+}
+
+// An entry of an OpenHome playlist, or a Radio preset. `metadata` is None
+// when the renderer holds none, or nothing usable, for the track.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlaylistTrack {
+    pub id: u32,
+    pub uri: String,
+    pub metadata: Option<Item>,
+}
+
+// An input of an OpenHome Product, e.g. the playlist, radio or an analog
+// input. Hidden sources aren't offered to the user.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProductSource {
+    pub name: String,
+    // "Playlist", "Radio", "Analog"...
+    pub source_type: String,
+    pub visible: bool,
+}
+
+// A group of Sonos players playing in sync, controlled through its
+// coordinator. Ungrouped players form a group of their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ZoneGroup {
+    pub id: String,
+    // Player id of the coordinator, e.g. "RINCON_000E58A0123401400".
+    pub coordinator: String,
+    pub members: Vec<ZoneGroupMember>,
+}
+
+impl ZoneGroup {
+    pub fn coordinator(&self) -> Option<&ZoneGroupMember> {
+        self.members
+            .iter()
+            .find(|member| member.player_id == self.coordinator)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ZoneGroupMember {
+    pub player_id: String,
+    // The room name, e.g. "Living Room".
+    pub zone_name: String,
+    // Device description URL.
+    pub location: String,
+    // Bonded players such as surround speakers and subwoofers are hidden.
+    pub invisible: bool,
+}
+
+// A recording a ScheduledRecording service (a PVR or tuner) will make,
+// once or repeatedly depending on its class.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecordSchedule {
+    pub id: String,
+    pub title: String,
+    // e.g. "OBJECT.RECORDSCHEDULE.DIRECT.MANUAL".
+    pub class: String,
+    pub channel_id: Option<String>,
+    // Local date and time, e.g. "2026-10-15T20:00:00".
+    pub start: Option<String>,
+    pub duration: Option<Duration>,
+    pub priority: Option<String>,
+    // "OPERATIONAL", "COMPLETED", "ERROR"...
+    pub state: Option<String>,
+}
+
+// A one-off recording of a channel for a fixed time, as a VCR would.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ManualRecording {
+    pub title: String,
+    pub channel_id: String,
+    // How `channel_id` identifies the channel: "DIGITAL" for a channel
+    // number like "5.1", "ANALOG", "FREQUENCY"...
+    pub channel_type: String,
+    pub start: String,
+    pub duration: Duration,
+}
+
+// The protocols a DeviceProtection service offers for pairing with it
+// (e.g. "WPS") and for logging in (e.g. "PKCS5").
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProtectionProtocols {
+    pub introduction: Vec<String>,
+    pub login: Vec<String>,
+}
+
+// What a Sonos player was playing, taken before an announcement so it can
+// be put back afterwards.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SonosSnapshot {
+    // An x-rincon-queue URI when playing from the queue, x-rincon when
+    // grouped with another player.
+    pub uri: String,
+    pub metadata: String,
+    pub transport_state: TransportState,
+    pub play_mode: PlayMode,
+    // Track number in the queue.
+    pub track: Option<u32>,
+    // None for streams, which can't be seeked.
+    pub position: Option<Duration>,
+    pub volume: u8,
+    pub mute: bool,
+}
+
+impl SonosSnapshot {
+    pub fn is_queue(&self) -> bool {
+        self.uri.starts_with("x-rincon-queue:")
+    }
+
+    pub fn is_grouped(&self) -> bool {
+        self.uri.starts_with("x-rincon:")
+    }
+}
+
+// What a Sonos AddURIToQueue did. Track numbers start at 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QueuedTracks {
+    pub first_track: u32,
+    pub tracks_added: u32,
+    pub queue_length: u32,
+}
+
+// The serde representation as a JSON value, for command line tools and web
+// frontends that print what the crate parsed.
+#[cfg(feature = "serde")]
+pub trait ToJson: Serialize {
+    fn to_json(&self) -> serde_json::Value {
+        // Every field is a string, number, list or string-keyed map.
+        serde_json::to_value(self).expect("JSON representation")
+    }
+}
+
+#[cfg(feature = "serde")]
+mod to_json {
+    use super::*;
+
+    impl ToJson for Device {}
+    impl ToJson for Service {}
+    impl ToJson for ServiceDescription {}
+    impl ToJson for DidlObject {}
+    impl ToJson for Container {}
+    impl ToJson for Item {}
+    impl ToJson for Metadata {}
+    impl ToJson for BrowseResult {}
+    impl ToJson for Event {}
+    impl ToJson for PositionInfo {}
+    impl ToJson for TransportInfo {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Criteria, DlnaFlags, DlnaOperations, Filter, Item, Metadata, ObjectClass, PlayMode,
+        ProtocolInfo, Resource, SortCriteria, TagSource, TransportState, TransportStatus,
+        UpnpClass, UpnpTime,
+    };
+    use crate::action::ActionValue;
+    use std::time::Duration;
+
+    fn resource(url: &str, protocol_info: &str) -> Resource {
+        Resource {
+            url: url.to_string(),
+            protocol_info: protocol_info.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_best_resource() {
+        let item = Item {
+            resources: vec![
+                resource("http://nas/cover.jpg", "http-get:*:image/jpeg:*"),
+                resource(
+                    "http://nas/track.mp3",
+                    "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_CI=1",
+                ),
+                resource("http://nas/track.flac", "http-get:*:audio/flac:*"),
+            ],
+            ..Default::default()
+        };
+        let sink = |entries: &[&str]| entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+        let best = |entries: &[&str]| item.best_resource(&sink(entries)).map(|r| r.url.as_str());
+        assert_eq!(
+            best(&[
+                "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3",
+                "http-get:*:audio/flac:*"
+            ]),
+            Some("http://nas/track.flac")
+        );
+        assert_eq!(
+            best(&[
+                "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3",
+                "http-get:*:audio/*:*"
+            ]),
+            Some("http://nas/track.mp3")
+        );
+        assert_eq!(
+            best(&["http-get:*:audio/*:*"]),
+            Some("http://nas/track.mp3")
+        );
+        assert_eq!(best(&["http-get:*:video/mp4:*"]), None);
+    }
+
+    #[test]
+    fn test_validating_sort_criteria() {
+        let sort = SortCriteria::new()
+            .ascending("upnp:album")
+            .descending("dc:date");
+        assert_eq!(sort.to_string(), "+upnp:album,-dc:date");
+        assert_eq!(sort.to_action_value(), "+upnp:album,-dc:date");
+
+        let caps = |caps: &[&str]| caps.iter().map(|cap| cap.to_string()).collect::<Vec<_>>();
+        assert_eq!(sort.validate(&caps(&["upnp:album", "dc:date"])), Ok(()));
+        assert_eq!(sort.validate(&caps(&["*"])), Ok(()));
+        assert_eq!(
+            sort.validate(&caps(&["upnp:album", "dc:title"])),
+            Err("Server can't sort on dc:date".to_string())
+        );
+        assert!(sort.validate(&[]).is_err());
+        assert_eq!(SortCriteria::new().validate(&[]), Ok(()));
+    }
+
+    #[test]
+    fn test_formatting_filters() {
+        assert_eq!(Filter::all().to_string(), "*");
+        assert_eq!(
+            Filter::new()
+                .property("upnp:artist")
+                .property("res@duration")
+                .property("sec:dcmInfo")
+                .to_string(),
+            "upnp:artist,res@duration,sec:dcmInfo"
+        );
+    }
+
+    #[test]
+    fn test_converting_items_to_load_options() {
+        let item = Item {
+            id: "64$3".to_string(),
+            title: "Blue in Green".to_string(),
+            artist: Some("Miles Davis".to_string()),
+            object_class: Some(ObjectClass::Audio),
+            resources: vec![resource(
+                "http://nas/1.mp3",
+                "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_OP=01",
+            )],
+            ..Default::default()
+        };
+        let options = item.to_load_options(0).unwrap();
+        assert_eq!(options.content_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(
+            options.dlna_features.as_deref(),
+            Some("DLNA.ORG_PN=MP3;DLNA.ORG_OP=01")
+        );
+        let metadata = options.metadata.unwrap();
+        assert_eq!(metadata.url, "http://nas/1.mp3");
+        assert_eq!(metadata.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(metadata.id.as_deref(), Some("64$3"));
+        assert_eq!(metadata.parent_id, None);
+        assert!(item.to_load_options(1).is_none());
+    }
+
+    #[test]
+    fn test_metadata_from_tags() {
+        struct Id3;
+
+        impl TagSource for Id3 {
+            fn title(&self) -> Option<String> {
+                Some("Intro".to_string())
+            }
+
+            fn artist(&self) -> Option<String> {
+                Some("The xx".to_string())
+            }
+
+            fn duration(&self) -> Option<Duration> {
+                Some(Duration::from_millis(127_500))
+            }
+
+            fn mime_type(&self) -> Option<String> {
+                Some("audio/mpeg".to_string())
+            }
+        }
+
+        let metadata = Metadata::from_tags(&Id3);
+        assert_eq!(metadata.title, "Intro");
+        assert_eq!(metadata.artist.as_deref(), Some("The xx"));
+        assert_eq!(metadata.album, None);
+        assert_eq!(metadata.duration.as_deref(), Some("00:02:07.500"));
+        assert_eq!(metadata.protocol_info, "http-get:*:audio/mpeg:*");
+        assert_eq!(metadata.object_class, Some(ObjectClass::Audio));
+    }
+
+    #[test]
+    fn test_class_hierarchy() {
+        let track = UpnpClass::new("object.item.audioItem.musicTrack");
+        assert!(track.derived_from(&UpnpClass::AUDIO_ITEM));
+        assert!(track.derived_from(&UpnpClass::MUSIC_TRACK));
+        assert!(track.derived_from(&UpnpClass::OBJECT));
+        assert!(!track.derived_from(&UpnpClass::VIDEO_ITEM));
+        assert!(
+            !UpnpClass::new("object.item.audioItemBroadcast").derived_from(&UpnpClass::AUDIO_ITEM)
+        );
+        assert_eq!(track.parent(), Some(UpnpClass::AUDIO_ITEM));
+        assert_eq!(UpnpClass::OBJECT.parent(), None);
+        assert_eq!(
+            UpnpClass::AUDIO_ITEM.search_criteria().to_string(),
+            r#"upnp:class derivedfrom "object.item.audioItem""#
+        );
+
+        let item = Item {
+            class: "object.item.videoItem.movie.vendorCut".to_string(),
+            ..Default::default()
+        };
+        assert!(item.derived_from(&UpnpClass::MOVIE));
+        assert!(!item.is_playlist());
+        assert_eq!(
+            ObjectClass::from("object.item.audioItem.audioBroadcast"),
+            ObjectClass::AudioBroadcast
+        );
+        assert_eq!(ObjectClass::from(item.class.as_str()), ObjectClass::Video);
+        assert_eq!(
+            ObjectClass::from("object.item.audioItem"),
+            ObjectClass::Audio
+        );
+        assert_eq!(
+            ObjectClass::for_content_type("audio/flac"),
+            ObjectClass::Audio
+        );
+        assert_eq!(
+            ObjectClass::for_content_type("application/x-mpegURL"),
+            ObjectClass::Video
+        );
+    }
+
+    #[test]
+    fn test_building_search_criteria() {
+        let criteria = Criteria::class_derived_from("object.item.audioItem").and(
+            Criteria::contains("dc:title", "live").or(Criteria::eq("upnp:album", "Say \"Hi\"")),
+        );
+        assert_eq!(
+            criteria.to_string(),
+            r#"upnp:class derivedfrom "object.item.audioItem" and (dc:title contains "live" or upnp:album = "Say \"Hi\"")"#
+        );
+        assert_eq!(
+            criteria.properties(),
+            ["upnp:class", "dc:title", "upnp:album"]
+        );
+        assert_eq!(
+            Criteria::All
+                .and(Criteria::exists("upnp:genre", false))
+                .to_string(),
+            "upnp:genre exists false"
+        );
+        assert_eq!(
+            Criteria::eq("dc:title", "x").or(Criteria::All),
+            Criteria::All
+        );
+
+        let capabilities = vec!["upnp:class".to_string(), "dc:title".to_string()];
+        assert_eq!(
+            criteria.validate(&capabilities),
+            Err("Server can't search on upnp:album".to_string())
+        );
+        assert!(criteria.validate(&["*".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_protocol_info() {
+        let info: ProtocolInfo = "http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_HP_HD_AAC;DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000"
+            .parse()
+            .unwrap();
+        assert_eq!(info.content_format, "video/mp4");
+        assert_eq!(info.profile(), Some("AVC_MP4_HP_HD_AAC"));
+        assert_eq!(
+            info.operations(),
+            Some(DlnaOperations {
+                time_seek: false,
+                range: true
+            })
+        );
+        assert_eq!(info.converted(), Some(false));
+        let flags = info.flags().unwrap();
+        assert!(flags.contains(DlnaFlags::STREAMING_TRANSFER | DlnaFlags::DLNA_V15));
+        assert!(!flags.contains(DlnaFlags::SENDER_PACED));
+
+        let built = ProtocolInfo::http_get("audio/mpeg")
+            .with_profile("MP3")
+            .with_operations(DlnaOperations {
+                time_seek: false,
+                range: true,
+            })
+            .with_converted(true)
+            .with_flags(DlnaFlags::STREAMING_TRANSFER | DlnaFlags::DLNA_V15)
+            .with_converted(false);
+        assert_eq!(
+            built.to_string(),
+            "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01100000000000000000000000000000"
+        );
+        assert_eq!(built.to_string().parse::<ProtocolInfo>().unwrap(), built);
+        assert_eq!(
+            "http-get:*:audio/flac"
+                .parse::<ProtocolInfo>()
+                .unwrap()
+                .additional_info,
+            "*"
+        );
+        assert!("http-get:*".parse::<ProtocolInfo>().is_err());
+    }
+
+    #[test]
+    fn test_allowed_values() {
+        assert_eq!(
+            "PAUSED_PLAYBACK".parse::<TransportState>().unwrap(),
+            TransportState::PausedPlayback
+        );
+        assert_eq!(
+            " playing ".parse::<TransportState>().unwrap(),
+            TransportState::Playing
+        );
+        let sonos = "SHUFFLE_NOREPEAT".parse::<PlayMode>().unwrap();
+        assert_eq!(sonos, PlayMode::Other("SHUFFLE_NOREPEAT".to_string()));
+        assert_eq!(sonos.to_string(), "SHUFFLE_NOREPEAT");
+        assert_eq!(PlayMode::RepeatAll.to_action_value(), "REPEAT_ALL");
+        assert_eq!(TransportStatus::default(), TransportStatus::Ok);
+    }
+
+    #[test]
+    fn test_upnp_time() {
+        let time = |value: &str| value.parse::<UpnpTime>().map(Duration::from);
+        assert_eq!(time("0:04:21"), Ok(Duration::from_secs(261)));
+        assert_eq!(time("+01:02:03.5"), Ok(Duration::from_millis(3_723_500)));
+        assert_eq!(time("4:21"), Ok(Duration::from_secs(261)));
+        assert_eq!(time("0:00:01.1/4"), Ok(Duration::from_millis(1_250)));
+        assert_eq!(time("0:00:01.123456789"), Ok(Duration::new(1, 123_456_789)));
+        assert!(time("0:00:01.4/4").is_err());
+        assert!(time("0:00:01.").is_err());
+        assert!(time("-0:00:10").is_err());
+        assert!(time("1::00").is_err());
+        assert!(time("NOT_IMPLEMENTED").is_err());
+
+        let display = |duration: Duration| UpnpTime(duration).to_string();
+        assert_eq!(display(Duration::from_secs(3725)), "01:02:05");
+        assert_eq!(display(Duration::from_secs(442_800)), "123:00:00");
+        assert_eq!(display(Duration::from_millis(1500)), "00:00:01.500");
+        assert_eq!(display(Duration::from_micros(1_000_400)), "00:00:01");
+        assert_eq!(
+            "100:00:00.250".parse::<UpnpTime>().unwrap().to_string(),
+            "100:00:00.250"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use super::{AVTransportEvent, DidlObject, Event, Metadata, QName, ToJson};
+        use std::collections::HashMap;
+
+        let item = DidlObject::Item(Item {
+            id: "64$1".to_string(),
+            title: "Blue in Green".to_string(),
+            resources: vec![resource("http://nas/1.flac", "http-get:*:audio/flac:*")],
+            last_playback_position: Some(std::time::Duration::from_millis(62_500)),
+            object_class: Some(ObjectClass::Audio),
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&item).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            DidlObject::Item(parsed) => {
+                assert_eq!(parsed.title, "Blue in Green");
+                assert_eq!(parsed.resources[0].url, "http://nas/1.flac");
+                assert_eq!(
+                    parsed.last_playback_position,
+                    Some(std::time::Duration::from_millis(62_500))
+                );
+            }
+            DidlObject::Container(_) => panic!("expected an item"),
+        }
+
+        let metadata = Metadata {
+            extra_elements: HashMap::from([(
+                QName::new("http://www.sec.co.kr/", "sec", "CaptionInfoEx"),
+                "http://nas/movie.srt".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&metadata).unwrap();
+        let parsed: Metadata = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.extra_elements, metadata.extra_elements);
+
+        let json = Event::AVTransport(AVTransportEvent::TransportState {
+            sid: "uuid:1".to_string(),
+            transport_state: TransportState::Playing,
+        })
+        .to_json();
+        assert_eq!(
+            json.to_string(),
+            r#"{"AVTransport":{"TransportState":{"sid":"uuid:1","transport_state":"Playing"}}}"#
+        );
+        assert_eq!(item.to_json()["Item"]["title"], "Blue in Green");
+    }
 }
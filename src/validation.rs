@@ -0,0 +1,326 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use crate::{
+    parser::parse_didl,
+    types::{DidlObject, DlnaFlags, Item, ProtocolInfo, Resource, UpnpClass},
+};
+
+// Something in DIDL-Lite sent to a renderer that the DLNA guidelines rule
+// out, and that picky renderers answer with a bare 714 or 716 error.
+// Resource problems name the resource's URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DlnaWarning {
+    MissingId,
+    MissingParentId,
+    MissingTitle,
+    MissingClass,
+    NoResource,
+    InvalidProtocolInfo {
+        url: String,
+        protocol_info: String,
+    },
+    // DLNA.ORG_PN names a profile of another mime type.
+    ProfileMismatch {
+        url: String,
+        profile: String,
+        content_format: String,
+    },
+    // An audio item with a video resource, say.
+    ClassMismatch {
+        url: String,
+        class: String,
+        content_format: String,
+    },
+    // DLNA.ORG_FLAGS without the DLNA 1.5 version flag, which renderers then
+    // ignore.
+    FlagsWithoutVersion {
+        url: String,
+    },
+    MissingDuration {
+        url: String,
+    },
+    MissingResolution {
+        url: String,
+    },
+}
+
+impl Display for DlnaWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DlnaWarning::MissingId => f.write_str("Item has no id"),
+            DlnaWarning::MissingParentId => f.write_str("Item has no parentID"),
+            DlnaWarning::MissingTitle => f.write_str("Item has no dc:title"),
+            DlnaWarning::MissingClass => f.write_str("Item has no upnp:class"),
+            DlnaWarning::NoResource => f.write_str("Item has no res"),
+            DlnaWarning::InvalidProtocolInfo { url, protocol_info } => {
+                write!(f, "Invalid protocolInfo {} for {}", protocol_info, url)
+            }
+            DlnaWarning::ProfileMismatch {
+                url,
+                profile,
+                content_format,
+            } => write!(
+                f,
+                "DLNA profile {} doesn't match {} for {}",
+                profile, content_format, url
+            ),
+            DlnaWarning::ClassMismatch {
+                url,
+                class,
+                content_format,
+            } => write!(f, "{} is a {} but served as {}", url, class, content_format),
+            DlnaWarning::FlagsWithoutVersion { url } => {
+                write!(f, "DLNA.ORG_FLAGS without the DLNA 1.5 flag for {}", url)
+            }
+            DlnaWarning::MissingDuration { url } => write!(f, "No duration for {}", url),
+            DlnaWarning::MissingResolution { url } => write!(f, "No resolution for {}", url),
+        }
+    }
+}
+
+// Mime types of the common DLNA media format profiles, by name prefix.
+const PROFILE_MIME_TYPES: &[(&str, &[&str])] = &[
+    ("MP3", &["audio/mpeg"]),
+    ("LPCM", &["audio/L16"]),
+    ("AAC_", &["audio/mp4", "audio/3gpp", "audio/vnd.dlna.adts"]),
+    (
+        "HEAAC_",
+        &["audio/mp4", "audio/3gpp", "audio/vnd.dlna.adts"],
+    ),
+    ("WMA", &["audio/x-ms-wma"]),
+    ("AC3", &["audio/vnd.dolby.dd-raw"]),
+    ("JPEG_", &["image/jpeg"]),
+    ("PNG_", &["image/png"]),
+    ("GIF_", &["image/gif"]),
+    ("MPEG1", &["video/mpeg"]),
+    ("MPEG_PS_", &["video/mpeg"]),
+    ("MPEG_TS_", &["video/vnd.dlna.mpeg-tts", "video/mpeg"]),
+    ("AVC_TS_", &["video/vnd.dlna.mpeg-tts", "video/mpeg"]),
+    ("AVC_MP4_", &["video/mp4"]),
+    ("MPEG4_P2_MP4_", &["video/mp4"]),
+    ("WMV", &["video/x-ms-wmv"]),
+];
+
+// Checks a DIDL-Lite document, such as the CurrentURIMetaData built for
+// `MediaRendererClient::load`, item by item.
+pub fn validate_didl(didl: &str) -> Result<Vec<DlnaWarning>> {
+    Ok(parse_didl(didl)?
+        .iter()
+        .flat_map(|object| match object {
+            DidlObject::Item(item) => validate_item(item),
+            DidlObject::Container(_) => vec![],
+        })
+        .collect())
+}
+
+pub fn validate_item(item: &Item) -> Vec<DlnaWarning> {
+    let mut warnings = vec![];
+    if item.id.is_empty() {
+        warnings.push(DlnaWarning::MissingId);
+    }
+    if item.parent_id.is_empty() {
+        warnings.push(DlnaWarning::MissingParentId);
+    }
+    if item.title.trim().is_empty() {
+        warnings.push(DlnaWarning::MissingTitle);
+    }
+    if item.class.is_empty() {
+        warnings.push(DlnaWarning::MissingClass);
+    }
+    if item.resources.is_empty() {
+        warnings.push(DlnaWarning::NoResource);
+    }
+    for resource in &item.resources {
+        warnings.extend(validate_resource(resource, &item.upnp_class()));
+    }
+    warnings
+}
+
+pub fn validate_resource(resource: &Resource, class: &UpnpClass) -> Vec<DlnaWarning> {
+    let url = || resource.url.clone();
+    let protocol: ProtocolInfo = match resource.protocol_info.parse() {
+        Ok(protocol) => protocol,
+        Err(_) => {
+            return vec![DlnaWarning::InvalidProtocolInfo {
+                url: url(),
+                protocol_info: resource.protocol_info.clone(),
+            }]
+        }
+    };
+    let mut warnings = validate_protocol_info(&protocol)
+        .into_iter()
+        .map(|warning| match warning {
+            DlnaWarning::ProfileMismatch {
+                profile,
+                content_format,
+                ..
+            } => DlnaWarning::ProfileMismatch {
+                url: url(),
+                profile,
+                content_format,
+            },
+            DlnaWarning::FlagsWithoutVersion { .. } => {
+                DlnaWarning::FlagsWithoutVersion { url: url() }
+            }
+            warning => warning,
+        })
+        .collect::<Vec<_>>();
+
+    let media_type = protocol
+        .content_format
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    let expected = [
+        (UpnpClass::AUDIO_ITEM, "audio"),
+        (UpnpClass::VIDEO_ITEM, "video"),
+        (UpnpClass::IMAGE_ITEM, "image"),
+    ]
+    .into_iter()
+    .find(|(parent, _)| class.derived_from(parent));
+    if let Some((_, expected)) = expected {
+        // Album art and subtitles are fine next to the media.
+        if (media_type == "audio" || media_type == "video") && media_type != expected {
+            warnings.push(DlnaWarning::ClassMismatch {
+                url: url(),
+                class: class.to_string(),
+                content_format: protocol.content_format.clone(),
+            });
+        }
+        // Broadcasts have no duration to give.
+        let broadcast = class.as_str().contains("Broadcast");
+        if expected != "image"
+            && media_type == expected
+            && !broadcast
+            && resource.duration.is_none()
+        {
+            warnings.push(DlnaWarning::MissingDuration { url: url() });
+        }
+        if expected != "audio" && media_type == expected && resource.resolution.is_none() {
+            warnings.push(DlnaWarning::MissingResolution { url: url() });
+        }
+    }
+    warnings
+}
+
+// The checks that need nothing but the protocolInfo. Warnings have an
+// empty URL.
+pub fn validate_protocol_info(protocol: &ProtocolInfo) -> Vec<DlnaWarning> {
+    let mut warnings = vec![];
+    if let Some(profile) = protocol.profile() {
+        let mime_types = PROFILE_MIME_TYPES
+            .iter()
+            .find(|(prefix, _)| profile.starts_with(prefix))
+            .map(|(_, mime_types)| *mime_types);
+        let matches = |mime_types: &[&str]| {
+            mime_types
+                .iter()
+                .any(|mime| protocol.content_format.eq_ignore_ascii_case(mime))
+        };
+        if mime_types.is_some_and(|mime_types| !matches(mime_types)) {
+            warnings.push(DlnaWarning::ProfileMismatch {
+                url: String::new(),
+                profile: profile.to_string(),
+                content_format: protocol.content_format.clone(),
+            });
+        }
+    }
+    if let Some(flags) = protocol.flags() {
+        if !flags.contains(DlnaFlags::DLNA_V15) {
+            warnings.push(DlnaWarning::FlagsWithoutVersion { url: String::new() });
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_didl, validate_protocol_info, DlnaWarning};
+    use crate::{
+        media_renderer::validate_load,
+        types::{DlnaFlags, LoadOptions, Metadata, ObjectClass, ProtocolInfo},
+    };
+
+    #[test]
+    fn test_validating_load_metadata() {
+        let options = LoadOptions {
+            content_type: Some("audio/flac".to_string()),
+            dlna_features: Some("DLNA.ORG_PN=MP3".to_string()),
+            metadata: Some(Metadata {
+                title: "Intro".to_string(),
+                duration: Some("0:01:00".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_load("http://nas/1.flac", &options).unwrap(),
+            [DlnaWarning::ProfileMismatch {
+                url: "http://nas/1.flac".to_string(),
+                profile: "MP3".to_string(),
+                content_format: "audio/flac".to_string(),
+            }]
+        );
+
+        let options = LoadOptions {
+            content_type: Some("video/mp4".to_string()),
+            object_class: Some(ObjectClass::Audio),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_load("http://nas/2.mp4", &options).unwrap(),
+            [
+                DlnaWarning::MissingTitle,
+                DlnaWarning::ClassMismatch {
+                    url: "http://nas/2.mp4".to_string(),
+                    class: "object.item.audioItem.musicTrack".to_string(),
+                    content_format: "video/mp4".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validating_didl() {
+        let didl = concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+            r#"<item id="1"><dc:title>Movie</dc:title><upnp:class>object.item.videoItem.movie</upnp:class>"#,
+            r#"<res protocolInfo="http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_BL_CIF15_AAC_520">http://nas/movie.mp4</res>"#,
+            r#"<res protocolInfo="http-get:*:image/jpeg:*">http://nas/movie.jpg</res>"#,
+            r#"<res protocolInfo="mp4">http://nas/movie.bin</res></item>"#,
+            r#"</DIDL-Lite>"#
+        );
+        let url = |url: &str| url.to_string();
+        assert_eq!(
+            validate_didl(didl).unwrap(),
+            [
+                DlnaWarning::MissingParentId,
+                DlnaWarning::MissingDuration {
+                    url: url("http://nas/movie.mp4")
+                },
+                DlnaWarning::MissingResolution {
+                    url: url("http://nas/movie.mp4")
+                },
+                DlnaWarning::InvalidProtocolInfo {
+                    url: url("http://nas/movie.bin"),
+                    protocol_info: "mp4".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validating_dlna_flags() {
+        let protocol =
+            ProtocolInfo::http_get("audio/mpeg").with_flags(DlnaFlags::STREAMING_TRANSFER);
+        assert_eq!(
+            validate_protocol_info(&protocol),
+            [DlnaWarning::FlagsWithoutVersion { url: String::new() }]
+        );
+        let protocol = protocol.with_flags(DlnaFlags::STREAMING_TRANSFER | DlnaFlags::DLNA_V15);
+        assert!(validate_protocol_info(&protocol).is_empty());
+    }
+}